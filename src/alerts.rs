@@ -0,0 +1,547 @@
+//! Alerting rules from the `[[alerts]]` tables: a single large transfer,
+//! outflows adding up past a limit within a window, a wallet balance below
+//! a floor, or a new delegate approval. Transfers and approvals are checked
+//! as the background indexer publishes them and balances on a timer; a rule that fires goes out through every
+//! configured webhook, Telegram chat and Discord webhook, then stays quiet
+//! for its cooldown.
+
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::config::{redact_url, Config, MintInfo};
+use crate::events::{self, ApprovalEvent, TransferEvent};
+use crate::indexer::fetch_balance;
+use crate::metrics::METRICS;
+use crate::model::{format_amount, ApprovalKind, Direction};
+use crate::rpc::SolanaRpc;
+use crate::{discord, telegram, webhook};
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One `[[alerts]]` entry.
+#[derive(Debug, Clone)]
+pub struct AlertRule {
+    pub name: String,
+    pub condition: AlertCondition,
+    /// Wallet the rule watches; any tracked wallet when `None`. Balance
+    /// rules always name one.
+    pub wallet: Option<Pubkey>,
+    pub mint: MintInfo,
+    /// Quiet period after the rule fires.
+    pub cooldown: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlertCondition {
+    /// One transfer of at least `min_raw`, optionally in one direction.
+    LargeTransfer {
+        min_raw: u64,
+        direction: Option<Direction>,
+    },
+    /// Sent and burned amounts adding up to at least `max_raw` within
+    /// `window` of chain time.
+    Outflow { max_raw: u64, window: Duration },
+    /// The wallet's balance is below `floor_raw`.
+    BalanceBelow { floor_raw: u64 },
+    /// A delegate was approved to spend at least `min_raw` from one of the
+    /// wallet's token accounts.
+    Approval { min_raw: u64 },
+}
+
+impl AlertCondition {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AlertCondition::LargeTransfer { .. } => "transfer",
+            AlertCondition::Outflow { .. } => "outflow",
+            AlertCondition::BalanceBelow { .. } => "balance",
+            AlertCondition::Approval { .. } => "approval",
+        }
+    }
+}
+
+/// A rule that fired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub rule: String,
+    pub kind: &'static str,
+    pub wallet: Pubkey,
+    pub mint: String,
+    pub symbol: String,
+    pub message: String,
+    /// The transfer that tipped the rule over, for transfer-driven rules.
+    pub signature: Option<String>,
+}
+
+impl Alert {
+    fn to_json(&self) -> Value {
+        json!({
+            "type": "alert",
+            "rule": self.rule,
+            "kind": self.kind,
+            "wallet": self.wallet.to_string(),
+            "mint": self.mint,
+            "symbol": self.symbol,
+            "message": self.message,
+            "signature": self.signature,
+        })
+    }
+
+    fn to_text(&self, config: &Config) -> String {
+        let mut text = format!(
+            "Alert: {}\n{}\nWallet: {}",
+            self.rule, self.message, self.wallet
+        );
+        if let Some(signature) = &self.signature {
+            text.push('\n');
+            text.push_str(&config.explorer_link(signature));
+        }
+        text
+    }
+}
+
+/// Rule state: when each rule last fired and the outflows inside each
+/// outflow rule's window.
+#[derive(Debug)]
+struct Evaluator {
+    rules: Vec<AlertRule>,
+    last_fired: HashMap<usize, Instant>,
+    outflows: HashMap<(usize, Pubkey), VecDeque<(i64, u64)>>,
+}
+
+impl Evaluator {
+    fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            rules,
+            last_fired: HashMap::new(),
+            outflows: HashMap::new(),
+        }
+    }
+
+    fn watches(rule: &AlertRule, wallet: &Pubkey, mint: &MintInfo) -> bool {
+        rule.mint.mint == mint.mint && rule.wallet.is_none_or(|watched| watched == *wallet)
+    }
+
+    /// The alerts `event` sets off.
+    fn on_transfer(&mut self, event: &TransferEvent, now: Instant) -> Vec<Alert> {
+        let transfer = &event.transfer;
+        if transfer.failed {
+            return Vec::new();
+        }
+        let mut fired = Vec::new();
+        for index in 0..self.rules.len() {
+            let rule = &self.rules[index];
+            if !Self::watches(rule, &event.wallet, &event.mint) {
+                continue;
+            }
+            let mint = &event.mint;
+            let message = match rule.condition {
+                AlertCondition::LargeTransfer { min_raw, direction } => {
+                    if direction.is_some_and(|d| d != transfer.direction) {
+                        continue;
+                    }
+                    (transfer.amount_raw >= min_raw).then(|| {
+                        format!(
+                            "{} (limit {} {})",
+                            event.headline(),
+                            format_amount(min_raw, mint.decimals),
+                            mint.symbol
+                        )
+                    })
+                }
+                AlertCondition::Outflow { max_raw, window } => {
+                    if !matches!(transfer.direction, Direction::Sent | Direction::Burned) {
+                        continue;
+                    }
+                    let recent = self.outflows.entry((index, event.wallet)).or_default();
+                    recent.push_back((transfer.block_time, transfer.amount_raw));
+                    let newest = recent.iter().map(|(time, _)| *time).max().unwrap_or(0);
+                    recent.retain(|(time, _)| newest - time < window.as_secs() as i64);
+                    let total: u64 = recent.iter().map(|(_, amount)| amount).sum();
+                    (total >= max_raw).then(|| {
+                        format!(
+                            "{} {} sent within {} minutes (limit {})",
+                            format_amount(total, mint.decimals),
+                            mint.symbol,
+                            window.as_secs() / 60,
+                            format_amount(max_raw, mint.decimals)
+                        )
+                    })
+                }
+                AlertCondition::BalanceBelow { .. } | AlertCondition::Approval { .. } => continue,
+            };
+            let alert = message.map(|message| Alert {
+                rule: rule.name.clone(),
+                kind: rule.condition.kind(),
+                wallet: event.wallet,
+                mint: mint.mint.to_string(),
+                symbol: mint.symbol.clone(),
+                message,
+                signature: Some(transfer.signature.clone()),
+            });
+            fired.extend(self.settle(index, alert, now));
+        }
+        fired
+    }
+
+    /// The alerts `event` sets off. Revokes only take risk away, so they
+    /// never do.
+    fn on_approval(&mut self, event: &ApprovalEvent, now: Instant) -> Vec<Alert> {
+        let approval = &event.approval;
+        let (ApprovalKind::Approve, Some(delegate), Some(amount_raw)) =
+            (approval.kind, &approval.delegate, approval.amount_raw)
+        else {
+            return Vec::new();
+        };
+        let mut fired = Vec::new();
+        for index in 0..self.rules.len() {
+            let rule = &self.rules[index];
+            let AlertCondition::Approval { min_raw } = rule.condition else {
+                continue;
+            };
+            if !Self::watches(rule, &event.wallet, &event.mint) {
+                continue;
+            }
+            let mint = &event.mint;
+            let alert = (amount_raw >= min_raw).then(|| Alert {
+                rule: rule.name.clone(),
+                kind: rule.condition.kind(),
+                wallet: event.wallet,
+                mint: mint.mint.to_string(),
+                symbol: mint.symbol.clone(),
+                message: format!(
+                    "Approved {} to spend {} {} from {}",
+                    delegate,
+                    format_amount(amount_raw, mint.decimals),
+                    mint.symbol,
+                    approval.token_account
+                ),
+                signature: Some(approval.signature.clone()),
+            });
+            fired.extend(self.settle(index, alert, now));
+        }
+        fired
+    }
+
+    /// The alert, if any, of balance rule `index` for a balance of
+    /// `amount_raw`.
+    fn on_balance(&mut self, index: usize, amount_raw: u64, now: Instant) -> Option<Alert> {
+        let rule = &self.rules[index];
+        let AlertCondition::BalanceBelow { floor_raw } = rule.condition else {
+            return None;
+        };
+        let wallet = rule.wallet?;
+        let decimals = rule.mint.decimals;
+        let alert = (amount_raw < floor_raw).then(|| Alert {
+            rule: rule.name.clone(),
+            kind: rule.condition.kind(),
+            wallet,
+            mint: rule.mint.mint.to_string(),
+            symbol: rule.mint.symbol.clone(),
+            message: format!(
+                "Balance {} {} is below {}",
+                format_amount(amount_raw, decimals),
+                rule.mint.symbol,
+                format_amount(floor_raw, decimals)
+            ),
+            signature: None,
+        });
+        self.settle(index, alert, now)
+    }
+
+    /// Counts the evaluation of rule `index` and lets `alert` through
+    /// unless the rule is cooling down.
+    fn settle(&mut self, index: usize, alert: Option<Alert>, now: Instant) -> Option<Alert> {
+        let rule = &self.rules[index];
+        let outcome = match &alert {
+            None => "quiet",
+            Some(_) => match self.last_fired.get(&index) {
+                Some(last) if now.duration_since(*last) < rule.cooldown => "cooldown",
+                _ => "fired",
+            },
+        };
+        METRICS
+            .alert_evaluations
+            .with_label_values(&[&rule.name, outcome])
+            .inc();
+        if outcome != "fired" {
+            return None;
+        }
+        self.last_fired.insert(index, now);
+        alert
+    }
+}
+
+/// Starts the evaluator if any rule is configured. It stops at `shutdown`.
+pub fn spawn(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    shutdown: watch::Receiver<bool>,
+) -> Option<JoinHandle<()>> {
+    if config.alerts.is_empty() {
+        return None;
+    }
+    Some(tokio::spawn(run(client, config, shutdown)))
+}
+
+async fn run(client: Arc<dyn SolanaRpc>, config: Arc<Config>, mut shutdown: watch::Receiver<bool>) {
+    let http = match reqwest::Client::builder().timeout(DELIVERY_TIMEOUT).build() {
+        Ok(http) => http,
+        Err(e) => {
+            error!(error = %e, "cannot build alert client, alerts disabled");
+            return;
+        }
+    };
+    let mut evaluator = Evaluator::new(config.alerts.clone());
+    let balance_rules: Vec<usize> = (0..config.alerts.len())
+        .filter(|&index| {
+            matches!(
+                config.alerts[index].condition,
+                AlertCondition::BalanceBelow { .. }
+            )
+        })
+        .collect();
+    let mut balance_checks = tokio::time::interval(config.alert_balance_interval);
+    let mut feed = events::subscribe();
+    let mut approvals = events::subscribe_approvals();
+    info!(rules = config.alerts.len(), "alert evaluator started");
+    loop {
+        let alerts = tokio::select! {
+            event = feed.recv() => match event {
+                Ok(event) => evaluator.on_transfer(&event, Instant::now()),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "alert evaluator fell behind, transfers not checked");
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            event = approvals.recv() => match event {
+                Ok(event) => evaluator.on_approval(&event, Instant::now()),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "alert evaluator fell behind, approvals not checked");
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            _ = balance_checks.tick(), if !balance_rules.is_empty() => {
+                let mut alerts = Vec::new();
+                for &index in &balance_rules {
+                    let rule = &config.alerts[index];
+                    let Some(wallet) = rule.wallet else { continue };
+                    match fetch_balance(client.as_ref(), &config, &wallet, &rule.mint, config.commitment).await {
+                        Ok(balance) => {
+                            alerts.extend(evaluator.on_balance(index, balance.amount_raw, Instant::now()));
+                        }
+                        Err(e) => {
+                            warn!(rule = %rule.name, %wallet, error = %e, "alert balance check failed");
+                            METRICS
+                                .alert_evaluations
+                                .with_label_values(&[&rule.name, "error"])
+                                .inc();
+                        }
+                    }
+                }
+                alerts
+            }
+            _ = shutdown.changed() => return,
+        };
+        for alert in alerts {
+            info!(rule = %alert.rule, wallet = %alert.wallet, message = %alert.message, "alert fired");
+            tokio::select! {
+                _ = notify(&http, &config, &alert) => {}
+                _ = shutdown.changed() => return,
+            }
+        }
+    }
+}
+
+/// Sends `alert` once to every channel; transfer notification filters
+/// don't apply.
+pub(crate) async fn notify(http: &reqwest::Client, config: &Config, alert: &Alert) {
+    let body = alert.to_json();
+    let text = alert.to_text(config);
+    for target in &config.webhooks {
+        let outcome = webhook::post_alert(http, target, &body).await;
+        record("webhook", &redact_url(&target.url), outcome);
+    }
+    if let Some(target) = &config.telegram {
+        let outcome = telegram::post_alert(http, target, &text).await;
+        record("telegram", &target.chat_id, outcome);
+    }
+    for target in &config.discord {
+        let outcome = discord::post_alert(http, &target.url, &text).await;
+        record("discord", &redact_url(&target.url), outcome);
+    }
+}
+
+fn record(channel: &str, target: &str, outcome: anyhow::Result<()>) {
+    let outcome = match outcome {
+        Ok(()) => "delivered",
+        Err(e) => {
+            warn!(channel, %target, error = %e, "alert delivery failed");
+            "failed"
+        }
+    };
+    METRICS
+        .alert_notifications
+        .with_label_values(&[channel, outcome])
+        .inc();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MintRegistry, DEFAULT_MINTS};
+    use crate::model::fixtures::transfer;
+    use crate::model::Approval;
+
+    fn usdc() -> MintInfo {
+        MintRegistry::parse(DEFAULT_MINTS).unwrap().mints[0].clone()
+    }
+
+    fn rule(name: &str, condition: AlertCondition) -> AlertRule {
+        AlertRule {
+            name: name.to_string(),
+            condition,
+            wallet: None,
+            mint: usdc(),
+            cooldown: Duration::from_secs(600),
+        }
+    }
+
+    fn event(direction: Direction, amount_raw: u64, block_time: i64) -> TransferEvent {
+        let mut transfer = transfer(direction, amount_raw, "alice");
+        transfer.block_time = block_time;
+        TransferEvent {
+            id: 0,
+            wallet: Pubkey::default(),
+            mint: usdc(),
+            transfer,
+        }
+    }
+
+    #[test]
+    fn fires_on_large_transfers_then_cools_down() {
+        let condition = AlertCondition::LargeTransfer {
+            min_raw: 1_000_000_000,
+            direction: Some(Direction::Received),
+        };
+        let mut evaluator = Evaluator::new(vec![rule("whale", condition)]);
+        let start = Instant::now();
+        assert!(evaluator
+            .on_transfer(&event(Direction::Received, 999_999_999, 0), start)
+            .is_empty());
+        assert!(evaluator
+            .on_transfer(&event(Direction::Sent, 5_000_000_000, 0), start)
+            .is_empty());
+
+        let fired = evaluator.on_transfer(&event(Direction::Received, 1_000_000_000, 0), start);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].rule, "whale");
+        assert_eq!(fired[0].signature.as_deref(), Some("sig-1000000000"));
+        assert_eq!(
+            fired[0].message,
+            "Received 1000.000000 USDC (limit 1000.000000 USDC)"
+        );
+
+        let big = event(Direction::Received, 2_000_000_000, 0);
+        assert!(evaluator
+            .on_transfer(&big, start + Duration::from_secs(599))
+            .is_empty());
+        assert_eq!(
+            evaluator
+                .on_transfer(&big, start + Duration::from_secs(600))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn sums_outflows_within_the_window() {
+        let condition = AlertCondition::Outflow {
+            max_raw: 10_000_000,
+            window: Duration::from_secs(1800),
+        };
+        let mut evaluator = Evaluator::new(vec![rule("drain", condition)]);
+        let now = Instant::now();
+        assert!(evaluator
+            .on_transfer(&event(Direction::Sent, 6_000_000, 0), now)
+            .is_empty());
+        // Incoming transfers don't offset outflows, and old ones age out.
+        assert!(evaluator
+            .on_transfer(&event(Direction::Received, 50_000_000, 100), now)
+            .is_empty());
+        assert!(evaluator
+            .on_transfer(&event(Direction::Sent, 4_000_000, 1800), now)
+            .is_empty());
+        let fired = evaluator.on_transfer(&event(Direction::Burned, 6_000_000, 1900), now);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(
+            fired[0].message,
+            "10.000000 USDC sent within 30 minutes (limit 10.000000)"
+        );
+    }
+
+    #[test]
+    fn fires_when_the_balance_drops_below_the_floor() {
+        let mut floor = rule("low", AlertCondition::BalanceBelow { floor_raw: 100 });
+        floor.wallet = Some(Pubkey::default());
+        let mut evaluator = Evaluator::new(vec![floor]);
+        let now = Instant::now();
+        assert_eq!(evaluator.on_balance(0, 100, now), None);
+        let alert = evaluator.on_balance(0, 99, now).unwrap();
+        assert_eq!(alert.kind, "balance");
+        assert_eq!(alert.message, "Balance 0.000099 USDC is below 0.000100");
+        assert_eq!(evaluator.on_balance(0, 1, now), None);
+    }
+
+    #[test]
+    fn fires_on_new_approvals_but_not_revokes() {
+        let condition = AlertCondition::Approval { min_raw: 1_000_000 };
+        let mut evaluator = Evaluator::new(vec![rule("delegate", condition)]);
+        let approval = |kind, amount_raw: Option<u64>| ApprovalEvent {
+            wallet: Pubkey::default(),
+            mint: usdc(),
+            approval: Approval {
+                signature: "sig-approve".to_string(),
+                slot: 1,
+                block_time: 0,
+                instruction_index: 0,
+                inner_index: None,
+                kind,
+                token_account: "wallet-ata".to_string(),
+                delegate: amount_raw.map(|_| "spender".to_string()),
+                amount_raw,
+                amount_ui: None,
+                mint: usdc().mint.to_string(),
+                symbol: "USDC".to_string(),
+            },
+        };
+        let now = Instant::now();
+        assert!(evaluator
+            .on_approval(&approval(ApprovalKind::Revoke, None), now)
+            .is_empty());
+        assert!(evaluator
+            .on_approval(&approval(ApprovalKind::Approve, Some(999_999)), now)
+            .is_empty());
+        // Transfers never trip an approval rule.
+        assert!(evaluator
+            .on_transfer(&event(Direction::Sent, 5_000_000, 0), now)
+            .is_empty());
+
+        let fired = evaluator.on_approval(&approval(ApprovalKind::Approve, Some(u64::MAX)), now);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].kind, "approval");
+        assert_eq!(fired[0].signature.as_deref(), Some("sig-approve"));
+        assert_eq!(
+            fired[0].message,
+            "Approved spender to spend 18446744073709.551615 USDC from wallet-ata"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! A network-level guard: when networks are allowed, requests from any
+//! other address get a 403. The address is the connection's peer, unless
+//! that peer is one of `trusted_proxies`; only then is the client read from
+//! the `Forwarded` or `X-Forwarded-For` header the proxy set, as anyone can
+//! send those headers and a proxy that doesn't strip them passes them on.
+//! Rate limits count requests against the same address.
+
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use tracing::warn;
+
+use crate::error::IndexerError;
+use crate::metrics::METRICS;
+
+/// A network in CIDR notation, or a single address.
+pub fn parse_network(value: &str) -> Result<IpNet, String> {
+    let value = value.trim();
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("'{}' is not an address or CIDR network", value))
+}
+
+/// Networks let in, and the proxies whose forwarding headers are believed.
+/// No allowed networks lets every request in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Allowlist {
+    pub allowed: Vec<IpNet>,
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl Allowlist {
+    pub fn is_empty(&self) -> bool {
+        self.allowed.is_empty()
+    }
+
+    fn trusted(&self, address: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(address))
+    }
+
+    /// The address a request came from, `None` when it can't be told. Each
+    /// proxy appends the address it got the request from, so the client is
+    /// the last hop that isn't itself a trusted proxy; earlier hops are
+    /// whatever the client claimed. A Unix socket has no peer address: its
+    /// peer is the proxy on the same host, trusted once any proxy is.
+    pub fn client(
+        &self,
+        peer: Option<SocketAddr>,
+        forwarded: Option<&str>,
+        forwarded_for: Option<&str>,
+    ) -> Option<IpAddr> {
+        let peer = peer.map(|addr| addr.ip());
+        let peer_trusted = match peer {
+            Some(peer) => self.trusted(&peer),
+            None => !self.trusted_proxies.is_empty(),
+        };
+        if !peer_trusted {
+            return peer;
+        }
+        let hops = match (forwarded, forwarded_for) {
+            (Some(header), _) => forwarded_hops(header),
+            (None, Some(header)) => header.split(',').map(parse_hop).collect(),
+            (None, None) => return peer,
+        };
+        match hops
+            .iter()
+            .rev()
+            .find(|hop| !hop.is_some_and(|hop| self.trusted(&hop)))
+        {
+            Some(hop) => *hop,
+            // Every hop is a trusted proxy: the first is as close to the
+            // client as it gets.
+            None => hops.first().copied().flatten().or(peer),
+        }
+    }
+
+    /// Lets `client` through when it is in an allowed network, or when none
+    /// are configured. Refusals are logged and counted under `server`.
+    pub fn check(&self, client: Option<IpAddr>, server: &str) -> Result<(), IndexerError> {
+        if self.is_empty()
+            || client.is_some_and(|client| self.allowed.iter().any(|net| net.contains(&client)))
+        {
+            return Ok(());
+        }
+        let client = client.map_or_else(|| "unknown".to_string(), |client| client.to_string());
+        warn!(%client, server, "request from outside the IP allowlist");
+        METRICS.allowlist_denied.with_label_values(&[server]).inc();
+        Err(IndexerError::Forbidden(format!(
+            "requests from {} are not allowed",
+            client
+        )))
+    }
+}
+
+/// The `for=` of each element of a `Forwarded` header (RFC 7239).
+fn forwarded_hops(header: &str) -> Vec<Option<IpAddr>> {
+    header
+        .split(',')
+        .map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then_some(value.trim().trim_matches('"'))
+            })
+        })
+        .map(|value| value.and_then(parse_hop))
+        .collect()
+}
+
+/// One hop: an address, possibly with a port and IPv6 in brackets. The
+/// obfuscated and `unknown` identifiers `Forwarded` allows are `None`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| {
+            hop.strip_prefix('[')
+                .and_then(|hop| hop.strip_suffix(']'))
+                .unwrap_or(hop)
+                .parse::<IpAddr>()
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowlist(allowed: &[&str], trusted_proxies: &[&str]) -> Allowlist {
+        let networks = |list: &[&str]| list.iter().map(|n| parse_network(n).unwrap()).collect();
+        Allowlist {
+            allowed: networks(allowed),
+            trusted_proxies: networks(trusted_proxies),
+        }
+    }
+
+    fn ip(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    #[test]
+    fn ignores_forwarding_headers_from_untrusted_peers() {
+        let peer = Some("198.51.100.9:443".parse().unwrap());
+        let list = allowlist(&["203.0.113.0/24"], &[]);
+        assert_eq!(
+            list.client(peer, None, Some("203.0.113.7")),
+            ip("198.51.100.9")
+        );
+        let list = allowlist(&["203.0.113.0/24"], &["10.0.0.0/8"]);
+        assert_eq!(
+            list.client(peer, Some("for=203.0.113.7"), None),
+            ip("198.51.100.9")
+        );
+        assert!(list.check(ip("198.51.100.9"), "http").is_err());
+    }
+
+    #[test]
+    fn takes_the_last_untrusted_hop_behind_trusted_proxies() {
+        let peer = Some("10.1.2.3:443".parse().unwrap());
+        let list = allowlist(&["203.0.113.0/24"], &["10.0.0.0/8"]);
+        // The client made up the first hop; the proxies added the rest.
+        assert_eq!(
+            list.client(peer, None, Some("192.0.2.1, 203.0.113.7, 10.0.0.5")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            list.client(
+                peer,
+                Some(r#"for=192.0.2.1, for="[2001:db8::17]:4711";proto=https"#),
+                Some("203.0.113.7")
+            ),
+            ip("2001:db8::17")
+        );
+        assert_eq!(list.client(peer, Some("for=unknown"), None), None);
+        assert_eq!(
+            list.client(None, None, Some("203.0.113.7")),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn lets_in_allowed_networks_only() {
+        let list = allowlist(&["203.0.113.0/24", "2001:db8::1"], &[]);
+        assert!(list.check(ip("203.0.113.200"), "http").is_ok());
+        assert!(list.check(ip("2001:db8::1"), "http").is_ok());
+        let denied = list.check(ip("203.0.114.1"), "http").unwrap_err();
+        assert_eq!(denied.code().as_str(), "FORBIDDEN");
+        assert!(list.check(None, "http").is_err());
+        assert!(Allowlist::default().check(None, "http").is_ok());
+        assert!(parse_network("10.0.0.0/33").is_err());
+    }
+}
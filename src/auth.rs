@@ -0,0 +1,97 @@
+//! API keys for the HTTP endpoints. Only digests of the keys are held, so
+//! they can't leak through `Debug` output or logs, and comparing digests
+//! keeps the time taken independent of how much of a key matched.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use crate::error::IndexerError;
+
+/// A set of accepted keys; several at once let a key be rotated without
+/// downtime. An empty set accepts every request.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct ApiKeys {
+    digests: Vec<[u8; 32]>,
+}
+
+impl fmt::Debug for ApiKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKeys({} keys)", self.digests.len())
+    }
+}
+
+impl ApiKeys {
+    pub fn new<S: AsRef<str>>(keys: impl IntoIterator<Item = S>) -> Self {
+        ApiKeys {
+            digests: keys
+                .into_iter()
+                .map(|key| key.as_ref().trim().to_string())
+                .filter(|key| !key.is_empty())
+                .map(|key| Sha256::digest(key.as_bytes()).into())
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.digests.len()
+    }
+
+    /// Whether `key` is one of the set. Every key is compared, whichever
+    /// one matches.
+    pub fn contains(&self, key: &str) -> bool {
+        let given: [u8; 32] = Sha256::digest(key.as_bytes()).into();
+        self.digests.iter().fold(false, |found, digest| {
+            let diff = digest
+                .iter()
+                .zip(&given)
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+            found | (diff == 0)
+        })
+    }
+
+    /// Checks the key a request carries, as `Authorization: Bearer <key>`
+    /// or `X-API-Key: <key>`.
+    pub fn authorize(
+        &self,
+        authorization: Option<&str>,
+        api_key: Option<&str>,
+    ) -> Result<(), IndexerError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let given = api_key.or_else(|| authorization.and_then(|v| v.strip_prefix("Bearer ")));
+        match given {
+            Some(key) if self.contains(key.trim()) => Ok(()),
+            Some(_) => Err(IndexerError::Unauthorized("invalid API key".to_string())),
+            None => Err(IndexerError::Unauthorized(
+                "missing API key; send it as a bearer token or in X-API-Key".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_any_configured_key_from_either_header() {
+        let keys = ApiKeys::new(["old-key", "new-key", " "]);
+        assert_eq!(keys.len(), 2);
+        assert!(keys.authorize(Some("Bearer old-key"), None).is_ok());
+        assert!(keys.authorize(None, Some("new-key")).is_ok());
+        assert!(matches!(
+            keys.authorize(Some("Bearer other"), None),
+            Err(IndexerError::Unauthorized(_))
+        ));
+        assert!(keys.authorize(Some("Basic new-key"), None).is_err());
+        assert!(keys.authorize(None, None).is_err());
+        assert!(!format!("{:?}", keys).contains("old-key"));
+
+        assert!(ApiKeys::default().authorize(None, None).is_ok());
+    }
+}
@@ -0,0 +1,142 @@
+//! Short-lived cache of computed responses. Identical requests arriving
+//! together share one computation, and its result is reused until the TTL
+//! runs out, so a dashboard refreshing several widgets at once costs one
+//! chain scan.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+/// Entries held at once; past this, new keys are computed without caching.
+const MAX_ENTRIES: usize = 1024;
+
+/// A computed value and when it was computed. Errors are never cached.
+type Slot<V> = Arc<OnceCell<(Instant, V)>>;
+
+#[derive(Debug)]
+pub struct ResponseCache<K, V> {
+    ttl: Duration,
+    slots: Mutex<HashMap<K, Slot<V>>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ResponseCache<K, V> {
+    pub fn new(ttl: Duration) -> Self {
+        ResponseCache {
+            ttl,
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The cached value for `key`, or the one `compute` produces. Callers
+    /// arriving while a computation for `key` is running wait for it
+    /// instead of starting their own. The flag is `true` when the value
+    /// wasn't computed by this call.
+    pub async fn get_or_compute<F, Fut, E>(&self, key: K, compute: F) -> Result<(V, bool), E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        let slot = {
+            let mut slots = self.slots.lock().expect("response cache lock poisoned");
+            let now = Instant::now();
+            slots.retain(|_, slot| {
+                slot.get()
+                    .is_none_or(|(computed_at, _)| now.duration_since(*computed_at) < self.ttl)
+            });
+            match slots.get(&key) {
+                Some(slot) => Some(slot.clone()),
+                None if slots.len() < MAX_ENTRIES => {
+                    let slot = Slot::default();
+                    slots.insert(key, slot.clone());
+                    Some(slot)
+                }
+                None => None,
+            }
+        };
+        let Some(slot) = slot else {
+            return Ok((compute().await?, false));
+        };
+
+        let mut computed = false;
+        let (_, value) = slot
+            .get_or_try_init(|| async {
+                computed = true;
+                Ok((Instant::now(), compute().await?))
+            })
+            .await?;
+        Ok((value.clone(), !computed))
+    }
+
+    /// Forgets every entry and returns how many there were. Computations
+    /// already running finish for their own callers only.
+    pub fn clear(&self) -> usize {
+        let mut slots = self.slots.lock().expect("response cache lock poisoned");
+        let entries = slots.len();
+        slots.clear();
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn shares_one_computation_between_concurrent_callers() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let runs = AtomicUsize::new(0);
+        let compute = || async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok::<_, String>(42)
+        };
+        let (a, b) = tokio::join!(
+            cache.get_or_compute("q", compute),
+            cache.get_or_compute("q", compute)
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        let hits = [a.unwrap(), b.unwrap()].map(|(value, hit)| {
+            assert_eq!(value, 42);
+            hit
+        });
+        assert_eq!(hits.iter().filter(|&&hit| hit).count(), 1);
+
+        assert_eq!(cache.get_or_compute("q", compute).await, Ok((42, true)));
+        assert_eq!(
+            cache.get_or_compute("other", compute).await,
+            Ok((42, false))
+        );
+    }
+
+    #[tokio::test]
+    async fn recomputes_after_errors_and_expiry() {
+        let cache = ResponseCache::new(Duration::ZERO);
+        let failed = cache
+            .get_or_compute("q", || async { Err::<u32, _>("rpc down") })
+            .await;
+        assert_eq!(failed, Err("rpc down"));
+        let ok = cache
+            .get_or_compute("q", || async { Ok::<_, &str>(1) })
+            .await;
+        assert_eq!(ok, Ok((1, false)));
+        let again = cache
+            .get_or_compute("q", || async { Ok::<_, &str>(2) })
+            .await;
+        assert_eq!(again, Ok((2, false)));
+
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        cache
+            .get_or_compute("q", || async { Ok::<_, &str>(1) })
+            .await
+            .unwrap();
+        assert_eq!(cache.clear(), 1);
+        let fresh = cache
+            .get_or_compute("q", || async { Ok::<_, &str>(2) })
+            .await;
+        assert_eq!(fresh, Ok((2, false)));
+    }
+}
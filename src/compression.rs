@@ -0,0 +1,219 @@
+//! `Content-Encoding: gzip`/`deflate` for the data routes, negotiated from
+//! `Accept-Encoding`. Bodies are compressed a chunk at a time and each
+//! chunk is flushed as it goes out, so a streamed NDJSON backfill reaches
+//! the client as it is produced instead of after the last chunk.
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use futures::stream;
+use std::io::{self, Write};
+use warp::http::header::{CONTENT_ENCODING, CONTENT_LENGTH, ETAG, VARY};
+use warp::http::HeaderValue;
+use warp::hyper::body::{Bytes, HttpBody};
+use warp::hyper::Body;
+use warp::reply::Response;
+
+use crate::etag;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// The encoding the `Accept-Encoding` value prefers, gzip on a tie;
+    /// `None` when it allows neither.
+    pub fn from_accept_encoding(accept: &str) -> Option<Encoding> {
+        let mut best: Option<(f32, Encoding)> = None;
+        for coding in accept.split(',') {
+            let mut parts = coding.split(';');
+            let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(q) => match q.trim().parse::<f32>() {
+                    Ok(q) => q,
+                    Err(_) => continue,
+                },
+                None => 1.0,
+            };
+            let encoding = match name.as_str() {
+                "gzip" | "x-gzip" | "*" => Encoding::Gzip,
+                "deflate" => Encoding::Deflate,
+                _ => continue,
+            };
+            let better = match best {
+                Some((q, current)) => {
+                    quality > q
+                        || (quality == q && encoding == Encoding::Gzip && current != encoding)
+                }
+                None => true,
+            };
+            if quality > 0.0 && better {
+                best = Some((quality, encoding));
+            }
+        }
+        best.map(|(_, encoding)| encoding)
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            Encoding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default()))
+            }
+        }
+    }
+
+    /// Compresses `chunk` and returns everything it produced, flushed so
+    /// the client can decode it without waiting for the next chunk.
+    fn chunk(&mut self, chunk: &[u8]) -> io::Result<Bytes> {
+        let buffer = match self {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            Encoder::Deflate(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(buffer)))
+    }
+
+    fn finish(self) -> io::Result<Bytes> {
+        let trailer = match self {
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Deflate(encoder) => encoder.finish()?,
+        };
+        Ok(Bytes::from(trailer))
+    }
+}
+
+/// `response` encoded per `accept_encoding`, unless the client accepts
+/// neither encoding, the body is already encoded or it is known to be
+/// smaller than `min_bytes`. Bodies of unknown length (streams) are always
+/// compressed.
+pub fn compress(response: Response, accept_encoding: Option<&str>, min_bytes: u64) -> Response {
+    let (mut parts, body) = response.into_parts();
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    let encoding = accept_encoding.and_then(Encoding::from_accept_encoding);
+    let too_small = body
+        .size_hint()
+        .exact()
+        .is_some_and(|size| size < min_bytes);
+    let Some(encoding) = encoding.filter(|_| {
+        !too_small && !parts.headers.contains_key(CONTENT_ENCODING) && parts.status.is_success()
+    }) else {
+        return Response::from_parts(parts, body);
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    if let Some(tag) = parts.headers.get(ETAG) {
+        match etag::for_encoding(tag, encoding) {
+            Some(tag) => parts.headers.insert(ETAG, tag),
+            None => parts.headers.remove(ETAG),
+        };
+    }
+    parts.headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+
+    let encoded = stream::unfold(Some((body, Encoder::new(encoding))), |state| async move {
+        let (mut body, mut encoder) = state?;
+        loop {
+            match body.data().await {
+                Some(Ok(chunk)) => match encoder.chunk(&chunk) {
+                    Ok(compressed) if compressed.is_empty() => continue,
+                    Ok(compressed) => return Some((Ok(compressed), Some((body, encoder)))),
+                    Err(e) => return Some((Err(e), None)),
+                },
+                Some(Err(e)) => return Some((Err(io::Error::other(e)), None)),
+                None => return Some((encoder.finish(), None)),
+            }
+        }
+    });
+    Response::from_parts(parts, Body::wrap_stream(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use std::io::Read;
+
+    #[test]
+    fn picks_the_preferred_encoding() {
+        let pick = Encoding::from_accept_encoding;
+        assert_eq!(pick("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(pick("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(pick("gzip;q=0.5, deflate"), Some(Encoding::Deflate));
+        assert_eq!(pick("*"), Some(Encoding::Gzip));
+        assert_eq!(pick("br, identity"), None);
+        assert_eq!(pick("gzip;q=0"), None);
+    }
+
+    #[tokio::test]
+    async fn compresses_large_bodies_only() {
+        let large = "transfer\n".repeat(1000);
+        let response = compress(Response::new(Body::from(large.clone())), Some("gzip"), 1024);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[VARY], "Accept-Encoding");
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let mut decoded = String::new();
+        GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, large);
+
+        let small = compress(Response::new(Body::from("ok")), Some("gzip"), 1024);
+        assert!(!small.headers().contains_key(CONTENT_ENCODING));
+        let unasked = compress(Response::new(Body::from(large.clone())), None, 1024);
+        assert!(!unasked.headers().contains_key(CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn flushes_each_streamed_chunk() {
+        let (mut sender, body) = Body::channel();
+        let response = compress(Response::new(body), Some("deflate"), 1024);
+        let mut body = response.into_body();
+
+        sender.send_data(Bytes::from("first\n")).await.unwrap();
+        let first = body.data().await.unwrap().unwrap();
+        let mut decoder = flate2::write::ZlibDecoder::new(Vec::new());
+        decoder.write_all(&first).unwrap();
+        decoder.flush().unwrap();
+        assert_eq!(decoder.get_ref().as_slice(), b"first\n");
+
+        sender.send_data(Bytes::from("second\n")).await.unwrap();
+        drop(sender);
+        let mut rest = first.to_vec();
+        while let Some(chunk) = body.data().await {
+            rest.extend_from_slice(&chunk.unwrap());
+        }
+        let mut decoded = String::new();
+        ZlibDecoder::new(&rest[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "first\nsecond\n");
+    }
+}
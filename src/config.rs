@@ -0,0 +1,2162 @@
+//! Runtime configuration: the mint registry and the TOML/env-driven
+//! [`Config`].
+
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::Deserialize;
+use solana_sdk::commitment_config::{CommitmentConfig, CommitmentLevel};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::alerts::{AlertCondition, AlertRule};
+use crate::allowlist::{self, Allowlist};
+use crate::auth::ApiKeys;
+use crate::discord::DiscordTarget;
+use crate::failover::RpcEndpoint;
+use crate::model::{parse_amount, Direction};
+use crate::parser::{SPL_TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID};
+use crate::price::{PriceProvider, PriceSource};
+use crate::reports::{ReportDelivery, ReportSchedule};
+use crate::s3::S3Target;
+use crate::telegram::TelegramTarget;
+use crate::throttle::RPC_METHODS;
+use crate::tls::TlsFiles;
+use crate::unix_socket::{self, UnixSocket};
+use crate::webhook::{WebhookFilter, WebhookTarget};
+
+pub const RPC_URL: &str = "https://api.mainnet-beta.solana.com";
+
+/// Mainnet registry, used when neither the config file nor MINTS sets one, in
+/// `SYMBOL:MINT:DECIMALS` form. The first entry is the default for requests
+/// that don't pick a mint.
+pub const DEFAULT_MINTS: &str = "USDC:EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v:6,\
+                             USDT:Es9vMFrzaCERH16Cdv83hA5KaM6rDx8JEX5Rk3z3aZ9o:6";
+
+/// Circle's devnet USDC faucet mint.
+pub const DEVNET_MINTS: &str = "USDC:4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU:6";
+
+// Used when `/backfill` is called without `?wallet=`; overridable via `wallet`.
+pub const DEFAULT_WALLET_ADDRESS: &str = "7cMEhpt9y3inBNVv8fNnuaEbx7hKHZnLvR1KWKKxuDDU";
+
+pub const DEFAULT_WINDOW_HOURS: i64 = 24;
+
+/// Largest span a single backfill may cover (31 days). Longer windows are
+/// clamped to this so one request can't paginate against the RPC forever.
+pub const MAX_WINDOW_SECS: i64 = 31 * 24 * 3600;
+
+// Render routes traffic to 0.0.0.0:10000.
+pub const DEFAULT_BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+pub const DEFAULT_PORT: u16 = 10000;
+pub const DEFAULT_EXPLORER_TX_URL: &str = "https://explorer.solana.com/tx/{signature}";
+pub const DEFAULT_FETCH_CONCURRENCY: usize = 8;
+/// Also the most `getSignaturesForAddress` returns per call.
+pub const DEFAULT_SIGNATURE_PAGE_SIZE: usize = 1000;
+pub const DEFAULT_MAX_SIGNATURE_PAGES: usize = 1000;
+pub const DEFAULT_RPC_MAX_ATTEMPTS: u32 = 5;
+pub const DEFAULT_RPC_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_RPC_FAILOVER_THRESHOLD: u32 = 3;
+pub const DEFAULT_RPC_UNHEALTHY_COOLDOWN_SECS: u64 = 30;
+pub const DEFAULT_POLL_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_READY_MAX_INDEX_AGE_SECS: u64 = 600;
+pub const DEFAULT_SNAPSHOT_INTERVAL_MINS: u64 = 60;
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const DEFAULT_MAX_PAGE_SIZE: usize = 1000;
+pub const DEFAULT_TOKEN_PROGRAMS: &str = "spl-token,spl-token-2022";
+pub const DEFAULT_TELEGRAM_MAX_MESSAGES_PER_MINUTE: u32 = 20;
+pub const DEFAULT_OWNER_CACHE_TTL_SECS: u64 = 3600;
+pub const DEFAULT_RESPONSE_CACHE_TTL_SECS: u64 = 60;
+pub const DEFAULT_MAX_CONCURRENT_BACKFILLS: usize = 2;
+/// Under the 30s most proxies give a response, so the client gets the 504
+/// with progress rather than the proxy's own error.
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 25;
+pub const DEFAULT_RATE_LIMIT_BURST: u32 = 10;
+pub const DEFAULT_COMPRESSION_MIN_BYTES: u64 = 1024;
+pub const DEFAULT_CORS_MAX_AGE_SECS: u64 = 600;
+pub const DEFAULT_ALERT_COOLDOWN_SECS: u64 = 3600;
+pub const DEFAULT_ALERT_BALANCE_INTERVAL_SECS: u64 = 300;
+pub const DEFAULT_S3_REGION: &str = "us-east-1";
+pub const DEFAULT_RAW_TRANSACTION_LIMIT: usize = 10_000;
+
+/// The Solana cluster indexed. It decides the defaults for the RPC URL, the
+/// mint registry and explorer links; `custom` has none, so `rpc_url` and
+/// `mints` must be set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Cluster {
+    #[default]
+    MainnetBeta,
+    Devnet,
+    Testnet,
+    Custom,
+}
+
+impl Cluster {
+    pub fn name(self) -> &'static str {
+        match self {
+            Cluster::MainnetBeta => "mainnet-beta",
+            Cluster::Devnet => "devnet",
+            Cluster::Testnet => "testnet",
+            Cluster::Custom => "custom",
+        }
+    }
+
+    /// The cluster's public RPC endpoint.
+    pub fn default_rpc_url(self) -> Option<&'static str> {
+        match self {
+            Cluster::MainnetBeta => Some(RPC_URL),
+            Cluster::Devnet => Some("https://api.devnet.solana.com"),
+            Cluster::Testnet => Some("https://api.testnet.solana.com"),
+            Cluster::Custom => None,
+        }
+    }
+
+    /// Well-known mints on the cluster, in [`MintRegistry::parse`] form.
+    /// Testnet has no USDC deployment to default to.
+    pub fn default_mints(self) -> Option<&'static str> {
+        match self {
+            Cluster::MainnetBeta => Some(DEFAULT_MINTS),
+            Cluster::Devnet => Some(DEVNET_MINTS),
+            Cluster::Testnet | Cluster::Custom => None,
+        }
+    }
+
+    pub fn default_explorer_tx_url(self) -> &'static str {
+        match self {
+            Cluster::MainnetBeta => DEFAULT_EXPLORER_TX_URL,
+            Cluster::Devnet => "https://explorer.solana.com/tx/{signature}?cluster=devnet",
+            Cluster::Testnet => "https://explorer.solana.com/tx/{signature}?cluster=testnet",
+            Cluster::Custom => "https://explorer.solana.com/tx/{signature}?cluster=custom",
+        }
+    }
+}
+
+impl FromStr for Cluster {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "mainnet-beta" | "mainnet" => Ok(Cluster::MainnetBeta),
+            "devnet" => Ok(Cluster::Devnet),
+            "testnet" => Ok(Cluster::Testnet),
+            "custom" => Ok(Cluster::Custom),
+            other => Err(format!(
+                "'{}' is not one of mainnet-beta, devnet, testnet, custom",
+                other
+            )),
+        }
+    }
+}
+
+/// A token program whose instructions the parser understands, by the name
+/// the RPC gives it in jsonParsed output and its program id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenProgram {
+    pub name: &'static str,
+    pub id: &'static str,
+}
+
+pub const SPL_TOKEN: TokenProgram = TokenProgram {
+    name: "spl-token",
+    id: SPL_TOKEN_PROGRAM_ID,
+};
+pub const SPL_TOKEN_2022: TokenProgram = TokenProgram {
+    name: "spl-token-2022",
+    id: TOKEN_2022_PROGRAM_ID,
+};
+
+impl TokenProgram {
+    pub const ALL: [TokenProgram; 2] = [SPL_TOKEN, SPL_TOKEN_2022];
+
+    /// Parses a comma-separated list of program names or ids.
+    pub fn parse_list(spec: &str) -> Result<Vec<TokenProgram>> {
+        let mut programs = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let program: TokenProgram = entry.parse()?;
+            if !programs.contains(&program) {
+                programs.push(program);
+            }
+        }
+        if programs.is_empty() {
+            anyhow::bail!("no token programs configured");
+        }
+        Ok(programs)
+    }
+
+    pub fn pubkey(&self) -> Pubkey {
+        Pubkey::from_str(self.id).expect("valid program id")
+    }
+}
+
+impl FromStr for TokenProgram {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        TokenProgram::ALL
+            .into_iter()
+            .find(|p| p.name == s || p.id == s)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unsupported token program '{}', expected spl-token or spl-token-2022",
+                    s
+                )
+            })
+    }
+}
+
+/// A token the indexer knows how to scale and label.
+#[derive(Debug, Clone)]
+pub struct MintInfo {
+    pub mint: Pubkey,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct MintRegistry {
+    pub mints: Vec<MintInfo>,
+}
+
+impl MintRegistry {
+    /// Parses a comma-separated list of `SYMBOL:MINT:DECIMALS` entries.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut mints = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parts: Vec<&str> = entry.split(':').collect();
+            let [symbol, mint, decimals] = parts[..] else {
+                anyhow::bail!("mint entry '{}' is not SYMBOL:MINT:DECIMALS", entry);
+            };
+            let mint = Pubkey::from_str(mint).map_err(|e| {
+                anyhow::anyhow!("mint entry '{}' has an invalid pubkey: {}", entry, e)
+            })?;
+            let decimals = decimals.parse::<u8>().map_err(|e| {
+                anyhow::anyhow!("mint entry '{}' has invalid decimals: {}", entry, e)
+            })?;
+            mints.push(MintInfo {
+                mint,
+                symbol: symbol.to_string(),
+                decimals,
+            });
+        }
+        if mints.is_empty() {
+            anyhow::bail!("no mints configured");
+        }
+        Ok(MintRegistry { mints })
+    }
+
+    pub fn default_mint(&self) -> &MintInfo {
+        &self.mints[0]
+    }
+
+    pub fn by_mint(&self, mint: &Pubkey) -> Option<&MintInfo> {
+        self.mints.iter().find(|m| m.mint == *mint)
+    }
+
+    pub fn by_symbol(&self, symbol: &str) -> Option<&MintInfo> {
+        self.mints
+            .iter()
+            .find(|m| m.symbol.eq_ignore_ascii_case(symbol))
+    }
+
+    /// Resolves the `mint`/`symbol` query parameters to a registered mint.
+    pub fn select(&self, mint: Option<&str>, symbol: Option<&str>) -> Result<&MintInfo, String> {
+        match (mint, symbol) {
+            (Some(_), Some(_)) => Err("'mint' and 'symbol' cannot be combined".to_string()),
+            (Some(mint), None) => {
+                let pubkey = Pubkey::from_str(mint)
+                    .map_err(|e| format!("invalid mint address '{}': {}", mint, e))?;
+                self.by_mint(&pubkey)
+                    .ok_or_else(|| format!("mint '{}' is not registered", mint))
+            }
+            (None, Some(symbol)) => self
+                .by_symbol(symbol)
+                .ok_or_else(|| format!("symbol '{}' is not registered", symbol)),
+            (None, None) => Ok(self.default_mint()),
+        }
+    }
+}
+
+/// Everything the service needs at runtime. Built-in defaults are
+/// overridden by the TOML file given via `--config`/`CONFIG_PATH`, which is
+/// in turn overridden by environment variables of the same name in upper
+/// case (`rpc_url` → `RPC_URL`).
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Cluster the defaults below were picked for.
+    pub cluster: Cluster,
+    /// The first of `rpc_endpoints`; the websocket URL is derived from it.
+    pub rpc_url: String,
+    /// RPC endpoints in priority order, from `rpc_endpoints` tables,
+    /// `RPC_URLS` (comma separated) or a single `rpc_url`.
+    pub rpc_endpoints: Vec<RpcEndpoint>,
+    /// How long one RPC request may take before it counts as failed.
+    pub rpc_timeout: Duration,
+    /// Consecutive 429s, 5xx or timeouts before an endpoint is benched.
+    pub rpc_failover_threshold: u32,
+    /// How long a benched endpoint stays out of rotation.
+    pub rpc_unhealthy_cooldown: Duration,
+    /// Commitment RPC reads are made at unless a request asks otherwise.
+    /// Transactions are never read below `confirmed`, which is the least
+    /// the RPC serves them at.
+    pub commitment: CommitmentConfig,
+    /// Requests per second all RPC calls share; unlimited when `None`
+    /// (`rpc_requests_per_second = 0`).
+    pub rpc_requests_per_second: Option<f64>,
+    /// Extra per-method budgets, by JSON-RPC method name, from the
+    /// `[rpc_method_limits]` table or `RPC_METHOD_LIMITS`
+    /// (`getTransaction=5,getSlot=1`).
+    pub rpc_method_limits: BTreeMap<String, f64>,
+    /// Interface to listen on; `127.0.0.1` keeps the server behind a local proxy.
+    pub bind_addr: IpAddr,
+    /// `0` asks the OS for an ephemeral port.
+    pub port: u16,
+    /// Port of the gRPC server, on `bind_addr`; it doesn't run when unset.
+    pub grpc_port: Option<u16>,
+    /// Certificate and key to serve HTTPS with, from `tls_cert_path` and
+    /// `tls_key_path`; plain HTTP when unset.
+    pub tls: Option<TlsFiles>,
+    /// Socket to listen on instead of `bind_addr` and `port`, from
+    /// `bind = "unix:<path>"`, with `unix_socket_mode` as its permissions.
+    pub unix_socket: Option<UnixSocket>,
+    /// Wallet used when `/backfill` has no `?wallet=`.
+    pub wallet: Pubkey,
+    /// Wallets the background indexer keeps warm and `?wallet=all` merges;
+    /// `wallet` first.
+    pub wallets: Vec<Pubkey>,
+    /// Lookback used when a request gives neither `hours` nor `start`; at
+    /// most the [`MAX_WINDOW_SECS`] any window is clamped to.
+    pub window_hours: i64,
+    /// Link template for each transfer's `explorer_url`; `{signature}` is
+    /// replaced with the transaction signature.
+    pub explorer_tx_url: String,
+    /// Check the mint registry against the chain before serving.
+    pub verify_mints: bool,
+    /// Maximum number of `getTransaction` calls in flight per backfill.
+    pub fetch_concurrency: usize,
+    /// Signatures asked for per `getSignaturesForAddress` call, at most 1000.
+    pub signature_page_size: usize,
+    /// Signature pages one backfill walks at most, over all the addresses it
+    /// lists; a backfill that needs more is cut short and marked truncated.
+    pub max_signature_pages: usize,
+    /// Total tries (first call included) for a retryable RPC failure.
+    pub rpc_max_attempts: u32,
+    pub mints: MintRegistry,
+    /// Database to persist transfers in, `postgres://...` or `sqlite:...`.
+    pub database_url: Option<String>,
+    /// SQLite file to persist transfers in, when no `database_url` is set;
+    /// in-memory only when neither is.
+    pub database_path: Option<String>,
+    /// JSON snapshot of the in-memory index, used when there's no database.
+    pub state_path: Option<PathBuf>,
+    /// How often the background poller syncs the default wallet; `None`
+    /// (`poll_interval_secs = 0`) disables it.
+    pub poll_interval: Option<Duration>,
+    /// How often the background indexer stores each wallet's balance per
+    /// mint; `None` (`snapshot_interval_mins = 0`) only snapshots on
+    /// `snapshot_every_transfers`.
+    pub snapshot_interval: Option<Duration>,
+    /// Also snapshot once this many transfers were stored since the last
+    /// snapshot; `None` (`0`, the default) doesn't.
+    pub snapshot_every_transfers: Option<u64>,
+    /// Websocket endpoint for live indexing (`live_indexing = true`);
+    /// defaults to the RPC URL with a ws(s) scheme, overridable via `ws_url`.
+    pub live_ws_url: Option<String>,
+    /// `/readyz` fails once the background index is older than this.
+    pub max_index_age: Duration,
+    /// Programs whose transfer instructions are indexed.
+    pub token_programs: Vec<TokenProgram>,
+    /// List signatures for each of the wallet's token accounts as well as
+    /// the owner address; `false` lists the owner address only.
+    pub index_token_accounts: bool,
+    /// Largest `?limit=` a listing accepts.
+    pub max_page_size: usize,
+    /// How long a looked-up counterparty owner is reused.
+    pub owner_cache_ttl: Duration,
+    /// How long a `/backfill` response is served again to identical
+    /// queries; `None` (`response_cache_ttl_secs = 0`) disables the cache.
+    pub response_cache_ttl: Option<Duration>,
+    /// Backfills computed at once across all routes; further requests get
+    /// a 429 and jobs wait their turn.
+    pub max_concurrent_backfills: usize,
+    /// How long a backfill a client waits on may run before it is cancelled
+    /// with a 504; jobs have no deadline.
+    pub request_timeout: Duration,
+    /// Requests per minute each client may make to the backfill routes;
+    /// `None` (`rate_limit_per_minute = 0`) turns the limit off.
+    pub rate_limit_per_minute: Option<u32>,
+    /// Requests a client may make at once before the rate applies.
+    pub rate_limit_burst: u32,
+    /// Networks requests may come from, from `allowed_ips`, and the proxies
+    /// whose forwarding headers say who the client is, from
+    /// `trusted_proxies`; every address may call when none are allowed. The
+    /// client found this way is also the one rate limits count against.
+    pub allowlist: Allowlist,
+    /// Serve a Swagger UI page for `/openapi.json` at `/docs`.
+    pub swagger_ui: bool,
+    /// Data responses at least this large are gzip/deflate encoded for
+    /// clients that accept it; `None` (`compression_min_bytes = 0`) turns
+    /// compression off.
+    pub compression_min_bytes: Option<u64>,
+    /// Where `price_usd`/`value_usd` are looked up (`price_source =
+    /// "coingecko"` or `"pyth"`); the fields stay `null` when unset.
+    pub price_source: Option<PriceSource>,
+    /// How many fetched transactions the store keeps as compressed raw
+    /// JSON, newest first, for `/tx/{signature}/raw` and reparsing; `None`
+    /// unless `store_raw_transactions` is set.
+    pub raw_transaction_limit: Option<usize>,
+    /// Origins browsers may call the API from, such as
+    /// `https://dashboard.example.com`, or `*` for any; no CORS headers are
+    /// sent when empty.
+    pub cors_origins: Vec<String>,
+    /// How long browsers may cache a preflight answer.
+    pub cors_max_age: Duration,
+    /// Endpoints new transfers are pushed to. Only settable in the config
+    /// file.
+    pub webhooks: Vec<WebhookTarget>,
+    /// Chat notified of new transfers, when a bot token and chat id are set.
+    pub telegram: Option<TelegramTarget>,
+    /// Discord channel webhooks new transfers are posted to. Only settable
+    /// in the config file.
+    pub discord: Vec<DiscordTarget>,
+    /// Rules whose alerts go out through the channels above. Only settable
+    /// in the config file.
+    pub alerts: Vec<AlertRule>,
+    /// How often balance alert rules read the wallet balance.
+    pub alert_balance_interval: Duration,
+    /// Daily statements and where they go. Only settable in the config
+    /// file.
+    pub reports: Vec<ReportSchedule>,
+    /// Bucket `POST /export/s3` and `deliver = "s3"` reports write to, when
+    /// an endpoint and bucket are set.
+    pub s3: Option<S3Target>,
+    /// Bearer token for the admin endpoints: `POST`/`DELETE /v1/wallets`,
+    /// `/admin/reparse`, `/admin/reindex` and `/admin/purge-cache`; they are
+    /// disabled when unset.
+    pub admin_token: Option<String>,
+    /// Keys accepted by the data endpoints; none means they are open.
+    /// `/healthz` and `/readyz` never take one.
+    pub api_keys: ApiKeys,
+    /// Key `/metrics` takes instead of the API keys; it is open when unset.
+    pub metrics_api_key: ApiKeys,
+}
+
+/// A `[[mints]]` table in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MintEntry {
+    symbol: String,
+    mint: String,
+    decimals: u8,
+}
+
+/// A `[[rpc_endpoints]]` table in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RpcEndpointEntry {
+    url: String,
+    /// Sent with every request, e.g. a provider's API key header.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+}
+
+/// Parses a `Name: value` header, as given in `RPC_AUTH_HEADER`.
+fn parse_header(spec: &str) -> Result<(String, String)> {
+    let Some((name, value)) = spec.split_once(':') else {
+        anyhow::bail!("RPC_AUTH_HEADER must look like 'Name: value'");
+    };
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
+/// Parses a commitment level: `processed`, `confirmed` or `finalized`.
+pub fn parse_commitment(level: &str) -> Result<CommitmentConfig, String> {
+    let commitment = match level {
+        "processed" => CommitmentLevel::Processed,
+        "confirmed" => CommitmentLevel::Confirmed,
+        "finalized" => CommitmentLevel::Finalized,
+        other => {
+            return Err(format!(
+                "'{}' is not one of processed, confirmed, finalized",
+                other
+            ))
+        }
+    };
+    Ok(CommitmentConfig { commitment })
+}
+
+/// The name [`parse_commitment`] accepts for `commitment`.
+pub fn commitment_name(commitment: CommitmentConfig) -> &'static str {
+    if commitment.is_finalized() {
+        "finalized"
+    } else if commitment.is_confirmed() {
+        "confirmed"
+    } else {
+        "processed"
+    }
+}
+
+/// Parses `RPC_METHOD_LIMITS`: `method=requests_per_second` pairs, comma
+/// separated.
+fn parse_method_limits(spec: &str) -> Result<BTreeMap<String, f64>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (method, rate) = entry.split_once('=').with_context(|| {
+                format!("RPC_METHOD_LIMITS entry '{}' is not method=rate", entry)
+            })?;
+            let rate = rate.trim().parse().with_context(|| {
+                format!("RPC_METHOD_LIMITS entry '{}' has an invalid rate", entry)
+            })?;
+            Ok((method.trim().to_string(), rate))
+        })
+        .collect()
+}
+
+/// A `[[webhooks]]` table in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WebhookEntry {
+    url: String,
+    secret: Option<String>,
+    direction: Option<String>,
+    min_amount: Option<String>,
+    /// Registered mint address or symbol.
+    mint: Option<String>,
+}
+
+impl WebhookEntry {
+    fn resolve(self, mints: &MintRegistry) -> Result<WebhookTarget> {
+        let context = || format!("webhook {}", redact_url(&self.url));
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            anyhow::bail!("{} must be an http(s) URL", context());
+        }
+        let filter = resolve_filter(
+            self.direction.as_deref(),
+            self.min_amount.as_deref(),
+            self.mint.as_deref(),
+            mints,
+        )
+        .with_context(context)?;
+        Ok(WebhookTarget {
+            filter,
+            url: self.url,
+            secret: self.secret.filter(|s| !s.is_empty()),
+        })
+    }
+}
+
+/// A `[[discord]]` table in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiscordEntry {
+    url: String,
+    direction: Option<String>,
+    min_amount: Option<String>,
+    /// Registered mint address or symbol.
+    mint: Option<String>,
+}
+
+impl DiscordEntry {
+    fn resolve(self, mints: &MintRegistry) -> Result<DiscordTarget> {
+        let context = || format!("discord webhook {}", redact_url(&self.url));
+        if !self.url.starts_with("https://") {
+            anyhow::bail!("{} must be an https URL", context());
+        }
+        let filter = resolve_filter(
+            self.direction.as_deref(),
+            self.min_amount.as_deref(),
+            self.mint.as_deref(),
+            mints,
+        )
+        .with_context(context)?;
+        Ok(DiscordTarget {
+            url: self.url,
+            filter,
+        })
+    }
+}
+
+/// An `[[alerts]]` table in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AlertEntry {
+    name: String,
+    /// `transfer`, `outflow`, `balance` or `approval`.
+    kind: String,
+    /// The transfer size, outflow total, balance floor or smallest approved
+    /// allowance, in UI units.
+    amount: String,
+    /// Outflow rules only.
+    window_minutes: Option<u64>,
+    /// Transfer rules only.
+    direction: Option<String>,
+    /// Any tracked wallet when unset; the default wallet for balance rules.
+    wallet: Option<String>,
+    /// Registered mint address or symbol; the default mint when unset.
+    mint: Option<String>,
+    cooldown_secs: Option<u64>,
+}
+
+impl AlertEntry {
+    fn resolve(self, mints: &MintRegistry, default_wallet: &Pubkey) -> Result<AlertRule> {
+        let context = || format!("alert '{}'", self.name);
+        let mint = match self.mint.as_deref() {
+            Some(mint) => match Pubkey::from_str(mint) {
+                Ok(pubkey) => mints.by_mint(&pubkey),
+                Err(_) => mints.by_symbol(mint),
+            }
+            .ok_or_else(|| anyhow::anyhow!("mint '{}' is not registered", mint))
+            .with_context(context)?,
+            None => mints.default_mint(),
+        };
+        let amount = parse_amount(&self.amount, mint.decimals)
+            .map_err(|e| anyhow::anyhow!("amount {}", e))
+            .with_context(context)?;
+        let mut wallet = self
+            .wallet
+            .as_deref()
+            .map(|wallet| {
+                Pubkey::from_str(wallet).map_err(|e| anyhow::anyhow!("wallet '{}': {}", wallet, e))
+            })
+            .transpose()
+            .with_context(context)?;
+        if self.window_minutes.is_some() && self.kind != "outflow" {
+            anyhow::bail!(
+                "{}: window_minutes only applies to outflow rules",
+                context()
+            );
+        }
+        if self.direction.is_some() && self.kind != "transfer" {
+            anyhow::bail!("{}: direction only applies to transfer rules", context());
+        }
+        let condition = match self.kind.as_str() {
+            "transfer" => AlertCondition::LargeTransfer {
+                min_raw: amount,
+                direction: self
+                    .direction
+                    .as_deref()
+                    .map(str::parse)
+                    .transpose()
+                    .with_context(context)?,
+            },
+            "outflow" => match self.window_minutes {
+                Some(minutes) if minutes > 0 => AlertCondition::Outflow {
+                    max_raw: amount,
+                    window: Duration::from_secs(minutes * 60),
+                },
+                _ => anyhow::bail!("{}: outflow rules need window_minutes", context()),
+            },
+            "balance" => {
+                wallet.get_or_insert(*default_wallet);
+                AlertCondition::BalanceBelow { floor_raw: amount }
+            }
+            "approval" => AlertCondition::Approval { min_raw: amount },
+            other => anyhow::bail!(
+                "{}: unknown kind '{}', expected transfer, outflow, balance or approval",
+                context(),
+                other
+            ),
+        };
+        Ok(AlertRule {
+            name: self.name,
+            condition,
+            wallet,
+            mint: mint.clone(),
+            cooldown: Duration::from_secs(
+                self.cooldown_secs.unwrap_or(DEFAULT_ALERT_COOLDOWN_SECS),
+            ),
+        })
+    }
+}
+
+/// A `[[reports]]` table in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReportEntry {
+    name: String,
+    /// Cron expression in UTC, with or without a leading seconds field,
+    /// e.g. `5 0 * * *` for five past midnight.
+    schedule: String,
+    /// Every tracked wallet when unset.
+    wallet: Option<String>,
+    /// Registered mint address or symbol; the default mint when unset.
+    mint: Option<String>,
+    /// `webhook`, `directory`, `telegram`, `discord` or `s3`.
+    deliver: String,
+    /// The webhook or Discord webhook URL.
+    url: Option<String>,
+    /// Webhooks only.
+    secret: Option<String>,
+    /// Directories only.
+    directory: Option<PathBuf>,
+}
+
+impl ReportEntry {
+    fn resolve(self, mints: &MintRegistry, telegram: bool, s3: bool) -> Result<ReportSchedule> {
+        let context = || format!("report '{}'", self.name);
+        // The cron crate wants seconds; plain crontab lines lack them.
+        let expression = match self.schedule.split_whitespace().count() {
+            5 => format!("0 {}", self.schedule),
+            _ => self.schedule.clone(),
+        };
+        let schedule = cron::Schedule::from_str(&expression)
+            .map_err(|e| anyhow::anyhow!("schedule '{}': {}", self.schedule, e))
+            .with_context(context)?;
+        let mint = match self.mint.as_deref() {
+            Some(mint) => match Pubkey::from_str(mint) {
+                Ok(pubkey) => mints.by_mint(&pubkey),
+                Err(_) => mints.by_symbol(mint),
+            }
+            .ok_or_else(|| anyhow::anyhow!("mint '{}' is not registered", mint))
+            .with_context(context)?,
+            None => mints.default_mint(),
+        };
+        let wallet = self
+            .wallet
+            .as_deref()
+            .map(|wallet| {
+                Pubkey::from_str(wallet).map_err(|e| anyhow::anyhow!("wallet '{}': {}", wallet, e))
+            })
+            .transpose()
+            .with_context(context)?;
+        if self.secret.is_some() && self.deliver != "webhook" {
+            anyhow::bail!("{}: secret only applies to webhook delivery", context());
+        }
+        if self.directory.is_some() && self.deliver != "directory" {
+            anyhow::bail!(
+                "{}: directory only applies to directory delivery",
+                context()
+            );
+        }
+        if self.url.is_some() && !matches!(self.deliver.as_str(), "webhook" | "discord") {
+            anyhow::bail!(
+                "{}: url only applies to webhook and discord delivery",
+                context()
+            );
+        }
+        let delivery = match (self.deliver.as_str(), self.url) {
+            ("webhook", Some(url)) if url.starts_with("http://") || url.starts_with("https://") => {
+                ReportDelivery::Webhook(WebhookTarget {
+                    url,
+                    secret: self.secret.filter(|s| !s.is_empty()),
+                    filter: WebhookFilter::default(),
+                })
+            }
+            ("webhook", _) => anyhow::bail!("{}: webhook delivery needs an http(s) url", context()),
+            ("discord", Some(url)) if url.starts_with("https://") => {
+                ReportDelivery::Discord { url }
+            }
+            ("discord", _) => anyhow::bail!("{}: discord delivery needs an https url", context()),
+            ("directory", _) => match self.directory {
+                Some(directory) => ReportDelivery::Directory(directory),
+                None => anyhow::bail!("{}: directory delivery needs a directory", context()),
+            },
+            ("telegram", _) if telegram => ReportDelivery::Telegram,
+            ("telegram", _) => anyhow::bail!(
+                "{}: telegram delivery needs the [telegram] bot_token and chat_id",
+                context()
+            ),
+            ("s3", _) if s3 => ReportDelivery::S3,
+            ("s3", _) => anyhow::bail!("{}: s3 delivery needs the [s3] bucket", context()),
+            (other, _) => anyhow::bail!(
+                "{}: unknown deliver '{}', expected webhook, directory, telegram, discord or s3",
+                context(),
+                other
+            ),
+        };
+        Ok(ReportSchedule {
+            name: self.name,
+            schedule,
+            wallet,
+            mint: mint.clone(),
+            delivery,
+        })
+    }
+}
+
+/// Checks the filter keys shared by `[[webhooks]]` and `[[discord]]`.
+pub(crate) fn resolve_filter(
+    direction: Option<&str>,
+    min_amount: Option<&str>,
+    mint: Option<&str>,
+    mints: &MintRegistry,
+) -> Result<WebhookFilter> {
+    let direction = direction.map(|d| d.parse::<Direction>()).transpose()?;
+    let mint = match mint {
+        Some(mint) => {
+            let info = match Pubkey::from_str(mint) {
+                Ok(pubkey) => mints.by_mint(&pubkey),
+                Err(_) => mints.by_symbol(mint),
+            };
+            let info = info.ok_or_else(|| anyhow::anyhow!("mint '{}' is not registered", mint))?;
+            Some(info)
+        }
+        None => None,
+    };
+    if let Some(min_amount) = min_amount {
+        let candidates = match mint {
+            Some(info) => std::slice::from_ref(info),
+            None => &mints.mints[..],
+        };
+        check_min_amount(min_amount, candidates)?;
+    }
+    Ok(WebhookFilter {
+        direction,
+        min_amount: min_amount.map(str::to_string),
+        mint: mint.map(|info| info.mint),
+    })
+}
+
+/// The `[telegram]` table in the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TelegramEntry {
+    bot_token: Option<String>,
+    chat_id: Option<String>,
+    min_amount: Option<String>,
+    max_messages_per_minute: Option<u32>,
+}
+
+/// The `[s3]` table in the config file. The credentials are only read from
+/// `S3_ACCESS_KEY_ID` and `S3_SECRET_ACCESS_KEY` (or their `AWS_`
+/// spellings), so they stay out of the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct S3Entry {
+    endpoint: Option<String>,
+    bucket: Option<String>,
+    region: Option<String>,
+    prefix: Option<String>,
+    dry_run: Option<bool>,
+}
+
+/// `min_amount` must fit the decimals of every mint it is compared against.
+fn check_min_amount(min_amount: &str, mints: &[MintInfo]) -> Result<()> {
+    for info in mints {
+        parse_amount(min_amount, info.decimals).map_err(|e| anyhow::anyhow!("min_amount {}", e))?;
+    }
+    Ok(())
+}
+
+/// The config file as written; every key is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    cluster: Option<Cluster>,
+    rpc_url: Option<String>,
+    rpc_endpoints: Option<Vec<RpcEndpointEntry>>,
+    rpc_timeout_secs: Option<u64>,
+    rpc_failover_threshold: Option<u32>,
+    rpc_unhealthy_cooldown_secs: Option<u64>,
+    rpc_requests_per_second: Option<f64>,
+    rpc_method_limits: Option<BTreeMap<String, f64>>,
+    commitment: Option<String>,
+    ws_url: Option<String>,
+    live_indexing: Option<bool>,
+    bind_addr: Option<IpAddr>,
+    port: Option<u16>,
+    grpc_port: Option<u16>,
+    tls_cert_path: Option<PathBuf>,
+    tls_key_path: Option<PathBuf>,
+    bind: Option<String>,
+    unix_socket_mode: Option<String>,
+    wallet: Option<String>,
+    wallets: Option<Vec<String>>,
+    mints: Option<Vec<MintEntry>>,
+    window_hours: Option<i64>,
+    explorer_tx_url: Option<String>,
+    verify_mints: Option<bool>,
+    fetch_concurrency: Option<usize>,
+    signature_page_size: Option<usize>,
+    max_signature_pages: Option<usize>,
+    rpc_max_attempts: Option<u32>,
+    database_url: Option<String>,
+    database_path: Option<String>,
+    state_path: Option<PathBuf>,
+    poll_interval_secs: Option<u64>,
+    snapshot_interval_mins: Option<u64>,
+    snapshot_every_transfers: Option<u64>,
+    ready_max_index_age_secs: Option<u64>,
+    token_programs: Option<Vec<String>>,
+    index_token_accounts: Option<bool>,
+    max_page_size: Option<usize>,
+    owner_cache_ttl_secs: Option<u64>,
+    response_cache_ttl_secs: Option<u64>,
+    max_concurrent_backfills: Option<usize>,
+    request_timeout_secs: Option<u64>,
+    rate_limit_per_minute: Option<u32>,
+    rate_limit_burst: Option<u32>,
+    /// Replaced by `trusted_proxies`; only read to warn about it.
+    trust_forwarded_for: Option<bool>,
+    allowed_ips: Option<Vec<String>>,
+    trusted_proxies: Option<Vec<String>>,
+    swagger_ui: Option<bool>,
+    compression_min_bytes: Option<u64>,
+    price_source: Option<String>,
+    price_api_url: Option<String>,
+    price_api_key: Option<String>,
+    store_raw_transactions: Option<bool>,
+    raw_transaction_limit: Option<usize>,
+    cors_origins: Option<Vec<String>>,
+    cors_max_age_secs: Option<u64>,
+    webhooks: Option<Vec<WebhookEntry>>,
+    telegram: Option<TelegramEntry>,
+    discord: Option<Vec<DiscordEntry>>,
+    alerts: Option<Vec<AlertEntry>>,
+    alert_balance_interval_secs: Option<u64>,
+    reports: Option<Vec<ReportEntry>>,
+    s3: Option<S3Entry>,
+    admin_token: Option<String>,
+    api_keys: Option<Vec<String>>,
+    metrics_api_key: Option<String>,
+}
+
+impl Config {
+    /// Loads the file at `path` (if any), applies environment overrides and
+    /// validates the result.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let file = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("cannot read config file {}", path.display()))?;
+                toml::from_str(&text)
+                    .with_context(|| format!("invalid config file {}", path.display()))?
+            }
+            None => ConfigFile::default(),
+        };
+        Self::resolve(file, &|name| std::env::var(name).ok())
+    }
+
+    fn resolve(file: ConfigFile, env: &dyn Fn(&str) -> Option<String>) -> Result<Self> {
+        if file.rpc_url.is_some() && file.rpc_endpoints.is_some() {
+            anyhow::bail!("set either rpc_url or rpc_endpoints, not both");
+        }
+        let cluster = env_value(env, "CLUSTER")?
+            .or(file.cluster)
+            .unwrap_or_default();
+        let mut rpc_endpoints = match (
+            env_value::<String>(env, "RPC_URLS")?,
+            env_value::<String>(env, "RPC_URL")?,
+        ) {
+            (Some(urls), _) => urls
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(RpcEndpoint::new)
+                .collect(),
+            (None, Some(url)) => vec![RpcEndpoint::new(url)],
+            (None, None) => match file.rpc_endpoints {
+                Some(entries) => entries
+                    .into_iter()
+                    .map(|entry| RpcEndpoint {
+                        url: entry.url,
+                        headers: entry.headers.into_iter().collect(),
+                    })
+                    .collect(),
+                None => match file.rpc_url.as_deref().or(cluster.default_rpc_url()) {
+                    Some(url) => vec![RpcEndpoint::new(url)],
+                    None => anyhow::bail!("cluster custom needs rpc_url or rpc_endpoints"),
+                },
+            },
+        };
+        if rpc_endpoints.is_empty() {
+            anyhow::bail!("no RPC endpoints configured");
+        }
+        if let Some(header) = env_value::<String>(env, "RPC_AUTH_HEADER")? {
+            let header = parse_header(&header)?;
+            for endpoint in &mut rpc_endpoints {
+                endpoint.headers.push(header.clone());
+            }
+        }
+        for endpoint in &rpc_endpoints {
+            if !endpoint.url.starts_with("http://") && !endpoint.url.starts_with("https://") {
+                anyhow::bail!(
+                    "rpc_url '{}' must be an http(s) URL",
+                    redact_url(&endpoint.url)
+                );
+            }
+        }
+        let rpc_url = rpc_endpoints[0].url.clone();
+        let rpc_timeout_secs = env_value(env, "RPC_TIMEOUT_SECS")?
+            .or(file.rpc_timeout_secs)
+            .unwrap_or(DEFAULT_RPC_TIMEOUT_SECS);
+        if rpc_timeout_secs == 0 {
+            anyhow::bail!("rpc_timeout_secs must be at least 1");
+        }
+        let rpc_failover_threshold = env_value(env, "RPC_FAILOVER_THRESHOLD")?
+            .or(file.rpc_failover_threshold)
+            .unwrap_or(DEFAULT_RPC_FAILOVER_THRESHOLD);
+        if rpc_failover_threshold == 0 {
+            anyhow::bail!("rpc_failover_threshold must be at least 1");
+        }
+        let rpc_unhealthy_cooldown_secs = env_value(env, "RPC_UNHEALTHY_COOLDOWN_SECS")?
+            .or(file.rpc_unhealthy_cooldown_secs)
+            .unwrap_or(DEFAULT_RPC_UNHEALTHY_COOLDOWN_SECS);
+        let commitment = match env_value::<String>(env, "COMMITMENT")?.or(file.commitment) {
+            Some(level) => {
+                parse_commitment(&level).map_err(|e| anyhow::anyhow!("commitment {}", e))?
+            }
+            None => CommitmentConfig::confirmed(),
+        };
+        let rpc_requests_per_second: Option<f64> = env_value(env, "RPC_REQUESTS_PER_SECOND")?
+            .or(file.rpc_requests_per_second)
+            .filter(|&rate| rate != 0.0);
+        let rpc_method_limits = match env_value::<String>(env, "RPC_METHOD_LIMITS")? {
+            Some(spec) => parse_method_limits(&spec)?,
+            None => file.rpc_method_limits.unwrap_or_default(),
+        };
+        for (method, rate) in rpc_requests_per_second
+            .iter()
+            .map(|rate| ("rpc_requests_per_second", rate))
+            .chain(rpc_method_limits.iter().map(|(m, r)| (m.as_str(), r)))
+        {
+            if !(rate.is_finite() && *rate > 0.0) {
+                anyhow::bail!(
+                    "{} must be a positive number of requests per second",
+                    method
+                );
+            }
+        }
+        if let Some(method) = rpc_method_limits
+            .keys()
+            .find(|method| !RPC_METHODS.contains(&method.as_str()))
+        {
+            anyhow::bail!(
+                "rpc_method_limits: unknown method '{}', expected one of {}",
+                method,
+                RPC_METHODS.join(", ")
+            );
+        }
+
+        let parse_wallet = |wallet: &str| {
+            Pubkey::from_str(wallet)
+                .map_err(|e| anyhow::anyhow!("wallet '{}' is not a valid pubkey: {}", wallet, e))
+        };
+        let listed = match env_value::<String>(env, "WALLETS")? {
+            Some(spec) => spec
+                .split(',')
+                .map(str::trim)
+                .filter(|w| !w.is_empty())
+                .map(parse_wallet)
+                .collect::<Result<Vec<_>>>()?,
+            None => file
+                .wallets
+                .unwrap_or_default()
+                .iter()
+                .map(|w| parse_wallet(w))
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let wallet = match env_value::<String>(env, "WALLET")?.or(file.wallet) {
+            Some(wallet) => parse_wallet(&wallet)?,
+            None => match listed.first() {
+                Some(first) => *first,
+                None => parse_wallet(DEFAULT_WALLET_ADDRESS)?,
+            },
+        };
+        let mut wallets = vec![wallet];
+        for listed in listed {
+            if !wallets.contains(&listed) {
+                wallets.push(listed);
+            }
+        }
+
+        let mints = match (env_value::<String>(env, "MINTS")?, file.mints) {
+            (Some(spec), _) => MintRegistry::parse(&spec)?,
+            (None, Some(entries)) => {
+                let spec: Vec<String> = entries
+                    .iter()
+                    .map(|m| format!("{}:{}:{}", m.symbol, m.mint, m.decimals))
+                    .collect();
+                MintRegistry::parse(&spec.join(","))?
+            }
+            (None, None) => match cluster.default_mints() {
+                Some(spec) => MintRegistry::parse(spec)?,
+                None => anyhow::bail!(
+                    "cluster {} has no built-in mints; configure mints",
+                    cluster.name()
+                ),
+            },
+        };
+
+        let window_hours = env_value(env, "WINDOW_HOURS")?
+            .or(file.window_hours)
+            .unwrap_or(DEFAULT_WINDOW_HOURS);
+        if window_hours <= 0 || window_hours > MAX_WINDOW_SECS / 3600 {
+            anyhow::bail!(
+                "window_hours must be between 1 and {}, got {}",
+                MAX_WINDOW_SECS / 3600,
+                window_hours
+            );
+        }
+        let explorer_tx_url = env_value(env, "EXPLORER_TX_URL")?
+            .or(file.explorer_tx_url)
+            .unwrap_or_else(|| cluster.default_explorer_tx_url().to_string());
+        if !explorer_tx_url.contains("{signature}") {
+            anyhow::bail!(
+                "explorer_tx_url '{}' must contain a {{signature}} placeholder",
+                explorer_tx_url
+            );
+        }
+        let fetch_concurrency = env_value(env, "FETCH_CONCURRENCY")?
+            .or(file.fetch_concurrency)
+            .unwrap_or(DEFAULT_FETCH_CONCURRENCY);
+        if fetch_concurrency == 0 {
+            anyhow::bail!("fetch_concurrency must be at least 1");
+        }
+        let signature_page_size = env_value(env, "SIGNATURE_PAGE_SIZE")?
+            .or(file.signature_page_size)
+            .unwrap_or(DEFAULT_SIGNATURE_PAGE_SIZE);
+        if !(1..=1000).contains(&signature_page_size) {
+            anyhow::bail!(
+                "signature_page_size must be between 1 and 1000, got {}",
+                signature_page_size
+            );
+        }
+        let max_signature_pages = env_value(env, "MAX_SIGNATURE_PAGES")?
+            .or(file.max_signature_pages)
+            .unwrap_or(DEFAULT_MAX_SIGNATURE_PAGES);
+        if max_signature_pages == 0 {
+            anyhow::bail!("max_signature_pages must be at least 1");
+        }
+        let rpc_max_attempts = env_value(env, "RPC_MAX_ATTEMPTS")?
+            .or(file.rpc_max_attempts)
+            .unwrap_or(DEFAULT_RPC_MAX_ATTEMPTS);
+        if rpc_max_attempts == 0 {
+            anyhow::bail!("rpc_max_attempts must be at least 1");
+        }
+
+        let live_indexing = env_value(env, "LIVE_INDEXING")?
+            .or(file.live_indexing)
+            .unwrap_or(false);
+        let ws_url = env_value(env, "WS_URL")?.or(file.ws_url);
+        let live_ws_url = live_indexing.then(|| {
+            ws_url.unwrap_or_else(|| {
+                rpc_url
+                    .replacen("https://", "wss://", 1)
+                    .replacen("http://", "ws://", 1)
+            })
+        });
+
+        let token_programs = match (
+            env_value::<String>(env, "TOKEN_PROGRAMS")?,
+            file.token_programs,
+        ) {
+            (Some(spec), _) => TokenProgram::parse_list(&spec)?,
+            (None, Some(names)) => TokenProgram::parse_list(&names.join(","))?,
+            (None, None) => TokenProgram::parse_list(DEFAULT_TOKEN_PROGRAMS)?,
+        };
+
+        let max_page_size = env_value(env, "MAX_PAGE_SIZE")?
+            .or(file.max_page_size)
+            .unwrap_or(DEFAULT_MAX_PAGE_SIZE);
+        if max_page_size == 0 {
+            anyhow::bail!("max_page_size must be at least 1");
+        }
+
+        let webhooks = file
+            .webhooks
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| entry.resolve(&mints))
+            .collect::<Result<Vec<_>>>()?;
+        let discord = file
+            .discord
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| entry.resolve(&mints))
+            .collect::<Result<Vec<_>>>()?;
+        let alerts = file
+            .alerts
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| entry.resolve(&mints, &wallet))
+            .collect::<Result<Vec<_>>>()?;
+        let alert_balance_interval_secs = file
+            .alert_balance_interval_secs
+            .unwrap_or(DEFAULT_ALERT_BALANCE_INTERVAL_SECS);
+        if alert_balance_interval_secs == 0 {
+            anyhow::bail!("alert_balance_interval_secs must be at least 1");
+        }
+
+        let telegram_file = file.telegram.unwrap_or_default();
+        let bot_token = env_value(env, "TELEGRAM_BOT_TOKEN")?.or(telegram_file.bot_token);
+        let chat_id = env_value(env, "TELEGRAM_CHAT_ID")?.or(telegram_file.chat_id);
+        let telegram = match (bot_token, chat_id) {
+            (Some(bot_token), Some(chat_id)) => {
+                let min_amount =
+                    env_value::<String>(env, "TELEGRAM_MIN_AMOUNT")?.or(telegram_file.min_amount);
+                if let Some(min_amount) = &min_amount {
+                    check_min_amount(min_amount, &mints.mints).context("telegram")?;
+                }
+                let max_messages_per_minute = env_value(env, "TELEGRAM_MAX_MESSAGES_PER_MINUTE")?
+                    .or(telegram_file.max_messages_per_minute)
+                    .unwrap_or(DEFAULT_TELEGRAM_MAX_MESSAGES_PER_MINUTE);
+                if max_messages_per_minute == 0 {
+                    anyhow::bail!("telegram max_messages_per_minute must be at least 1");
+                }
+                Some(TelegramTarget {
+                    bot_token,
+                    chat_id,
+                    min_amount,
+                    max_messages_per_minute,
+                })
+            }
+            (None, None) => None,
+            _ => anyhow::bail!("telegram needs both bot_token and chat_id"),
+        };
+        let s3_file = file.s3.unwrap_or_default();
+        let s3_endpoint = env_value::<String>(env, "S3_ENDPOINT")?.or(s3_file.endpoint);
+        let s3_bucket = env_value(env, "S3_BUCKET")?.or(s3_file.bucket);
+        let s3 = match (s3_endpoint, s3_bucket) {
+            (Some(endpoint), Some(bucket)) => {
+                if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                    anyhow::bail!("s3 endpoint must be an http(s) URL");
+                }
+                let access_key_id = env_value(env, "S3_ACCESS_KEY_ID")?
+                    .or(env_value(env, "AWS_ACCESS_KEY_ID")?)
+                    .context("s3 needs S3_ACCESS_KEY_ID")?;
+                let secret_access_key = env_value(env, "S3_SECRET_ACCESS_KEY")?
+                    .or(env_value(env, "AWS_SECRET_ACCESS_KEY")?)
+                    .context("s3 needs S3_SECRET_ACCESS_KEY")?;
+                let mut prefix: String = env_value(env, "S3_PREFIX")?
+                    .or(s3_file.prefix)
+                    .unwrap_or_default();
+                if !prefix.is_empty() && !prefix.ends_with('/') {
+                    prefix.push('/');
+                }
+                Some(S3Target {
+                    endpoint,
+                    bucket,
+                    region: env_value(env, "S3_REGION")?
+                        .or(s3_file.region)
+                        .unwrap_or_else(|| DEFAULT_S3_REGION.to_string()),
+                    prefix,
+                    access_key_id,
+                    secret_access_key,
+                    dry_run: env_value(env, "S3_DRY_RUN")?
+                        .or(s3_file.dry_run)
+                        .unwrap_or(false),
+                })
+            }
+            (None, None) => None,
+            _ => anyhow::bail!("s3 needs both endpoint and bucket"),
+        };
+
+        let reports = file
+            .reports
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| entry.resolve(&mints, telegram.is_some(), s3.is_some()))
+            .collect::<Result<Vec<_>>>()?;
+
+        let raw_transaction_limit = env_value(env, "RAW_TRANSACTION_LIMIT")?
+            .or(file.raw_transaction_limit)
+            .unwrap_or(DEFAULT_RAW_TRANSACTION_LIMIT);
+        if raw_transaction_limit == 0 {
+            anyhow::bail!("raw_transaction_limit must be at least 1");
+        }
+        let raw_transaction_limit = env_value(env, "STORE_RAW_TRANSACTIONS")?
+            .or(file.store_raw_transactions)
+            .unwrap_or(false)
+            .then_some(raw_transaction_limit);
+
+        let response_cache_ttl_secs = env_value(env, "RESPONSE_CACHE_TTL_SECS")?
+            .or(file.response_cache_ttl_secs)
+            .unwrap_or(DEFAULT_RESPONSE_CACHE_TTL_SECS);
+
+        let max_concurrent_backfills = env_value(env, "MAX_CONCURRENT_BACKFILLS")?
+            .or(file.max_concurrent_backfills)
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_BACKFILLS);
+        if max_concurrent_backfills == 0 {
+            anyhow::bail!("max_concurrent_backfills must be at least 1");
+        }
+        let request_timeout_secs = env_value(env, "REQUEST_TIMEOUT_SECS")?
+            .or(file.request_timeout_secs)
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS);
+        if request_timeout_secs == 0 {
+            anyhow::bail!("request_timeout_secs must be at least 1");
+        }
+        let rate_limit_per_minute = env_value(env, "RATE_LIMIT_PER_MINUTE")?
+            .or(file.rate_limit_per_minute)
+            .filter(|&rate: &u32| rate > 0);
+        let rate_limit_burst = env_value(env, "RATE_LIMIT_BURST")?
+            .or(file.rate_limit_burst)
+            .unwrap_or(DEFAULT_RATE_LIMIT_BURST);
+        if rate_limit_burst == 0 {
+            anyhow::bail!("rate_limit_burst must be at least 1");
+        }
+
+        let database_url = env_value::<String>(env, "DATABASE_URL")?.or(file.database_url);
+        if let Some(url) = &database_url {
+            if !["postgres://", "postgresql://", "sqlite:"]
+                .iter()
+                .any(|scheme| url.starts_with(scheme))
+            {
+                anyhow::bail!(
+                    "database_url '{}' must be a postgres:// or sqlite: URL",
+                    redact_url(url)
+                );
+            }
+        }
+
+        let cors_origins: Vec<String> = match env_value::<String>(env, "CORS_ORIGINS")? {
+            Some(origins) => origins.split(',').map(str::to_string).collect(),
+            None => file.cors_origins.unwrap_or_default(),
+        }
+        .into_iter()
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+        for origin in &cors_origins {
+            let valid = origin == "*"
+                || origin
+                    .strip_prefix("https://")
+                    .or_else(|| origin.strip_prefix("http://"))
+                    .is_some_and(|host| !host.is_empty() && !host.contains('/'));
+            if !valid {
+                anyhow::bail!(
+                    "cors origin '{}' must be '*' or scheme://host[:port]",
+                    origin
+                );
+            }
+        }
+
+        let allowlist = Allowlist {
+            allowed: networks(env, "ALLOWED_IPS", file.allowed_ips)?,
+            trusted_proxies: networks(env, "TRUSTED_PROXIES", file.trusted_proxies)?,
+        };
+        // Trusting whoever sends the header let any client pick the address
+        // it was counted against, so the setting is ignored: requests count
+        // against the proxy instead, which only tightens rate limits. With
+        // allowed_ips, though, everyone would get in through an allowed proxy.
+        if env_value(env, "TRUST_FORWARDED_FOR")?
+            .or(file.trust_forwarded_for)
+            .unwrap_or(false)
+            && allowlist.trusted_proxies.is_empty()
+        {
+            if !allowlist.is_empty() {
+                anyhow::bail!(
+                    "trust_forwarded_for was replaced by trusted_proxies, which allowed_ips \
+                     needs behind a proxy: list the addresses of the proxies in front"
+                );
+            }
+            warn!(
+                "trust_forwarded_for is deprecated and ignored; list the proxies in front in \
+                 trusted_proxies to read client addresses from forwarding headers"
+            );
+        }
+
+        let price_source = match env_value::<String>(env, "PRICE_SOURCE")?.or(file.price_source) {
+            Some(name) if name != "none" => {
+                let provider: PriceProvider = name.parse().map_err(|e| anyhow::anyhow!("{}", e))?;
+                Some(PriceSource {
+                    provider,
+                    api_url: env_value(env, "PRICE_API_URL")?
+                        .or(file.price_api_url)
+                        .unwrap_or_else(|| provider.default_url().to_string()),
+                    api_key: env_value(env, "PRICE_API_KEY")?.or(file.price_api_key),
+                })
+            }
+            _ => None,
+        };
+
+        let poll_interval_secs = env_value(env, "POLL_INTERVAL_SECS")?
+            .or(file.poll_interval_secs)
+            .unwrap_or(DEFAULT_POLL_INTERVAL_SECS);
+        let snapshot_interval_mins = env_value(env, "SNAPSHOT_INTERVAL_MINS")?
+            .or(file.snapshot_interval_mins)
+            .unwrap_or(DEFAULT_SNAPSHOT_INTERVAL_MINS);
+        let snapshot_every_transfers = env_value(env, "SNAPSHOT_EVERY_TRANSFERS")?
+            .or(file.snapshot_every_transfers)
+            .unwrap_or(0);
+
+        let port = env_value(env, "PORT")?
+            .or(file.port)
+            .unwrap_or(DEFAULT_PORT);
+        let grpc_port = env_value(env, "GRPC_PORT")?.or(file.grpc_port);
+        if grpc_port.is_some_and(|grpc_port| grpc_port != 0 && grpc_port == port) {
+            anyhow::bail!("grpc_port must differ from port {}", port);
+        }
+        let tls = match (
+            env_value(env, "TLS_CERT_PATH")?.or(file.tls_cert_path),
+            env_value(env, "TLS_KEY_PATH")?.or(file.tls_key_path),
+        ) {
+            (Some(cert), Some(key)) => Some(TlsFiles { cert, key }),
+            (None, None) => None,
+            _ => anyhow::bail!("tls_cert_path and tls_key_path must be set together"),
+        };
+        let unix_socket = match env_value::<String>(env, "BIND")?.or(file.bind) {
+            Some(bind) => {
+                let mode =
+                    match env_value::<String>(env, "UNIX_SOCKET_MODE")?.or(file.unix_socket_mode) {
+                        Some(mode) => unix_socket::parse_mode(&mode)?,
+                        None => unix_socket::DEFAULT_MODE,
+                    };
+                Some(UnixSocket {
+                    path: unix_socket::parse_bind(&bind)?,
+                    mode,
+                })
+            }
+            None => None,
+        };
+        if unix_socket.is_some() && tls.is_some() {
+            anyhow::bail!(
+                "tls_cert_path can't be used with a unix: bind; let the proxy in front terminate TLS"
+            );
+        }
+
+        Ok(Config {
+            cluster,
+            bind_addr: env_value(env, "BIND_ADDR")?
+                .or(file.bind_addr)
+                .unwrap_or(DEFAULT_BIND_ADDR),
+            port,
+            grpc_port,
+            tls,
+            unix_socket,
+            wallet,
+            wallets,
+            window_hours,
+            explorer_tx_url,
+            verify_mints: env_value(env, "VERIFY_MINTS")?
+                .or(file.verify_mints)
+                .unwrap_or(true),
+            fetch_concurrency,
+            signature_page_size,
+            max_signature_pages,
+            rpc_max_attempts,
+            mints,
+            database_url,
+            database_path: env_value(env, "DATABASE_PATH")?.or(file.database_path),
+            state_path: env_value(env, "STATE_PATH")?.or(file.state_path),
+            poll_interval: (poll_interval_secs > 0)
+                .then(|| Duration::from_secs(poll_interval_secs)),
+            snapshot_interval: (snapshot_interval_mins > 0)
+                .then(|| Duration::from_secs(snapshot_interval_mins * 60)),
+            snapshot_every_transfers: (snapshot_every_transfers > 0)
+                .then_some(snapshot_every_transfers),
+            live_ws_url,
+            max_index_age: Duration::from_secs(
+                env_value(env, "READY_MAX_INDEX_AGE_SECS")?
+                    .or(file.ready_max_index_age_secs)
+                    .unwrap_or(DEFAULT_READY_MAX_INDEX_AGE_SECS),
+            ),
+            token_programs,
+            index_token_accounts: env_value(env, "INDEX_TOKEN_ACCOUNTS")?
+                .or(file.index_token_accounts)
+                .unwrap_or(true),
+            max_page_size,
+            owner_cache_ttl: Duration::from_secs(
+                env_value(env, "OWNER_CACHE_TTL_SECS")?
+                    .or(file.owner_cache_ttl_secs)
+                    .unwrap_or(DEFAULT_OWNER_CACHE_TTL_SECS),
+            ),
+            response_cache_ttl: (response_cache_ttl_secs > 0)
+                .then(|| Duration::from_secs(response_cache_ttl_secs)),
+            max_concurrent_backfills,
+            request_timeout: Duration::from_secs(request_timeout_secs),
+            rate_limit_per_minute,
+            rate_limit_burst,
+            allowlist,
+            swagger_ui: env_value(env, "SWAGGER_UI")?
+                .or(file.swagger_ui)
+                .unwrap_or(false),
+            compression_min_bytes: Some(
+                env_value(env, "COMPRESSION_MIN_BYTES")?
+                    .or(file.compression_min_bytes)
+                    .unwrap_or(DEFAULT_COMPRESSION_MIN_BYTES),
+            )
+            .filter(|&bytes| bytes > 0),
+            price_source,
+            raw_transaction_limit,
+            cors_origins,
+            cors_max_age: Duration::from_secs(
+                env_value(env, "CORS_MAX_AGE_SECS")?
+                    .or(file.cors_max_age_secs)
+                    .unwrap_or(DEFAULT_CORS_MAX_AGE_SECS),
+            ),
+            webhooks,
+            telegram,
+            discord,
+            alerts,
+            alert_balance_interval: Duration::from_secs(alert_balance_interval_secs),
+            reports,
+            s3,
+            admin_token: env_value::<String>(env, "ADMIN_TOKEN")?.or(file.admin_token),
+            api_keys: match env_value::<String>(env, "API_KEYS")? {
+                Some(keys) => ApiKeys::new(keys.split(',')),
+                None => ApiKeys::new(file.api_keys.unwrap_or_default()),
+            },
+            metrics_api_key: ApiKeys::new(
+                env_value::<String>(env, "METRICS_API_KEY")?.or(file.metrics_api_key),
+            ),
+            rpc_url,
+            rpc_endpoints,
+            rpc_timeout: Duration::from_secs(rpc_timeout_secs),
+            rpc_failover_threshold,
+            rpc_unhealthy_cooldown: Duration::from_secs(rpc_unhealthy_cooldown_secs),
+            rpc_requests_per_second,
+            rpc_method_limits,
+            commitment,
+        })
+    }
+
+    /// `wallet` as a metrics label: configured wallets by address, anything
+    /// else (wallets added at runtime included) as `other`, so label sets
+    /// stay bounded.
+    pub fn wallet_label(&self, wallet: &Pubkey) -> String {
+        if self.wallets.contains(wallet) {
+            wallet.to_string()
+        } else {
+            "other".to_string()
+        }
+    }
+
+    /// Explorer link for transaction `signature`.
+    pub fn explorer_link(&self, signature: &str) -> String {
+        self.explorer_tx_url.replace("{signature}", signature)
+    }
+
+    /// Logs the effective configuration, with credentials stripped from URLs.
+    pub fn log_effective(&self) {
+        let symbols: Vec<&str> = self.mints.mints.iter().map(|m| m.symbol.as_str()).collect();
+        let programs: Vec<&str> = self.token_programs.iter().map(|p| p.name).collect();
+        let webhooks: Vec<String> = self.webhooks.iter().map(|w| redact_url(&w.url)).collect();
+        let discord: Vec<String> = self.discord.iter().map(|d| redact_url(&d.url)).collect();
+        let alerts: Vec<&str> = self.alerts.iter().map(|a| a.name.as_str()).collect();
+        let reports: Vec<&str> = self.reports.iter().map(|r| r.name.as_str()).collect();
+        let rpc_endpoints: Vec<String> = self
+            .rpc_endpoints
+            .iter()
+            .map(|e| redact_url(&e.url))
+            .collect();
+        info!(
+            cluster = self.cluster.name(),
+            rpc_endpoints = ?rpc_endpoints,
+            rpc_timeout = ?self.rpc_timeout,
+            rpc_failover_threshold = self.rpc_failover_threshold,
+            rpc_unhealthy_cooldown = ?self.rpc_unhealthy_cooldown,
+            rpc_requests_per_second = ?self.rpc_requests_per_second,
+            commitment = commitment_name(self.commitment),
+            rpc_method_limits = ?self.rpc_method_limits,
+            live_ws_url = ?self.live_ws_url.as_deref().map(redact_url),
+            bind_addr = %self.bind_addr,
+            port = self.port,
+            grpc_port = ?self.grpc_port,
+            tls_cert_path = ?self.tls.as_ref().map(|tls| &tls.cert),
+            unix_socket = ?self.unix_socket.as_ref().map(|socket| &socket.path),
+            wallet = %self.wallet,
+            wallets = self.wallets.len(),
+            mints = ?symbols,
+            window_hours = self.window_hours,
+            explorer_tx_url = %self.explorer_tx_url,
+            verify_mints = self.verify_mints,
+            fetch_concurrency = self.fetch_concurrency,
+            signature_page_size = self.signature_page_size,
+            max_signature_pages = self.max_signature_pages,
+            rpc_max_attempts = self.rpc_max_attempts,
+            database_url = ?self.database_url.as_deref().map(redact_url),
+            database_path = ?self.database_path.as_deref().map(redact_url),
+            state_path = ?self.state_path,
+            poll_interval = ?self.poll_interval,
+            snapshot_interval = ?self.snapshot_interval,
+            snapshot_every_transfers = ?self.snapshot_every_transfers,
+            max_index_age = ?self.max_index_age,
+            token_programs = ?programs,
+            index_token_accounts = self.index_token_accounts,
+            max_page_size = self.max_page_size,
+            owner_cache_ttl = ?self.owner_cache_ttl,
+            response_cache_ttl = ?self.response_cache_ttl,
+            max_concurrent_backfills = self.max_concurrent_backfills,
+            request_timeout = ?self.request_timeout,
+            rate_limit_per_minute = ?self.rate_limit_per_minute,
+            rate_limit_burst = self.rate_limit_burst,
+            allowed_ips = ?self.allowlist.allowed,
+            trusted_proxies = ?self.allowlist.trusted_proxies,
+            swagger_ui = self.swagger_ui,
+            compression_min_bytes = ?self.compression_min_bytes,
+            price_source = ?self.price_source.as_ref().map(|source| source.provider.name()),
+            price_api_url = ?self.price_source.as_ref().map(|source| redact_url(&source.api_url)),
+            raw_transaction_limit = ?self.raw_transaction_limit,
+            cors_origins = ?self.cors_origins,
+            cors_max_age = ?self.cors_max_age,
+            webhooks = ?webhooks,
+            discord = ?discord,
+            alerts = ?alerts,
+            reports = ?reports,
+            s3_bucket = ?self.s3.as_ref().map(|s3| &s3.bucket),
+            s3_dry_run = self.s3.as_ref().is_some_and(|s3| s3.dry_run),
+            telegram_chat_id = ?self.telegram.as_ref().map(|t| &t.chat_id),
+            admin_api = self.admin_token.is_some(),
+            api_keys = self.api_keys.len(),
+            metrics_api_key = !self.metrics_api_key.is_empty(),
+            "effective configuration"
+        );
+    }
+}
+
+/// A comma-separated list of networks from `name`, else the file's list.
+fn networks(
+    env: &dyn Fn(&str) -> Option<String>,
+    name: &str,
+    from_file: Option<Vec<String>>,
+) -> Result<Vec<IpNet>> {
+    let list: Vec<String> = match env_value::<String>(env, name)? {
+        Some(list) => list.split(',').map(str::to_string).collect(),
+        None => from_file.unwrap_or_default(),
+    };
+    list.iter()
+        .filter(|network| !network.trim().is_empty())
+        .map(|network| {
+            allowlist::parse_network(network)
+                .map_err(|e| anyhow::anyhow!("{}: {}", name.to_lowercase(), e))
+        })
+        .collect()
+}
+
+/// Reads and parses one environment override; empty values count as unset.
+fn env_value<T>(env: &dyn Fn(&str) -> Option<String>, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env(name).filter(|v| !v.is_empty()) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("{}='{}' is invalid: {}", name, value, e)),
+        None => Ok(None),
+    }
+}
+
+/// Keeps only the scheme and host of a URL. Providers put API keys in the
+/// userinfo, the path or the query string, so none of those are logged.
+pub fn redact_url(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let authority = &rest[..end];
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let remainder = &rest[end..];
+    if authority.contains('@') || !matches!(remainder, "" | "/") {
+        format!("{}://{}/<redacted>", scheme, host)
+    } else {
+        format!("{}://{}{}", scheme, host, remainder)
+    }
+}
+
+impl Default for Config {
+    /// Built-in defaults with no persistence and no background indexing.
+    fn default() -> Self {
+        let wallet = Pubkey::from_str(DEFAULT_WALLET_ADDRESS).expect("built-in wallet is valid");
+        Config {
+            cluster: Cluster::MainnetBeta,
+            rpc_url: RPC_URL.to_string(),
+            rpc_endpoints: vec![RpcEndpoint::new(RPC_URL)],
+            rpc_timeout: Duration::from_secs(DEFAULT_RPC_TIMEOUT_SECS),
+            rpc_failover_threshold: DEFAULT_RPC_FAILOVER_THRESHOLD,
+            rpc_unhealthy_cooldown: Duration::from_secs(DEFAULT_RPC_UNHEALTHY_COOLDOWN_SECS),
+            rpc_requests_per_second: None,
+            commitment: CommitmentConfig::confirmed(),
+            rpc_method_limits: BTreeMap::new(),
+            bind_addr: DEFAULT_BIND_ADDR,
+            port: DEFAULT_PORT,
+            grpc_port: None,
+            tls: None,
+            unix_socket: None,
+            wallet,
+            wallets: vec![wallet],
+            window_hours: DEFAULT_WINDOW_HOURS,
+            explorer_tx_url: DEFAULT_EXPLORER_TX_URL.to_string(),
+            verify_mints: true,
+            fetch_concurrency: DEFAULT_FETCH_CONCURRENCY,
+            signature_page_size: DEFAULT_SIGNATURE_PAGE_SIZE,
+            max_signature_pages: DEFAULT_MAX_SIGNATURE_PAGES,
+            rpc_max_attempts: DEFAULT_RPC_MAX_ATTEMPTS,
+            mints: MintRegistry::parse(DEFAULT_MINTS).expect("built-in mint registry is valid"),
+            database_url: None,
+            database_path: None,
+            state_path: None,
+            poll_interval: None,
+            snapshot_interval: None,
+            snapshot_every_transfers: None,
+            live_ws_url: None,
+            max_index_age: Duration::from_secs(DEFAULT_READY_MAX_INDEX_AGE_SECS),
+            token_programs: TokenProgram::ALL.to_vec(),
+            index_token_accounts: true,
+            max_page_size: DEFAULT_MAX_PAGE_SIZE,
+            owner_cache_ttl: Duration::from_secs(DEFAULT_OWNER_CACHE_TTL_SECS),
+            response_cache_ttl: Some(Duration::from_secs(DEFAULT_RESPONSE_CACHE_TTL_SECS)),
+            max_concurrent_backfills: DEFAULT_MAX_CONCURRENT_BACKFILLS,
+            request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS),
+            rate_limit_per_minute: None,
+            rate_limit_burst: DEFAULT_RATE_LIMIT_BURST,
+            allowlist: Allowlist::default(),
+            swagger_ui: false,
+            compression_min_bytes: Some(DEFAULT_COMPRESSION_MIN_BYTES),
+            price_source: None,
+            raw_transaction_limit: None,
+            cors_origins: Vec::new(),
+            cors_max_age: Duration::from_secs(DEFAULT_CORS_MAX_AGE_SECS),
+            webhooks: Vec::new(),
+            telegram: None,
+            discord: Vec::new(),
+            alerts: Vec::new(),
+            alert_balance_interval: Duration::from_secs(DEFAULT_ALERT_BALANCE_INTERVAL_SECS),
+            reports: Vec::new(),
+            s3: None,
+            admin_token: None,
+            api_keys: ApiKeys::default(),
+            metrics_api_key: ApiKeys::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve(toml: &str, env: &[(&str, &str)]) -> Result<Config> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::resolve(toml::from_str(toml)?, &|name| env.get(name).cloned())
+    }
+
+    #[test]
+    fn env_overrides_file_which_overrides_defaults() {
+        let config = resolve(
+            r#"
+                port = 8080
+                bind_addr = "127.0.0.1"
+                explorer_tx_url = "https://solscan.io/tx/{signature}?cluster=devnet"
+                fetch_concurrency = 2
+                [[mints]]
+                symbol = "USDC"
+                mint = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"
+                decimals = 6
+            "#,
+            &[("PORT", "9090")],
+        )
+        .unwrap();
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.bind_addr, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(config.fetch_concurrency, 2);
+        assert_eq!(config.rpc_max_attempts, DEFAULT_RPC_MAX_ATTEMPTS);
+        assert_eq!(config.mints.mints.len(), 1);
+        assert_eq!(config.token_programs, TokenProgram::ALL);
+        assert_eq!(
+            config.snapshot_interval,
+            Some(Duration::from_secs(DEFAULT_SNAPSHOT_INTERVAL_MINS * 60))
+        );
+        assert_eq!(config.snapshot_every_transfers, None);
+        assert_eq!(
+            config.explorer_link("abc"),
+            "https://solscan.io/tx/abc?cluster=devnet"
+        );
+    }
+
+    #[test]
+    fn cluster_picks_the_rpc_url_mints_and_explorer() {
+        let config = resolve(r#"cluster = "devnet""#, &[]).unwrap();
+        assert_eq!(config.cluster, Cluster::Devnet);
+        assert_eq!(config.rpc_url, "https://api.devnet.solana.com");
+        assert_eq!(
+            config.mints.default_mint().mint.to_string(),
+            "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU"
+        );
+        assert_eq!(
+            config.explorer_link("abc"),
+            "https://explorer.solana.com/tx/abc?cluster=devnet"
+        );
+
+        // Explicit settings still win over the cluster's defaults.
+        let config = resolve("", &[("CLUSTER", "testnet"), ("MINTS", DEVNET_MINTS)]).unwrap();
+        assert_eq!(config.rpc_url, "https://api.testnet.solana.com");
+        assert!(resolve("", &[("CLUSTER", "testnet")]).is_err());
+        assert!(resolve("", &[("CLUSTER", "custom"), ("MINTS", DEVNET_MINTS)]).is_err());
+        let config = resolve(
+            "",
+            &[
+                ("CLUSTER", "custom"),
+                ("MINTS", DEVNET_MINTS),
+                ("RPC_URL", "http://localhost:8899"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.cluster.name(), "custom");
+        assert!(resolve("", &[("CLUSTER", "localnet")]).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_values_with_the_offending_key() {
+        let err = resolve("", &[("WALLET", "not-a-key")]).unwrap_err();
+        assert!(err.to_string().contains("wallet 'not-a-key'"), "{}", err);
+        let err = resolve("", &[("FETCH_CONCURRENCY", "many")]).unwrap_err();
+        assert!(err.to_string().contains("FETCH_CONCURRENCY"), "{}", err);
+        assert!(resolve("prot = 1", &[]).is_err());
+        assert!(resolve("", &[("EXPLORER_TX_URL", "https://solscan.io/tx")]).is_err());
+        let err = resolve("", &[("TOKEN_PROGRAMS", "spl-token,token-2023")]).unwrap_err();
+        assert!(err.to_string().contains("token-2023"), "{}", err);
+        let err = resolve("", &[("RPC_METHOD_LIMITS", "getBlock=2")]).unwrap_err();
+        assert!(err.to_string().contains("getBlock"), "{}", err);
+        assert!(resolve("rpc_requests_per_second = -1.0", &[]).is_err());
+        let err = resolve("", &[("PRICE_SOURCE", "chainlink")]).unwrap_err();
+        assert!(err.to_string().contains("chainlink"), "{}", err);
+        let err = resolve("", &[("CORS_ORIGINS", "dashboard.example.com")]).unwrap_err();
+        assert!(err.to_string().contains("dashboard.example.com"), "{}", err);
+        assert!(resolve("raw_transaction_limit = 0", &[]).is_err());
+        let err = resolve("", &[("WINDOW_HOURS", "9223372036854775807")]).unwrap_err();
+        assert!(err.to_string().contains("window_hours"), "{}", err);
+        assert!(resolve("", &[("REQUEST_TIMEOUT_SECS", "0")]).is_err());
+        let err = resolve("port = 9000\ngrpc_port = 9000", &[]).unwrap_err();
+        assert!(err.to_string().contains("grpc_port"), "{}", err);
+        let err = resolve("", &[("TLS_CERT_PATH", "/etc/ssl/cert.pem")]).unwrap_err();
+        assert!(err.to_string().contains("tls_key_path"), "{}", err);
+        assert!(resolve("", &[("TRUST_FORWARDED_FOR", "true")]).is_ok());
+        let err = resolve(
+            "",
+            &[
+                ("TRUST_FORWARDED_FOR", "true"),
+                ("ALLOWED_IPS", "10.0.0.0/8"),
+            ],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("trusted_proxies"), "{}", err);
+        let err = resolve("trusted_proxies = [\"10.0.0.0/8\", \"proxy\"]", &[]).unwrap_err();
+        assert!(err.to_string().contains("trusted_proxies"), "{}", err);
+        let config = resolve("", &[("ALLOWED_IPS", "203.0.113.0/24, 2001:db8::1")]).unwrap();
+        assert_eq!(config.allowlist.allowed.len(), 2);
+        assert!(resolve("", &[("BIND", "/run/indexer.sock")]).is_err());
+        let err = resolve(
+            "bind = \"unix:/run/indexer.sock\"",
+            &[
+                ("TLS_CERT_PATH", "/etc/ssl/cert.pem"),
+                ("TLS_KEY_PATH", "/etc/ssl/key.pem"),
+            ],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unix:"), "{}", err);
+        assert!(resolve(
+            "",
+            &[
+                ("BIND", "unix:/run/indexer.sock"),
+                ("UNIX_SOCKET_MODE", "rw")
+            ]
+        )
+        .is_err());
+        let config = resolve(
+            "bind = \"unix:/run/indexer.sock\"\nunix_socket_mode = \"600\"",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            config.unix_socket,
+            Some(UnixSocket {
+                path: PathBuf::from("/run/indexer.sock"),
+                mode: 0o600,
+            })
+        );
+        assert!(resolve("", &[("SIGNATURE_PAGE_SIZE", "5000")]).is_err());
+        assert!(resolve("max_signature_pages = 0", &[]).is_err());
+        let config = resolve("", &[("RPC_METHOD_LIMITS", "getTransaction=2.5")]).unwrap();
+        assert_eq!(config.rpc_method_limits["getTransaction"], 2.5);
+        assert!(resolve("", &[("DATABASE_URL", "mysql://db/index")]).is_err());
+        let config = resolve("", &[("DATABASE_URL", "postgres://db/index")]).unwrap();
+        assert_eq!(config.database_url.as_deref(), Some("postgres://db/index"));
+    }
+
+    #[test]
+    fn reads_rpc_endpoints_in_priority_order() {
+        let file = r#"
+            [[rpc_endpoints]]
+            url = "https://mainnet.helius-rpc.com/?api-key=k"
+            [[rpc_endpoints]]
+            url = "https://rpc.example.com"
+            headers = { "x-token" = "t" }
+        "#;
+        let config = resolve(file, &[]).unwrap();
+        assert_eq!(config.rpc_endpoints.len(), 2);
+        assert_eq!(config.rpc_url, "https://mainnet.helius-rpc.com/?api-key=k");
+        assert_eq!(
+            config.rpc_endpoints[1].headers,
+            vec![("x-token".to_string(), "t".to_string())]
+        );
+
+        let config = resolve(
+            file,
+            &[
+                ("RPC_URLS", "https://a.example.com, https://b.example.com"),
+                ("RPC_AUTH_HEADER", "Authorization: Bearer k"),
+            ],
+        )
+        .unwrap();
+        let urls: Vec<&str> = config
+            .rpc_endpoints
+            .iter()
+            .map(|e| e.url.as_str())
+            .collect();
+        assert_eq!(urls, ["https://a.example.com", "https://b.example.com"]);
+        assert_eq!(config.rpc_endpoints[1].headers[0].1, "Bearer k");
+        assert!(!format!("{:?}", config).contains("Bearer k"));
+
+        assert!(resolve("rpc_url = \"https://a\"\nrpc_endpoints = []", &[]).is_err());
+        assert!(resolve("", &[("RPC_URLS", "wss://a.example.com")]).is_err());
+    }
+
+    #[test]
+    fn resolves_webhook_filters_against_the_registry() {
+        let config = resolve(
+            r#"
+                [[webhooks]]
+                url = "https://hooks.example.com/usdc"
+                secret = "s3cret"
+                direction = "received"
+                min_amount = "100.5"
+                mint = "USDC"
+            "#,
+            &[],
+        )
+        .unwrap();
+        let filter = &config.webhooks[0].filter;
+        assert_eq!(filter.direction, Some(Direction::Received));
+        assert_eq!(filter.mint, Some(config.mints.mints[0].mint));
+
+        let too_precise = r#"
+            [[webhooks]]
+            url = "https://hooks.example.com"
+            min_amount = "0.0000001"
+        "#;
+        assert!(resolve(too_precise, &[]).is_err());
+        assert!(resolve("[[webhooks]]\nurl = \"https://x\"\nmint = \"BONK\"", &[]).is_err());
+    }
+
+    #[test]
+    fn resolves_discord_webhooks_per_direction() {
+        let config = resolve(
+            r#"
+                [[discord]]
+                url = "https://discord.com/api/webhooks/1/in"
+                direction = "received"
+                min_amount = "10"
+
+                [[discord]]
+                url = "https://discord.com/api/webhooks/1/out"
+                direction = "sent"
+            "#,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(config.discord.len(), 2);
+        assert_eq!(
+            config.discord[0].filter.direction,
+            Some(Direction::Received)
+        );
+        assert_eq!(config.discord[1].filter.direction, Some(Direction::Sent));
+        assert!(resolve("[[discord]]\nurl = \"http://x\"", &[]).is_err());
+    }
+
+    #[test]
+    fn resolves_alert_rules() {
+        let config = resolve(
+            r#"
+                [[alerts]]
+                name = "drain"
+                kind = "outflow"
+                amount = "5000"
+                window_minutes = 30
+
+                [[alerts]]
+                name = "low"
+                kind = "balance"
+                amount = "100"
+                mint = "USDC"
+                cooldown_secs = 60
+
+                [[alerts]]
+                name = "delegate"
+                kind = "approval"
+                amount = "0"
+            "#,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            config.alerts[0].condition,
+            AlertCondition::Outflow {
+                max_raw: 5_000_000_000,
+                window: Duration::from_secs(1800),
+            }
+        );
+        assert_eq!(config.alerts[0].wallet, None);
+        assert_eq!(config.alerts[1].wallet, Some(config.wallet));
+        assert_eq!(config.alerts[1].cooldown, Duration::from_secs(60));
+        assert_eq!(
+            config.alerts[2].condition,
+            AlertCondition::Approval { min_raw: 0 }
+        );
+
+        let no_window = "[[alerts]]\nname = \"x\"\nkind = \"outflow\"\namount = \"1\"";
+        let err = resolve(no_window, &[]).unwrap_err();
+        assert!(format!("{:#}", err).contains("alert 'x'"), "{:#}", err);
+        assert!(resolve(
+            "[[alerts]]\nname = \"x\"\nkind = \"spike\"\namount = \"1\"",
+            &[]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn resolves_report_schedules_and_deliveries() {
+        let config = resolve(
+            r#"
+                [[reports]]
+                name = "daily"
+                schedule = "5 0 * * *"
+                deliver = "directory"
+                directory = "/var/lib/indexer/reports"
+
+                [[reports]]
+                name = "ledger"
+                schedule = "0 0 6 * * *"
+                mint = "USDT"
+                deliver = "webhook"
+                url = "https://ledger.example.com/statements"
+                secret = "s3cret"
+            "#,
+            &[],
+        )
+        .unwrap();
+        let daily = &config.reports[0];
+        assert_eq!(daily.mint.symbol, "USDC");
+        assert!(matches!(
+            &daily.delivery,
+            ReportDelivery::Directory(path) if path == Path::new("/var/lib/indexer/reports")
+        ));
+        let next = daily.schedule.upcoming(chrono::Utc).next().unwrap();
+        assert_eq!(next.format("%H:%M:%S").to_string(), "00:05:00");
+        let ReportDelivery::Webhook(target) = &config.reports[1].delivery else {
+            panic!("expected webhook delivery");
+        };
+        assert_eq!(target.secret.as_deref(), Some("s3cret"));
+        assert_eq!(config.reports[1].mint.symbol, "USDT");
+
+        let report = |rest: &str| {
+            resolve(
+                &format!(
+                    "[[reports]]\nname = \"x\"\nschedule = \"5 0 * * *\"\n{}",
+                    rest
+                ),
+                &[],
+            )
+        };
+        let err = report("deliver = \"telegram\"").unwrap_err();
+        assert!(format!("{:#}", err).contains("report 'x'"), "{:#}", err);
+        assert!(report("deliver = \"webhook\"").is_err());
+        assert!(
+            report("deliver = \"directory\"\ndirectory = \"/tmp\"\nurl = \"https://x\"").is_err()
+        );
+        assert!(resolve(
+            "[[reports]]\nname = \"x\"\nschedule = \"every day\"\ndeliver = \"telegram\"",
+            &[]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn resolves_the_s3_bucket_with_env_credentials() {
+        let file = r#"
+            [s3]
+            endpoint = "https://s3.example.com"
+            bucket = "exports"
+            prefix = "indexer"
+            dry_run = true
+
+            [[reports]]
+            name = "daily"
+            schedule = "5 0 * * *"
+            deliver = "s3"
+        "#;
+        let config = resolve(
+            file,
+            &[
+                ("AWS_ACCESS_KEY_ID", "AKID"),
+                ("S3_SECRET_ACCESS_KEY", "wJalrXUtnFEMI"),
+            ],
+        )
+        .unwrap();
+        assert!(!format!("{:?}", config).contains("wJalrXUtnFEMI"));
+        let target = config.s3.as_ref().unwrap();
+        assert_eq!(target.prefix, "indexer/");
+        assert_eq!(target.region, DEFAULT_S3_REGION);
+        assert_eq!(target.access_key_id, "AKID");
+        assert!(target.dry_run);
+        assert!(matches!(config.reports[0].delivery, ReportDelivery::S3));
+
+        let err = resolve(file, &[("S3_ACCESS_KEY_ID", "AKID")]).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("S3_SECRET_ACCESS_KEY"),
+            "{:#}",
+            err
+        );
+        assert!(resolve("[s3]\nbucket = \"exports\"", &[]).is_err());
+    }
+
+    #[test]
+    fn tracks_the_default_wallet_first() {
+        let a = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+        let b = DEFAULT_WALLET_ADDRESS;
+        let config = resolve(&format!("wallets = [\"{}\", \"{}\"]", a, b), &[]).unwrap();
+        assert_eq!(config.wallet.to_string(), a);
+        assert_eq!(config.wallets.len(), 2);
+
+        let config = resolve("", &[("WALLETS", &format!("{},{}", a, b)), ("WALLET", b)]).unwrap();
+        assert_eq!(config.wallet.to_string(), b);
+        assert_eq!(config.wallets[1].to_string(), a);
+        assert!(resolve("wallets = [\"nope\"]", &[]).is_err());
+    }
+
+    #[test]
+    fn telegram_needs_token_and_chat_id() {
+        assert!(resolve("", &[]).unwrap().telegram.is_none());
+        let config = resolve(
+            "[telegram]\nchat_id = \"-100\"\nmin_amount = \"50\"",
+            &[("TELEGRAM_BOT_TOKEN", "123:abc")],
+        )
+        .unwrap();
+        assert!(!format!("{:?}", config).contains("123:abc"));
+        let telegram = config.telegram.unwrap();
+        assert_eq!(telegram.bot_token, "123:abc");
+        assert_eq!(telegram.chat_id, "-100");
+        assert_eq!(
+            telegram.max_messages_per_minute,
+            DEFAULT_TELEGRAM_MAX_MESSAGES_PER_MINUTE
+        );
+        assert!(resolve("", &[("TELEGRAM_CHAT_ID", "1")]).is_err());
+    }
+
+    #[test]
+    fn redacts_credentials_in_urls() {
+        assert_eq!(
+            redact_url("https://mainnet.helius-rpc.com/?api-key=secret"),
+            "https://mainnet.helius-rpc.com/<redacted>"
+        );
+        assert_eq!(
+            redact_url("https://user:pw@rpc.example.com"),
+            "https://rpc.example.com/<redacted>"
+        );
+        assert_eq!(
+            redact_url("https://api.mainnet-beta.solana.com"),
+            "https://api.mainnet-beta.solana.com"
+        );
+    }
+}
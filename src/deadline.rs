@@ -0,0 +1,94 @@
+//! Deadlines and cancellation for backfills. Work runs in the scope of a
+//! [`CancellationToken`] that the indexer checks between signature pages
+//! and transaction fetches, so a request past its deadline, a client that
+//! went away or a cancelled job stops spending RPC calls at the next
+//! boundary.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::IndexerError;
+use crate::jobs::{self, JobProgress};
+
+tokio::task_local! {
+    static CANCEL: CancellationToken;
+}
+
+/// Fails with [`IndexerError::Cancelled`] once the token of the scope the
+/// caller runs in is cancelled. Outside any scope nothing is cancelled.
+pub(crate) fn check() -> Result<(), IndexerError> {
+    let cancelled = CANCEL
+        .try_with(CancellationToken::is_cancelled)
+        .unwrap_or(false);
+    if cancelled {
+        Err(IndexerError::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs `work` with `token` as the one [`check`] consults.
+pub async fn scope<F: Future>(token: CancellationToken, work: F) -> F::Output {
+    CANCEL.scope(token, work).await
+}
+
+/// Runs `work` for a client waiting on the answer. Past `timeout` it is
+/// cancelled and fails with [`IndexerError::Timeout`], reporting how far
+/// the scan got. When the client disconnects, hyper drops the handler's
+/// future and this one with it, which cancels the token for anything
+/// `work` handed it on to.
+pub async fn run<F, T>(timeout: Duration, work: F) -> Result<T, IndexerError>
+where
+    F: Future<Output = Result<T, IndexerError>>,
+{
+    let token = CancellationToken::new();
+    let _disconnect = token.clone().drop_guard();
+    let progress = Arc::new(JobProgress::default());
+    let work = jobs::track(progress.clone(), scope(token.clone(), work));
+    match tokio::time::timeout(timeout, work).await {
+        Ok(result) => result,
+        Err(_) => {
+            token.cancel();
+            Err(IndexerError::Timeout {
+                after: timeout,
+                progress: progress.snapshot(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn times_out_with_the_progress_so_far() {
+        let result = run(Duration::from_millis(20), async {
+            jobs::record_signatures(1000, Some(1_700_000_000));
+            jobs::record_transfer();
+            std::future::pending::<Result<(), IndexerError>>().await
+        })
+        .await;
+        let Err(IndexerError::Timeout { after, progress }) = result else {
+            panic!("expected a timeout, got {:?}", result);
+        };
+        assert_eq!(after, Duration::from_millis(20));
+        assert_eq!(progress.signatures_scanned, 1000);
+        assert_eq!(progress.transfers_found, 1);
+        assert_eq!(progress.oldest_block_time, Some(1_700_000_000));
+    }
+
+    #[tokio::test]
+    async fn checks_see_the_scope_token() {
+        assert!(check().is_ok());
+        let token = CancellationToken::new();
+        scope(token.clone(), async {
+            assert!(check().is_ok());
+            token.cancel();
+            assert!(matches!(check(), Err(IndexerError::Cancelled)));
+        })
+        .await;
+    }
+}
@@ -0,0 +1,383 @@
+//! Fallback for transactions that come back without `jsonParsed`: some
+//! providers (and archival `getTransaction` on old slots) answer in base58 or
+//! base64, or with the raw message shape. Such a transaction is rewritten
+//! into the parsed shape the parser reads, with its SPL-token `transfer` and
+//! `transferChecked` instructions decoded from their data bytes and every
+//! other instruction left partially decoded, as the RPC would.
+
+use serde_json::json;
+use solana_sdk::bs58;
+use solana_sdk::message::MessageHeader;
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::parse_accounts::{ParsedAccount, ParsedAccountSource};
+use solana_transaction_status::parse_instruction::ParsedInstruction;
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, UiAddressTableLookup,
+    UiCompiledInstruction, UiInnerInstructions, UiInstruction, UiLoadedAddresses, UiMessage,
+    UiParsedInstruction, UiParsedMessage, UiPartiallyDecodedInstruction, UiTransaction,
+};
+
+use crate::config::TokenProgram;
+use crate::model::format_amount;
+
+/// Instruction tags of the token program, shared by Token-2022.
+const TRANSFER: u8 = 3;
+const TRANSFER_CHECKED: u8 = 12;
+
+/// A message as compiled, whatever shape it arrived in.
+struct CompiledMessage {
+    signatures: Vec<String>,
+    header: MessageHeader,
+    static_keys: Vec<String>,
+    recent_blockhash: String,
+    instructions: Vec<UiCompiledInstruction>,
+    lookups: Option<Vec<UiAddressTableLookup>>,
+}
+
+impl CompiledMessage {
+    fn of(transaction: &EncodedTransaction) -> Option<Self> {
+        if let EncodedTransaction::Json(ui_tx) = transaction {
+            let UiMessage::Raw(message) = &ui_tx.message else {
+                return None;
+            };
+            return Some(CompiledMessage {
+                signatures: ui_tx.signatures.clone(),
+                header: message.header,
+                static_keys: message.account_keys.clone(),
+                recent_blockhash: message.recent_blockhash.clone(),
+                instructions: message.instructions.clone(),
+                lookups: message.address_table_lookups.clone(),
+            });
+        }
+        let decoded = transaction.decode()?;
+        let message = &decoded.message;
+        Some(CompiledMessage {
+            signatures: decoded.signatures.iter().map(ToString::to_string).collect(),
+            header: *message.header(),
+            static_keys: message
+                .static_account_keys()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            recent_blockhash: message.recent_blockhash().to_string(),
+            instructions: message
+                .instructions()
+                .iter()
+                .map(|ix| UiCompiledInstruction {
+                    program_id_index: ix.program_id_index,
+                    accounts: ix.accounts.clone(),
+                    data: bs58::encode(&ix.data).into_string(),
+                    stack_height: None,
+                })
+                .collect(),
+            lookups: message
+                .address_table_lookups()
+                .map(|lookups| lookups.iter().map(UiAddressTableLookup::from).collect()),
+        })
+    }
+
+    /// Every account key in index order: the static keys, then those loaded
+    /// from lookup tables (writable first).
+    fn accounts(&self, loaded: Option<&UiLoadedAddresses>) -> Vec<ParsedAccount> {
+        let header = &self.header;
+        let signers = header.num_required_signatures as usize;
+        let writable_signers = signers.saturating_sub(header.num_readonly_signed_accounts as usize);
+        let writable_unsigned = self
+            .static_keys
+            .len()
+            .saturating_sub(header.num_readonly_unsigned_accounts as usize);
+        let mut accounts: Vec<ParsedAccount> = self
+            .static_keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| ParsedAccount {
+                pubkey: key.clone(),
+                writable: if index < signers {
+                    index < writable_signers
+                } else {
+                    index < writable_unsigned
+                },
+                signer: index < signers,
+                source: Some(ParsedAccountSource::Transaction),
+            })
+            .collect();
+        if let Some(loaded) = loaded {
+            let lookup = |keys: &[String], writable: bool| {
+                keys.iter()
+                    .map(|key| ParsedAccount {
+                        pubkey: key.clone(),
+                        writable,
+                        signer: false,
+                        source: Some(ParsedAccountSource::LookupTable),
+                    })
+                    .collect::<Vec<_>>()
+            };
+            accounts.extend(lookup(&loaded.writable, true));
+            accounts.extend(lookup(&loaded.readonly, false));
+        }
+        accounts
+    }
+}
+
+/// `tx` in the `jsonParsed` shape, or `None` if it already is in that
+/// shape or can't be read (bad encoding, an account index out of range).
+pub fn json_parsed(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    programs: &[TokenProgram],
+) -> Option<EncodedConfirmedTransactionWithStatusMeta> {
+    let message = CompiledMessage::of(&tx.transaction.transaction)?;
+    let meta = tx.transaction.meta.as_ref();
+    let loaded: Option<&UiLoadedAddresses> =
+        meta.and_then(|meta| meta.loaded_addresses.as_ref().into());
+    let accounts = message.accounts(loaded);
+    let keys: Vec<&str> = accounts.iter().map(|a| a.pubkey.as_str()).collect();
+
+    let instructions = message
+        .instructions
+        .iter()
+        .map(|ix| parsed_instruction(ix, &keys, programs))
+        .collect::<Option<Vec<_>>>()?;
+    let mut converted = tx.transaction.clone();
+    if let Some(meta) = converted.meta.as_mut() {
+        if let OptionSerializer::Some(groups) = &meta.inner_instructions {
+            let groups = groups
+                .iter()
+                .map(|group| {
+                    let instructions = group
+                        .instructions
+                        .iter()
+                        .map(|ix| match ix {
+                            UiInstruction::Compiled(ix) => parsed_instruction(ix, &keys, programs),
+                            parsed => Some(parsed.clone()),
+                        })
+                        .collect::<Option<Vec<_>>>()?;
+                    Some(UiInnerInstructions {
+                        index: group.index,
+                        instructions,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?;
+            meta.inner_instructions = OptionSerializer::Some(groups);
+        }
+    }
+    converted.transaction = EncodedTransaction::Json(UiTransaction {
+        signatures: message.signatures,
+        message: UiMessage::Parsed(UiParsedMessage {
+            account_keys: accounts,
+            recent_blockhash: message.recent_blockhash,
+            instructions,
+            address_table_lookups: message.lookups,
+        }),
+    });
+    Some(EncodedConfirmedTransactionWithStatusMeta {
+        slot: tx.slot,
+        transaction: converted,
+        block_time: tx.block_time,
+    })
+}
+
+fn parsed_instruction(
+    ix: &UiCompiledInstruction,
+    keys: &[&str],
+    programs: &[TokenProgram],
+) -> Option<UiInstruction> {
+    let program_id = *keys.get(ix.program_id_index as usize)?;
+    let accounts = ix
+        .accounts
+        .iter()
+        .map(|&index| keys.get(index as usize).map(|key| key.to_string()))
+        .collect::<Option<Vec<_>>>()?;
+    let data = bs58::decode(&ix.data).into_vec().ok()?;
+    let token = programs
+        .iter()
+        .find(|program| program.id == program_id)
+        .and_then(|program| Some((program, token_instruction(&data, &accounts)?)));
+    Some(UiInstruction::Parsed(match token {
+        Some((program, parsed)) => UiParsedInstruction::Parsed(ParsedInstruction {
+            program: program.name.to_string(),
+            program_id: program_id.to_string(),
+            parsed,
+            stack_height: ix.stack_height,
+        }),
+        None => UiParsedInstruction::PartiallyDecoded(UiPartiallyDecodedInstruction {
+            program_id: program_id.to_string(),
+            accounts,
+            data: ix.data.clone(),
+            stack_height: ix.stack_height,
+        }),
+    }))
+}
+
+/// The `parsed` value the RPC gives a `transfer` or `transferChecked`;
+/// `None` for any other instruction or malformed data.
+fn token_instruction(data: &[u8], accounts: &[String]) -> Option<serde_json::Value> {
+    let (&tag, rest) = data.split_first()?;
+    let amount = u64::from_le_bytes(rest.get(..8)?.try_into().ok()?);
+    let (kind, authority, mut info) = match tag {
+        TRANSFER if accounts.len() >= 3 => (
+            "transfer",
+            2,
+            json!({
+                "source": accounts[0],
+                "destination": accounts[1],
+                "amount": amount.to_string(),
+            }),
+        ),
+        TRANSFER_CHECKED if accounts.len() >= 4 => {
+            let decimals = *rest.get(8)?;
+            (
+                "transferChecked",
+                3,
+                json!({
+                    "source": accounts[0],
+                    "mint": accounts[1],
+                    "destination": accounts[2],
+                    "tokenAmount": {
+                        "amount": amount.to_string(),
+                        "decimals": decimals,
+                        "uiAmountString": format_amount(amount, decimals),
+                    },
+                }),
+            )
+        }
+        _ => return None,
+    };
+    // Accounts past the authority are the signers of a multisig one.
+    match &accounts[authority..] {
+        [owner] => info["authority"] = json!(owner),
+        [owner, signers @ ..] => {
+            info["multisigAuthority"] = json!(owner);
+            info["signers"] = json!(signers);
+        }
+        [] => unreachable!("the authority is checked for above"),
+    }
+    Some(json!({ "type": kind, "info": info }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MintInfo;
+    use crate::model::Direction;
+    use crate::parser::{extract_transfers, WalletContext};
+    use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+    use solana_sdk::instruction::CompiledInstruction;
+    use solana_sdk::message::v0::LoadedAddresses;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_transaction_status::{
+        ConfirmedTransactionWithStatusMeta, InnerInstruction, InnerInstructions,
+        TransactionBinaryEncoding, TransactionStatusMeta, TransactionWithStatusMeta,
+        UiTransactionEncoding, VersionedTransactionWithStatusMeta,
+    };
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    const WALLET: &str = "7cMEhpt9y3inBNVv8fNnuaEbx7hKHZnLvR1KWKKxuDDU";
+    const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+    const COUNTERPARTY_TOKEN_ACCOUNT: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+
+    /// A v0 transaction in which `WALLET` sends 1.5 USDC from its associated
+    /// token account with a plain `transfer`, followed by a memo. The
+    /// counterparty's token account and the mint come from a lookup table.
+    const FIXTURE: &str = "AQAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACAAQACBGI0VMM1PFLxxeB3zwCWKvUD/kgy/7iy+Yv3fTwni69TQBNlJEnNiF0asjV93qsa8k+eaYhe9PCiocRTUh+lJMwG3fbh12Whk9nL4UbO63msHLSF7V9bN5E6jPWFfv8AqQVKU1qZKSEGTSTocWDaOHx8NbXdvJK7geQfqEBBBUSNAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACAgMBBAAJA2DjFgAAAAAAAwAJaW52b2ljZSA3AQJ3pq+XM5t6yI0YkskERvUAAjCSZvYuU8EYJEmCAAAAAQABAQ==";
+
+    /// The fixture as the RPC returns it in `encoding`, with a CPI of the
+    /// transfer paying 0.25 USDC back through `transferChecked`.
+    fn fetched(encoding: UiTransactionEncoding) -> EncodedConfirmedTransactionWithStatusMeta {
+        let transaction =
+            EncodedTransaction::Binary(FIXTURE.to_string(), TransactionBinaryEncoding::Base64)
+                .decode()
+                .unwrap();
+        let mut data = vec![TRANSFER_CHECKED];
+        data.extend(250_000u64.to_le_bytes());
+        data.push(6);
+        let meta = TransactionStatusMeta {
+            inner_instructions: Some(vec![InnerInstructions {
+                index: 0,
+                instructions: vec![InnerInstruction {
+                    instruction: CompiledInstruction {
+                        program_id_index: 2,
+                        accounts: vec![4, 5, 1, 0],
+                        data,
+                    },
+                    stack_height: Some(2),
+                }],
+            }]),
+            loaded_addresses: LoadedAddresses {
+                writable: vec![Pubkey::from_str(COUNTERPARTY_TOKEN_ACCOUNT).unwrap()],
+                readonly: vec![Pubkey::from_str(USDC).unwrap()],
+            },
+            ..Default::default()
+        };
+        ConfirmedTransactionWithStatusMeta {
+            slot: 42,
+            tx_with_meta: TransactionWithStatusMeta::Complete(VersionedTransactionWithStatusMeta {
+                transaction,
+                meta,
+            }),
+            block_time: Some(1_700_000_000),
+        }
+        .encode(encoding, Some(0))
+        .unwrap()
+    }
+
+    #[test]
+    fn decodes_binary_and_raw_transactions_like_json_parsed() {
+        let usdc = MintInfo {
+            mint: Pubkey::from_str(USDC).unwrap(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        };
+        let wallet = WalletContext::new(
+            &Pubkey::from_str(WALLET).unwrap(),
+            &usdc,
+            &TokenProgram::ALL,
+        );
+        let sig_info = RpcConfirmedTransactionStatusWithSignature {
+            signature: "sig".to_string(),
+            slot: 42,
+            err: None,
+            memo: None,
+            block_time: Some(1_700_000_000),
+            confirmation_status: None,
+        };
+        let extract = |tx: &EncodedConfirmedTransactionWithStatusMeta| {
+            extract_transfers(
+                tx,
+                &sig_info,
+                1_700_000_000,
+                &wallet,
+                &usdc,
+                &HashMap::new(),
+            )
+        };
+
+        let parsed = fetched(UiTransactionEncoding::JsonParsed);
+        assert!(json_parsed(&parsed, &TokenProgram::ALL).is_none());
+        let expected = extract(&parsed).unwrap();
+        assert_eq!(expected.len(), 2);
+        assert_eq!(expected[0].direction, Direction::Sent);
+        assert_eq!(expected[0].amount_raw, 1_500_000);
+        assert_eq!(expected[0].destination, COUNTERPARTY_TOKEN_ACCOUNT);
+        assert_eq!(expected[0].memo.as_deref(), Some("invoice 7"));
+        assert_eq!(expected[1].direction, Direction::Received);
+        assert_eq!(expected[1].amount_raw, 250_000);
+        assert_eq!(expected[1].inner_index, Some(0));
+
+        for encoding in [
+            UiTransactionEncoding::Base64,
+            UiTransactionEncoding::Base58,
+            UiTransactionEncoding::Json,
+        ] {
+            let tx = fetched(encoding);
+            assert!(extract(&tx).is_none(), "{:?}", encoding);
+            let decoded = json_parsed(&tx, &TokenProgram::ALL).unwrap();
+            assert_eq!(
+                serde_json::to_value(extract(&decoded).unwrap()).unwrap(),
+                serde_json::to_value(&expected).unwrap(),
+                "{:?}",
+                encoding
+            );
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! What a backfill left out and why. Every signature or instruction the
+//! indexer gives up on is counted here under a reason, with a few example
+//! signatures to look up, so a provider quietly changing its response shape
+//! shows up in the `/backfill` body instead of as missing transfers. The
+//! same skips are counted in the metrics.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+/// Example signatures kept per reason.
+pub const MAX_EXAMPLES: usize = 5;
+
+/// A listed signature had no block time, not even by slot.
+pub const MISSING_BLOCK_TIME: &str = "missing_block_time";
+/// `getTransaction` failed after its retries.
+pub const FETCH_FAILED: &str = "fetch_failed";
+/// The transaction came back in a shape that couldn't be turned into
+/// parsed instructions.
+pub const UNSUPPORTED_ENCODING: &str = "unsupported_encoding";
+/// The balance strategy needs `meta`, and there was none.
+pub const MISSING_META: &str = "missing_meta";
+/// A token instruction's parsed `info` lacked a field.
+pub const MISSING_INFO: &str = "missing_info";
+pub const UNPARSABLE_AMOUNT: &str = "unparsable_amount";
+/// A plain `transfer` whose mint neither balances nor lookups revealed.
+pub const UNRESOLVED_MINT: &str = "unresolved_mint";
+
+tokio::task_local! {
+    static CURRENT: Arc<Mutex<Diagnostics>>;
+}
+
+/// Skips of one reason.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SkipCount {
+    pub count: u64,
+    /// Up to [`MAX_EXAMPLES`] signatures, first seen first.
+    pub examples: Vec<String>,
+}
+
+/// Skip reason → what was skipped for it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(transparent)]
+pub struct Diagnostics(pub BTreeMap<String, SkipCount>);
+
+impl Diagnostics {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn add(&mut self, reason: &str, count: u64, examples: impl IntoIterator<Item = String>) {
+        let skipped = self.0.entry(reason.to_string()).or_default();
+        skipped.count += count;
+        for example in examples {
+            if skipped.examples.len() >= MAX_EXAMPLES {
+                break;
+            }
+            if !skipped.examples.contains(&example) {
+                skipped.examples.push(example);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: Diagnostics) {
+        for (reason, skipped) in other.0 {
+            self.add(&reason, skipped.count, skipped.examples);
+        }
+    }
+}
+
+/// Runs `work`, collecting what [`record`] is told inside it.
+pub async fn collect<F: Future>(work: F) -> (F::Output, Diagnostics) {
+    let diagnostics = Arc::new(Mutex::new(Diagnostics::default()));
+    let output = CURRENT.scope(diagnostics.clone(), work).await;
+    let collected = std::mem::take(&mut *diagnostics.lock().unwrap());
+    (output, collected)
+}
+
+/// Counts one skip of `signature` for `reason` towards the surrounding
+/// [`collect`], if any.
+pub fn record(reason: &str, signature: &str) {
+    let _ = CURRENT.try_with(|diagnostics| {
+        diagnostics
+            .lock()
+            .unwrap()
+            .add(reason, 1, [signature.to_string()]);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn counts_every_skip_but_keeps_a_few_examples() {
+        let ((), diagnostics) = collect(async {
+            for n in 0..MAX_EXAMPLES + 2 {
+                record(UNPARSABLE_AMOUNT, &format!("sig-{}", n));
+            }
+            record(UNPARSABLE_AMOUNT, "sig-0");
+            record(FETCH_FAILED, "sig-9");
+        })
+        .await;
+        let unparsable = &diagnostics.0[UNPARSABLE_AMOUNT];
+        assert_eq!(unparsable.count, MAX_EXAMPLES as u64 + 3);
+        assert_eq!(unparsable.examples.len(), MAX_EXAMPLES);
+        assert_eq!(unparsable.examples[0], "sig-0");
+        assert_eq!(diagnostics.0[FETCH_FAILED].examples, ["sig-9"]);
+
+        // Outside a collection nothing is kept.
+        record(FETCH_FAILED, "sig-10");
+        let mut merged = Diagnostics::default();
+        merged.merge(diagnostics);
+        assert_eq!(merged.0[FETCH_FAILED].count, 1);
+    }
+}
@@ -0,0 +1,365 @@
+//! Posts new transfers to the Discord channel webhooks configured under
+//! `[[discord]]`. Transfers that arrive together are batched, one embed per
+//! slot, so a busy wallet stays under Discord's per-webhook rate limit.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::config::{redact_url, Config};
+use crate::events::{self, TransferEvent};
+use crate::metrics::METRICS;
+use crate::model::Direction;
+use crate::webhook::WebhookFilter;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Total tries per message, the first one included.
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_MIN_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound on a `retry_after` Discord asks us to wait.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+/// How long to keep collecting events after the first one of a batch.
+const BATCH_WINDOW: Duration = Duration::from_secs(2);
+const MAX_BATCH_EVENTS: usize = 250;
+/// Discord's limits on embeds per message and fields per embed.
+const MAX_EMBEDS_PER_MESSAGE: usize = 10;
+const MAX_FIELDS_PER_EMBED: usize = 25;
+
+/// One `[[discord]]` entry.
+#[derive(Debug, Clone)]
+pub struct DiscordTarget {
+    pub url: String,
+    pub filter: WebhookFilter,
+}
+
+/// Embed accent for a direction.
+fn color(direction: Direction) -> u32 {
+    match direction {
+        Direction::Received => 0x2ecc71,
+        Direction::Sent => 0xe74c3c,
+        Direction::Internal => 0x95a5a6,
+        Direction::Minted => 0x3498db,
+        Direction::Burned => 0xe67e22,
+    }
+}
+
+/// The message bodies to post for `events`: one embed per slot, listing
+/// every transfer of that slot, split to fit Discord's limits.
+fn messages(events: &[&TransferEvent], config: &Config) -> Vec<Value> {
+    let mut embeds = Vec::new();
+    let mut rest = events;
+    while let Some(first) = rest.first() {
+        let same_slot = rest
+            .iter()
+            .take_while(|e| e.transfer.slot == first.transfer.slot)
+            .count()
+            .min(MAX_FIELDS_PER_EMBED);
+        let (group, tail) = rest.split_at(same_slot);
+        embeds.push(embed(group, config));
+        rest = tail;
+    }
+    embeds
+        .chunks(MAX_EMBEDS_PER_MESSAGE)
+        .map(|chunk| json!({ "embeds": chunk }))
+        .collect()
+}
+
+fn embed(group: &[&TransferEvent], config: &Config) -> Value {
+    let first = group[0];
+    let url = config.explorer_link(&first.transfer.signature);
+    if let [event] = group {
+        let (counterparty, _) = event.transfer.counterparty_address();
+        return json!({
+            "title": event.headline(),
+            "url": url,
+            "color": color(event.transfer.direction),
+            "timestamp": event.transfer.timestamp_rfc3339(),
+            "fields": [
+                { "name": event.counterparty_label(), "value": counterparty },
+                { "name": "Wallet", "value": event.wallet.to_string() },
+            ],
+        });
+    }
+
+    let direction = first.transfer.direction;
+    let accent = if group.iter().all(|e| e.transfer.direction == direction) {
+        color(direction)
+    } else {
+        color(Direction::Internal)
+    };
+    let fields: Vec<Value> = group
+        .iter()
+        .map(|event| {
+            let (counterparty, _) = event.transfer.counterparty_address();
+            json!({
+                "name": event.headline(),
+                "value": format!(
+                    "{}: {}\n[Transaction]({})",
+                    event.counterparty_label(),
+                    counterparty,
+                    config.explorer_link(&event.transfer.signature)
+                ),
+            })
+        })
+        .collect();
+    json!({
+        "title": format!("{} transfers in slot {}", group.len(), first.transfer.slot),
+        "color": accent,
+        "timestamp": first.transfer.timestamp_rfc3339(),
+        "fields": fields,
+    })
+}
+
+/// Starts the poster if any Discord webhook is configured. It stops at
+/// `shutdown`, dropping the batch being sent, if any.
+pub fn spawn(config: Arc<Config>, shutdown: watch::Receiver<bool>) -> Option<JoinHandle<()>> {
+    if config.discord.is_empty() {
+        return None;
+    }
+    Some(tokio::spawn(run(config, shutdown)))
+}
+
+async fn run(config: Arc<Config>, mut shutdown: watch::Receiver<bool>) {
+    let client = match reqwest::Client::builder().timeout(DELIVERY_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!(error = %e, "cannot build Discord client, Discord posts disabled");
+            return;
+        }
+    };
+    let mut feed = events::subscribe();
+    info!(targets = config.discord.len(), "Discord poster started");
+    loop {
+        let first = tokio::select! {
+            event = feed.recv() => event,
+            _ = shutdown.changed() => return,
+        };
+        let mut batch = Vec::new();
+        let mut received = Some(first);
+        let window = tokio::time::sleep(BATCH_WINDOW);
+        tokio::pin!(window);
+        loop {
+            match received.take() {
+                Some(Ok(event)) => batch.push(event),
+                Some(Err(RecvError::Lagged(missed))) => {
+                    warn!(missed, "Discord poster fell behind, events dropped");
+                }
+                Some(Err(RecvError::Closed)) => return,
+                None => {}
+            }
+            if batch.len() >= MAX_BATCH_EVENTS {
+                break;
+            }
+            received = tokio::select! {
+                event = feed.recv() => Some(event),
+                _ = &mut window => break,
+                _ = shutdown.changed() => return,
+            };
+        }
+
+        for target in &config.discord {
+            let matching: Vec<&TransferEvent> =
+                batch.iter().filter(|e| target.filter.matches(e)).collect();
+            if matching.is_empty() {
+                continue;
+            }
+            let host = redact_url(&target.url);
+            for body in messages(&matching, &config) {
+                tokio::select! {
+                    _ = deliver(&client, &target.url, &host, &body) => {}
+                    _ = shutdown.changed() => return,
+                }
+            }
+        }
+    }
+}
+
+/// Posts `body` until Discord accepts it or the attempts run out.
+async fn deliver(client: &reqwest::Client, url: &str, host: &str, body: &Value) {
+    let mut delay = RETRY_MIN_DELAY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        match post(client, url, body).await {
+            Ok(()) => {
+                info!(target = %host, attempt, "Discord message posted");
+                METRICS
+                    .discord_messages
+                    .with_label_values(&[host, "delivered"])
+                    .inc();
+                return;
+            }
+            Err((e, retry_after)) => {
+                if let Some(retry_after) = retry_after {
+                    delay = retry_after.min(RETRY_MAX_DELAY);
+                }
+                warn!(
+                    target = %host,
+                    attempt,
+                    ?delay,
+                    error = %e,
+                    "Discord message failed"
+                );
+                METRICS
+                    .discord_messages
+                    .with_label_values(&[host, "failed"])
+                    .inc();
+            }
+        }
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(RETRY_MAX_DELAY);
+        }
+    }
+    error!(
+        target = %host,
+        attempts = MAX_ATTEMPTS,
+        "giving up on Discord message"
+    );
+    METRICS
+        .discord_messages
+        .with_label_values(&[host, "abandoned"])
+        .inc();
+}
+
+/// Body of a 429 reply.
+#[derive(Debug, Deserialize)]
+struct RateLimited {
+    retry_after: f64,
+}
+
+/// Posts an alert once, as a red embed; see [`crate::alerts`].
+pub(crate) async fn post_alert(
+    client: &reqwest::Client,
+    url: &str,
+    text: &str,
+) -> anyhow::Result<()> {
+    let (title, description) = text.split_once('\n').unwrap_or((text, ""));
+    let body = json!({
+        "embeds": [{ "title": title, "description": description, "color": 0xe74c3c }],
+    });
+    post(client, url, &body).await.map_err(|(e, _)| e)
+}
+
+/// Posts a scheduled report once, `text` as the message and the CSV
+/// attached; see [`crate::reports`].
+pub(crate) async fn post_file(
+    client: &reqwest::Client,
+    url: &str,
+    text: &str,
+    file_name: &str,
+    contents: Vec<u8>,
+) -> anyhow::Result<()> {
+    let file = reqwest::multipart::Part::bytes(contents)
+        .file_name(file_name.to_string())
+        .mime_str("text/csv")?;
+    let form = reqwest::multipart::Form::new()
+        .text("payload_json", json!({ "content": text }).to_string())
+        .part("files[0]", file);
+    let response = client
+        .post(url)
+        .multipart(form)
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("HTTP {}", status);
+    }
+    Ok(())
+}
+
+/// On failure, also returns how long Discord asked us to back off.
+async fn post(
+    client: &reqwest::Client,
+    url: &str,
+    body: &Value,
+) -> Result<(), (anyhow::Error, Option<Duration>)> {
+    // The webhook token is part of the URL, which reqwest errors would print.
+    let response = client
+        .post(url)
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| (e.without_url().into(), None))?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let retry_after = if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        response
+            .json::<RateLimited>()
+            .await
+            .ok()
+            .map(|r| Duration::from_secs_f64(r.retry_after.max(0.0)))
+    } else {
+        None
+    };
+    Err((anyhow::anyhow!("HTTP {}", status), retry_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::transfer;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn event(direction: Direction, amount_raw: u64, slot: u64) -> TransferEvent {
+        let config = Config::default();
+        let mut transfer = transfer(direction, amount_raw, "alice");
+        transfer.slot = slot;
+        TransferEvent {
+            id: 0,
+            wallet: Pubkey::default(),
+            mint: config.mints.mints[0].clone(),
+            transfer,
+        }
+    }
+
+    #[test]
+    fn posts_one_embed_per_transfer_or_slot() {
+        let config = Config::default();
+        let single = event(Direction::Received, 1_500_000, 7);
+        let body = &messages(&[&single], &config)[0];
+        let embed = &body["embeds"][0];
+        assert_eq!(embed["title"], "Received 1.500000 USDC");
+        assert_eq!(embed["color"], 0x2ecc71);
+        assert_eq!(embed["url"], config.explorer_link("sig-1500000"));
+        assert_eq!(embed["fields"][0]["name"], "From");
+        assert_eq!(embed["fields"][0]["value"], "alice");
+
+        let sent: Vec<TransferEvent> = (1..=3).map(|n| event(Direction::Sent, n, 8)).collect();
+        let mut batch: Vec<&TransferEvent> = sent.iter().collect();
+        batch.push(&single);
+        let bodies = messages(&batch, &config);
+        assert_eq!(bodies.len(), 1);
+        let embeds = bodies[0]["embeds"].as_array().unwrap();
+        assert_eq!(embeds.len(), 2);
+        assert_eq!(embeds[0]["title"], "3 transfers in slot 8");
+        assert_eq!(embeds[0]["color"], 0xe74c3c);
+        assert_eq!(embeds[0]["fields"].as_array().unwrap().len(), 3);
+        assert_eq!(embeds[1]["title"], "Received 1.500000 USDC");
+    }
+
+    #[test]
+    fn splits_large_batches_to_fit_discord_limits() {
+        let config = Config::default();
+        let events: Vec<TransferEvent> = (0..30)
+            .map(|n| event(Direction::Received, n + 1, 1))
+            .chain((0..11).map(|n| event(Direction::Sent, 1, 100 + n)))
+            .collect();
+        let batch: Vec<&TransferEvent> = events.iter().collect();
+        let bodies = messages(&batch, &config);
+        // 25 + 5 fields for slot 1, then one embed each for 11 slots.
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0]["embeds"].as_array().unwrap().len(), 10);
+        assert_eq!(bodies[1]["embeds"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            bodies[0]["embeds"][0]["fields"].as_array().unwrap().len(),
+            25
+        );
+    }
+}
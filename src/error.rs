@@ -0,0 +1,359 @@
+//! Typed failures surfaced to API clients, and how they map onto HTTP.
+
+use serde::Serialize;
+use solana_client::client_error::ClientError;
+use std::fmt;
+use std::time::Duration;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+
+use crate::jobs::ProgressSnapshot;
+use crate::rpc::{classify_rpc_error, RpcFailure, RETRY_MAX_DELAY};
+
+#[derive(Debug, thiserror::Error)]
+pub enum IndexerError {
+    #[error("invalid address '{address}': {reason}")]
+    InvalidAddress { address: String, reason: String },
+    #[error("{0}")]
+    InvalidWindow(String),
+    /// Any other malformed or contradictory query parameter.
+    #[error("{0}")]
+    InvalidParameter(String),
+    #[error("{0}")]
+    NotFound(String),
+    /// The `Accept` header allows none of the endpoint's formats.
+    #[error("{0}")]
+    NotAcceptable(String),
+    /// The transaction exists but moved none of the tracked mints for the
+    /// requested wallets.
+    #[error("{0}")]
+    NoRelevantTransfers(String),
+    /// The stored history doesn't reach far enough, or has a gap, for the
+    /// answer to be right.
+    #[error("{0}")]
+    HistoryIncomplete(String),
+    /// Missing or wrong admin credentials.
+    #[error("{0}")]
+    Unauthorized(String),
+    /// The client's address is outside the IP allowlist.
+    #[error("{0}")]
+    Forbidden(String),
+    /// The service is at capacity for this kind of request.
+    #[error("{0}")]
+    Overloaded(String),
+    /// This client sent more requests than its rate allows.
+    #[error("too many requests, retry in {}s", retry_after.as_secs())]
+    RateLimited { retry_after: Duration },
+    /// Every backfill slot is taken.
+    #[error("too many backfills are running, retry in {}s", retry_after.as_secs())]
+    BackfillsBusy { retry_after: Duration },
+    #[error("the RPC provider is rate limiting requests")]
+    RpcRateLimited { retry_after: Duration },
+    /// The request ran past `request_timeout`; `progress` is how far the
+    /// scan got before it was cancelled.
+    #[error(
+        "gave up after {}s with {} signatures scanned and {} transfers found; narrow the window or submit a job",
+        after.as_secs(),
+        progress.signatures_scanned,
+        progress.transfers_found
+    )]
+    Timeout {
+        after: Duration,
+        progress: ProgressSnapshot,
+    },
+    /// The work was cancelled, because its client went away or its job was
+    /// cancelled, before it finished.
+    #[error("cancelled")]
+    Cancelled,
+    /// Timeouts, connection failures and unhealthy-node responses that
+    /// outlasted the retry budget.
+    #[error("the RPC provider is unavailable: {0}")]
+    RpcUnavailable(String),
+    /// The RPC answered, but with an error retrying won't fix.
+    #[error("the RPC provider returned an error: {0}")]
+    Rpc(String),
+    /// The S3-compatible bucket refused or failed an upload.
+    #[error("the object store failed: {0}")]
+    ObjectStore(String),
+    #[error("could not decode {0}")]
+    Decode(String),
+    #[error("transfer store error: {0}")]
+    Store(String),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl IndexerError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            IndexerError::InvalidAddress { .. }
+            | IndexerError::InvalidWindow(_)
+            | IndexerError::InvalidParameter(_) => StatusCode::BAD_REQUEST,
+            IndexerError::NotFound(_) => StatusCode::NOT_FOUND,
+            IndexerError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            IndexerError::NoRelevantTransfers(_) | IndexerError::HistoryIncomplete(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            IndexerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            IndexerError::Forbidden(_) => StatusCode::FORBIDDEN,
+            IndexerError::RateLimited { .. }
+            | IndexerError::BackfillsBusy { .. }
+            | IndexerError::RpcRateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            IndexerError::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+            IndexerError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            // What nginx logs for a client that closed the connection; only
+            // ever seen in logs and metrics, as nobody is left to send it to.
+            IndexerError::Cancelled => {
+                StatusCode::from_u16(499).expect("499 is a valid status code")
+            }
+            IndexerError::RpcUnavailable(_)
+            | IndexerError::Rpc(_)
+            | IndexerError::ObjectStore(_) => StatusCode::BAD_GATEWAY,
+            IndexerError::Decode(_) | IndexerError::Store(_) | IndexerError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Stable machine-readable identifier for the `code` field of error bodies.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            IndexerError::InvalidAddress { .. } => ErrorCode::InvalidAddress,
+            IndexerError::InvalidWindow(_) => ErrorCode::InvalidWindow,
+            IndexerError::InvalidParameter(_) => ErrorCode::InvalidParameter,
+            IndexerError::NotFound(_) => ErrorCode::NotFound,
+            IndexerError::NotAcceptable(_) => ErrorCode::NotAcceptable,
+            IndexerError::NoRelevantTransfers(_) => ErrorCode::NoRelevantTransfers,
+            IndexerError::HistoryIncomplete(_) => ErrorCode::HistoryIncomplete,
+            IndexerError::Unauthorized(_) => ErrorCode::Unauthorized,
+            IndexerError::Forbidden(_) => ErrorCode::Forbidden,
+            IndexerError::Overloaded(_) => ErrorCode::Overloaded,
+            IndexerError::RateLimited { .. } => ErrorCode::RateLimited,
+            IndexerError::BackfillsBusy { .. } => ErrorCode::TooManyBackfills,
+            IndexerError::RpcRateLimited { .. } => ErrorCode::RpcRateLimited,
+            IndexerError::Timeout { .. } => ErrorCode::Timeout,
+            IndexerError::Cancelled => ErrorCode::Cancelled,
+            IndexerError::RpcUnavailable(_) => ErrorCode::RpcUnavailable,
+            IndexerError::Rpc(_) => ErrorCode::RpcError,
+            IndexerError::ObjectStore(_) => ErrorCode::ObjectStoreError,
+            IndexerError::Decode(_) => ErrorCode::DecodeError,
+            IndexerError::Store(_) => ErrorCode::StoreError,
+            IndexerError::Internal(_) => ErrorCode::InternalError,
+        }
+    }
+
+    /// The message clients see. Failures of the RPC provider, the store or
+    /// the bucket say what failed but not how: their details can name RPC
+    /// URLs with keys in them, hosts and paths, and belong in the logs.
+    pub fn public_message(&self) -> String {
+        match self {
+            IndexerError::RpcUnavailable(_) => "the RPC provider is unavailable".to_string(),
+            IndexerError::Rpc(_) => "the RPC provider returned an error".to_string(),
+            IndexerError::ObjectStore(_) => "the object store failed".to_string(),
+            IndexerError::Store(_) => "the transfer store failed".to_string(),
+            IndexerError::Internal(_) => "internal error".to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl IndexerError {
+    /// How long the client should wait before trying again, for the
+    /// `Retry-After` header.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            IndexerError::RateLimited { retry_after }
+            | IndexerError::BackfillsBusy { retry_after }
+            | IndexerError::RpcRateLimited { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// How far a timed-out scan got, for the error body.
+    pub fn progress(&self) -> Option<ProgressSnapshot> {
+        match self {
+            IndexerError::Timeout { progress, .. } => Some(*progress),
+            _ => None,
+        }
+    }
+}
+
+impl warp::reject::Reject for IndexerError {}
+
+/// The `code` of an error body. Serialized in `SCREAMING_SNAKE_CASE` and
+/// listed in the OpenAPI spec, so clients can match on it; a code, once
+/// shipped, keeps its meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    InvalidAddress,
+    InvalidWindow,
+    InvalidParameter,
+    /// A header is missing or malformed.
+    InvalidHeader,
+    /// A JSON request body doesn't match the endpoint's schema.
+    InvalidBody,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    NoRelevantTransfers,
+    HistoryIncomplete,
+    Unauthorized,
+    /// The client's address is outside the IP allowlist.
+    Forbidden,
+    CorsForbidden,
+    Overloaded,
+    RateLimited,
+    TooManyBackfills,
+    RpcRateLimited,
+    Timeout,
+    Cancelled,
+    RpcUnavailable,
+    RpcError,
+    ObjectStoreError,
+    DecodeError,
+    StoreError,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidAddress => "INVALID_ADDRESS",
+            ErrorCode::InvalidWindow => "INVALID_WINDOW",
+            ErrorCode::InvalidParameter => "INVALID_PARAMETER",
+            ErrorCode::InvalidHeader => "INVALID_HEADER",
+            ErrorCode::InvalidBody => "INVALID_BODY",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            ErrorCode::NotAcceptable => "NOT_ACCEPTABLE",
+            ErrorCode::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ErrorCode::UnsupportedMediaType => "UNSUPPORTED_MEDIA_TYPE",
+            ErrorCode::NoRelevantTransfers => "NO_RELEVANT_TRANSFERS",
+            ErrorCode::HistoryIncomplete => "HISTORY_INCOMPLETE",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::CorsForbidden => "CORS_FORBIDDEN",
+            ErrorCode::Overloaded => "OVERLOADED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::TooManyBackfills => "TOO_MANY_BACKFILLS",
+            ErrorCode::RpcRateLimited => "RPC_RATE_LIMITED",
+            ErrorCode::Timeout => "TIMEOUT",
+            ErrorCode::Cancelled => "CANCELLED",
+            ErrorCode::RpcUnavailable => "RPC_UNAVAILABLE",
+            ErrorCode::RpcError => "RPC_ERROR",
+            ErrorCode::ObjectStoreError => "OBJECT_STORE_ERROR",
+            ErrorCode::DecodeError => "DECODE_ERROR",
+            ErrorCode::StoreError => "STORE_ERROR",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+        }
+    }
+
+    /// Whether the same request may succeed later unchanged: capacity and
+    /// rate limits, and an RPC provider that's down for now.
+    pub fn retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::Overloaded
+                | ErrorCode::RateLimited
+                | ErrorCode::TooManyBackfills
+                | ErrorCode::RpcRateLimited
+                | ErrorCode::RpcUnavailable
+        )
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<ClientError> for IndexerError {
+    fn from(err: ClientError) -> Self {
+        match classify_rpc_error(&err) {
+            RpcFailure::RateLimited => IndexerError::RpcRateLimited {
+                retry_after: RETRY_MAX_DELAY,
+            },
+            RpcFailure::Transient => IndexerError::RpcUnavailable(err.to_string()),
+            RpcFailure::Permanent => IndexerError::Rpc(err.to_string()),
+        }
+    }
+}
+
+/// Recovers the typed error from the `anyhow` chains the stores return.
+impl From<anyhow::Error> for IndexerError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<IndexerError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<ClientError>() {
+            Ok(err) => return err.into(),
+            Err(err) => err,
+        };
+        if err.is::<sqlx::Error>() || err.is::<std::io::Error>() || err.is::<serde_json::Error>() {
+            IndexerError::Store(err.to_string())
+        } else {
+            IndexerError::Internal(err.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::client_error::ClientErrorKind;
+    use solana_client::rpc_request::{RpcError, RpcResponseErrorData};
+
+    fn rpc_error(code: i64, message: &str) -> ClientError {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            code,
+            message: message.to_string(),
+            data: RpcResponseErrorData::Empty,
+        })
+        .into()
+    }
+
+    #[test]
+    fn maps_rpc_failures_to_gateway_statuses() {
+        let throttled = IndexerError::from(rpc_error(429, "Too Many Requests"));
+        assert_eq!(throttled.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(matches!(throttled, IndexerError::RpcRateLimited { .. }));
+
+        let rejected = IndexerError::from(rpc_error(-32602, "Invalid params"));
+        assert_eq!(rejected.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(rejected.code(), ErrorCode::RpcError);
+        assert!(!rejected.code().retryable());
+    }
+
+    #[test]
+    fn codes_serialize_as_they_print() {
+        for code in [ErrorCode::RpcRateLimited, ErrorCode::TooManyBackfills] {
+            assert_eq!(
+                serde_json::to_value(code).unwrap(),
+                serde_json::json!(code.as_str())
+            );
+        }
+        assert_eq!(ErrorCode::RpcRateLimited.as_str(), "RPC_RATE_LIMITED");
+    }
+
+    #[test]
+    fn keeps_provider_details_out_of_public_messages() {
+        let err = IndexerError::RpcUnavailable("https://rpc.example/?api-key=secret".to_string());
+        assert!(!err.public_message().contains("secret"));
+        let err = IndexerError::InvalidWindow("start after end".to_string());
+        assert_eq!(err.public_message(), "start after end");
+    }
+
+    #[test]
+    fn recovers_typed_errors_from_anyhow() {
+        let err = anyhow::Error::from(IndexerError::InvalidWindow("bad".to_string()));
+        assert_eq!(IndexerError::from(err).code(), ErrorCode::InvalidWindow);
+
+        let err = anyhow::Error::from(rpc_error(429, "Too Many Requests"));
+        assert_eq!(IndexerError::from(err).code(), ErrorCode::RpcRateLimited);
+    }
+}
@@ -0,0 +1,118 @@
+//! Strong `ETag`s for the data routes and `304 Not Modified` answers to a
+//! matching `If-None-Match`. The tag is a digest of the body, so it changes
+//! exactly when the rendered result set does; with the response cache in
+//! front, a dashboard re-polling an unchanged window costs a hash and a few
+//! bytes. Streamed bodies aren't tagged.
+
+use sha2::{Digest, Sha256};
+use warp::http::header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG};
+use warp::http::{HeaderValue, StatusCode};
+use warp::hyper::body::HttpBody;
+use warp::hyper::Body;
+use warp::reply::Response;
+
+use crate::compression::Encoding;
+
+/// The tag of a body.
+fn etag_for(body: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(body)[..16]))
+}
+
+/// Whether `if_none_match` lists `etag`, or the tag of one of its encoded
+/// variants. The comparison is weak, as RFC 9110 asks for this header.
+fn matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = etag.trim_matches('"');
+    if_none_match.split(',').any(|candidate| {
+        let candidate = candidate.trim();
+        let candidate = candidate.strip_prefix("W/").unwrap_or(candidate);
+        candidate == "*"
+            || candidate == etag
+            || [Encoding::Gzip, Encoding::Deflate]
+                .iter()
+                .any(|encoding| candidate == encoded(opaque, *encoding))
+    })
+}
+
+/// The tag of the `encoding`-encoded representation of a body tagged
+/// `opaque`; a strong tag must differ between the two.
+fn encoded(opaque: &str, encoding: Encoding) -> String {
+    format!("\"{}-{}\"", opaque, encoding.as_str())
+}
+
+/// Rewrites a response's `ETag` for its `encoding`-encoded body.
+pub(crate) fn for_encoding(etag: &HeaderValue, encoding: Encoding) -> Option<HeaderValue> {
+    let opaque = etag.to_str().ok()?.trim_matches('"');
+    HeaderValue::from_str(&encoded(opaque, encoding)).ok()
+}
+
+/// Tags a `200` with a buffered body, and turns it into a bodiless `304`
+/// when `if_none_match` already has that tag.
+pub async fn tag(response: Response, if_none_match: Option<&str>) -> Response {
+    if response.status() != StatusCode::OK
+        || response.headers().contains_key(ETAG)
+        || response.body().size_hint().exact().is_none()
+    {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Buffered already, so this only gathers the chunks.
+    let Ok(body) = warp::hyper::body::to_bytes(body).await else {
+        parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+        return Response::from_parts(parts, Body::empty());
+    };
+    let etag = etag_for(&body);
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        parts.headers.insert(ETAG, value);
+    }
+    if if_none_match.is_some_and(|header| matches(header, &etag)) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.remove(CONTENT_TYPE);
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_a_matching_if_none_match_with_304() {
+        let fresh = tag(Response::new(Body::from("[1,2,3]")), None).await;
+        assert_eq!(fresh.status(), StatusCode::OK);
+        let etag = fresh.headers()[ETAG].to_str().unwrap().to_string();
+
+        let unchanged = tag(Response::new(Body::from("[1,2,3]")), Some(&etag)).await;
+        assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(unchanged.headers()[ETAG], etag.as_str());
+        let body = warp::hyper::body::to_bytes(unchanged.into_body())
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        let changed = tag(Response::new(Body::from("[1,2,3,4]")), Some(&etag)).await;
+        assert_eq!(changed.status(), StatusCode::OK);
+
+        let gzip_tag = for_encoding(&HeaderValue::from_str(&etag).unwrap(), Encoding::Gzip)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_ne!(gzip_tag, etag);
+        let header = format!("\"other\", W/{}", gzip_tag);
+        let unchanged = tag(Response::new(Body::from("[1,2,3]")), Some(&header)).await;
+        assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn leaves_streams_and_errors_untagged() {
+        let (_sender, body) = Body::channel();
+        let streamed = tag(Response::new(body), None).await;
+        assert!(!streamed.headers().contains_key(ETAG));
+
+        let mut failed = Response::new(Body::from("{}"));
+        *failed.status_mut() = StatusCode::BAD_GATEWAY;
+        assert!(!tag(failed, None).await.headers().contains_key(ETAG));
+    }
+}
@@ -0,0 +1,187 @@
+//! In-process feed of transfers as the indexer discovers them, for
+//! consumers (webhooks and the like) that react to new activity rather than
+//! query history. Delegate approvals have a feed of their own, without
+//! replay, for the alert rules.
+
+use futures::{stream, Stream};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::VecDeque;
+use std::sync::{LazyLock, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use crate::config::MintInfo;
+use crate::model::{Approval, Direction, Transfer};
+
+/// Events a slow subscriber may fall behind by before it starts missing
+/// some (and is told how many via `RecvError::Lagged`).
+const FEED_CAPACITY: usize = 1024;
+
+/// Most recent events kept for subscribers catching up after a reconnect.
+const REPLAY_CAPACITY: usize = 1024;
+
+static NEW_TRANSFERS: LazyLock<broadcast::Sender<TransferEvent>> =
+    LazyLock::new(|| broadcast::channel(FEED_CAPACITY).0);
+
+static NEW_APPROVALS: LazyLock<broadcast::Sender<ApprovalEvent>> =
+    LazyLock::new(|| broadcast::channel(FEED_CAPACITY).0);
+
+/// Ids handed out so far and the events behind the newest of them. Sending
+/// happens under the same lock, so a replay and the live feed that follows
+/// it neither overlap nor leave a gap.
+static RECENT: LazyLock<Mutex<(u64, VecDeque<TransferEvent>)>> =
+    LazyLock::new(|| Mutex::new((0, VecDeque::with_capacity(REPLAY_CAPACITY))));
+
+/// A transfer that landed after the wallet/mint pair was first indexed.
+#[derive(Debug, Clone)]
+pub struct TransferEvent {
+    /// Increasing from 1 in publishing order; restarts with the process.
+    pub id: u64,
+    pub wallet: Pubkey,
+    pub mint: MintInfo,
+    pub transfer: Transfer,
+}
+
+impl TransferEvent {
+    /// `"Received 1.500000 USDC"`, the first line of a chat notification.
+    pub fn headline(&self) -> String {
+        let verb = match self.transfer.direction {
+            Direction::Received => "Received",
+            Direction::Sent => "Sent",
+            Direction::Internal => "Moved",
+            Direction::Minted => "Minted",
+            Direction::Burned => "Burned",
+        };
+        format!(
+            "{} {} {}",
+            verb, self.transfer.amount_ui, self.transfer.symbol
+        )
+    }
+
+    /// Label for [`Transfer::counterparty_address`] in a notification.
+    pub fn counterparty_label(&self) -> &'static str {
+        match self.transfer.direction {
+            Direction::Received => "From",
+            Direction::Sent | Direction::Internal => "To",
+            Direction::Minted | Direction::Burned => "Mint",
+        }
+    }
+}
+
+/// An approval or revoke that landed after the wallet/mint pair was first
+/// indexed.
+#[derive(Debug, Clone)]
+pub struct ApprovalEvent {
+    pub wallet: Pubkey,
+    pub mint: MintInfo,
+    pub approval: Approval,
+}
+
+/// Announces `approvals` to every current approval subscriber.
+pub fn publish_approvals(wallet: &Pubkey, mint: &MintInfo, approvals: &[Approval]) {
+    for approval in approvals {
+        let _ = NEW_APPROVALS.send(ApprovalEvent {
+            wallet: *wallet,
+            mint: mint.clone(),
+            approval: approval.clone(),
+        });
+    }
+}
+
+/// Receives every approval published from now on.
+pub fn subscribe_approvals() -> broadcast::Receiver<ApprovalEvent> {
+    NEW_APPROVALS.subscribe()
+}
+
+/// Announces `transfers` to every current subscriber. Without subscribers
+/// this is a no-op.
+pub fn publish(wallet: &Pubkey, mint: &MintInfo, transfers: &[Transfer]) {
+    let mut recent = RECENT.lock().expect("event feed lock poisoned");
+    let (last_id, events) = &mut *recent;
+    for transfer in transfers {
+        *last_id += 1;
+        let event = TransferEvent {
+            id: *last_id,
+            wallet: *wallet,
+            mint: mint.clone(),
+            transfer: transfer.clone(),
+        };
+        if events.len() == REPLAY_CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+        let _ = NEW_TRANSFERS.send(event);
+    }
+}
+
+/// Receives every event published from now on.
+pub fn subscribe() -> broadcast::Receiver<TransferEvent> {
+    NEW_TRANSFERS.subscribe()
+}
+
+/// `feed` as a stream that skips over what a slow consumer missed and ends
+/// when the feed closes.
+pub fn live(feed: broadcast::Receiver<TransferEvent>) -> impl Stream<Item = TransferEvent> {
+    stream::unfold(feed, |mut feed| async move {
+        loop {
+            match feed.recv().await {
+                Ok(event) => return Some((event, feed)),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "stream subscriber fell behind, events dropped");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+/// The buffered events after `last_id`, oldest first, and a receiver for
+/// everything published after them. Events older than the buffer are gone;
+/// the ids show the gap.
+pub fn subscribe_after(last_id: u64) -> (Vec<TransferEvent>, broadcast::Receiver<TransferEvent>) {
+    let recent = RECENT.lock().expect("event feed lock poisoned");
+    let missed = recent
+        .1
+        .iter()
+        .filter(|event| event.id > last_id)
+        .cloned()
+        .collect();
+    (missed, NEW_TRANSFERS.subscribe())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MintRegistry, DEFAULT_MINTS};
+    use crate::model::fixtures::transfer;
+
+    #[test]
+    fn replays_what_a_subscriber_missed_then_goes_live() {
+        let mint = MintRegistry::parse(DEFAULT_MINTS).unwrap().mints.remove(0);
+        // Other tests publish too, so only this wallet's events count.
+        let wallet = Pubkey::new_unique();
+        publish(&wallet, &mint, &[transfer(Direction::Received, 1, "alice")]);
+        let (seen, _) = subscribe_after(0);
+        let first = seen
+            .iter()
+            .rfind(|event| event.wallet == wallet)
+            .unwrap()
+            .id;
+
+        publish(&wallet, &mint, &[transfer(Direction::Sent, 2, "bob")]);
+        let (missed, mut live) = subscribe_after(first);
+        let missed: Vec<_> = missed.iter().filter(|e| e.wallet == wallet).collect();
+        assert_eq!(missed.len(), 1);
+        assert_eq!(missed[0].transfer.amount_raw, 2);
+        assert!(missed[0].id > first);
+
+        publish(&wallet, &mint, &[transfer(Direction::Sent, 3, "bob")]);
+        let next = loop {
+            let event = live.try_recv().unwrap();
+            if event.wallet == wallet {
+                break event;
+            }
+        };
+        assert_eq!(next.transfer.amount_raw, 3);
+    }
+}
@@ -0,0 +1,347 @@
+//! File exports of transfer rows for `/export` and `/export/s3`. A file is
+//! written a chunk of rows at a time and handed over as it grows, so a long
+//! window never has to fit in memory: Parquet gets a row group per chunk,
+//! CSV its header before the first.
+
+use anyhow::Result;
+use arrow_array::{ArrayRef, Decimal128Array, Int64Array, RecordBatch, StringArray};
+use arrow_array::{TimestampSecondArray, UInt64Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, GzipLevel};
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::model::Transfer;
+use crate::output::{OutputFormat, CSV_HEADER};
+
+pub const PARQUET_CONTENT_TYPE: &str = "application/vnd.apache.parquet";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => OutputFormat::Csv.content_type(),
+            ExportFormat::Parquet => PARQUET_CONTENT_TYPE,
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            other => Err(format!(
+                "unknown export format '{}', expected csv or parquet",
+                other
+            )),
+        }
+    }
+}
+
+/// Rows per row group; a chunk with more is split by the writer.
+const MAX_ROW_GROUP_ROWS: usize = 64 * 1024;
+
+/// Digits a `u64` amount can have.
+const AMOUNT_PRECISION: u8 = 20;
+
+/// The columns of an exported file, one row per transfer. Only documents
+/// the layout; the rows are written straight from [`Transfer`]s.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExportRow {
+    /// Block time; Parquet `TIMESTAMP(SECONDS, UTC)`.
+    pub timestamp: i64,
+    pub signature: String,
+    /// `UINT64`.
+    pub slot: u64,
+    /// `sent`, `received`, `internal`, `minted` or `burned`.
+    pub direction: String,
+    /// `DECIMAL(20, decimals)` with the mint's decimals, e.g. `12.5`.
+    #[schema(value_type = String)]
+    pub amount: String,
+    /// The same amount in base units; `INT64`.
+    pub amount_raw: i64,
+    /// Wallet on the other side when known, else its token account.
+    pub counterparty: String,
+    pub mint: String,
+    /// Memo text, null when the transaction had none.
+    pub memo: Option<String>,
+}
+
+/// Arrow schema of the rows for a mint with `decimals`.
+pub fn schema(decimals: u8) -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Second, Some("UTC".into())),
+            false,
+        ),
+        Field::new("signature", DataType::Utf8, false),
+        Field::new("slot", DataType::UInt64, false),
+        Field::new("direction", DataType::Utf8, false),
+        Field::new(
+            "amount",
+            DataType::Decimal128(AMOUNT_PRECISION, decimals as i8),
+            false,
+        ),
+        Field::new("amount_raw", DataType::Int64, false),
+        Field::new("counterparty", DataType::Utf8, false),
+        Field::new("mint", DataType::Utf8, false),
+        Field::new("memo", DataType::Utf8, true),
+    ]))
+}
+
+fn record_batch(schema: &SchemaRef, decimals: u8, transfers: &[Transfer]) -> Result<RecordBatch> {
+    let amount_raw = transfers
+        .iter()
+        .map(|t| i64::try_from(t.amount_raw))
+        .collect::<Result<Vec<_>, _>>()?;
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            TimestampSecondArray::from_iter_values(transfers.iter().map(|t| t.block_time))
+                .with_timezone("UTC"),
+        ),
+        Arc::new(StringArray::from_iter_values(
+            transfers.iter().map(|t| &t.signature),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            transfers.iter().map(|t| t.slot),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            transfers.iter().map(|t| t.direction.as_str()),
+        )),
+        Arc::new(
+            Decimal128Array::from_iter_values(transfers.iter().map(|t| t.amount_raw as i128))
+                .with_precision_and_scale(AMOUNT_PRECISION, decimals as i8)?,
+        ),
+        Arc::new(Int64Array::from(amount_raw)),
+        Arc::new(StringArray::from_iter_values(
+            transfers.iter().map(|t| t.counterparty_address().0),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            transfers.iter().map(|t| &t.mint),
+        )),
+        Arc::new(StringArray::from(
+            transfers
+                .iter()
+                .map(|t| t.memo.as_deref())
+                .collect::<Vec<_>>(),
+        )),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// What the writer has produced and nobody has taken yet.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A Parquet file being written chunk by chunk. Each call returns the
+/// bytes that are ready to send; concatenated they make the file.
+pub struct ParquetExport {
+    writer: ArrowWriter<SharedBuffer>,
+    buffer: SharedBuffer,
+    schema: SchemaRef,
+    decimals: u8,
+}
+
+impl ParquetExport {
+    pub fn new(decimals: u8) -> Result<Self> {
+        let schema = schema(decimals);
+        let buffer = SharedBuffer::default();
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::GZIP(GzipLevel::default()))
+            .set_max_row_group_size(MAX_ROW_GROUP_ROWS)
+            .build();
+        let writer = ArrowWriter::try_new(buffer.clone(), schema.clone(), Some(properties))?;
+        Ok(ParquetExport {
+            writer,
+            buffer,
+            schema,
+            decimals,
+        })
+    }
+
+    /// Writes `transfers` as one or more complete row groups.
+    pub fn write(&mut self, transfers: &[Transfer]) -> Result<Vec<u8>> {
+        if !transfers.is_empty() {
+            let batch = record_batch(&self.schema, self.decimals, transfers)?;
+            self.writer.write(&batch)?;
+            self.writer.flush()?;
+        }
+        Ok(self.buffer.take())
+    }
+
+    /// Writes the footer.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        self.writer.close()?;
+        Ok(self.buffer.take())
+    }
+}
+
+/// A file of either format being written chunk by chunk. CSV rows carry
+/// the base [`CSV_HEADER`] columns only, since price and balance columns
+/// would come and go between chunks.
+pub enum FileExport {
+    Csv { rows: usize },
+    Parquet(Box<ParquetExport>),
+}
+
+impl FileExport {
+    pub fn new(format: ExportFormat, decimals: u8) -> Result<Self> {
+        Ok(match format {
+            ExportFormat::Csv => FileExport::Csv { rows: 0 },
+            ExportFormat::Parquet => FileExport::Parquet(Box::new(ParquetExport::new(decimals)?)),
+        })
+    }
+
+    pub fn write(&mut self, transfers: &[Transfer]) -> Result<Vec<u8>> {
+        match self {
+            FileExport::Csv { rows } => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                if *rows == 0 && !transfers.is_empty() {
+                    writer.write_record(CSV_HEADER)?;
+                }
+                for transfer in transfers {
+                    writer.write_record([
+                        transfer.timestamp_rfc3339().as_str(),
+                        &transfer.signature,
+                        transfer.direction.as_str(),
+                        &transfer.amount_ui,
+                        transfer.counterparty(),
+                        &transfer.mint,
+                    ])?;
+                }
+                *rows += transfers.len();
+                Ok(writer.into_inner()?)
+            }
+            FileExport::Parquet(export) => export.write(transfers),
+        }
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>> {
+        match self {
+            // An empty window still gets its header.
+            FileExport::Csv { rows: 0 } => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                writer.write_record(CSV_HEADER)?;
+                Ok(writer.into_inner()?)
+            }
+            FileExport::Csv { .. } => Ok(Vec::new()),
+            FileExport::Parquet(export) => export.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures;
+    use crate::model::Direction;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use warp::hyper::body::Bytes;
+
+    fn transfer(slot: u64, amount_raw: u64, memo: Option<&str>) -> Transfer {
+        Transfer {
+            slot,
+            block_time: 1_700_000_000 + slot as i64,
+            counterparty_owner: Some("sender".to_string()),
+            memo: memo.map(str::to_string),
+            ..fixtures::transfer(Direction::Received, amount_raw, "source-ata")
+        }
+    }
+
+    #[test]
+    fn writes_a_row_group_per_chunk() {
+        let mut export = ParquetExport::new(6).unwrap();
+        let mut file = export
+            .write(&[transfer(1, 1_500_000, Some("invoice 7"))])
+            .unwrap();
+        file.extend(export.write(&[]).unwrap());
+        file.extend(export.write(&[transfer(2, 25, None)]).unwrap());
+        file.extend(export.finish().unwrap());
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file)).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        assert_eq!(reader.schema().fields(), schema(6).fields());
+        let batches: Vec<RecordBatch> = reader
+            .with_batch_size(1)
+            .build()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 2);
+
+        let first = &batches[0];
+        let amount = first
+            .column_by_name("amount")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Decimal128Array>()
+            .unwrap();
+        assert_eq!(amount.value_as_string(0), "1.500000");
+        let counterparty = first
+            .column_by_name("counterparty")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(counterparty.value(0), "sender");
+        let memo = batches[1].column_by_name("memo").unwrap();
+        assert!(memo.is_null(0));
+    }
+
+    #[test]
+    fn writes_the_csv_header_once() {
+        let mut export = FileExport::new("csv".parse().unwrap(), 6).unwrap();
+        let mut file = export.write(&[transfer(1, 1, None)]).unwrap();
+        file.extend(export.write(&[transfer(2, 2, None)]).unwrap());
+        file.extend(export.finish().unwrap());
+        let text = String::from_utf8(file).unwrap();
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.starts_with("timestamp,"));
+
+        let empty = FileExport::new(ExportFormat::Csv, 6).unwrap();
+        assert_eq!(
+            empty.finish().unwrap(),
+            b"timestamp,signature,direction,amount,counterparty,mint\n"
+        );
+    }
+}
@@ -0,0 +1,397 @@
+//! Several RPC endpoints behind one [`SolanaRpc`], tried in priority order.
+//! An endpoint that keeps failing with 429s, 5xx or timeouts is benched for
+//! a cooldown and calls go to the next one; benched endpoints are only
+//! tried once no healthy one is left.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use solana_client::client_error::ClientError;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClientConfig};
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_client::rpc_response::{
+    Response as RpcResponse, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount,
+};
+use solana_rpc_client::http_sender::HttpSender;
+use solana_sdk::account::Account;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, TransactionStatus};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn, Span};
+
+use crate::config::{redact_url, Config};
+use crate::metrics::METRICS;
+use crate::rpc::{classify_rpc_error, RpcFailure, SolanaRpc};
+
+/// One configured RPC endpoint. Providers take their key either in the URL
+/// or in a header, so both stay out of `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RpcEndpoint {
+    pub url: String,
+    /// Extra request headers, e.g. `("Authorization", "Bearer ...")`.
+    pub headers: Vec<(String, String)>,
+}
+
+impl fmt::Debug for RpcEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.headers.iter().map(|(name, _)| name.as_str()).collect();
+        f.debug_struct("RpcEndpoint")
+            .field("url", &redact_url(&self.url))
+            .field("headers", &names)
+            .finish()
+    }
+}
+
+impl RpcEndpoint {
+    pub fn new(url: impl Into<String>) -> Self {
+        RpcEndpoint {
+            url: url.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    fn client(&self, timeout: Duration) -> Result<RpcClient> {
+        let mut headers = HttpSender::default_headers();
+        for (name, value) in &self.headers {
+            headers.insert(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                    .with_context(|| format!("invalid RPC header name '{}'", name))?,
+                reqwest::header::HeaderValue::from_str(value)
+                    .with_context(|| format!("invalid value for RPC header '{}'", name))?,
+            );
+        }
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(timeout)
+            .pool_idle_timeout(timeout)
+            .build()
+            .context("building the RPC HTTP client")?;
+        Ok(RpcClient::new_sender(
+            HttpSender::new_with_client(&self.url, http),
+            RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+        ))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+struct Endpoint {
+    /// Metrics and log label; never carries credentials.
+    label: String,
+    client: Box<dyn SolanaRpc>,
+    health: Mutex<Health>,
+}
+
+/// The shared client every indexer RPC call goes through.
+pub struct FailoverRpc {
+    endpoints: Vec<Endpoint>,
+    /// Consecutive retryable failures before an endpoint is benched.
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl FailoverRpc {
+    /// `endpoints` are `(label, client)` pairs, highest priority first.
+    pub fn new(
+        endpoints: Vec<(String, Box<dyn SolanaRpc>)>,
+        threshold: u32,
+        cooldown: Duration,
+    ) -> Self {
+        assert!(!endpoints.is_empty(), "at least one RPC endpoint is needed");
+        let endpoints = endpoints
+            .into_iter()
+            .map(|(label, client)| {
+                METRICS
+                    .rpc_endpoint_healthy
+                    .with_label_values(&[&label])
+                    .set(1);
+                Endpoint {
+                    label,
+                    client,
+                    health: Mutex::new(Health::default()),
+                }
+            })
+            .collect();
+        FailoverRpc {
+            endpoints,
+            threshold: threshold.max(1),
+            cooldown,
+        }
+    }
+
+    /// One HTTP client per configured endpoint.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut endpoints: Vec<(String, Box<dyn SolanaRpc>)> = Vec::new();
+        for (priority, endpoint) in config.rpc_endpoints.iter().enumerate() {
+            let mut label = redact_url(&endpoint.url);
+            // Two keys for the same provider redact to the same URL.
+            if endpoints.iter().any(|(other, _)| *other == label) {
+                label = format!("{}#{}", label, priority);
+            }
+            let client = endpoint
+                .client(config.rpc_timeout)
+                .with_context(|| format!("RPC endpoint {}", label))?;
+            endpoints.push((label, Box::new(client)));
+        }
+        Ok(Self::new(
+            endpoints,
+            config.rpc_failover_threshold,
+            config.rpc_unhealthy_cooldown,
+        ))
+    }
+
+    /// Healthy endpoints by priority, then benched ones by when their
+    /// cooldown ends.
+    fn order(&self, now: Instant) -> Vec<usize> {
+        let mut healthy = Vec::new();
+        let mut benched = Vec::new();
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            let health = endpoint
+                .health
+                .lock()
+                .expect("endpoint health lock poisoned");
+            match health.unhealthy_until {
+                Some(until) if until > now => benched.push((until, index)),
+                _ => healthy.push(index),
+            }
+        }
+        benched.sort();
+        healthy.extend(benched.into_iter().map(|(_, index)| index));
+        healthy
+    }
+
+    fn record(&self, endpoint: &Endpoint, failure: Option<RpcFailure>) {
+        let outcome = match failure {
+            None => "ok",
+            Some(RpcFailure::RateLimited) => "rate_limited",
+            Some(RpcFailure::Transient) => "transient",
+            Some(RpcFailure::Permanent) => "permanent",
+        };
+        METRICS
+            .rpc_endpoint_requests
+            .with_label_values(&[&endpoint.label, outcome])
+            .inc();
+        let mut health = endpoint
+            .health
+            .lock()
+            .expect("endpoint health lock poisoned");
+        match failure {
+            // A permanent error is still an answer from a working node.
+            None | Some(RpcFailure::Permanent) => {
+                if health.unhealthy_until.take().is_some() {
+                    info!(endpoint = %endpoint.label, "RPC endpoint recovered");
+                    METRICS
+                        .rpc_endpoint_healthy
+                        .with_label_values(&[&endpoint.label])
+                        .set(1);
+                }
+                health.consecutive_failures = 0;
+            }
+            Some(failure) => {
+                health.consecutive_failures += 1;
+                let now = Instant::now();
+                let benched = health.unhealthy_until.is_some_and(|until| until > now);
+                if health.consecutive_failures >= self.threshold && !benched {
+                    warn!(
+                        endpoint = %endpoint.label,
+                        ?failure,
+                        failures = health.consecutive_failures,
+                        cooldown = ?self.cooldown,
+                        "RPC endpoint marked unhealthy"
+                    );
+                    health.unhealthy_until = Some(now + self.cooldown);
+                    METRICS
+                        .rpc_endpoint_healthy
+                        .with_label_values(&[&endpoint.label])
+                        .set(0);
+                    METRICS
+                        .rpc_failovers
+                        .with_label_values(&[&endpoint.label])
+                        .inc();
+                }
+            }
+        }
+    }
+
+    /// Runs `op` against each endpoint in turn until one answers. Retryable
+    /// failures move on to the next endpoint; permanent ones are returned
+    /// as they are, since another node would say the same.
+    async fn call<'a, T>(
+        &'a self,
+        op: impl Fn(&'a dyn SolanaRpc) -> BoxFuture<'a, Result<T, ClientError>>,
+    ) -> Result<T, ClientError> {
+        let mut last_error = None;
+        for index in self.order(Instant::now()) {
+            let endpoint = &self.endpoints[index];
+            // On the `rpc` span of the call, so the last one tried is kept.
+            Span::current().record("endpoint", endpoint.label.as_str());
+            match op(endpoint.client.as_ref()).await {
+                Ok(value) => {
+                    self.record(endpoint, None);
+                    return Ok(value);
+                }
+                Err(err) => {
+                    let failure = classify_rpc_error(&err);
+                    self.record(endpoint, Some(failure));
+                    if failure == RpcFailure::Permanent {
+                        return Err(err);
+                    }
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.expect("FailoverRpc always has an endpoint"))
+    }
+}
+
+#[async_trait]
+impl SolanaRpc for FailoverRpc {
+    async fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, ClientError> {
+        self.call(|rpc| {
+            rpc.get_signatures_for_address(
+                address,
+                GetConfirmedSignaturesForAddress2Config {
+                    before: config.before,
+                    until: config.until,
+                    limit: config.limit,
+                    commitment: config.commitment,
+                },
+            )
+        })
+        .await
+    }
+
+    async fn get_transaction(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+        self.call(|rpc| rpc.get_transaction(signature, config))
+            .await
+    }
+
+    async fn get_account(
+        &self,
+        address: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<Account>, ClientError> {
+        self.call(|rpc| rpc.get_account(address, commitment)).await
+    }
+
+    async fn get_slot(&self) -> Result<u64, ClientError> {
+        self.call(|rpc| rpc.get_slot()).await
+    }
+
+    async fn get_finalized_slot(&self) -> Result<u64, ClientError> {
+        self.call(|rpc| rpc.get_finalized_slot()).await
+    }
+
+    async fn get_block_time(&self, slot: u64) -> Result<i64, ClientError> {
+        self.call(|rpc| rpc.get_block_time(slot)).await
+    }
+
+    async fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<RpcResponse<Vec<RpcKeyedAccount>>, ClientError> {
+        self.call(|rpc| rpc.get_token_accounts_by_owner(owner, mint, commitment))
+            .await
+    }
+
+    async fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> Result<Vec<Option<TransactionStatus>>, ClientError> {
+        self.call(|rpc| rpc.get_signature_statuses(signatures))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockRpc;
+
+    fn failover(cooldown: Duration) -> FailoverRpc {
+        let primary = MockRpc {
+            signatures_time_out: true,
+            slot: 1,
+            ..MockRpc::default()
+        };
+        let backup = MockRpc {
+            slot: 2,
+            ..MockRpc::default()
+        };
+        FailoverRpc::new(
+            vec![
+                ("primary".to_string(), Box::new(primary)),
+                ("backup".to_string(), Box::new(backup)),
+            ],
+            2,
+            cooldown,
+        )
+    }
+
+    #[tokio::test]
+    async fn benches_a_failing_endpoint_until_its_cooldown_ends() {
+        let rpc = failover(Duration::from_secs(60));
+        let wallet = Pubkey::new_unique();
+        let list = || {
+            rpc.get_signatures_for_address(
+                &wallet,
+                GetConfirmedSignaturesForAddress2Config::default(),
+            )
+        };
+        // Each failure falls through to the backup within the same call.
+        assert!(list().await.is_ok());
+        assert_eq!(rpc.order(Instant::now()), vec![0, 1]);
+        assert!(list().await.is_ok());
+        assert_eq!(rpc.order(Instant::now()), vec![1, 0]);
+        assert_eq!(rpc.get_slot().await.unwrap(), 2);
+
+        // Once the cooldown is over the primary is preferred again.
+        assert_eq!(
+            rpc.order(Instant::now() + Duration::from_secs(61)),
+            vec![0, 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_success_restores_a_benched_endpoint() {
+        let rpc = failover(Duration::ZERO);
+        let wallet = Pubkey::new_unique();
+        let list = || {
+            rpc.get_signatures_for_address(
+                &wallet,
+                GetConfirmedSignaturesForAddress2Config::default(),
+            )
+        };
+        list().await.unwrap();
+        list().await.unwrap();
+        assert!(rpc.endpoints[0]
+            .health
+            .lock()
+            .unwrap()
+            .unhealthy_until
+            .is_some());
+        assert_eq!(rpc.get_slot().await.unwrap(), 1);
+        let health = rpc.endpoints[0].health.lock().unwrap();
+        assert!(health.unhealthy_until.is_none());
+        assert_eq!(health.consecutive_failures, 0);
+    }
+}
@@ -0,0 +1,383 @@
+//! `/graphql`: the data behind `/backfill`, `/summary`, `/counterparties`
+//! and `/balance` as one schema, plus a `transferAdded` subscription on the
+//! live feed. The object types are the REST response types themselves,
+//! derived with snake_case field names, so a field reads the same either
+//! way.
+
+use async_graphql::{
+    Context, EmptyMutation, Enum, ErrorExtensions, InputObject, Object, Schema, Subscription,
+};
+use futures::{Stream, StreamExt};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::config::{resolve_filter, Config};
+use crate::error::IndexerError;
+use crate::events;
+use crate::limits::BackfillPermits;
+use crate::model::{Direction, Transfer, WalletBalance};
+use crate::output::BackfillResponse;
+use crate::rpc::SolanaRpc;
+use crate::server::{
+    backfill_for_query, balance_for_query, run_backfill, summary_for_query, wallet_param,
+    BackfillQuery, BalanceQuery, MAX_COUNTERPARTIES_LIMIT,
+};
+use crate::stats::{top_counterparties, CounterpartyTotals, Summary};
+use crate::store::Storage;
+
+pub type IndexerSchema = Schema<Query, EmptyMutation, Subscription>;
+
+/// Deep enough for every object type, shallow enough that a query can't
+/// nest its way into a huge response.
+const MAX_QUERY_DEPTH: usize = 8;
+
+pub fn schema(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: BackfillPermits,
+) -> IndexerSchema {
+    Schema::build(Query, EmptyMutation, Subscription)
+        .data(client)
+        .data(config)
+        .data(store)
+        .data(permits)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .finish()
+}
+
+/// An error as GraphQL reports it, with the REST error `code` and
+/// `retryable` under `extensions`.
+fn graphql_error(e: IndexerError) -> async_graphql::Error {
+    if e.status().is_server_error() {
+        warn!(code = %e.code(), error = %e, "GraphQL query failed");
+    }
+    let code = e.code();
+    async_graphql::Error::new(e.public_message()).extend_with(|_, extensions| {
+        extensions.set("code", code.as_str());
+        extensions.set("retryable", code.retryable());
+    })
+}
+
+/// `hours`, or `start`/`end`, as for `/backfill`.
+#[derive(Debug, Default, InputObject)]
+pub struct WindowInput {
+    pub hours: Option<i64>,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+#[derive(Debug, Default, InputObject)]
+pub struct TransferFilter {
+    pub direction: Option<Direction>,
+    /// Inclusive amount bounds in UI units.
+    pub min_amount: Option<String>,
+    pub max_amount: Option<String>,
+    /// Token account or owner wallet on the other side.
+    pub counterparty: Option<String>,
+    pub memo_contains: Option<String>,
+    #[graphql(default)]
+    pub include_failed: bool,
+    #[graphql(default)]
+    pub include_orphaned: bool,
+    #[graphql(default = true)]
+    pub include_mints: bool,
+    #[graphql(default)]
+    pub include_internal: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(rename_items = "lowercase")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Default, InputObject)]
+pub struct Pagination {
+    /// Page size, 100 by default and at most `max_page_size`.
+    pub limit: Option<usize>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    /// Newest first by default.
+    pub order: Option<Order>,
+}
+
+/// Which wallet, mint and window a query is about.
+struct Selection {
+    wallet: Option<String>,
+    mint: Option<String>,
+    symbol: Option<String>,
+    window: WindowInput,
+    commitment: Option<String>,
+}
+
+impl Selection {
+    fn backfill_query(self, filters: TransferFilter, pagination: Pagination) -> BackfillQuery {
+        BackfillQuery {
+            wallet: self.wallet,
+            mint: self.mint,
+            symbol: self.symbol,
+            hours: self.window.hours,
+            start: self.window.start,
+            end: self.window.end,
+            format: None,
+            order: pagination.order.map(|order| match order {
+                Order::Asc => "asc".to_string(),
+                Order::Desc => "desc".to_string(),
+            }),
+            strategy: None,
+            include_failed: filters.include_failed,
+            include_orphaned: filters.include_orphaned,
+            include_mints: filters.include_mints,
+            include_internal: filters.include_internal,
+            direction: filters.direction.map(|d| d.as_str().to_string()),
+            min_amount: filters.min_amount,
+            max_amount: filters.max_amount,
+            counterparty: filters.counterparty,
+            memo_contains: filters.memo_contains,
+            limit: pagination.limit,
+            cursor: pagination.cursor,
+            running_balance: false,
+            commitment: self.commitment,
+        }
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// A page of transfers, as `GET /backfill` returns it.
+    #[allow(clippy::too_many_arguments)]
+    async fn transfers(
+        &self,
+        ctx: &Context<'_>,
+        wallet: Option<String>,
+        mint: Option<String>,
+        symbol: Option<String>,
+        #[graphql(default)] window: WindowInput,
+        #[graphql(default)] filters: TransferFilter,
+        #[graphql(default)] pagination: Pagination,
+        #[graphql(default)] running_balance: bool,
+        commitment: Option<String>,
+    ) -> async_graphql::Result<BackfillResponse> {
+        let selection = Selection {
+            wallet,
+            mint,
+            symbol,
+            window,
+            commitment,
+        };
+        let query = BackfillQuery {
+            running_balance,
+            ..selection.backfill_query(filters, pagination)
+        };
+        let (client, config, store) = services(ctx);
+        let _permit = permit(ctx)?;
+        let (_, response) = run_backfill(query, client, config, store)
+            .await
+            .map_err(graphql_error)?;
+        Ok(response)
+    }
+
+    /// Totals over the window, as `GET /summary` returns them.
+    #[allow(clippy::too_many_arguments)]
+    async fn summary(
+        &self,
+        ctx: &Context<'_>,
+        wallet: Option<String>,
+        mint: Option<String>,
+        symbol: Option<String>,
+        #[graphql(default)] window: WindowInput,
+        #[graphql(default)] filters: TransferFilter,
+        commitment: Option<String>,
+    ) -> async_graphql::Result<Summary> {
+        let selection = Selection {
+            wallet,
+            mint,
+            symbol,
+            window,
+            commitment,
+        };
+        let query = selection.backfill_query(filters, Pagination::default());
+        let (client, config, store) = services(ctx);
+        let _permit = permit(ctx)?;
+        let (_, summary) = summary_for_query(query, client, config, store)
+            .await
+            .map_err(graphql_error)?;
+        Ok(summary)
+    }
+
+    /// The largest counterparties by volume, as `GET /counterparties`.
+    #[allow(clippy::too_many_arguments)]
+    async fn counterparties(
+        &self,
+        ctx: &Context<'_>,
+        wallet: Option<String>,
+        mint: Option<String>,
+        symbol: Option<String>,
+        #[graphql(default)] window: WindowInput,
+        #[graphql(default)] filters: TransferFilter,
+        #[graphql(default = 20)] limit: usize,
+        commitment: Option<String>,
+    ) -> async_graphql::Result<Vec<CounterpartyTotals>> {
+        if limit == 0 || limit > MAX_COUNTERPARTIES_LIMIT {
+            return Err(graphql_error(IndexerError::InvalidParameter(format!(
+                "'limit' must be between 1 and {}, got {}",
+                MAX_COUNTERPARTIES_LIMIT, limit
+            ))));
+        }
+        let selection = Selection {
+            wallet,
+            mint,
+            symbol,
+            window,
+            commitment,
+        };
+        let query = selection.backfill_query(filters, Pagination::default());
+        let (client, config, store) = services(ctx);
+        let _permit = permit(ctx)?;
+        let (mint, response) = backfill_for_query(&query, client, config, store)
+            .await
+            .map_err(graphql_error)?;
+        Ok(top_counterparties(
+            &response.transfers,
+            mint.decimals,
+            limit,
+        ))
+    }
+
+    /// The current on-chain balance, as `GET /balance`.
+    async fn balance(
+        &self,
+        ctx: &Context<'_>,
+        wallet: Option<String>,
+        mint: Option<String>,
+        symbol: Option<String>,
+        commitment: Option<String>,
+    ) -> async_graphql::Result<WalletBalance> {
+        let query = BalanceQuery {
+            wallet,
+            mint,
+            symbol,
+            commitment,
+        };
+        let (client, config, _) = services(ctx);
+        balance_for_query(&query, client, config)
+            .await
+            .map_err(graphql_error)
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Transfers the background indexer finds from now on, like
+    /// `GET /stream` without the replay.
+    async fn transfer_added(
+        &self,
+        ctx: &Context<'_>,
+        wallet: Option<String>,
+        mint: Option<String>,
+        direction: Option<Direction>,
+        #[graphql(desc = "Inclusive lower bound in UI units.")] min_amount: Option<String>,
+    ) -> async_graphql::Result<impl Stream<Item = Transfer>> {
+        let config = ctx.data_unchecked::<Arc<Config>>().clone();
+        let wallet = match wallet.as_deref() {
+            Some(wallet) => Some(wallet_param(Some(wallet), &config).map_err(graphql_error)?),
+            None => None,
+        };
+        let filter = resolve_filter(
+            direction.map(|d| d.as_str()),
+            min_amount.as_deref(),
+            mint.as_deref(),
+            &config.mints,
+        )
+        .map_err(|e| graphql_error(IndexerError::InvalidParameter(e.to_string())))?;
+        Ok(events::live(events::subscribe())
+            .filter(move |event| {
+                let wanted =
+                    wallet.is_none_or(|wallet| event.wallet == wallet) && filter.matches(event);
+                async move { wanted }
+            })
+            .map(move |event| {
+                let mut transfer = event.transfer;
+                transfer.wallet = Some(event.wallet.to_string());
+                transfer.annotate(config.explorer_link(&transfer.signature));
+                transfer
+            }))
+    }
+}
+
+fn services<'a>(ctx: &Context<'a>) -> (&'a dyn SolanaRpc, &'a Config, &'a Storage) {
+    (
+        ctx.data_unchecked::<Arc<dyn SolanaRpc>>().as_ref(),
+        ctx.data_unchecked::<Arc<Config>>(),
+        ctx.data_unchecked::<Storage>(),
+    )
+}
+
+/// Queries that index take a backfill slot like their REST counterparts.
+fn permit(ctx: &Context<'_>) -> async_graphql::Result<tokio::sync::OwnedSemaphorePermit> {
+    ctx.data_unchecked::<BackfillPermits>()
+        .try_acquire()
+        .map_err(graphql_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures;
+    use crate::rpc::mock::MockRpc;
+    use crate::store::MemoryStore;
+
+    async fn test_schema() -> IndexerSchema {
+        let store: Storage = Arc::new(MemoryStore::open(None).await.unwrap());
+        schema(
+            Arc::new(MockRpc::default()),
+            Arc::new(Config::default()),
+            store,
+            BackfillPermits::new(1),
+        )
+    }
+
+    #[tokio::test]
+    async fn transfer_fields_match_the_rest_json() {
+        let mut transfer = fixtures::transfer(Direction::Received, 1_500_000, "source-ata");
+        transfer.wallet = Some("wallet".to_string());
+        transfer.annotate("https://explorer/tx".to_string());
+        let serde_json::Value::Object(json) = serde_json::to_value(&transfer).unwrap() else {
+            panic!("a transfer serializes to an object");
+        };
+
+        let response = test_schema()
+            .await
+            .execute(r#"{ __type(name: "Transfer") { fields { name } } }"#)
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        let fields: Vec<&str> = data["__type"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["name"].as_str().unwrap())
+            .collect();
+        for key in json.keys() {
+            assert!(fields.contains(&key.as_str()), "no GraphQL field {}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_carry_the_rest_code() {
+        let response = test_schema()
+            .await
+            .execute("{ counterparties(limit: 0) { address volume } }")
+            .await;
+        let error = &response.errors[0];
+        assert!(error.message.contains("'limit'"), "{}", error.message);
+        let code = error.extensions.as_ref().unwrap().get("code").unwrap();
+        assert_eq!(code, &async_graphql::Value::from("INVALID_PARAMETER"));
+    }
+}
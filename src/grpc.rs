@@ -0,0 +1,454 @@
+//! The gRPC API (`proto/indexer.proto`) for services that would rather not
+//! speak HTTP. Every call goes through the same validation, store and
+//! backfill code as its HTTP route; only the encoding differs.
+
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Request, Response, Status};
+use tracing::warn;
+
+use crate::config::{resolve_filter, Config};
+use crate::deadline;
+use crate::error::IndexerError;
+use crate::events;
+use crate::limits::BackfillPermits;
+use crate::metrics::METRICS;
+use crate::model;
+use crate::output::BackfillResponse;
+use crate::rpc::SolanaRpc;
+use crate::server::{
+    balance_for_query, run_backfill, summary_for_query, wallet_param, BackfillQuery, BalanceQuery,
+};
+use crate::stats::{DirectionCounts, Summary};
+use crate::store::Storage;
+
+pub mod proto {
+    tonic::include_proto!("indexer.v1");
+}
+
+use proto::indexer_server::{Indexer, IndexerServer};
+
+/// Serves the gRPC API on `listener` until `shutdown` flips. Open
+/// `StreamTransfers` calls end then too, so they don't hold up the drain.
+pub async fn serve(
+    listener: TcpListener,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: BackfillPermits,
+    shutdown: watch::Receiver<bool>,
+) -> Result<(), tonic::transport::Error> {
+    let api_keys = config.api_keys.clone();
+    let allowlist = config.allowlist.clone();
+    let service = IndexerService {
+        client,
+        config,
+        store,
+        permits,
+        shutdown: shutdown.clone(),
+    };
+    let authenticated = IndexerServer::with_interceptor(service, move |request: Request<()>| {
+        let metadata = request.metadata();
+        let header = |name: &str| metadata.get(name).and_then(|value| value.to_str().ok());
+        let client = allowlist.client(
+            request.remote_addr(),
+            header("forwarded"),
+            header("x-forwarded-for"),
+        );
+        allowlist.check(client, "grpc").map_err(status)?;
+        api_keys
+            .authorize(header("authorization"), header("x-api-key"))
+            .map_err(status)?;
+        Ok(request)
+    });
+    let mut shutdown = shutdown;
+    tonic::transport::Server::builder()
+        .add_service(authenticated)
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+            let _ = shutdown.changed().await;
+        })
+        .await
+}
+
+struct IndexerService {
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: BackfillPermits,
+    shutdown: watch::Receiver<bool>,
+}
+
+type TransferStream = Pin<Box<dyn Stream<Item = Result<proto::Transfer, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Indexer for IndexerService {
+    async fn get_transfers(
+        &self,
+        request: Request<proto::TransfersRequest>,
+    ) -> Result<Response<proto::TransfersResponse>, Status> {
+        let request = request.into_inner();
+        let mut query = backfill_query(request.selection, request.filter);
+        query.limit = request.limit.map(|limit| limit as usize);
+        query.cursor = request.cursor;
+        query.order = request.order;
+        query.running_balance = request.running_balance;
+        let result = async {
+            let _permit = self.permits.try_acquire()?;
+            let (_, response) = deadline::run(
+                self.config.request_timeout,
+                run_backfill(query, self.client.as_ref(), &self.config, &self.store),
+            )
+            .await?;
+            Ok(response.into())
+        }
+        .await;
+        finish("GetTransfers", result)
+    }
+
+    async fn get_summary(
+        &self,
+        request: Request<proto::SummaryRequest>,
+    ) -> Result<Response<proto::SummaryResponse>, Status> {
+        let request = request.into_inner();
+        let query = backfill_query(request.selection, request.filter);
+        let result = async {
+            let _permit = self.permits.try_acquire()?;
+            let (response, summary) = deadline::run(
+                self.config.request_timeout,
+                summary_for_query(query, self.client.as_ref(), &self.config, &self.store),
+            )
+            .await?;
+            Ok(summary_response(response, summary))
+        }
+        .await;
+        finish("GetSummary", result)
+    }
+
+    async fn get_balance(
+        &self,
+        request: Request<proto::BalanceRequest>,
+    ) -> Result<Response<proto::Balance>, Status> {
+        let request = request.into_inner();
+        let query = BalanceQuery {
+            wallet: request.wallet,
+            mint: request.mint,
+            symbol: request.symbol,
+            commitment: request.commitment,
+        };
+        let result = balance_for_query(&query, self.client.as_ref(), &self.config)
+            .await
+            .map(proto::Balance::from);
+        finish("GetBalance", result)
+    }
+
+    type StreamTransfersStream = TransferStream;
+
+    async fn stream_transfers(
+        &self,
+        request: Request<proto::StreamTransfersRequest>,
+    ) -> Result<Response<TransferStream>, Status> {
+        let request = request.into_inner();
+        let result = transfer_stream(request, self.config.clone(), self.shutdown.clone());
+        finish("StreamTransfers", result)
+    }
+}
+
+/// Transfers from the live feed that match `request`, until `shutdown`
+/// flips.
+fn transfer_stream(
+    request: proto::StreamTransfersRequest,
+    config: Arc<Config>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<TransferStream, IndexerError> {
+    let wallet = match request.wallet.as_deref() {
+        Some(wallet) => Some(wallet_param(Some(wallet), &config)?),
+        None => None,
+    };
+    let filter = resolve_filter(
+        request.direction.as_deref(),
+        request.min_amount.as_deref(),
+        request.mint.as_deref(),
+        &config.mints,
+    )
+    .map_err(|e| IndexerError::InvalidParameter(e.to_string()))?;
+    let transfers = events::live(events::subscribe())
+        .take_until(async move {
+            let _ = shutdown.changed().await;
+        })
+        .filter(move |event| {
+            let wanted =
+                wallet.is_none_or(|wallet| event.wallet == wallet) && filter.matches(event);
+            async move { wanted }
+        })
+        .map(move |event| {
+            let mut transfer = event.transfer;
+            transfer.wallet = Some(event.wallet.to_string());
+            transfer.annotate(config.explorer_link(&transfer.signature));
+            Ok(transfer.into())
+        });
+    Ok(Box::pin(transfers))
+}
+
+/// The `/backfill` query a selection and filter stand for.
+fn backfill_query(
+    selection: Option<proto::Selection>,
+    filter: Option<proto::TransferFilter>,
+) -> BackfillQuery {
+    let selection = selection.unwrap_or_default();
+    let filter = filter.unwrap_or_default();
+    BackfillQuery {
+        wallet: selection.wallet,
+        mint: selection.mint,
+        symbol: selection.symbol,
+        hours: selection.hours,
+        start: selection.start,
+        end: selection.end,
+        format: None,
+        order: None,
+        strategy: None,
+        include_failed: filter.include_failed,
+        include_orphaned: filter.include_orphaned,
+        include_mints: !filter.exclude_mints,
+        include_internal: filter.include_internal,
+        direction: filter.direction,
+        min_amount: filter.min_amount,
+        max_amount: filter.max_amount,
+        counterparty: filter.counterparty,
+        memo_contains: filter.memo_contains,
+        limit: None,
+        cursor: None,
+        running_balance: false,
+        commitment: selection.commitment,
+    }
+}
+
+/// Records the call in the metrics and turns failures into statuses.
+fn finish<T>(method: &str, result: Result<T, IndexerError>) -> Result<Response<T>, Status> {
+    let result = result.map(Response::new).map_err(status);
+    let code = match &result {
+        Ok(_) => Code::Ok,
+        Err(status) => status.code(),
+    };
+    METRICS
+        .grpc_requests
+        .with_label_values(&[method, &format!("{:?}", code)])
+        .inc();
+    result
+}
+
+/// The gRPC status closest to the HTTP one `e` maps to, with the error's
+/// stable `code` (and any retry delay) in the trailers.
+fn status(e: IndexerError) -> Status {
+    use warp::http::StatusCode;
+    let code = match e.status() {
+        _ if matches!(e, IndexerError::Cancelled) => Code::Cancelled,
+        StatusCode::GATEWAY_TIMEOUT => Code::DeadlineExceeded,
+        StatusCode::BAD_REQUEST | StatusCode::NOT_ACCEPTABLE => Code::InvalidArgument,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::UNPROCESSABLE_ENTITY => Code::FailedPrecondition,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    if e.status().is_server_error() {
+        warn!(code = %e.code(), error = %e, "gRPC call failed");
+    }
+    let mut status = Status::new(code, e.public_message());
+    let metadata = status.metadata_mut();
+    metadata.insert("error-code", MetadataValue::from_static(e.code().as_str()));
+    if let Some(retry_after) = e.retry_after() {
+        metadata.insert("retry-after", retry_after.as_secs().max(1).into());
+    }
+    status
+}
+
+fn summary_response(response: BackfillResponse, summary: Summary) -> proto::SummaryResponse {
+    proto::SummaryResponse {
+        wallet: response.wallet,
+        mint: response.mint,
+        symbol: response.symbol,
+        window: Some(response.window.into()),
+        total_received: summary.total_received,
+        total_sent: summary.total_sent,
+        net_flow: summary.net_flow,
+        internal_volume: summary.internal_volume,
+        counts: Some(summary.counts.into()),
+        largest_transfer: summary.largest_transfer.map(Into::into),
+        distinct_counterparties: summary.distinct_counterparties as u64,
+        network_fees: Some(summary.network_fees.into()),
+    }
+}
+
+impl From<BackfillResponse> for proto::TransfersResponse {
+    fn from(response: BackfillResponse) -> Self {
+        proto::TransfersResponse {
+            wallet: response.wallet,
+            mint: response.mint,
+            symbol: response.symbol,
+            window: Some(response.window.into()),
+            transfers: response.transfers.into_iter().map(Into::into).collect(),
+            undecodable_transactions: response.undecodable_transactions as u64,
+            pages_scanned: response.pages_scanned as u64,
+            truncated: response.truncated,
+            has_more: response.has_more,
+            next_cursor: response.next_cursor,
+        }
+    }
+}
+
+impl From<model::Transfer> for proto::Transfer {
+    fn from(transfer: model::Transfer) -> Self {
+        proto::Transfer {
+            signature: transfer.signature,
+            slot: transfer.slot,
+            block_time: transfer.block_time,
+            instruction_index: transfer.instruction_index as u64,
+            inner_index: transfer.inner_index.map(|index| index as u64),
+            direction: transfer.direction.as_str().to_string(),
+            amount_raw: transfer.amount_raw,
+            amount_ui: transfer.amount_ui,
+            source: transfer.source,
+            destination: transfer.destination,
+            mint: transfer.mint,
+            symbol: transfer.symbol,
+            fee: transfer.fee.map(|fee| proto::TransferFee {
+                fee_raw: fee.fee_raw,
+                fee_ui: fee.fee_ui,
+                net_amount_raw: fee.net_amount_raw,
+                net_amount_ui: fee.net_amount_ui,
+            }),
+            counterparty_owner: transfer.counterparty_owner,
+            fee_payer: transfer.fee_payer,
+            network_fee: transfer.network_fee.map(Into::into),
+            memo: transfer.memo,
+            commitment: transfer.commitment,
+            wallet: transfer.wallet,
+            explorer_url: transfer.explorer_url,
+            counterparty: transfer
+                .counterparty
+                .map(|counterparty| proto::Counterparty {
+                    token_account: counterparty.token_account,
+                    owner: counterparty.owner,
+                    resolved: counterparty.resolved,
+                }),
+            failed: transfer.failed,
+            orphaned: transfer.orphaned,
+            via_delegate: transfer.via_delegate,
+            balance_after_raw: transfer.balance_after_raw,
+            balance_after: transfer.balance_after,
+            balance_excluded: transfer.balance_excluded,
+            price_usd: transfer.price_usd,
+            value_usd: transfer.value_usd,
+        }
+    }
+}
+
+impl From<model::TimeWindow> for proto::TimeWindow {
+    fn from(window: model::TimeWindow) -> Self {
+        proto::TimeWindow {
+            start: window.start,
+            end: window.end,
+        }
+    }
+}
+
+impl From<model::NetworkFee> for proto::NetworkFee {
+    fn from(fee: model::NetworkFee) -> Self {
+        proto::NetworkFee {
+            lamports: fee.lamports,
+            sol: fee.sol,
+        }
+    }
+}
+
+impl From<DirectionCounts> for proto::DirectionCounts {
+    fn from(counts: DirectionCounts) -> Self {
+        proto::DirectionCounts {
+            received: counts.received as u64,
+            sent: counts.sent as u64,
+            internal: counts.internal as u64,
+            minted: counts.minted as u64,
+            burned: counts.burned as u64,
+        }
+    }
+}
+
+impl From<model::WalletBalance> for proto::Balance {
+    fn from(balance: model::WalletBalance) -> Self {
+        proto::Balance {
+            wallet: balance.wallet,
+            mint: balance.mint,
+            symbol: balance.symbol,
+            decimals: u32::from(balance.decimals),
+            amount_raw: balance.amount_raw,
+            amount_ui: balance.amount_ui,
+            token_accounts: balance
+                .token_accounts
+                .into_iter()
+                .map(|account| proto::TokenAccountBalance {
+                    address: account.address,
+                    amount_raw: account.amount_raw,
+                    amount_ui: account.amount_ui,
+                })
+                .collect(),
+            slot: balance.slot,
+            commitment: balance.commitment,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{fixtures, Direction};
+    use std::time::Duration;
+
+    #[test]
+    fn transfers_keep_every_json_field() {
+        let mut transfer = fixtures::transfer(Direction::Received, 1_500_000, "source-ata");
+        transfer.wallet = Some("wallet".to_string());
+        transfer.annotate("https://explorer/tx".to_string());
+        let converted = proto::Transfer::from(transfer.clone());
+        assert_eq!(converted.signature, transfer.signature);
+        assert_eq!(converted.direction, "received");
+        assert_eq!(converted.amount_ui, transfer.amount_ui);
+        assert_eq!(converted.wallet.as_deref(), Some("wallet"));
+        assert_eq!(converted.explorer_url, transfer.explorer_url);
+        assert_eq!(
+            converted.counterparty.map(|c| c.token_account),
+            transfer.counterparty.map(|c| c.token_account)
+        );
+    }
+
+    #[test]
+    fn errors_map_to_status_codes_with_the_rest_code() {
+        let invalid = status(IndexerError::InvalidParameter("bad limit".to_string()));
+        assert_eq!(invalid.code(), Code::InvalidArgument);
+        assert_eq!(invalid.message(), "bad limit");
+        assert_eq!(
+            invalid.metadata().get("error-code").unwrap(),
+            "INVALID_PARAMETER"
+        );
+
+        let busy = status(IndexerError::BackfillsBusy {
+            retry_after: Duration::from_secs(5),
+        });
+        assert_eq!(busy.code(), Code::ResourceExhausted);
+        assert_eq!(busy.metadata().get("retry-after").unwrap(), "5");
+    }
+
+    #[test]
+    fn filters_default_to_the_http_defaults() {
+        let query = backfill_query(None, None);
+        assert!(query.include_mints);
+        assert!(!query.include_internal);
+        assert_eq!(query.wallet, None);
+    }
+}
@@ -0,0 +1,2955 @@
+//! Signature pagination, transaction fetching and keeping the store in sync.
+
+use anyhow::Result;
+use chrono::Utc;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use solana_account_decoder::UiAccountData;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::{
+    RpcTransactionConfig, RpcTransactionLogsConfig, RpcTransactionLogsFilter,
+};
+use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+use solana_sdk::account::Account;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, TransactionConfirmationStatus, UiTransactionEncoding,
+};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error, info, instrument, warn};
+use utoipa::ToSchema;
+
+use crate::config::{commitment_name, Config, MintInfo, TokenProgram, SPL_TOKEN};
+use crate::deadline;
+use crate::decode;
+use crate::diagnostics;
+use crate::error::IndexerError;
+use crate::events;
+use crate::jobs;
+use crate::metrics::METRICS;
+use crate::model::{
+    format_amount, format_signed_amount, replay_balance, sort_transfers,
+    unexplained_balance_change, AccountEvent, AnchorSource, Approval, BackfillRequest,
+    BalanceAnchor, BalanceAnchorSource, BalanceAt, BalanceSnapshot, Delegation, Delegations,
+    Discrepancy, NetworkFee, SortOrder, Strategy, TimeWindow, TokenAccountBalance,
+    TransactionEffect, Transfer, WalletBalance, WindowAnchor,
+};
+use crate::parser::{
+    accounts_missing_mint, associated_token_address_for_program, balance_transfers,
+    extract_account_events, extract_approvals, extract_transfers, find_discrepancy,
+    message_account_keys, token_account_owners, WalletContext,
+};
+use crate::progress;
+use crate::raw::RawTransaction;
+use crate::rpc::{with_retry, SolanaRpc};
+use crate::store::{Storage, SyncState, UnfinalizedSignature};
+
+/// Slots behind the tip an orphaned signature is still re-checked for. A
+/// fork that dropped it is settled long before this, so a transaction still
+/// missing by then is gone for good.
+pub const REORG_WINDOW_SLOTS: u64 = 150;
+
+/// Most signatures `getSignatureStatuses` takes in one call.
+const MAX_STATUS_BATCH: usize = 256;
+
+/// Fetches `wallet`'s balance of `mint` across all of its token accounts.
+pub async fn fetch_balance(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    wallet: &Pubkey,
+    mint: &MintInfo,
+    commitment: CommitmentConfig,
+) -> Result<WalletBalance, IndexerError> {
+    let response = with_retry("getTokenAccountsByOwner", config.rpc_max_attempts, || {
+        client.get_token_accounts_by_owner(wallet, &mint.mint, commitment)
+    })
+    .await?;
+
+    let mut token_accounts = Vec::with_capacity(response.value.len());
+    let mut total: u64 = 0;
+    for keyed in &response.value {
+        let amount_raw = parsed_token_amount(&keyed.account.data).ok_or_else(|| {
+            IndexerError::Decode(format!(
+                "token account {} has no parsed amount",
+                keyed.pubkey
+            ))
+        })?;
+        total = total.saturating_add(amount_raw);
+        token_accounts.push(TokenAccountBalance {
+            address: keyed.pubkey.clone(),
+            amount_raw,
+            amount_ui: format_amount(amount_raw, mint.decimals),
+        });
+    }
+
+    Ok(WalletBalance {
+        wallet: wallet.to_string(),
+        mint: mint.mint.to_string(),
+        symbol: mint.symbol.clone(),
+        decimals: mint.decimals,
+        amount_raw: total,
+        amount_ui: format_amount(total, mint.decimals),
+        token_accounts,
+        slot: response.context.slot,
+        commitment: commitment_name(commitment).to_string(),
+    })
+}
+
+/// The delegates `wallet`'s token accounts of `mint` currently have, with
+/// the allowance each has left.
+pub async fn fetch_delegations(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    wallet: &Pubkey,
+    mint: &MintInfo,
+    commitment: CommitmentConfig,
+) -> Result<Delegations, IndexerError> {
+    let response = with_retry("getTokenAccountsByOwner", config.rpc_max_attempts, || {
+        client.get_token_accounts_by_owner(wallet, &mint.mint, commitment)
+    })
+    .await?;
+
+    let mut delegations = Vec::new();
+    for keyed in &response.value {
+        let UiAccountData::Json(parsed) = &keyed.account.data else {
+            return Err(IndexerError::Decode(format!(
+                "token account {} is not jsonParsed",
+                keyed.pubkey
+            )));
+        };
+        let info = &parsed.parsed["info"];
+        let Some(delegate) = info["delegate"].as_str() else {
+            continue;
+        };
+        let amount_raw = info["delegatedAmount"]["amount"]
+            .as_str()
+            .and_then(|amount| amount.parse().ok())
+            .ok_or_else(|| {
+                IndexerError::Decode(format!(
+                    "token account {} has no parsed delegated amount",
+                    keyed.pubkey
+                ))
+            })?;
+        delegations.push(Delegation {
+            token_account: keyed.pubkey.clone(),
+            delegate: delegate.to_string(),
+            amount_raw,
+            amount_ui: format_amount(amount_raw, mint.decimals),
+        });
+    }
+
+    Ok(Delegations {
+        wallet: wallet.to_string(),
+        mint: mint.mint.to_string(),
+        symbol: mint.symbol.clone(),
+        delegations,
+        slot: response.context.slot,
+        commitment: commitment_name(commitment).to_string(),
+    })
+}
+
+/// Block time before a snapshot that reading the transfers after it starts
+/// from: `taken_at` is the host clock, which may run ahead of the chain's.
+const SNAPSHOT_CLOCK_SLACK_SECS: i64 = 600;
+
+/// `wallet`'s balance of `mint` at the end of second `ts`, replayed from
+/// whichever is nearer in time, the current balance or a stored snapshot,
+/// through the stored transfers in between. The tail of the history is
+/// synced first, but nothing before the indexed history is fetched; a `ts`
+/// before it, or a history with a gap, is refused rather than answered
+/// with a wrong number.
+pub async fn balance_at(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+    mint: &MintInfo,
+    ts: i64,
+) -> Result<BalanceAt, IndexerError> {
+    let indexed_from = match store.sync_state(wallet, &mint.mint).await? {
+        Some(state) if state.indexed_from <= ts => state.indexed_from,
+        Some(state) => {
+            return Err(IndexerError::HistoryIncomplete(format!(
+                "{} predates the indexed history of {} {}, which starts at {}; backfill further \
+                 back first",
+                ts, wallet, mint.symbol, state.indexed_from
+            )))
+        }
+        None => {
+            return Err(IndexerError::HistoryIncomplete(format!(
+                "{} {} has no indexed history yet",
+                wallet, mint.symbol
+            )))
+        }
+    };
+
+    let now = Utc::now().timestamp();
+    let indexed = TimeWindow {
+        start: indexed_from,
+        end: i64::MAX,
+    };
+    let nearest = store
+        .balance_snapshots(wallet, &mint.mint, &indexed)
+        .await?
+        .into_iter()
+        .min_by_key(|snapshot| snapshot.taken_at.abs_diff(ts));
+    let anchor = match nearest.filter(|snapshot| snapshot.taken_at.abs_diff(ts) < now.abs_diff(ts))
+    {
+        Some(snapshot) => BalanceAnchor {
+            source: BalanceAnchorSource::Snapshot,
+            slot: snapshot.slot,
+            taken_at: snapshot.taken_at,
+            amount_raw: snapshot.amount_raw,
+            amount_ui: format_amount(snapshot.amount_raw, mint.decimals),
+        },
+        // Read before syncing, so everything the balance reflects is in
+        // the history synced next.
+        None => {
+            let current = fetch_balance(client, config, wallet, mint, config.commitment).await?;
+            BalanceAnchor {
+                source: BalanceAnchorSource::Current,
+                slot: current.slot,
+                taken_at: now,
+                amount_raw: current.amount_raw,
+                amount_ui: current.amount_ui,
+            }
+        }
+    };
+    let report = sync_store(client, config, store, wallet, mint, indexed_from).await?;
+    if report.truncated {
+        return Err(IndexerError::HistoryIncomplete(format!(
+            "the history of {} {} could not be synced up to now",
+            wallet, mint.symbol
+        )));
+    }
+    let request = BackfillRequest {
+        wallet: *wallet,
+        mint: mint.clone(),
+        window: TimeWindow {
+            start: ts.min(anchor.taken_at.saturating_sub(SNAPSHOT_CLOCK_SLACK_SECS)),
+            end: i64::MAX,
+        },
+        until: None,
+        include_failed: false,
+        include_orphaned: false,
+        strategy: Strategy::Instructions,
+        commitment: config.commitment,
+    };
+    let transfers = store.query_transfers(&request).await?;
+    let (amount_raw, transfers_applied) =
+        replay_balance(anchor.amount_raw, anchor.slot, &transfers, ts).ok_or_else(|| {
+            IndexerError::HistoryIncomplete(format!(
+                "replaying the transfers of {} {} to {} went below zero; some balance change \
+                 is missing from the indexed history",
+                wallet, mint.symbol, ts
+            ))
+        })?;
+
+    Ok(BalanceAt {
+        wallet: wallet.to_string(),
+        mint: mint.mint.to_string(),
+        symbol: mint.symbol.clone(),
+        decimals: mint.decimals,
+        timestamp: ts,
+        amount_raw,
+        amount_ui: format_amount(amount_raw, mint.decimals),
+        anchor,
+        transfers_applied,
+    })
+}
+
+/// Reads `info.tokenAmount.amount` from a `jsonParsed` token account.
+fn parsed_token_amount(data: &UiAccountData) -> Option<u64> {
+    let UiAccountData::Json(parsed) = data else {
+        return None;
+    };
+    parsed.parsed["info"]["tokenAmount"]["amount"]
+        .as_str()?
+        .parse()
+        .ok()
+}
+
+/// Size of an spl-token `Mint` account and the offsets of the fields checked
+/// at startup (see `spl_token::state::Mint::unpack`). Token-2022 mints share
+/// the layout but may carry extensions after it.
+const MINT_ACCOUNT_LEN: usize = 82;
+const MINT_DECIMALS_OFFSET: usize = 44;
+const MINT_IS_INITIALIZED_OFFSET: usize = 45;
+
+/// spl-token `Account` size; the mint is its first field.
+const TOKEN_ACCOUNT_MIN_LEN: usize = 165;
+
+const LIVE_RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const LIVE_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Checks that every registered mint exists on chain, is owned by one of the
+/// configured token programs, is an initialized mint, and has the configured
+/// decimals.
+pub async fn verify_mints(client: &dyn SolanaRpc, config: &Config) -> Result<()> {
+    for info in &config.mints.mints {
+        let account = with_retry("getAccountInfo", config.rpc_max_attempts, || {
+            client.get_account(&info.mint, config.commitment)
+        })
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("{} mint {} does not exist", info.symbol, info.mint))?;
+
+        let Some(program) = config
+            .token_programs
+            .iter()
+            .find(|p| p.pubkey() == account.owner)
+        else {
+            anyhow::bail!(
+                "{} mint {} is owned by {}, not a configured token program",
+                info.symbol,
+                info.mint,
+                account.owner
+            );
+        };
+        let layout_ok = if *program == SPL_TOKEN {
+            account.data.len() == MINT_ACCOUNT_LEN
+        } else {
+            account.data.len() >= MINT_ACCOUNT_LEN
+        };
+        if !layout_ok || account.data[MINT_IS_INITIALIZED_OFFSET] != 1 {
+            anyhow::bail!(
+                "{} mint {} is not an initialized mint account",
+                info.symbol,
+                info.mint
+            );
+        }
+        let decimals = account.data[MINT_DECIMALS_OFFSET];
+        if decimals != info.decimals {
+            anyhow::bail!(
+                "{} mint {} has {} decimals on chain but {} are configured",
+                info.symbol,
+                info.mint,
+                decimals,
+                info.decimals
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Token account → mint (`None`: not a token account), filled by
+/// [`lookup_token_account_mints`]. A token account's mint never changes, so
+/// entries don't expire; the map is just dropped if it grows too large.
+static TOKEN_ACCOUNT_MINTS: LazyLock<Mutex<HashMap<String, Option<String>>>> =
+    LazyLock::new(Default::default);
+const TOKEN_ACCOUNT_MINTS_CAPACITY: usize = 10_000;
+
+/// Resolves the mint of each token account in `accounts` with
+/// `getAccountInfo`, caching the answers. Accounts that can't be fetched
+/// right now are left out and retried next time.
+async fn lookup_token_account_mints(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    accounts: &[String],
+) -> HashMap<String, String> {
+    let mut resolved = HashMap::new();
+    for account in accounts {
+        let cached = TOKEN_ACCOUNT_MINTS.lock().unwrap().get(account).cloned();
+        let mint = match cached {
+            Some(mint) => mint,
+            None => {
+                let Ok(address) = Pubkey::from_str(account) else {
+                    continue;
+                };
+                let fetched = with_retry("getAccountInfo", config.rpc_max_attempts, || {
+                    client.get_account(&address, config.commitment)
+                })
+                .await;
+                let mint = match fetched {
+                    Ok(account) => account
+                        .and_then(|account| token_account_mint(&account, &config.token_programs)),
+                    Err(e) => {
+                        warn!(account = %address, error = %e, "token account lookup failed");
+                        continue;
+                    }
+                };
+                let mut cache = TOKEN_ACCOUNT_MINTS.lock().unwrap();
+                if cache.len() >= TOKEN_ACCOUNT_MINTS_CAPACITY {
+                    cache.clear();
+                }
+                cache.insert(account.clone(), mint.clone());
+                mint
+            }
+        };
+        if let Some(mint) = mint {
+            resolved.insert(account.clone(), mint);
+        }
+    }
+    resolved
+}
+
+/// Slot → block time, filled by [`block_time`] for signatures listed
+/// without one. A slot's time is fixed once it is known.
+static BLOCK_TIMES: LazyLock<Mutex<HashMap<u64, i64>>> = LazyLock::new(Default::default);
+const BLOCK_TIMES_CAPACITY: usize = 100_000;
+
+/// `listed` if the RPC gave a block time alongside the signature, else
+/// `getBlockTime` of its `slot`. `None` only when that fails too; the
+/// signature is then counted as skipped.
+async fn block_time(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    signature: &str,
+    slot: u64,
+    listed: Option<i64>,
+) -> Option<i64> {
+    if listed.is_some() {
+        return listed;
+    }
+    if let Some(&cached) = BLOCK_TIMES.lock().unwrap().get(&slot) {
+        return Some(cached);
+    }
+    match with_retry("getBlockTime", config.rpc_max_attempts, || {
+        client.get_block_time(slot)
+    })
+    .await
+    {
+        Ok(block_time) => {
+            let mut cache = BLOCK_TIMES.lock().unwrap();
+            if cache.len() >= BLOCK_TIMES_CAPACITY {
+                cache.clear();
+            }
+            cache.insert(slot, block_time);
+            Some(block_time)
+        }
+        Err(e) => {
+            warn!(signature, slot, error = %e, "no block time for signature, skipping");
+            METRICS
+                .signatures_skipped
+                .with_label_values(&[diagnostics::MISSING_BLOCK_TIME])
+                .inc();
+            diagnostics::record(diagnostics::MISSING_BLOCK_TIME, signature);
+            None
+        }
+    }
+}
+
+/// What a backfill window ending at `end` (or, without it, at the present)
+/// is measured back from. The present is the block time of the latest
+/// finalized slot rather than the host clock, which may be skewed; the host
+/// clock is only used when the chain can't be asked.
+pub async fn window_anchor(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    end: Option<i64>,
+) -> WindowAnchor {
+    if let Some(end) = end {
+        return WindowAnchor {
+            source: AnchorSource::Request,
+            time: end,
+            slot: None,
+        };
+    }
+    let slot = with_retry("getSlot", config.rpc_max_attempts, || {
+        client.get_finalized_slot()
+    })
+    .await;
+    let chain = match slot {
+        Ok(slot) => with_retry("getBlockTime", config.rpc_max_attempts, || {
+            client.get_block_time(slot)
+        })
+        .await
+        .map(|time| (slot, time)),
+        Err(e) => Err(e),
+    };
+    match chain {
+        Ok((slot, time)) => WindowAnchor {
+            source: AnchorSource::Chain,
+            time,
+            slot: Some(slot),
+        },
+        Err(e) => {
+            warn!(error = %e, "no finalized block time, anchoring the window to the system clock");
+            WindowAnchor {
+                source: AnchorSource::SystemClock,
+                time: Utc::now().timestamp(),
+                slot: None,
+            }
+        }
+    }
+}
+
+/// Token account → owner wallet and when it was looked up, filled by
+/// [`resolve_counterparty_owners`]. Ownership can be reassigned, so entries
+/// older than `owner_cache_ttl` are fetched again.
+static TOKEN_ACCOUNT_OWNERS: LazyLock<Mutex<HashMap<String, CachedOwner>>> =
+    LazyLock::new(Default::default);
+type CachedOwner = (Option<String>, Instant);
+const TOKEN_ACCOUNT_OWNERS_CAPACITY: usize = 10_000;
+
+/// Fills in `counterparty_owner` where the transaction's token balances
+/// didn't name it, from `getAccountInfo` of the counterparty token account.
+/// Each distinct account is fetched at most once per TTL; accounts that
+/// can't be fetched now stay unresolved.
+#[instrument(skip_all, fields(transfers = transfers.len()))]
+async fn resolve_counterparty_owners(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    transfers: &mut [Transfer],
+) {
+    let unresolved: HashSet<String> = transfers
+        .iter()
+        .filter(|t| t.counterparty_owner.is_none() && !t.direction.is_supply_change())
+        .map(|t| t.counterparty().to_string())
+        .collect();
+    if unresolved.is_empty() {
+        return;
+    }
+    let owners: HashMap<String, String> = stream::iter(unresolved)
+        .map(|account| async move {
+            let owner = token_account_owner(client, config, &account).await;
+            owner.map(|owner| (account, owner))
+        })
+        .buffer_unordered(config.fetch_concurrency)
+        .filter_map(|resolved| async move { resolved })
+        .collect()
+        .await;
+    for transfer in transfers {
+        if transfer.counterparty_owner.is_none() {
+            transfer.counterparty_owner = owners.get(transfer.counterparty()).cloned();
+        }
+    }
+}
+
+async fn token_account_owner(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    account: &str,
+) -> Option<String> {
+    let cached = TOKEN_ACCOUNT_OWNERS.lock().unwrap().get(account).cloned();
+    if let Some((owner, looked_up)) = cached {
+        if looked_up.elapsed() < config.owner_cache_ttl {
+            return owner;
+        }
+    }
+    let address = Pubkey::from_str(account).ok()?;
+    let fetched = with_retry("getAccountInfo", config.rpc_max_attempts, || {
+        client.get_account(&address, config.commitment)
+    })
+    .await;
+    let owner = match fetched {
+        Ok(fetched) => {
+            fetched.and_then(|fetched| token_account_owner_field(&fetched, &config.token_programs))
+        }
+        Err(e) => {
+            warn!(account = %address, error = %e, "counterparty owner lookup failed");
+            return None;
+        }
+    };
+    let mut cache = TOKEN_ACCOUNT_OWNERS.lock().unwrap();
+    if cache.len() >= TOKEN_ACCOUNT_OWNERS_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(account.to_string(), (owner.clone(), Instant::now()));
+    owner
+}
+
+/// The owner of a token account: bytes 32..64 of its data, after the mint.
+fn token_account_owner_field(account: &Account, programs: &[TokenProgram]) -> Option<String> {
+    token_account_mint(account, programs)?;
+    let owner: [u8; 32] = account.data[32..64].try_into().ok()?;
+    Some(Pubkey::new_from_array(owner).to_string())
+}
+
+/// The mint of a token account: the first 32 bytes of its data, under
+/// either token program.
+fn token_account_mint(account: &Account, programs: &[TokenProgram]) -> Option<String> {
+    if !programs.iter().any(|p| p.pubkey() == account.owner)
+        || account.data.len() < TOKEN_ACCOUNT_MIN_LEN
+    {
+        return None;
+    }
+    let mint: [u8; 32] = account.data[..32].try_into().ok()?;
+    Some(Pubkey::new_from_array(mint).to_string())
+}
+
+/// The commitment transactions and signature listings are read at: the
+/// RPC serves neither below `confirmed`.
+pub fn history_commitment(commitment: CommitmentConfig) -> CommitmentConfig {
+    if commitment.is_at_least_confirmed() {
+        commitment
+    } else {
+        CommitmentConfig::confirmed()
+    }
+}
+
+#[instrument(skip(client, config, commitment))]
+pub async fn fetch_transaction(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    signature: &str,
+    commitment: CommitmentConfig,
+) -> Result<EncodedConfirmedTransactionWithStatusMeta, IndexerError> {
+    let signature: Signature = signature
+        .parse()
+        .map_err(|e| IndexerError::Decode(format!("signature '{}': {}", signature, e)))?;
+    let tx = with_retry("getTransaction", config.rpc_max_attempts, || {
+        client.get_transaction(
+            &signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::JsonParsed),
+                commitment: Some(history_commitment(commitment)),
+                // Without this the RPC rejects any transaction using address
+                // lookup tables, which is most aggregator traffic.
+                max_supported_transaction_version: Some(0),
+            },
+        )
+    })
+    .await?;
+    Ok(tx)
+}
+
+/// Parses `signature` on its own, the way a backfill would, for each of
+/// `wallets` and every configured mint. The cluster's status for it is
+/// checked first, so an unknown signature is a `NotFound` rather than an
+/// RPC error.
+pub async fn transaction_effect(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    signature: &str,
+    wallets: &[Pubkey],
+    strategy: Strategy,
+    commitment: CommitmentConfig,
+) -> Result<TransactionEffect, IndexerError> {
+    let parsed: Signature = signature
+        .parse()
+        .map_err(|e| IndexerError::InvalidParameter(format!("signature '{}': {}", signature, e)))?;
+    let statuses = with_retry("getSignatureStatuses", config.rpc_max_attempts, || {
+        client.get_signature_statuses(std::slice::from_ref(&parsed))
+    })
+    .await?;
+    if statuses.into_iter().next().flatten().is_none() {
+        return Err(IndexerError::NotFound(format!(
+            "transaction {} not found",
+            signature
+        )));
+    }
+    let tx = fetch_transaction(client, config, signature, commitment).await?;
+    // Transfers are dated by their block; without its time there is nothing
+    // honest to put on them.
+    let Some(time) = block_time(client, config, signature, tx.slot, tx.block_time).await else {
+        return Err(IndexerError::Rpc(format!(
+            "no block time for slot {} of transaction {}",
+            tx.slot, signature
+        )));
+    };
+    let meta = tx.transaction.meta.as_ref();
+    let sig_info = RpcConfirmedTransactionStatusWithSignature {
+        signature: signature.to_string(),
+        slot: tx.slot,
+        err: meta.and_then(|meta| meta.err.clone()),
+        memo: None,
+        block_time: Some(time),
+        confirmation_status: None,
+    };
+
+    let mut transfers = Vec::new();
+    let mut discrepancies = Vec::new();
+    for wallet in wallets {
+        for mint in &config.mints.mints {
+            let wallet_context = WalletContext::new(wallet, mint, &config.token_programs);
+            let Some(found) = extract(
+                client,
+                config,
+                &tx,
+                &sig_info,
+                time,
+                &wallet_context,
+                mint,
+                strategy,
+            )
+            .await
+            else {
+                return Err(IndexerError::Decode(format!("transaction {}", signature)));
+            };
+            discrepancies.extend(found.discrepancy);
+            transfers.extend(found.transfers.into_iter().map(|mut transfer| {
+                transfer.wallet = Some(wallet.to_string());
+                transfer
+            }));
+        }
+    }
+    resolve_counterparty_owners(client, config, &mut transfers).await;
+    sort_transfers(&mut transfers, SortOrder::Asc);
+    Ok(TransactionEffect {
+        signature: signature.to_string(),
+        slot: tx.slot,
+        block_time: Some(time),
+        fee_payer: message_account_keys(&tx).into_iter().next(),
+        network_fee: meta.map(|meta| NetworkFee::new(meta.fee)),
+        error: sig_info.err.as_ref().map(|err| err.to_string()),
+        transfers,
+        discrepancies,
+    })
+}
+
+/// Result of a backfill: the transfers plus how much data couldn't be read.
+#[derive(Debug, Default)]
+pub struct BackfillOutcome {
+    pub transfers: Vec<Transfer>,
+    /// Transactions fetched but not returned in a decodable (jsonParsed) shape.
+    pub undecodable_transactions: usize,
+    /// Newest signature inside the window, whether or not it held a transfer.
+    pub newest_signature: Option<String>,
+    /// Slot and block time of `newest_signature`.
+    pub newest_block: Option<(u64, i64)>,
+    /// Only filled with [`Strategy::Both`].
+    pub discrepancies: Vec<Discrepancy>,
+    /// Every transaction fetched, when `raw_transaction_limit` is set.
+    pub raw: Vec<RawTransaction>,
+    /// Transactions read from the stored raw JSON instead of the RPC.
+    pub reused_raw_transactions: usize,
+    /// `getSignaturesForAddress` pages walked.
+    pub pages_scanned: usize,
+    /// `max_signature_pages` ran out before the window was covered, so
+    /// older transfers may be missing.
+    pub truncated: bool,
+    /// Signature and block time of every transaction decoded, to be recorded
+    /// once its transfers are stored.
+    pub processed: Vec<(String, i64)>,
+    /// Delegate approvals and revokes of the wallet's token accounts that
+    /// landed in the window.
+    pub approvals: Vec<Approval>,
+    /// Openings and closings of the wallet's token accounts in the window.
+    pub account_events: Vec<AccountEvent>,
+}
+
+/// The signature pages one backfill has walked against
+/// `max_signature_pages`.
+#[derive(Debug, Default)]
+struct PageBudget {
+    scanned: usize,
+    truncated: bool,
+}
+
+pub async fn backfill_transfers(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    request: &BackfillRequest,
+) -> Result<BackfillOutcome, IndexerError> {
+    backfill_from(client, config, request, None, None).await
+}
+
+/// [`backfill_transfers`], reading each transaction from `raw_store` when
+/// it holds it and from the RPC otherwise, and skipping those
+/// `processed_store` already holds the transfers of. Signatures are always
+/// listed from the RPC.
+#[instrument(
+    skip_all,
+    fields(
+        wallet = %request.wallet,
+        mint = %request.mint.symbol,
+        start = request.window.start,
+        end = request.window.end,
+    )
+)]
+async fn backfill_from(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    request: &BackfillRequest,
+    raw_store: Option<&Storage>,
+    processed_store: Option<&Storage>,
+) -> Result<BackfillOutcome, IndexerError> {
+    let BackfillRequest {
+        wallet,
+        mint,
+        window,
+        until,
+        include_failed,
+        strategy,
+        commitment,
+        // Nothing read from the chain here has been re-checked yet.
+        include_orphaned: _,
+    } = request;
+    // Anything short of finalized is marked, as it may still roll back.
+    let read_at = history_commitment(*commitment);
+    let recorded = (!read_at.is_finalized()).then(|| commitment_name(read_at).to_string());
+    let wallet_label = config.wallet_label(wallet);
+    let _timer = METRICS
+        .backfill_duration
+        .with_label_values(&[&wallet_label])
+        .start_timer();
+    let wallet_context = WalletContext::new(wallet, mint, &config.token_programs);
+    // Guards against counting an event twice if pages ever overlap.
+    let mut seen = HashSet::new();
+
+    let mut transfers = Vec::new();
+    let mut undecodable_transactions = 0;
+    let mut discrepancies = Vec::new();
+    let mut raw = Vec::new();
+    let mut reused_raw_transactions = 0;
+
+    let addresses =
+        signature_addresses(client, config, wallet, mint, &wallet_context, *commitment).await?;
+    // The same transaction usually shows up in several listings; each is
+    // read once. Listings are worked through a page at a time, so only the
+    // signatures seen so far are held, not every page of every address.
+    let mut listed = HashSet::new();
+    let mut newest: Option<(i64, u64, String)> = None;
+    let mut processed = Vec::new();
+    let mut approvals = Vec::new();
+    let mut account_events = Vec::new();
+    let mut pages = PageBudget::default();
+    for address in &addresses {
+        let mut before = None;
+        loop {
+            deadline::check()?;
+            let page = signature_page(
+                client, config, address, *until, before, window, read_at, &mut pages,
+            )
+            .await?;
+            let mut batch = Vec::new();
+            for (sig_info, block_time) in page.in_window {
+                if !listed.insert(sig_info.signature.clone()) {
+                    continue;
+                }
+                let is_newer = newest.as_ref().is_none_or(|(time, slot, signature)| {
+                    (block_time, sig_info.slot)
+                        .cmp(&(*time, *slot))
+                        .then_with(|| signature.cmp(&sig_info.signature))
+                        .is_gt()
+                });
+                if is_newer {
+                    newest = Some((block_time, sig_info.slot, sig_info.signature.clone()));
+                }
+                // Failed transactions are known from the listing alone, so
+                // skip them before spending a getTransaction call.
+                if sig_info.err.is_some() && !include_failed {
+                    debug!(signature = %sig_info.signature, "skipping failed transaction");
+                    continue;
+                }
+                batch.push((sig_info, block_time));
+            }
+            if let Some(store) = processed_store.filter(|_| !batch.is_empty()) {
+                let signatures: Vec<String> =
+                    batch.iter().map(|(s, _)| s.signature.clone()).collect();
+                let done = store
+                    .processed_signatures(wallet, &mint.mint, &signatures)
+                    .await?;
+                if !done.is_empty() {
+                    debug!(
+                        signatures = done.len(),
+                        "skipping already processed transactions"
+                    );
+                    METRICS
+                        .signatures_skipped
+                        .with_label_values(&["already_processed"])
+                        .inc_by(done.len() as u64);
+                    batch.retain(|(s, _)| !done.contains(&s.signature));
+                }
+            }
+
+            let mut fetched = stream::iter(batch)
+                .map(|(sig_info, block_time)| async move {
+                    let result = match stored_transaction(raw_store, &sig_info.signature).await {
+                        Some(tx) => Ok((tx, true)),
+                        None => fetch_transaction(client, config, &sig_info.signature, read_at)
+                            .await
+                            .map(|tx| (tx, false)),
+                    };
+                    (sig_info, block_time, result)
+                })
+                .buffered(config.fetch_concurrency);
+            while let Some((sig_info, block_time, result)) = fetched.next().await {
+                // Dropping `fetched` abandons the fetches still in flight.
+                deadline::check()?;
+                match result {
+                    Ok((tx, reused)) => {
+                        if reused {
+                            reused_raw_transactions += 1;
+                        } else if config.raw_transaction_limit.is_some() {
+                            match RawTransaction::encode(&sig_info.signature, &tx) {
+                                Ok(encoded) => raw.push(encoded),
+                                Err(e) => warn!(
+                                    signature = %sig_info.signature,
+                                    error = %e,
+                                    "failed to encode raw transaction"
+                                ),
+                            }
+                        }
+                        let Some(extracted) = extract(
+                            client,
+                            config,
+                            &tx,
+                            &sig_info,
+                            block_time,
+                            &wallet_context,
+                            mint,
+                            *strategy,
+                        )
+                        .await
+                        else {
+                            warn!(
+                                signature = %sig_info.signature,
+                                "transaction could not be decoded, skipping"
+                            );
+                            METRICS
+                                .transactions_parsed
+                                .with_label_values(&["undecodable"])
+                                .inc();
+                            let reason = if tx.transaction.meta.is_none()
+                                && *strategy != Strategy::Instructions
+                            {
+                                diagnostics::MISSING_META
+                            } else {
+                                diagnostics::UNSUPPORTED_ENCODING
+                            };
+                            diagnostics::record(reason, &sig_info.signature);
+                            undecodable_transactions += 1;
+                            continue;
+                        };
+                        METRICS
+                            .transactions_parsed
+                            .with_label_values(&["decoded"])
+                            .inc();
+                        processed.push((sig_info.signature.clone(), block_time));
+                        approvals.extend(extracted.approvals);
+                        account_events.extend(extracted.account_events);
+                        if let Some(discrepancy) = extracted.discrepancy {
+                            warn!(
+                                signature = %discrepancy.signature,
+                                accounts = discrepancy.accounts.len(),
+                                "instruction and balance strategies disagree"
+                            );
+                            discrepancies.push(discrepancy);
+                        }
+                        for mut transfer in extracted.transfers {
+                            if transfer.failed && !include_failed {
+                                continue;
+                            }
+                            transfer.commitment = recorded.clone();
+                            if seen.insert(transfer.event_key()) {
+                                jobs::record_transfer();
+                                METRICS
+                                    .transfers_found
+                                    .with_label_values(&[
+                                        &wallet_label,
+                                        transfer.direction.as_str(),
+                                    ])
+                                    .inc();
+                                transfers.push(transfer);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!(
+                            signature = %sig_info.signature,
+                            error = %e,
+                            "failed to fetch transaction, skipping"
+                        );
+                        METRICS
+                            .signatures_skipped
+                            .with_label_values(&[diagnostics::FETCH_FAILED])
+                            .inc();
+                        diagnostics::record(diagnostics::FETCH_FAILED, &sig_info.signature);
+                    }
+                }
+            }
+
+            match page.next {
+                Some(next) => before = Some(next),
+                None => break,
+            }
+        }
+    }
+    if pages.truncated {
+        METRICS
+            .backfills_truncated
+            .with_label_values(&[&wallet_label])
+            .inc();
+    }
+    let newest_signature = newest.as_ref().map(|(_, _, signature)| signature.clone());
+    let newest_block = newest.map(|(block_time, slot, _)| (slot, block_time));
+
+    resolve_counterparty_owners(client, config, &mut transfers).await;
+    sort_transfers(&mut transfers, SortOrder::Asc);
+    info!(
+        transfers = transfers.len(),
+        undecodable_transactions,
+        pages_scanned = pages.scanned,
+        truncated = pages.truncated,
+        "backfill finished"
+    );
+    Ok(BackfillOutcome {
+        transfers,
+        undecodable_transactions,
+        newest_signature,
+        newest_block,
+        discrepancies,
+        raw,
+        reused_raw_transactions,
+        pages_scanned: pages.scanned,
+        truncated: pages.truncated,
+        processed,
+        approvals,
+        account_events,
+    })
+}
+
+/// `signature`'s raw transaction, if `store` is given and holds a readable
+/// copy.
+async fn stored_transaction(
+    store: Option<&Storage>,
+    signature: &str,
+) -> Option<EncodedConfirmedTransactionWithStatusMeta> {
+    let raw = match store?.raw_transaction(signature).await {
+        Ok(raw) => raw?,
+        Err(e) => {
+            warn!(%signature, error = %e, "failed to read raw transaction");
+            return None;
+        }
+    };
+    match raw.decode() {
+        Ok(tx) => Some(tx),
+        Err(e) => {
+            warn!(%signature, error = %e, "stored transaction unreadable, fetching it");
+            None
+        }
+    }
+}
+
+/// Addresses whose signature listings together cover the wallet's history
+/// of `mint`. Incoming transfers often reference only the receiving token
+/// account, so with `index_token_accounts` every token account the wallet
+/// holds for the mint (plus the derived ATAs, which may be closed) is listed
+/// alongside the owner; the owner stays in so closed non-ATA accounts'
+/// sends are still covered.
+#[instrument(skip_all)]
+async fn signature_addresses(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    wallet: &Pubkey,
+    mint: &MintInfo,
+    wallet_context: &WalletContext,
+    commitment: CommitmentConfig,
+) -> Result<Vec<Pubkey>, IndexerError> {
+    let mut addresses = vec![*wallet];
+    if !config.index_token_accounts {
+        return Ok(addresses);
+    }
+    let response = with_retry("getTokenAccountsByOwner", config.rpc_max_attempts, || {
+        client.get_token_accounts_by_owner(wallet, &mint.mint, commitment)
+    })
+    .await?;
+    let held = response.value.iter().map(|keyed| keyed.pubkey.as_str());
+    for account in held.chain(wallet_context.derived_accounts()) {
+        let address = Pubkey::from_str(account)
+            .map_err(|e| IndexerError::Decode(format!("token account '{}': {}", account, e)))?;
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    Ok(addresses)
+}
+
+/// One `getSignaturesForAddress` page, cut to the window.
+struct SignaturePage {
+    in_window: Vec<(RpcConfirmedTransactionStatusWithSignature, i64)>,
+    /// Where the next page starts; `None` once the listing is done.
+    next: Option<Signature>,
+}
+
+/// The page of `address`'s signatures (newest first) before `before`,
+/// keeping those inside `window` with their block time. The listing is done
+/// at `window.start` or `until`, or early, marking `pages` truncated, once
+/// `max_signature_pages` are used up.
+#[instrument(skip_all, fields(%address, before = ?before))]
+#[allow(clippy::too_many_arguments)]
+async fn signature_page(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    address: &Pubkey,
+    until: Option<Signature>,
+    before: Option<Signature>,
+    window: &TimeWindow,
+    commitment: CommitmentConfig,
+    pages: &mut PageBudget,
+) -> Result<SignaturePage, IndexerError> {
+    let mut page = SignaturePage {
+        in_window: Vec::new(),
+        next: None,
+    };
+    if pages.scanned >= config.max_signature_pages {
+        warn!(
+            %address,
+            max_signature_pages = config.max_signature_pages,
+            "signature page cap reached, backfill truncated"
+        );
+        pages.truncated = true;
+        return Ok(page);
+    }
+    pages.scanned += 1;
+    let sigs = with_retry("getSignaturesForAddress", config.rpc_max_attempts, || {
+        client.get_signatures_for_address(
+            address,
+            GetConfirmedSignaturesForAddress2Config {
+                before,
+                until,
+                limit: Some(config.signature_page_size),
+                commitment: Some(commitment),
+            },
+        )
+    })
+    .await?;
+
+    // Signatures come newest-first, so everything after the first one older
+    // than the window can be ignored along with later pages. Times missing
+    // from the listing are looked up by slot, so a page of them still
+    // reaches the cutoff.
+    let mut reached_start = false;
+    let mut oldest = None;
+    for sig_info in &sigs {
+        let listed = sig_info.block_time;
+        let Some(block_time) =
+            block_time(client, config, &sig_info.signature, sig_info.slot, listed).await
+        else {
+            continue;
+        };
+        oldest = Some(oldest.map_or(block_time, |oldest: i64| oldest.min(block_time)));
+
+        if block_time > window.end {
+            continue;
+        }
+        if block_time < window.start {
+            reached_start = true;
+            break;
+        }
+        page.in_window.push((sig_info.clone(), block_time));
+    }
+    jobs::record_signatures(sigs.len(), oldest);
+    debug!(
+        %address,
+        signatures = sigs.len(),
+        before = ?before,
+        "fetched signature page"
+    );
+
+    if !reached_start {
+        page.next = sigs.last().and_then(|s| s.signature.parse().ok());
+    }
+    Ok(page)
+}
+
+/// What [`extract`] found in one transaction.
+struct Extracted {
+    transfers: Vec<Transfer>,
+    approvals: Vec<Approval>,
+    account_events: Vec<AccountEvent>,
+    discrepancy: Option<Discrepancy>,
+}
+
+/// Runs the extraction `strategy` asks for on one fetched transaction,
+/// plus the wallet's approvals and account lifecycle, which only
+/// instructions show. `None` means the transaction couldn't be decoded.
+#[instrument(name = "parse", skip_all, fields(signature = %sig_info.signature))]
+#[allow(clippy::too_many_arguments)]
+async fn extract(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    sig_info: &RpcConfirmedTransactionStatusWithSignature,
+    block_time: i64,
+    wallet: &WalletContext,
+    mint: &MintInfo,
+    strategy: Strategy,
+) -> Option<Extracted> {
+    let converted = decode::json_parsed(tx, wallet.programs());
+    if converted.is_some() {
+        debug!(signature = %sig_info.signature, "decoding a transaction without jsonParsed");
+    }
+    let tx = converted.as_ref().unwrap_or(tx);
+    let mut extracted = Extracted {
+        transfers: Vec::new(),
+        approvals: extract_approvals(tx, sig_info, block_time, wallet, mint),
+        account_events: extract_account_events(tx, sig_info, block_time, wallet, mint),
+        discrepancy: None,
+    };
+    if strategy == Strategy::Balances {
+        extracted.transfers = balance_transfers(tx, sig_info, block_time, wallet, mint)?;
+        return Some(extracted);
+    }
+    let missing = accounts_missing_mint(tx, wallet, mint);
+    let looked_up = lookup_token_account_mints(client, config, &missing).await;
+    let transfers = extract_transfers(tx, sig_info, block_time, wallet, mint, &looked_up)?;
+    if strategy == Strategy::Instructions {
+        extracted.transfers = transfers;
+        return Some(extracted);
+    }
+    extracted.discrepancy =
+        balance_transfers(tx, sig_info, block_time, wallet, mint).and_then(|from_balances| {
+            find_discrepancy(
+                &sig_info.signature,
+                sig_info.slot,
+                &transfers,
+                &from_balances,
+                wallet,
+                &token_account_owners(tx),
+                mint.decimals,
+            )
+        });
+    extracted.transfers = transfers;
+    Some(extracted)
+}
+
+/// What one [`sync_store`] call added to the store.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub new_transfers: usize,
+    pub undecodable_transactions: usize,
+    pub newest_signature: Option<String>,
+    pub pages_scanned: usize,
+    /// A backfill hit `max_signature_pages`; the sync state was left as it
+    /// was so the gap isn't recorded as indexed.
+    pub truncated: bool,
+}
+
+/// Brings the store up to date for `wallet`/`mint` over `[start, now]`:
+/// fetches new signatures since the stored cursor, plus older history if
+/// `start` reaches further back than anything indexed so far.
+pub async fn sync_store(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+    mint: &MintInfo,
+    start: i64,
+) -> Result<SyncReport, IndexerError> {
+    let now = Utc::now().timestamp();
+    let state = store.sync_state(wallet, &mint.mint).await?;
+    let tip = with_retry("getSlot", config.rpc_max_attempts, || client.get_slot()).await?;
+
+    // Store everything, failed or not; `include_failed` applies at query time.
+    let fetch = |start: i64, end: i64, until: Option<Signature>| BackfillRequest {
+        wallet: *wallet,
+        mint: mint.clone(),
+        window: TimeWindow { start, end },
+        until,
+        include_failed: true,
+        include_orphaned: false,
+        strategy: Strategy::Instructions,
+        commitment: config.commitment,
+    };
+
+    let mut report = SyncReport::default();
+    let previous_signature = state.as_ref().and_then(|s| s.newest_signature.clone());
+    let (new_state, newest_block) = match state {
+        None => {
+            let outcome = backfill_new(client, config, store, &fetch(start, now, None)).await?;
+            save_outcome(config, store, wallet, mint, &outcome).await?;
+            report.add(&outcome);
+            let state = SyncState {
+                indexed_from: start,
+                indexed_until: now,
+                newest_signature: outcome.newest_signature,
+            };
+            (state, outcome.newest_block)
+        }
+        Some(state) => {
+            let until = state
+                .newest_signature
+                .as_deref()
+                .and_then(|s| s.parse().ok());
+            let head = backfill_new(
+                client,
+                config,
+                store,
+                &fetch(state.indexed_until, now, until),
+            )
+            .await?;
+            save_outcome(config, store, wallet, mint, &head).await?;
+            // Only the head is news; first syncs and tail fills are history.
+            let landed: Vec<Transfer> = head
+                .transfers
+                .iter()
+                .filter(|t| !t.failed)
+                .cloned()
+                .collect();
+            events::publish(wallet, mint, &landed);
+            events::publish_approvals(wallet, mint, &head.approvals);
+            report.add(&head);
+
+            if start < state.indexed_from {
+                let tail = backfill_new(
+                    client,
+                    config,
+                    store,
+                    &fetch(start, state.indexed_from, None),
+                )
+                .await?;
+                save_outcome(config, store, wallet, mint, &tail).await?;
+                report.add(&tail);
+            }
+
+            let state = SyncState {
+                indexed_from: state.indexed_from.min(start),
+                indexed_until: now,
+                newest_signature: head.newest_signature.or(state.newest_signature),
+            };
+            (state, head.newest_block)
+        }
+    };
+    if report.truncated {
+        warn!(%wallet, mint = %mint.symbol, "sync truncated, keeping the previous sync state");
+        report.newest_signature = previous_signature;
+        return Ok(report);
+    }
+    store.set_sync_state(wallet, &mint.mint, &new_state).await?;
+    record_progress(store, wallet, mint, tip, now, newest_block).await;
+    report.newest_signature = new_state.newest_signature;
+    Ok(report)
+}
+
+impl SyncReport {
+    fn add(&mut self, outcome: &BackfillOutcome) {
+        self.new_transfers += outcome.transfers.len();
+        self.undecodable_transactions += outcome.undecodable_transactions;
+        self.pages_scanned += outcome.pages_scanned;
+        self.truncated |= outcome.truncated;
+    }
+}
+
+/// [`backfill_transfers`] for a sync: transactions whose transfers `store`
+/// already holds for the wallet/mint aren't read again.
+async fn backfill_new(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    request: &BackfillRequest,
+) -> Result<BackfillOutcome, IndexerError> {
+    backfill_from(client, config, request, None, Some(store)).await
+}
+
+/// Publishes a finished sync to [`progress`] and the lag gauges.
+async fn record_progress(
+    store: &Storage,
+    wallet: &Pubkey,
+    mint: &MintInfo,
+    tip: u64,
+    synced_at: i64,
+    newest_block: Option<(u64, i64)>,
+) {
+    let name = wallet.to_string();
+    let mint_address = mint.mint.to_string();
+    progress::record_sync(&name, &mint_address, tip, newest_block);
+    let labels = [name.as_str(), mint.symbol.as_str()];
+    METRICS
+        .synced_slot
+        .with_label_values(&labels)
+        .set(tip as i64);
+    METRICS.synced_at.with_label_values(&labels).set(synced_at);
+    if let Some((slot, block_time)) = progress::mint(&name, &mint_address).and_then(|p| p.newest) {
+        METRICS
+            .newest_indexed_slot
+            .with_label_values(&labels)
+            .set(slot as i64);
+        METRICS
+            .newest_block_time
+            .with_label_values(&labels)
+            .set(block_time);
+    }
+    match store.count_transfers(wallet, &mint.mint).await {
+        Ok(count) => METRICS
+            .stored_transfers
+            .with_label_values(&labels)
+            .set(count as i64),
+        Err(e) => {
+            warn!(%wallet, mint = %mint.symbol, error = %e, "counting stored transfers failed")
+        }
+    }
+}
+
+/// Stores what one backfill found, with the raw transactions if kept.
+async fn save_outcome(
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+    mint: &MintInfo,
+    outcome: &BackfillOutcome,
+) -> Result<(), IndexerError> {
+    store
+        .insert_batch(wallet, &mint.mint, &outcome.transfers)
+        .await?;
+    if let Some(limit) = config.raw_transaction_limit {
+        store.put_raw_transactions(&outcome.raw, limit).await?;
+    }
+    store
+        .insert_account_events(wallet, &mint.mint, &outcome.account_events)
+        .await?;
+    store
+        .mark_processed(wallet, &mint.mint, &outcome.processed)
+        .await?;
+    Ok(())
+}
+
+/// Answers `request` from the store. If the background poller (or another
+/// request) synced this wallet/mint within the last poll interval and the
+/// window is already covered, this is a pure read; otherwise the store is
+/// synced first.
+pub async fn backfill_with_store(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    request: &BackfillRequest,
+) -> Result<BackfillOutcome, IndexerError> {
+    // The store only holds instruction-derived transfers, read at the
+    // configured commitment.
+    if request.strategy != Strategy::Instructions || request.commitment != config.commitment {
+        return backfill_transfers(client, config, request).await;
+    }
+    let now = Utc::now().timestamp();
+    let state = store
+        .sync_state(&request.wallet, &request.mint.mint)
+        .await?;
+    let warm = match (&state, config.poll_interval) {
+        (Some(state), Some(interval)) => {
+            state.indexed_from <= request.window.start
+                && now - state.indexed_until <= interval.as_secs() as i64
+        }
+        _ => false,
+    };
+
+    let report = if warm {
+        SyncReport {
+            newest_signature: state.and_then(|s| s.newest_signature),
+            ..SyncReport::default()
+        }
+    } else {
+        sync_store(
+            client,
+            config,
+            store,
+            &request.wallet,
+            &request.mint,
+            request.window.start,
+        )
+        .await?
+    };
+
+    Ok(BackfillOutcome {
+        transfers: store.query_transfers(request).await?,
+        undecodable_transactions: report.undecodable_transactions,
+        newest_signature: report.newest_signature,
+        newest_block: None,
+        discrepancies: Vec::new(),
+        raw: Vec::new(),
+        reused_raw_transactions: 0,
+        pages_scanned: report.pages_scanned,
+        truncated: report.truncated,
+        processed: Vec::new(),
+        approvals: Vec::new(),
+        account_events: Vec::new(),
+    })
+}
+
+/// What one [`reverify_unfinalized`] pass found, by signature.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReverifyReport {
+    pub finalized: usize,
+    pub pending: usize,
+    pub orphaned: usize,
+}
+
+/// Re-checks `wallet`'s stored transactions that were read below
+/// `finalized`. Finalized ones are cleared and never checked again. Ones
+/// the cluster no longer has, or that now land with an error, are marked
+/// orphaned and drop out of default queries; if one turns up again within
+/// [`REORG_WINDOW_SLOTS`] it is restored.
+pub async fn reverify_unfinalized(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+) -> Result<ReverifyReport, IndexerError> {
+    let mut report = ReverifyReport::default();
+    let unfinalized = store.unfinalized_signatures(wallet).await?;
+    if unfinalized.is_empty() {
+        return Ok(report);
+    }
+    let tip = with_retry("getSlot", config.rpc_max_attempts, || client.get_slot()).await?;
+    let due: Vec<UnfinalizedSignature> = unfinalized
+        .into_iter()
+        .filter(|s| !s.orphaned || tip.saturating_sub(s.slot) <= REORG_WINDOW_SLOTS)
+        .collect();
+
+    for batch in due.chunks(MAX_STATUS_BATCH) {
+        let signatures = batch
+            .iter()
+            .map(|s| {
+                Signature::from_str(&s.signature).map_err(|e| {
+                    IndexerError::Decode(format!("stored signature {}: {}", s.signature, e))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let statuses = with_retry("getSignatureStatuses", config.rpc_max_attempts, || {
+            client.get_signature_statuses(&signatures)
+        })
+        .await?;
+
+        for (stored, status) in batch.iter().zip(statuses) {
+            let (commitment, orphaned) = match &status {
+                None => (Some(stored.commitment.as_str()), true),
+                Some(status) => {
+                    // A reverted transaction was recorded as such; one that
+                    // only reverts on the surviving fork was not.
+                    let orphaned = status.err.is_some() && !stored.failed;
+                    let commitment = match status.confirmation_status() {
+                        TransactionConfirmationStatus::Finalized => None,
+                        TransactionConfirmationStatus::Confirmed => Some("confirmed"),
+                        TransactionConfirmationStatus::Processed => Some("processed"),
+                    };
+                    (commitment, orphaned)
+                }
+            };
+            let outcome = match (orphaned, commitment) {
+                (true, _) => {
+                    report.orphaned += 1;
+                    "orphaned"
+                }
+                (false, None) => {
+                    report.finalized += 1;
+                    "finalized"
+                }
+                (false, Some(_)) => {
+                    report.pending += 1;
+                    "pending"
+                }
+            };
+            METRICS
+                .signature_reverifications
+                .with_label_values(&[outcome])
+                .inc();
+            if orphaned != stored.orphaned {
+                if orphaned {
+                    warn!(%wallet, signature = %stored.signature, slot = stored.slot, "transfer orphaned");
+                } else {
+                    info!(%wallet, signature = %stored.signature, "orphaned transfer restored");
+                }
+            }
+            if orphaned != stored.orphaned || commitment != Some(stored.commitment.as_str()) {
+                store
+                    .set_confirmation(wallet, &stored.signature, commitment, orphaned)
+                    .await?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// What [`reindex`] rebuilt for one mint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct MintReindex {
+    pub mint: String,
+    pub symbol: String,
+    /// The part of the requested window that had been indexed; the rest is
+    /// left to the next sync.
+    pub window: TimeWindow,
+    pub removed_transfers: u64,
+    pub transfers: usize,
+    /// Transactions taken from the stored raw JSON rather than the RPC.
+    pub reused_raw_transactions: usize,
+}
+
+/// Result of an admin reindex job.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ReindexReport {
+    pub wallet: String,
+    /// One entry per mint indexed for the wallet within the window.
+    pub mints: Vec<MintReindex>,
+}
+
+/// Rebuilds `wallet`'s stored transfers of each of `mints` inside
+/// `window`: the window is backfilled again, from the stored raw
+/// transactions where they are kept, and only then are the old records
+/// replaced, so a failed run leaves them as they were. Mints never indexed
+/// for the wallet, and parts of `window` outside what was indexed, are
+/// skipped.
+pub async fn reindex(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+    mints: &[MintInfo],
+    window: TimeWindow,
+) -> Result<ReindexReport, IndexerError> {
+    let mut report = ReindexReport {
+        wallet: wallet.to_string(),
+        mints: Vec::new(),
+    };
+    let raw_store = config.raw_transaction_limit.map(|_| store);
+    for mint in mints {
+        let Some(state) = store.sync_state(wallet, &mint.mint).await? else {
+            continue;
+        };
+        let window = TimeWindow {
+            start: window.start.max(state.indexed_from),
+            end: window.end.min(state.indexed_until),
+        };
+        if window.start > window.end {
+            continue;
+        }
+        let request = BackfillRequest {
+            wallet: *wallet,
+            mint: mint.clone(),
+            window,
+            until: None,
+            include_failed: true,
+            include_orphaned: false,
+            strategy: Strategy::Instructions,
+            commitment: config.commitment,
+        };
+        let outcome = backfill_from(client, config, &request, raw_store, None).await?;
+        let removed_transfers = store.delete_transfers(wallet, &mint.mint, &window).await?;
+        save_outcome(config, store, wallet, mint, &outcome).await?;
+        info!(
+            %wallet,
+            mint = %mint.symbol,
+            start = window.start,
+            end = window.end,
+            removed_transfers,
+            transfers = outcome.transfers.len(),
+            reused_raw_transactions = outcome.reused_raw_transactions,
+            "reindex finished"
+        );
+        report.mints.push(MintReindex {
+            mint: mint.mint.to_string(),
+            symbol: mint.symbol.clone(),
+            window,
+            removed_transfers,
+            transfers: outcome.transfers.len(),
+            reused_raw_transactions: outcome.reused_raw_transactions,
+        });
+    }
+    Ok(report)
+}
+
+/// Stored raw transactions parsed per store page in [`reparse`].
+const REPARSE_PAGE: usize = 200;
+
+/// What one [`reparse`] run did.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ReparseReport {
+    /// Stored raw transactions read.
+    pub transactions: usize,
+    /// Of those, ones the parser couldn't read.
+    pub undecodable_transactions: usize,
+    /// Transfers stored in place of the earlier ones.
+    pub transfers: usize,
+}
+
+/// Re-extracts `wallet`'s transfers from every stored raw transaction, for
+/// each mint it has been indexed for, and replaces the transfers stored
+/// from those transactions. Only the token account lookups the parser
+/// needs touch the RPC. Reparsed transfers count as read at the configured
+/// commitment again until the next re-check.
+pub async fn reparse(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+) -> Result<ReparseReport, IndexerError> {
+    let read_at = history_commitment(config.commitment);
+    let recorded = (!read_at.is_finalized()).then(|| commitment_name(read_at).to_string());
+    let mut mints = Vec::new();
+    for mint in &config.mints.mints {
+        if store.sync_state(wallet, &mint.mint).await?.is_some() {
+            mints.push(mint);
+        }
+    }
+    let mut report = ReparseReport::default();
+    let mut after: Option<String> = None;
+    loop {
+        let page = store
+            .raw_transactions(after.as_deref(), REPARSE_PAGE)
+            .await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = Some(last.signature.clone());
+        report.transactions += page.len();
+        let mut decoded = Vec::new();
+        for raw in &page {
+            match raw.decode() {
+                Ok(tx) => {
+                    let listed = tx.block_time;
+                    if let Some(time) =
+                        block_time(client, config, &raw.signature, tx.slot, listed).await
+                    {
+                        decoded.push((raw.signature.clone(), tx, time));
+                    }
+                }
+                Err(e) => {
+                    warn!(signature = %raw.signature, error = %e, "stored transaction unreadable");
+                    report.undecodable_transactions += 1;
+                }
+            }
+        }
+        // A transaction that is unreadable, or whose block time can't be
+        // found, keeps the transfers it had.
+        let signatures: Vec<String> = decoded.iter().map(|(sig, _, _)| sig.clone()).collect();
+        for mint in &mints {
+            let wallet_context = WalletContext::new(wallet, mint, &config.token_programs);
+            let mut transfers = Vec::new();
+            let mut account_events = Vec::new();
+            for (signature, tx, time) in &decoded {
+                let sig_info = RpcConfirmedTransactionStatusWithSignature {
+                    signature: signature.clone(),
+                    slot: tx.slot,
+                    err: tx
+                        .transaction
+                        .meta
+                        .as_ref()
+                        .and_then(|meta| meta.err.clone()),
+                    memo: None,
+                    block_time: Some(*time),
+                    confirmation_status: None,
+                };
+                let Some(found) = extract(
+                    client,
+                    config,
+                    tx,
+                    &sig_info,
+                    *time,
+                    &wallet_context,
+                    mint,
+                    Strategy::Instructions,
+                )
+                .await
+                else {
+                    continue;
+                };
+                transfers.extend(found.transfers.into_iter().map(|mut transfer| {
+                    transfer.commitment = recorded.clone();
+                    transfer
+                }));
+                account_events.extend(found.account_events);
+            }
+            resolve_counterparty_owners(client, config, &mut transfers).await;
+            store
+                .replace_transfers(wallet, &mint.mint, &signatures, &transfers)
+                .await?;
+            store
+                .insert_account_events(wallet, &mint.mint, &account_events)
+                .await?;
+            report.transfers += transfers.len();
+        }
+    }
+    info!(
+        %wallet,
+        transactions = report.transactions,
+        transfers = report.transfers,
+        "reparse finished"
+    );
+    Ok(report)
+}
+
+/// Keeps the default wallet's index warm for every registered mint, then
+/// re-checks whatever is stored below `finalized`. Runs iterations on a
+/// fixed interval; a tick that takes longer than the interval delays the
+/// next one instead of stacking on top of it.
+async fn run_poller(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    wallet: Pubkey,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.changed() => return,
+        }
+        if sync_all_mints(client.as_ref(), &config, &store, &wallet, "poll").await {
+            progress::record_poll(&wallet.to_string());
+        }
+        match reverify_unfinalized(client.as_ref(), &config, &store, &wallet).await {
+            Ok(report) if report != ReverifyReport::default() => info!(
+                %wallet,
+                finalized = report.finalized,
+                pending = report.pending,
+                orphaned = report.orphaned,
+                "re-verified unfinalized transfers"
+            ),
+            Ok(_) => {}
+            Err(e) => error!(%wallet, error = %e, "re-verifying unfinalized transfers failed"),
+        }
+    }
+}
+
+/// Syncs the configured lookback window of every registered mint for `wallet`,
+/// logging (not propagating) failures so long-running loops keep going.
+/// Returns whether every mint synced.
+async fn sync_all_mints(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+    source: &str,
+) -> bool {
+    let start = Utc::now().timestamp() - config.window_hours * 3600;
+    let mut synced = true;
+    for mint in &config.mints.mints {
+        // Read before the sync, so every transfer the balance reflects is
+        // stored by the time it is reconciled.
+        let snapshot = match due_snapshot(client, config, store, wallet, mint).await {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!(
+                    source,
+                    %wallet,
+                    mint = %mint.symbol,
+                    error = %e,
+                    "reading the balance to snapshot failed"
+                );
+                None
+            }
+        };
+        match sync_store(client, config, store, wallet, mint, start).await {
+            Ok(report) => {
+                info!(
+                    source,
+                    %wallet,
+                    mint = %mint.symbol,
+                    new_transfers = report.new_transfers,
+                    "sync finished"
+                );
+                if let Some((previous, balance)) = snapshot {
+                    let saved = save_snapshot(
+                        config,
+                        store,
+                        wallet,
+                        mint,
+                        previous,
+                        &balance,
+                        !report.truncated,
+                    )
+                    .await;
+                    if let Err(e) = saved {
+                        warn!(
+                            source,
+                            %wallet,
+                            mint = %mint.symbol,
+                            error = %e,
+                            "storing a balance snapshot failed"
+                        );
+                    }
+                }
+            }
+            Err(e) => {
+                error!(source, %wallet, mint = %mint.symbol, error = %e, "sync failed");
+                synced = false;
+            }
+        }
+    }
+    synced
+}
+
+/// The latest snapshot of `wallet`/`mint` and the balance now, when another
+/// snapshot is due after `snapshot_interval` or `snapshot_every_transfers`.
+async fn due_snapshot(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+    mint: &MintInfo,
+) -> Result<Option<(Option<BalanceSnapshot>, WalletBalance)>, IndexerError> {
+    if config.snapshot_interval.is_none() && config.snapshot_every_transfers.is_none() {
+        return Ok(None);
+    }
+    let previous = store.latest_balance_snapshot(wallet, &mint.mint).await?;
+    let due = match &previous {
+        None => true,
+        Some(previous) => {
+            let count = store.count_transfers(wallet, &mint.mint).await?;
+            config.snapshot_interval.is_some_and(|interval| {
+                Utc::now().timestamp() - previous.taken_at >= interval.as_secs() as i64
+            }) || config
+                .snapshot_every_transfers
+                .is_some_and(|every| count.saturating_sub(previous.transfer_count) >= every)
+        }
+    };
+    if !due {
+        return Ok(None);
+    }
+    let balance = fetch_balance(client, config, wallet, mint, config.commitment).await?;
+    Ok(Some((previous, balance)))
+}
+
+/// Stores `balance` as a snapshot, checking the change since `previous`
+/// against the stored transfers in between when the sync before it was
+/// `complete`. A mismatch is logged and counted: it means some flow moved
+/// the balance without the parser seeing a transfer.
+async fn save_snapshot(
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+    mint: &MintInfo,
+    previous: Option<BalanceSnapshot>,
+    balance: &WalletBalance,
+    complete: bool,
+) -> Result<(), IndexerError> {
+    let unexplained_raw = match previous.filter(|_| complete) {
+        Some(previous) => {
+            let request = BackfillRequest {
+                wallet: *wallet,
+                mint: mint.clone(),
+                window: TimeWindow {
+                    start: previous.taken_at - SNAPSHOT_CLOCK_SLACK_SECS,
+                    end: i64::MAX,
+                },
+                until: None,
+                include_failed: false,
+                include_orphaned: false,
+                strategy: Strategy::Instructions,
+                commitment: config.commitment,
+            };
+            let transfers = store.query_transfers(&request).await?;
+            let unexplained =
+                unexplained_balance_change(&previous, balance.amount_raw, balance.slot, &transfers);
+            let unexplained = i64::try_from(unexplained).unwrap_or(i64::MAX);
+            let name = wallet.to_string();
+            let labels = [name.as_str(), mint.symbol.as_str()];
+            METRICS
+                .balance_unexplained
+                .with_label_values(&labels)
+                .set(unexplained);
+            if unexplained != 0 {
+                METRICS.balance_mismatches.with_label_values(&labels).inc();
+                warn!(
+                    %wallet,
+                    mint = %mint.symbol,
+                    from_slot = previous.slot,
+                    to_slot = balance.slot,
+                    unexplained = %format_signed_amount(unexplained.into(), mint.decimals),
+                    "balance change since the last snapshot doesn't match the stored transfers"
+                );
+            }
+            Some(unexplained)
+        }
+        None => None,
+    };
+    let snapshot = BalanceSnapshot {
+        slot: balance.slot,
+        taken_at: Utc::now().timestamp(),
+        amount_raw: balance.amount_raw,
+        transfer_count: store.count_transfers(wallet, &mint.mint).await?,
+        unexplained_raw,
+    };
+    store
+        .insert_balance_snapshot(wallet, &mint.mint, &snapshot)
+        .await?;
+    Ok(())
+}
+
+/// Picks up new transactions for `wallet` within a slot or two via
+/// `logsSubscribe`, reconnecting with exponential backoff when the socket
+/// drops. Each session starts with a sync from the stored cursor, which
+/// fills whatever landed while disconnected.
+async fn run_live_indexer(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    wallet: Pubkey,
+    ws_url: String,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut backoff = LIVE_RECONNECT_MIN_DELAY;
+    loop {
+        let session = live_session(
+            client.as_ref(),
+            &config,
+            &store,
+            &wallet,
+            &ws_url,
+            &mut shutdown,
+        )
+        .await;
+        progress::set_live_connected(&wallet.to_string(), false);
+        if *shutdown.borrow() {
+            return;
+        }
+        match session {
+            Ok(()) => {
+                info!(%wallet, "live subscription closed, reconnecting");
+                backoff = LIVE_RECONNECT_MIN_DELAY;
+            }
+            Err(e) => {
+                warn!(%wallet, ?backoff, error = %e, "live subscription failed, retrying");
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = shutdown.changed() => return,
+                }
+                backoff = (backoff * 2).min(LIVE_RECONNECT_MAX_DELAY);
+            }
+        }
+    }
+}
+
+/// One websocket connection: subscribes to logs mentioning the wallet or any
+/// of its associated token accounts and syncs on every notification. Returns
+/// `Ok` when the server ends the subscription or shutdown is requested; a
+/// sync already in progress is allowed to finish first.
+async fn live_session(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    wallet: &Pubkey,
+    ws_url: &str,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Result<()> {
+    let pubsub = PubsubClient::new(ws_url).await?;
+
+    // The RPC only accepts a single address per `mentions` filter.
+    let mut addresses = vec![wallet.to_string()];
+    for mint in &config.mints.mints {
+        for program in &config.token_programs {
+            addresses.push(
+                associated_token_address_for_program(wallet, &mint.mint, &program.pubkey())
+                    .to_string(),
+            );
+        }
+    }
+    let mut streams = Vec::new();
+    for address in addresses {
+        let (stream, _unsubscribe) = pubsub
+            .logs_subscribe(
+                RpcTransactionLogsFilter::Mentions(vec![address]),
+                RpcTransactionLogsConfig {
+                    commitment: Some(CommitmentConfig::confirmed()),
+                },
+            )
+            .await?;
+        streams.push(stream);
+    }
+    let name = wallet.to_string();
+    progress::set_live_connected(&name, true);
+
+    if sync_all_mints(client, config, store, wallet, "live").await {
+        progress::record_live_sync(&name);
+    }
+
+    let mut notifications = stream::select_all(streams);
+    loop {
+        let notification = tokio::select! {
+            notification = notifications.next() => notification,
+            _ = shutdown.changed() => break,
+        };
+        let Some(notification) = notification else {
+            break;
+        };
+        debug!(
+            %wallet,
+            signature = %notification.value.signature,
+            "live notification"
+        );
+        if sync_all_mints(client, config, store, wallet, "live").await {
+            progress::record_live_sync(&name);
+        }
+    }
+    Ok(())
+}
+
+/// Starts the background poller and, if configured, the live indexer for
+/// `wallet`. They stop between syncs once `shutdown` flips. With
+/// `initial_sync` the lookback window is synced right away even when no
+/// poller is configured, as for a wallet added at runtime.
+pub fn spawn_wallet(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    wallet: Pubkey,
+    initial_sync: bool,
+    shutdown: watch::Receiver<bool>,
+) -> Vec<JoinHandle<()>> {
+    let mut tasks = Vec::new();
+    match config.poll_interval {
+        Some(interval) => tasks.push(tokio::spawn(run_poller(
+            client.clone(),
+            config.clone(),
+            store.clone(),
+            wallet,
+            interval,
+            shutdown.clone(),
+        ))),
+        // The poller's first tick syncs immediately; without one, do it here.
+        None if initial_sync => {
+            let (client, config, store) = (client.clone(), config.clone(), store.clone());
+            let mut shutdown = shutdown.clone();
+            tasks.push(tokio::spawn(async move {
+                tokio::select! {
+                    _ = sync_all_mints(client.as_ref(), &config, &store, &wallet, "watch") => {}
+                    _ = shutdown.changed() => {}
+                }
+            }));
+        }
+        None => {}
+    }
+    if let Some(ws_url) = config.live_ws_url.clone() {
+        tasks.push(tokio::spawn(run_live_indexer(
+            client, config, store, wallet, ws_url, shutdown,
+        )));
+    }
+    tasks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MintRegistry;
+    use crate::config::{DEFAULT_MINTS, DEFAULT_WINDOW_HOURS};
+    use crate::parser::{associated_token_address, SPL_TOKEN_PROGRAM_ID};
+    use crate::rpc::mock::MockRpc;
+    use serde_json::json;
+    use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+    use solana_transaction_status::TransactionStatus;
+
+    const WALLET: &str = "7cMEhpt9y3inBNVv8fNnuaEbx7hKHZnLvR1KWKKxuDDU";
+    const COUNTERPARTY_TOKEN_ACCOUNT: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+    const NOW: i64 = 1_700_000_000;
+
+    fn usdc() -> MintInfo {
+        MintRegistry::parse(DEFAULT_MINTS).unwrap().mints.remove(0)
+    }
+
+    fn signature(n: usize) -> String {
+        let mut bytes = [1u8; 64];
+        bytes[..8].copy_from_slice(&(n as u64).to_le_bytes());
+        Signature::from(bytes).to_string()
+    }
+
+    /// Adds a signature (appended, so callers go newest to oldest) whose
+    /// transaction moves one base unit from a counterparty into the wallet's
+    /// ATA. `with_transaction: false` leaves it unfetchable.
+    fn push_received(rpc: &mut MockRpc, n: usize, block_time: i64, with_transaction: bool) {
+        let signature = signature(n);
+        let ata = associated_token_address(&Pubkey::from_str(WALLET).unwrap(), &usdc().mint);
+        rpc.signatures
+            .push(RpcConfirmedTransactionStatusWithSignature {
+                signature: signature.clone(),
+                slot: n as u64,
+                err: None,
+                memo: None,
+                block_time: Some(block_time),
+                confirmation_status: None,
+            });
+        if !with_transaction {
+            return;
+        }
+        let tx = json!({
+            "slot": n,
+            "blockTime": block_time,
+            "version": 0,
+            "meta": null,
+            "transaction": {
+                "signatures": [signature],
+                "message": {
+                    "accountKeys": [
+                        { "pubkey": WALLET, "writable": true, "signer": true, "source": "transaction" },
+                    ],
+                    "recentBlockhash": "11111111111111111111111111111111",
+                    "instructions": [{
+                        "program": "spl-token",
+                        "programId": SPL_TOKEN_PROGRAM_ID,
+                        "parsed": {
+                            "type": "transfer",
+                            "info": {
+                                "source": COUNTERPARTY_TOKEN_ACCOUNT,
+                                "destination": ata.to_string(),
+                                "amount": "1",
+                            },
+                        },
+                    }],
+                },
+            },
+        });
+        rpc.transactions.insert(signature, tx);
+    }
+
+    fn last_24h() -> BackfillRequest {
+        BackfillRequest {
+            wallet: Pubkey::from_str(WALLET).unwrap(),
+            mint: usdc(),
+            window: TimeWindow {
+                start: NOW - DEFAULT_WINDOW_HOURS * 3600,
+                end: NOW,
+            },
+            until: None,
+            include_failed: false,
+            include_orphaned: false,
+            strategy: Strategy::Instructions,
+            commitment: CommitmentConfig::confirmed(),
+        }
+    }
+
+    /// Lists the owner address only, so each test's history is served once.
+    fn config() -> Config {
+        Config {
+            rpc_max_attempts: 1,
+            index_token_accounts: false,
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn looks_up_each_counterparty_owner_once() {
+        let token_account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let mut data = vec![0u8; TOKEN_ACCOUNT_MIN_LEN];
+        data[..32].copy_from_slice(usdc().mint.as_ref());
+        data[32..64].copy_from_slice(owner.as_ref());
+        let mut rpc = MockRpc::default();
+        rpc.accounts.insert(
+            token_account,
+            Account {
+                lamports: 1,
+                data,
+                owner: Pubkey::from_str(SPL_TOKEN_PROGRAM_ID).unwrap(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        let received = crate::model::fixtures::transfer(
+            crate::model::Direction::Received,
+            1,
+            &token_account.to_string(),
+        );
+        let mut transfers = vec![received.clone(), received.clone(), received];
+        transfers[2].counterparty_owner = Some("known".to_string());
+
+        let config = config();
+        resolve_counterparty_owners(&rpc, &config, &mut transfers).await;
+        // A later backfill is answered from the cache.
+        transfers[1].counterparty_owner = None;
+        resolve_counterparty_owners(&rpc, &config, &mut transfers).await;
+        assert_eq!(
+            transfers[0].counterparty_owner.as_deref(),
+            Some(owner.to_string().as_str())
+        );
+        assert_eq!(
+            transfers[1].counterparty_owner,
+            transfers[0].counterparty_owner
+        );
+        assert_eq!(transfers[2].counterparty_owner.as_deref(), Some("known"));
+        assert_eq!(*rpc.accounts_requested.lock().unwrap(), vec![token_account]);
+    }
+
+    #[tokio::test]
+    async fn paginates_past_one_signature_page() {
+        let mut rpc = MockRpc::default();
+        for n in 0..1500 {
+            push_received(&mut rpc, n, NOW - n as i64, true);
+        }
+
+        let outcome = backfill_transfers(&rpc, &config(), &last_24h())
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.transfers.len(), 1500);
+        assert_eq!(outcome.newest_signature, Some(signature(0)));
+        let pages = rpc.pages_requested.lock().unwrap();
+        assert_eq!(pages[0], None);
+        assert_eq!(pages[1], Some(signature(999).parse().unwrap()));
+        // Oldest first.
+        assert_eq!(outcome.transfers[0].signature, signature(1499));
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_24h_cutoff() {
+        let mut rpc = MockRpc::default();
+        let start = NOW - DEFAULT_WINDOW_HOURS * 3600;
+        push_received(&mut rpc, 0, NOW + 60, true); // after the window
+        push_received(&mut rpc, 1, NOW, true);
+        push_received(&mut rpc, 2, start, true);
+        push_received(&mut rpc, 3, start - 1, true); // before the window
+        push_received(&mut rpc, 4, start - 3600, true);
+
+        let outcome = backfill_transfers(&rpc, &config(), &last_24h())
+            .await
+            .unwrap();
+
+        let signatures: Vec<_> = outcome
+            .transfers
+            .iter()
+            .map(|t| t.signature.clone())
+            .collect();
+        assert_eq!(signatures, vec![signature(2), signature(1)]);
+        assert_eq!(rpc.pages_requested.lock().unwrap().len(), 1);
+        let fetched = rpc.transactions_requested.lock().unwrap();
+        assert!(!fetched.contains(&signature(0)));
+        assert!(!fetched.contains(&signature(3)));
+    }
+
+    #[tokio::test]
+    async fn looks_up_missing_block_times_by_slot() {
+        let mut rpc = MockRpc::default();
+        let start = NOW - DEFAULT_WINDOW_HOURS * 3600;
+        push_received(&mut rpc, 0, NOW, true);
+        push_received(&mut rpc, 1, NOW - 60, true);
+        push_received(&mut rpc, 2, NOW - 120, true); // no time anywhere
+                                                     // A full page listed without times, all before the window.
+        for n in 3..1203 {
+            push_received(&mut rpc, n, start - n as i64, false);
+        }
+        for sig_info in rpc.signatures.iter_mut().skip(1) {
+            // Clear of the slots other tests may have cached.
+            sig_info.slot += 7_000_000;
+            if sig_info.signature != signature(2) {
+                rpc.block_times
+                    .insert(sig_info.slot, sig_info.block_time.unwrap());
+            }
+            sig_info.block_time = None;
+        }
+
+        let outcome = backfill_transfers(&rpc, &config(), &last_24h())
+            .await
+            .unwrap();
+
+        let signatures: Vec<_> = outcome
+            .transfers
+            .iter()
+            .map(|t| (t.signature.clone(), t.block_time))
+            .collect();
+        assert_eq!(
+            signatures,
+            vec![(signature(1), NOW - 60), (signature(0), NOW)]
+        );
+        // The first looked-up time before the window ends the listing.
+        assert_eq!(rpc.pages_requested.lock().unwrap().len(), 1);
+        assert_eq!(
+            *rpc.block_times_requested.lock().unwrap(),
+            vec![7_000_001, 7_000_002, 7_000_003]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_skipped_signatures_with_examples() {
+        let mut rpc = MockRpc::default();
+        push_received(&mut rpc, 1, NOW, true);
+        push_received(&mut rpc, 2, NOW - 60, false);
+        let (outcome, diagnostics) =
+            diagnostics::collect(backfill_transfers(&rpc, &config(), &last_24h())).await;
+        assert_eq!(outcome.unwrap().transfers.len(), 1);
+        let failed = &diagnostics.0[diagnostics::FETCH_FAILED];
+        assert_eq!(failed.count, 1);
+        assert_eq!(failed.examples, [signature(2)]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_signature_page_cap() {
+        let mut rpc = MockRpc::default();
+        for n in 0..5 {
+            push_received(&mut rpc, n, NOW - n as i64 * 60, true);
+        }
+        let config = Config {
+            signature_page_size: 2,
+            max_signature_pages: 2,
+            ..config()
+        };
+
+        let outcome = backfill_transfers(&rpc, &config, &last_24h())
+            .await
+            .unwrap();
+        assert_eq!(outcome.transfers.len(), 4);
+        assert_eq!(outcome.pages_scanned, 2);
+        assert!(outcome.truncated);
+
+        // Nothing is recorded as indexed past the gap.
+        let store: Storage = Arc::new(crate::store::MemoryStore::open(None).await.unwrap());
+        let wallet = Pubkey::from_str(WALLET).unwrap();
+        let report = sync_store(&rpc, &config, &store, &wallet, &usdc(), NOW - 3600)
+            .await
+            .unwrap();
+        assert!(report.truncated);
+        assert_eq!(report.new_transfers, 4);
+        assert!(store
+            .sync_state(&wallet, &usdc().mint)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Retrying lists the same pages but reads none of them again.
+        rpc.transactions_requested.lock().unwrap().clear();
+        let report = sync_store(&rpc, &config, &store, &wallet, &usdc(), NOW - 3600)
+            .await
+            .unwrap();
+        assert_eq!(report.new_transfers, 0);
+        assert!(rpc.transactions_requested.lock().unwrap().is_empty());
+        assert_eq!(
+            store.count_transfers(&wallet, &usdc().mint).await.unwrap(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn anchors_windows_to_the_finalized_block_time() {
+        let mut rpc = MockRpc {
+            slot: 7_100_000,
+            ..MockRpc::default()
+        };
+        let requested = window_anchor(&rpc, &config(), Some(NOW - 3600)).await;
+        assert_eq!(requested.source, AnchorSource::Request);
+        assert_eq!(requested.time, NOW - 3600);
+        assert!(rpc.block_times_requested.lock().unwrap().is_empty());
+
+        let fallback = window_anchor(&rpc, &config(), None).await;
+        assert_eq!(fallback.source, AnchorSource::SystemClock);
+        assert_eq!(fallback.slot, None);
+
+        rpc.block_times.insert(7_100_000, NOW);
+        assert_eq!(
+            window_anchor(&rpc, &config(), None).await,
+            WindowAnchor {
+                source: AnchorSource::Chain,
+                time: NOW,
+                slot: Some(7_100_000),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn marks_transfers_read_below_finalized() {
+        let mut rpc = MockRpc::default();
+        push_received(&mut rpc, 1, NOW, true);
+        let outcome = backfill_transfers(&rpc, &config(), &last_24h())
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome.transfers[0].commitment.as_deref(),
+            Some("confirmed")
+        );
+
+        let finalized = BackfillRequest {
+            commitment: CommitmentConfig::finalized(),
+            ..last_24h()
+        };
+        let outcome = backfill_transfers(&rpc, &config(), &finalized)
+            .await
+            .unwrap();
+        assert_eq!(outcome.transfers[0].commitment, None);
+        // The RPC lists no history below confirmed.
+        assert_eq!(
+            history_commitment(CommitmentConfig::processed()),
+            CommitmentConfig::confirmed()
+        );
+    }
+
+    #[tokio::test]
+    async fn looks_up_one_transaction_for_each_wallet() {
+        let mut rpc = MockRpc::default();
+        push_received(&mut rpc, 1, NOW, true);
+        push_received(&mut rpc, 2, NOW, true);
+        rpc.signature_statuses.insert(
+            signature(1),
+            TransactionStatus {
+                slot: 1,
+                confirmations: None,
+                status: Ok(()),
+                err: None,
+                confirmation_status: Some(TransactionConfirmationStatus::Finalized),
+            },
+        );
+        let wallet = Pubkey::from_str(WALLET).unwrap();
+        let other = Pubkey::new_unique();
+        let effect = transaction_effect(
+            &rpc,
+            &config(),
+            &signature(1),
+            &[wallet, other],
+            Strategy::Instructions,
+            CommitmentConfig::confirmed(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(effect.slot, 1);
+        assert_eq!(effect.block_time, Some(NOW));
+        assert_eq!(effect.error, None);
+        assert_eq!(effect.transfers.len(), 1);
+        assert_eq!(effect.transfers[0].wallet.as_deref(), Some(WALLET));
+        assert_eq!(effect.transfers[0].amount_raw, 1);
+
+        let unknown = transaction_effect(
+            &rpc,
+            &config(),
+            &signature(2),
+            &[wallet],
+            Strategy::Instructions,
+            CommitmentConfig::confirmed(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(unknown, IndexerError::NotFound(_)), "{}", unknown);
+
+        // No block time anywhere: an error rather than a transfer from 1970.
+        let undated = 7_001;
+        push_received(&mut rpc, undated, NOW, true);
+        rpc.transactions.get_mut(&signature(undated)).unwrap()["blockTime"] = json!(null);
+        rpc.signature_statuses.insert(
+            signature(undated),
+            TransactionStatus {
+                slot: undated as u64,
+                confirmations: None,
+                status: Ok(()),
+                err: None,
+                confirmation_status: Some(TransactionConfirmationStatus::Finalized),
+            },
+        );
+        let undated = transaction_effect(
+            &rpc,
+            &config(),
+            &signature(undated),
+            &[wallet],
+            Strategy::Instructions,
+            CommitmentConfig::confirmed(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(undated, IndexerError::Rpc(_)), "{}", undated);
+    }
+
+    #[tokio::test]
+    async fn orphans_transfers_the_cluster_no_longer_has() {
+        let mut rpc = MockRpc::default();
+        for n in 1..=3 {
+            push_received(&mut rpc, n, NOW - n as i64, true);
+        }
+        let store: Storage = Arc::new(crate::store::MemoryStore::open(None).await.unwrap());
+        let wallet = Pubkey::from_str(WALLET).unwrap();
+        let outcome = backfill_transfers(&rpc, &config(), &last_24h())
+            .await
+            .unwrap();
+        store
+            .insert_batch(&wallet, &usdc().mint, &outcome.transfers)
+            .await
+            .unwrap();
+
+        let status = |n: usize, confirmation_status| TransactionStatus {
+            slot: n as u64,
+            confirmations: (confirmation_status != TransactionConfirmationStatus::Finalized)
+                .then_some(1),
+            status: Ok(()),
+            err: None,
+            confirmation_status: Some(confirmation_status),
+        };
+        rpc.slot = 10;
+        rpc.signature_statuses = HashMap::from([
+            (
+                signature(1),
+                status(1, TransactionConfirmationStatus::Finalized),
+            ),
+            (
+                signature(2),
+                status(2, TransactionConfirmationStatus::Confirmed),
+            ),
+        ]);
+        let report = reverify_unfinalized(&rpc, &config(), &store, &wallet)
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            ReverifyReport {
+                finalized: 1,
+                pending: 1,
+                orphaned: 1
+            }
+        );
+
+        let visible = store.query_transfers(&last_24h()).await.unwrap();
+        let signatures: Vec<&str> = visible.iter().map(|t| t.signature.as_str()).collect();
+        assert_eq!(signatures, [signature(2), signature(1)]);
+        assert_eq!(visible[1].commitment, None);
+        let all = BackfillRequest {
+            include_orphaned: true,
+            ..last_24h()
+        };
+        let all = store.query_transfers(&all).await.unwrap();
+        assert!(all
+            .iter()
+            .any(|t| t.orphaned && t.signature == signature(3)));
+
+        // Finalized signatures are done with; the orphan has left the
+        // reorg window.
+        rpc.slot = 3 + REORG_WINDOW_SLOTS + 1;
+        let report = reverify_unfinalized(&rpc, &config(), &store, &wallet)
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            ReverifyReport {
+                pending: 1,
+                ..ReverifyReport::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn reparses_stored_raw_transactions() {
+        let mut rpc = MockRpc::default();
+        push_received(&mut rpc, 1, NOW, true);
+        push_received(&mut rpc, 2, NOW - 1, true);
+        let config = Config {
+            raw_transaction_limit: Some(10),
+            ..config()
+        };
+        let store: Storage = Arc::new(crate::store::MemoryStore::open(None).await.unwrap());
+        let wallet = Pubkey::from_str(WALLET).unwrap();
+        let outcome = backfill_transfers(&rpc, &config, &last_24h())
+            .await
+            .unwrap();
+        assert_eq!(outcome.raw.len(), 2);
+        save_outcome(&config, &store, &wallet, &usdc(), &outcome)
+            .await
+            .unwrap();
+        let state = SyncState {
+            indexed_from: NOW - 3600,
+            indexed_until: NOW,
+            newest_signature: Some(signature(1)),
+        };
+        store
+            .set_sync_state(&wallet, &usdc().mint, &state)
+            .await
+            .unwrap();
+        // A record an earlier parser read out of the first transaction.
+        let mut misread = outcome.transfers[0].clone();
+        misread.instruction_index = 7;
+        store
+            .insert_batch(&wallet, &usdc().mint, &[misread])
+            .await
+            .unwrap();
+        store
+            .put_raw_transactions(
+                &[RawTransaction {
+                    signature: "unreadable".to_string(),
+                    slot: 3,
+                    data: b"not gzip".to_vec(),
+                }],
+                10,
+            )
+            .await
+            .unwrap();
+
+        let report = reparse(&rpc, &config, &store, &wallet).await.unwrap();
+        assert_eq!(
+            report,
+            ReparseReport {
+                transactions: 3,
+                undecodable_transactions: 1,
+                transfers: 2,
+            }
+        );
+        let stored = store.query_transfers(&last_24h()).await.unwrap();
+        assert_eq!(stored.len(), 2);
+        assert!(stored.iter().all(|t| t.instruction_index == 0));
+    }
+
+    #[tokio::test]
+    async fn reindexes_from_stored_raw_transactions_first() {
+        let mut rpc = MockRpc::default();
+        push_received(&mut rpc, 1, NOW, true);
+        push_received(&mut rpc, 2, NOW - 1, true);
+        let config = Config {
+            raw_transaction_limit: Some(10),
+            ..config()
+        };
+        let store: Storage = Arc::new(crate::store::MemoryStore::open(None).await.unwrap());
+        let wallet = Pubkey::from_str(WALLET).unwrap();
+        let tx = serde_json::from_value(rpc.transactions[&signature(1)].clone()).unwrap();
+        store
+            .put_raw_transactions(&[RawTransaction::encode(&signature(1), &tx).unwrap()], 10)
+            .await
+            .unwrap();
+        let mut stale = crate::model::fixtures::transfer(crate::model::Direction::Sent, 5, "bob");
+        stale.block_time = NOW - 10;
+        store
+            .insert_batch(&wallet, &usdc().mint, &[stale])
+            .await
+            .unwrap();
+        let state = SyncState {
+            indexed_from: NOW - 3600,
+            indexed_until: NOW,
+            newest_signature: Some(signature(1)),
+        };
+        store
+            .set_sync_state(&wallet, &usdc().mint, &state)
+            .await
+            .unwrap();
+
+        let window = TimeWindow {
+            start: NOW - 7200,
+            end: NOW,
+        };
+        let report = reindex(&rpc, &config, &store, &wallet, &[usdc()], window)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.mints,
+            [MintReindex {
+                mint: usdc().mint.to_string(),
+                symbol: usdc().symbol,
+                window: TimeWindow {
+                    start: NOW - 3600,
+                    end: NOW,
+                },
+                removed_transfers: 1,
+                transfers: 2,
+                reused_raw_transactions: 1,
+            }]
+        );
+        assert_eq!(*rpc.transactions_requested.lock().unwrap(), [signature(2)]);
+        assert!(store
+            .raw_transaction(&signature(2))
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(store.query_transfers(&last_24h()).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn propagates_signature_listing_errors() {
+        let mut rpc = MockRpc::default();
+        push_received(&mut rpc, 0, NOW, true);
+        rpc.fail_signatures = true;
+
+        assert!(backfill_transfers(&rpc, &config(), &last_24h())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn skips_transactions_that_cannot_be_fetched() {
+        let mut rpc = MockRpc::default();
+        push_received(&mut rpc, 0, NOW - 10, true);
+        push_received(&mut rpc, 1, NOW - 20, false);
+        push_received(&mut rpc, 2, NOW - 30, true);
+
+        let outcome = backfill_transfers(&rpc, &config(), &last_24h())
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.transfers.len(), 2);
+        assert_eq!(rpc.transactions_requested.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn merges_signatures_of_the_owner_and_its_token_accounts() {
+        let wallet = Pubkey::from_str(WALLET).unwrap();
+        let held = Pubkey::from_str(COUNTERPARTY_TOKEN_ACCOUNT).unwrap();
+        let mut rpc = MockRpc {
+            token_accounts: vec![serde_json::from_value(token_account(
+                COUNTERPARTY_TOKEN_ACCOUNT,
+                "0",
+            ))
+            .unwrap()],
+            ..MockRpc::default()
+        };
+        // Only the token account's listing has signature 0; both have 1.
+        push_received(&mut rpc, 0, NOW - 10, true);
+        push_received(&mut rpc, 1, NOW - 20, true);
+        let listed = std::mem::take(&mut rpc.signatures);
+        rpc.signatures_by_address.insert(held, listed.clone());
+        rpc.signatures_by_address
+            .insert(wallet, listed[1..].to_vec());
+
+        let outcome = backfill_transfers(
+            &rpc,
+            &Config {
+                index_token_accounts: true,
+                ..config()
+            },
+            &last_24h(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(outcome.transfers.len(), 2);
+        assert_eq!(outcome.newest_signature, Some(signature(0)));
+        let mut fetched = rpc.transactions_requested.lock().unwrap().clone();
+        fetched.sort();
+        let mut expected = vec![signature(0), signature(1)];
+        expected.sort();
+        assert_eq!(fetched, expected);
+    }
+
+    fn token_account(address: &str, amount: &str) -> serde_json::Value {
+        json!({
+            "pubkey": address,
+            "account": {
+                "lamports": 2039280,
+                "owner": SPL_TOKEN_PROGRAM_ID,
+                "executable": false,
+                "rentEpoch": 0,
+                "data": {
+                    "program": "spl-token",
+                    "space": 165,
+                    "parsed": {
+                        "type": "account",
+                        "info": { "tokenAmount": { "amount": amount, "decimals": 6 } },
+                    },
+                },
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn sums_balances_across_token_accounts() {
+        let rpc = MockRpc {
+            slot: 42,
+            token_accounts: vec![
+                serde_json::from_value(token_account(COUNTERPARTY_TOKEN_ACCOUNT, "1500000"))
+                    .unwrap(),
+                serde_json::from_value(token_account(WALLET, "250")).unwrap(),
+            ],
+            ..MockRpc::default()
+        };
+        let balance = fetch_balance(
+            &rpc,
+            &config(),
+            &Pubkey::from_str(WALLET).unwrap(),
+            &usdc(),
+            CommitmentConfig::finalized(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(balance.amount_raw, 1_500_250);
+        assert_eq!(balance.amount_ui, "1.500250");
+        assert_eq!(balance.token_accounts.len(), 2);
+        assert_eq!(balance.slot, 42);
+        assert_eq!(balance.commitment, "finalized");
+    }
+
+    #[tokio::test]
+    async fn lists_outstanding_delegations() {
+        let mut delegated = token_account(COUNTERPARTY_TOKEN_ACCOUNT, "1500000");
+        delegated["account"]["data"]["parsed"]["info"]["delegate"] = json!(WALLET);
+        delegated["account"]["data"]["parsed"]["info"]["delegatedAmount"] =
+            json!({ "amount": "250000", "decimals": 6, "uiAmountString": "0.25" });
+        let rpc = MockRpc {
+            slot: 42,
+            token_accounts: vec![
+                serde_json::from_value(delegated).unwrap(),
+                serde_json::from_value(token_account(WALLET, "250")).unwrap(),
+            ],
+            ..MockRpc::default()
+        };
+        let delegations = fetch_delegations(
+            &rpc,
+            &config(),
+            &Pubkey::from_str(WALLET).unwrap(),
+            &usdc(),
+            CommitmentConfig::finalized(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            delegations.delegations,
+            [Delegation {
+                token_account: COUNTERPARTY_TOKEN_ACCOUNT.to_string(),
+                delegate: WALLET.to_string(),
+                amount_raw: 250_000,
+                amount_ui: "0.250000".to_string(),
+            }]
+        );
+        assert_eq!(delegations.slot, 42);
+    }
+
+    #[tokio::test]
+    async fn reconciles_snapshots_and_replays_balances_from_them() {
+        // The store keeps only what is inside the longest window of now.
+        let now = Utc::now().timestamp();
+        let mut rpc = MockRpc::default();
+        push_received(&mut rpc, 2, now - 3000, true);
+        push_received(&mut rpc, 1, now - 3300, true);
+        let config = config();
+        let store: Storage = Arc::new(crate::store::MemoryStore::open(None).await.unwrap());
+        let wallet = Pubkey::from_str(WALLET).unwrap();
+        sync_store(&rpc, &config, &store, &wallet, &usdc(), now - 3600)
+            .await
+            .unwrap();
+
+        let previous = BalanceSnapshot {
+            slot: 0,
+            taken_at: now - 3600,
+            amount_raw: 10,
+            transfer_count: 0,
+            unexplained_raw: None,
+        };
+        store
+            .insert_balance_snapshot(&wallet, &usdc().mint, &previous)
+            .await
+            .unwrap();
+        let balance = |slot, amount_raw| WalletBalance {
+            wallet: WALLET.to_string(),
+            mint: usdc().mint.to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+            amount_raw,
+            amount_ui: format_amount(amount_raw, 6),
+            token_accounts: Vec::new(),
+            slot,
+            commitment: "confirmed".to_string(),
+        };
+        let labels = [WALLET, "USDC"];
+        let mismatches = METRICS.balance_mismatches.with_label_values(&labels).get();
+        // Both deposits show up in the balance.
+        save_snapshot(
+            &config,
+            &store,
+            &wallet,
+            &usdc(),
+            Some(previous.clone()),
+            &balance(5, 12),
+            true,
+        )
+        .await
+        .unwrap();
+        let latest = store
+            .latest_balance_snapshot(&wallet, &usdc().mint)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (latest.unexplained_raw, latest.transfer_count),
+            (Some(0), 2)
+        );
+        // One unit left by a flow the parser didn't see.
+        save_snapshot(
+            &config,
+            &store,
+            &wallet,
+            &usdc(),
+            Some(previous),
+            &balance(6, 11),
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            METRICS.balance_mismatches.with_label_values(&labels).get(),
+            mismatches + 1
+        );
+
+        // Closer in time than the current balance, the first snapshot
+        // anchors the replay forward.
+        let at = balance_at(&rpc, &config, &store, &wallet, &usdc(), now - 3150)
+            .await
+            .unwrap();
+        assert_eq!(at.anchor.source, BalanceAnchorSource::Snapshot);
+        assert_eq!((at.amount_raw, at.transfers_applied), (11, 1));
+        let err = balance_at(&rpc, &config, &store, &wallet, &usdc(), now - 7200)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code().as_str(), "HISTORY_INCOMPLETE");
+    }
+}
@@ -0,0 +1,397 @@
+//! Backfills run as background jobs, for windows too long to answer before
+//! a proxy in front of the service gives up on the request.
+//! `POST /v1/backfill` starts one and `GET /v1/backfill/{id}` polls it; the
+//! admin reindex and cache purge run as jobs too. Jobs are kept in memory
+//! only.
+
+use chrono::Utc;
+use rand::RngCore;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{warn, Instrument};
+use utoipa::ToSchema;
+
+use crate::deadline;
+use crate::error::{ErrorCode, IndexerError};
+use crate::indexer::ReindexReport;
+use crate::output::BackfillResponse;
+
+/// Jobs held at once, finished ones included.
+const MAX_JOBS: usize = 256;
+/// How long a finished job stays around to be collected.
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(3600);
+
+tokio::task_local! {
+    static PROGRESS: Arc<JobProgress>;
+}
+
+/// Counters a running job reports, updated by the indexer as it goes.
+#[derive(Debug)]
+pub struct JobProgress {
+    signatures_scanned: AtomicU64,
+    transfers_found: AtomicU64,
+    /// Unix time of the oldest signature scanned so far; `i64::MAX` until
+    /// the first one.
+    oldest_block_time: AtomicI64,
+}
+
+impl Default for JobProgress {
+    fn default() -> Self {
+        JobProgress {
+            signatures_scanned: AtomicU64::new(0),
+            transfers_found: AtomicU64::new(0),
+            oldest_block_time: AtomicI64::new(i64::MAX),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ProgressSnapshot {
+    pub signatures_scanned: u64,
+    pub transfers_found: u64,
+    pub oldest_block_time: Option<i64>,
+}
+
+impl JobProgress {
+    pub(crate) fn snapshot(&self) -> ProgressSnapshot {
+        let oldest = self.oldest_block_time.load(Ordering::Relaxed);
+        ProgressSnapshot {
+            signatures_scanned: self.signatures_scanned.load(Ordering::Relaxed),
+            transfers_found: self.transfers_found.load(Ordering::Relaxed),
+            oldest_block_time: (oldest != i64::MAX).then_some(oldest),
+        }
+    }
+}
+
+/// Runs `work` with `progress` as the counters the indexer reports to,
+/// for work that isn't a job but still wants to know how far it got.
+pub(crate) async fn track<F: Future>(progress: Arc<JobProgress>, work: F) -> F::Output {
+    PROGRESS.scope(progress, work).await
+}
+
+/// Counts a page of listed signatures towards the current job, if the
+/// caller runs inside one.
+pub(crate) fn record_signatures(count: usize, oldest_block_time: Option<i64>) {
+    let _ = PROGRESS.try_with(|progress| {
+        progress
+            .signatures_scanned
+            .fetch_add(count as u64, Ordering::Relaxed);
+        if let Some(block_time) = oldest_block_time {
+            progress
+                .oldest_block_time
+                .fetch_min(block_time, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Counts a transfer found towards the current job, if any.
+pub(crate) fn record_transfer() {
+    let _ = PROGRESS.try_with(|progress| {
+        progress.transfers_found.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// What a finished job produced. Untagged, so a backfill job's `result` is
+/// the JSON `/backfill` body.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum JobResult {
+    Backfill(Box<BackfillResponse>),
+    Reindex(ReindexReport),
+    CachePurge { purged_entries: usize },
+}
+
+impl From<BackfillResponse> for JobResult {
+    fn from(response: BackfillResponse) -> Self {
+        JobResult::Backfill(Box::new(response))
+    }
+}
+
+/// A job as reported by `GET /v1/backfill/{id}`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobReport {
+    pub id: String,
+    pub status: JobStatus,
+    /// Unix time the job was submitted.
+    pub created_at: i64,
+    pub progress: ProgressSnapshot,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<JobResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JobError>,
+}
+
+struct Job {
+    status: JobStatus,
+    created_at: i64,
+    finished_at: Option<Instant>,
+    progress: Arc<JobProgress>,
+    result: Option<JobResult>,
+    error: Option<JobError>,
+    cancel: CancellationToken,
+}
+
+impl Job {
+    fn report(&self, id: &str) -> JobReport {
+        JobReport {
+            id: id.to_string(),
+            status: self.status,
+            created_at: self.created_at,
+            progress: self.progress.snapshot(),
+            result: self.result.clone(),
+            error: self.error.clone(),
+        }
+    }
+}
+
+/// Bounded registry of backfill jobs. Finished jobs expire after
+/// [`FINISHED_JOB_TTL`], or earlier when room is needed for a new one.
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl JobRegistry {
+    /// Registers a job and spawns `work` for it, inside the job's progress
+    /// scope. Fails when every slot holds an unfinished job.
+    pub fn submit<F, R>(self: &Arc<Self>, work: F) -> Result<JobReport, IndexerError>
+    where
+        F: Future<Output = Result<R, IndexerError>> + Send + 'static,
+        R: Into<JobResult>,
+    {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let id = hex::encode(bytes);
+        let cancel = CancellationToken::new();
+        let cancelled = cancel.clone();
+        let progress = Arc::new(JobProgress::default());
+        let job = Job {
+            status: JobStatus::Pending,
+            created_at: Utc::now().timestamp(),
+            finished_at: None,
+            progress: progress.clone(),
+            result: None,
+            error: None,
+            cancel,
+        };
+        let report = {
+            let mut jobs = self.jobs.lock().expect("job registry lock poisoned");
+            prune(&mut jobs, Instant::now());
+            if jobs.len() >= MAX_JOBS {
+                return Err(IndexerError::Overloaded(format!(
+                    "{} backfill jobs are already in progress",
+                    jobs.len()
+                )));
+            }
+            let report = job.report(&id);
+            jobs.insert(id.clone(), job);
+            report
+        };
+
+        let registry = self.clone();
+        let task = async move {
+            registry.update(&id, |job| job.status = JobStatus::Running);
+            let work = PROGRESS.scope(progress, deadline::scope(cancelled.clone(), work));
+            let outcome = tokio::select! {
+                outcome = work => Some(outcome),
+                _ = cancelled.cancelled() => None,
+            };
+            registry.update(&id, |job| {
+                match outcome {
+                    Some(Ok(result)) => {
+                        job.status = JobStatus::Done;
+                        job.result = Some(result.into());
+                    }
+                    Some(Err(e)) => {
+                        warn!(job = %id, code = %e.code(), error = %e, "Backfill job failed");
+                        job.status = JobStatus::Failed;
+                        job.error = Some(JobError {
+                            code: e.code(),
+                            message: e.public_message(),
+                        });
+                    }
+                    None => job.status = JobStatus::Cancelled,
+                }
+                job.finished_at = Some(Instant::now());
+            });
+        };
+        tokio::spawn(task.in_current_span());
+        Ok(report)
+    }
+
+    fn update(&self, id: &str, apply: impl FnOnce(&mut Job)) {
+        let mut jobs = self.jobs.lock().expect("job registry lock poisoned");
+        // A cancelled job keeps its status even if the work raced it.
+        if let Some(job) = jobs.get_mut(id).filter(|job| !job.status.is_finished()) {
+            apply(job);
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobReport> {
+        let mut jobs = self.jobs.lock().expect("job registry lock poisoned");
+        prune(&mut jobs, Instant::now());
+        jobs.get(id).map(|job| job.report(id))
+    }
+
+    /// Cancels an unfinished job, or forgets a finished one. Returns the
+    /// job as it stands afterwards.
+    pub fn cancel(&self, id: &str) -> Option<JobReport> {
+        let mut jobs = self.jobs.lock().expect("job registry lock poisoned");
+        let job = jobs.get_mut(id)?;
+        if job.status.is_finished() {
+            return jobs.remove(id).map(|job| job.report(id));
+        }
+        job.cancel.cancel();
+        job.status = JobStatus::Cancelled;
+        job.finished_at = Some(Instant::now());
+        Some(job.report(id))
+    }
+}
+
+/// Drops expired finished jobs, then the oldest finished ones while the
+/// registry is full.
+fn prune(jobs: &mut HashMap<String, Job>, now: Instant) {
+    jobs.retain(|_, job| {
+        job.finished_at
+            .is_none_or(|finished| now.duration_since(finished) < FINISHED_JOB_TTL)
+    });
+    while jobs.len() >= MAX_JOBS {
+        let oldest = jobs
+            .iter()
+            .filter_map(|(id, job)| job.finished_at.map(|finished| (finished, id.clone())))
+            .min();
+        match oldest {
+            Some((_, id)) => jobs.remove(&id),
+            None => break,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{AnchorSource, TimeWindow, WindowAnchor};
+
+    fn response() -> BackfillResponse {
+        BackfillResponse {
+            wallet: "wallet".to_string(),
+            mint: "mint".to_string(),
+            symbol: "USDC".to_string(),
+            window: TimeWindow { start: 0, end: 1 },
+            window_anchor: WindowAnchor {
+                source: AnchorSource::Request,
+                time: 1,
+                slot: None,
+            },
+            transfers: Vec::new(),
+            undecodable_transactions: 0,
+            diagnostics: Default::default(),
+            pages_scanned: 1,
+            truncated: false,
+            discrepancies: Vec::new(),
+            has_more: false,
+            next_cursor: None,
+            running_balance: None,
+            price_source: None,
+        }
+    }
+
+    async fn wait_until_finished(registry: &JobRegistry, id: &str) -> JobReport {
+        loop {
+            let report = registry.get(id).unwrap();
+            if report.status.is_finished() {
+                return report;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_progress_and_result() {
+        let registry = Arc::new(JobRegistry::default());
+        let submitted = registry
+            .submit(async {
+                record_signatures(3, Some(100));
+                record_signatures(2, Some(50));
+                record_transfer();
+                Ok(response())
+            })
+            .unwrap();
+        assert_eq!(submitted.status, JobStatus::Pending);
+
+        let report = wait_until_finished(&registry, &submitted.id).await;
+        assert_eq!(report.status, JobStatus::Done);
+        assert_eq!(
+            report.progress,
+            ProgressSnapshot {
+                signatures_scanned: 5,
+                transfers_found: 1,
+                oldest_block_time: Some(50),
+            }
+        );
+        let Some(JobResult::Backfill(result)) = report.result else {
+            panic!("no backfill result");
+        };
+        assert_eq!(result.wallet, "wallet");
+
+        let failed = registry
+            .submit(async {
+                Err::<JobResult, _>(IndexerError::InvalidParameter("bad".to_string()))
+            })
+            .unwrap();
+        let report = wait_until_finished(&registry, &failed.id).await;
+        assert_eq!(report.status, JobStatus::Failed);
+        assert_eq!(report.error.unwrap().code, ErrorCode::InvalidParameter);
+    }
+
+    #[tokio::test]
+    async fn cancels_a_running_job() {
+        let registry = Arc::new(JobRegistry::default());
+        let submitted = registry
+            .submit(async {
+                std::future::pending::<()>().await;
+                Ok(response())
+            })
+            .unwrap();
+        let report = registry.cancel(&submitted.id).unwrap();
+        assert_eq!(report.status, JobStatus::Cancelled);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            registry.get(&submitted.id).unwrap().status,
+            JobStatus::Cancelled
+        );
+        // A second DELETE forgets the finished job.
+        assert!(registry.cancel(&submitted.id).is_some());
+        assert!(registry.get(&submitted.id).is_none());
+    }
+}
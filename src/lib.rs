@@ -0,0 +1,81 @@
+//! Indexes SPL token transfers (USDC by default) for a Solana wallet and
+//! serves them over HTTP.
+//!
+//! The binary wires these modules into a warp server; embedders can call
+//! [`backfill`] directly or drive [`indexer::backfill_transfers`] with their
+//! own client and config.
+
+pub mod alerts;
+pub mod allowlist;
+pub mod auth;
+pub mod cache;
+pub mod compression;
+pub mod config;
+pub mod deadline;
+pub mod decode;
+pub mod diagnostics;
+pub mod discord;
+pub mod error;
+pub mod etag;
+pub mod events;
+pub mod export;
+pub mod failover;
+pub mod graphql;
+pub mod grpc;
+pub mod indexer;
+pub mod jobs;
+pub mod limits;
+mod metrics;
+pub mod model;
+pub mod openapi;
+pub mod output;
+pub mod parser;
+pub mod price;
+pub mod progress;
+pub mod raw;
+pub mod reports;
+pub mod request_id;
+pub mod rpc;
+pub mod s3;
+pub mod server;
+pub mod stats;
+pub mod store;
+pub mod telegram;
+pub mod telemetry;
+pub mod throttle;
+pub mod tls;
+pub mod unix_socket;
+pub mod watchlist;
+pub mod webhook;
+pub mod ws;
+
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+
+pub use config::{Config, MintInfo, MintRegistry};
+pub use error::IndexerError;
+pub use model::{Direction, TimeWindow, Transfer};
+
+/// Fetches `wallet`'s successful `mint` transfers inside `window` straight
+/// from mainnet, oldest first, without touching any store.
+pub async fn backfill(
+    wallet: &Pubkey,
+    mint: &MintInfo,
+    window: TimeWindow,
+) -> Result<Vec<Transfer>> {
+    let client =
+        RpcClient::new_with_commitment(config::RPC_URL.to_string(), CommitmentConfig::confirmed());
+    let request = model::BackfillRequest {
+        wallet: *wallet,
+        mint: mint.clone(),
+        window,
+        until: None,
+        include_failed: false,
+        include_orphaned: false,
+        strategy: model::Strategy::Instructions,
+        commitment: CommitmentConfig::confirmed(),
+    };
+    let outcome = indexer::backfill_transfers(&client, &Config::default(), &request).await?;
+    Ok(outcome.transfers)
+}
@@ -0,0 +1,137 @@
+//! Admission control for the HTTP side: a cap on backfills running at once
+//! and an optional per-client request rate. One cheap request can cost
+//! thousands of RPC calls, so both are enforced before any work starts.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::error::IndexerError;
+
+/// What a client turned away for lack of a backfill slot is told to wait.
+const BACKFILL_RETRY_AFTER: Duration = Duration::from_secs(5);
+/// Buckets tracked before refilled ones are dropped to make room.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Slots for backfills running at once.
+#[derive(Debug, Clone)]
+pub struct BackfillPermits {
+    semaphore: Arc<Semaphore>,
+}
+
+impl BackfillPermits {
+    pub fn new(max_concurrent: usize) -> Self {
+        BackfillPermits {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+        }
+    }
+
+    /// A slot for a request waiting on the answer, or a 429 if none is free.
+    pub fn try_acquire(&self) -> Result<OwnedSemaphorePermit, IndexerError> {
+        self.semaphore
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| IndexerError::BackfillsBusy {
+                retry_after: BACKFILL_RETRY_AFTER,
+            })
+    }
+
+    /// A slot for a background job, which can wait its turn.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("backfill semaphore is never closed")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token bucket per client address: `burst` requests at once, refilled at
+/// `per_minute`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    burst: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        RateLimiter {
+            per_minute,
+            burst,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for `client`; when there is none, the error says how
+    /// long until there will be.
+    pub fn check(&self, client: IpAddr, now: Instant) -> Result<(), IndexerError> {
+        let rate = f64::from(self.per_minute) / 60.0;
+        let capacity = f64::from(self.burst);
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&client) {
+            // A full bucket carries no state worth keeping.
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.refilled_at).as_secs_f64() * rate
+                    < capacity
+            });
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let wait = (1.0 - bucket.tokens) / rate;
+        Err(IndexerError::RateLimited {
+            retry_after: Duration::from_secs_f64(wait.ceil()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refills_each_client_bucket_at_the_configured_rate() {
+        let limiter = RateLimiter::new(60, 2);
+        let start = Instant::now();
+        let alice: IpAddr = "10.0.0.1".parse().unwrap();
+        let bob: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(limiter.check(alice, start).is_ok());
+        assert!(limiter.check(alice, start).is_ok());
+        match limiter.check(alice, start) {
+            Err(IndexerError::RateLimited { retry_after }) => {
+                assert_eq!(retry_after, Duration::from_secs(1))
+            }
+            other => panic!("expected a rate limit, got {:?}", other),
+        }
+        assert!(limiter.check(bob, start).is_ok());
+        assert!(limiter.check(alice, start + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn caps_concurrent_backfills() {
+        let permits = BackfillPermits::new(1);
+        let held = permits.try_acquire().unwrap();
+        let busy = permits.try_acquire().unwrap_err();
+        assert_eq!(busy.code().as_str(), "TOO_MANY_BACKFILLS");
+        drop(held);
+        assert!(permits.try_acquire().is_ok());
+    }
+}
@@ -1,166 +1,426 @@
-use anyhow::Result;
-use chrono::{DateTime, Utc};
-use solana_client::rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient};
-use solana_client::rpc_config::RpcTransactionConfig;
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
-use solana_transaction_status::{
-    EncodedTransaction, UiInstruction, UiMessage, UiParsedInstruction, UiTransactionEncoding,
+use clap::{Args, Parser, Subcommand};
+use futures::FutureExt;
+use solana_usdc_indexer::config::Config;
+use solana_usdc_indexer::failover::FailoverRpc;
+use solana_usdc_indexer::limits::BackfillPermits;
+use solana_usdc_indexer::output::OutputFormat;
+use solana_usdc_indexer::rpc::SolanaRpc;
+use solana_usdc_indexer::server::BackfillQuery;
+use solana_usdc_indexer::store::{self, Storage};
+use solana_usdc_indexer::throttle::ThrottledRpc;
+use solana_usdc_indexer::watchlist::Watchlist;
+use solana_usdc_indexer::{
+    alerts, discord, grpc, indexer, reports, s3, server, telegram, telemetry, tls, unix_socket,
+    webhook,
 };
+use std::path::PathBuf;
 use std::str::FromStr;
-use warp::Filter;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
-const USDC_MINT_ADDRESS: &str = "Es9vMFrzaCERH16Cdv83hA5KaM6rDx8JEX5Rk3z3aZ9o";
-const WALLET_ADDRESS: &str = "7cMEhpt9y3inBNVv8fNnuaEbx7hKHZnLvR1KWKKxuDDU";
+/// Render sends SIGKILL 30s after SIGTERM; leave time to flush the store.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(25);
 
-async fn backfill_usdc_transfers() -> Result<String> {
-    let rpc_url = "https://api.mainnet-beta.solana.com";
-    let client = RpcClient::new_with_commitment(rpc_url.to_string(), CommitmentConfig::confirmed());
-
-    let wallet = Pubkey::from_str(WALLET_ADDRESS)?;
+/// Installs the global subscriber, writing to stderr so stdout stays clean
+/// for `backfill` output. `RUST_LOG` sets the filter (default
+/// `info`); `LOG_FORMAT=json` switches to one JSON object per line and
+/// `LOG_FORMAT=pretty` to multi-line human output. Spans also go to an
+/// OTLP collector when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+fn init_tracing() -> anyhow::Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let fmt = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => fmt.json().boxed(),
+        Ok("pretty") => fmt.pretty().boxed(),
+        _ => fmt.boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(telemetry::layer()?)
+        .init();
+    Ok(())
+}
 
-    let now = chrono::Utc::now();
-    let cutoff_ts = now.timestamp() - 24 * 3600; // Last 24 hours
+#[derive(Debug, Parser)]
+#[command(version, about = "Indexes SPL stablecoin transfers for Solana wallets")]
+struct Cli {
+    /// TOML config file; environment variables override its values.
+    #[arg(long, global = true, env = "CONFIG_PATH")]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    let mut before_signature: Option<Signature> = None;
-    let mut transfers = Vec::new();
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the HTTP server and background indexer (the default).
+    Serve,
+    /// Index one wallet once, write the result to stdout and exit.
+    Backfill(Box<BackfillArgs>),
+}
 
-    'outer: loop {
-        let sigs = client.get_signatures_for_address_with_config(
-            &wallet,
-            GetConfirmedSignaturesForAddress2Config {
-                before: before_signature,
-                until: None,
-                limit: Some(1000),
-                commitment: Some(CommitmentConfig::confirmed()),
-            },
-        )?;
+#[derive(Debug, Args)]
+struct BackfillArgs {
+    /// Wallet to index, or `all` for every tracked wallet; defaults to the
+    /// configured wallet.
+    #[arg(long)]
+    wallet: Option<String>,
+    /// Mint pubkey to index; alternatively pick one by `--symbol`.
+    #[arg(long)]
+    mint: Option<String>,
+    #[arg(long)]
+    symbol: Option<String>,
+    /// Lookback from `--end` (or now) in hours. Conflicts with `--start`.
+    #[arg(long, conflicts_with = "start")]
+    hours: Option<i64>,
+    /// Inclusive window bounds as unix timestamps.
+    #[arg(long)]
+    start: Option<i64>,
+    #[arg(long)]
+    end: Option<i64>,
+    /// `json`, `csv`, `text` or `ndjson`; the same bodies `GET /backfill`
+    /// returns.
+    #[arg(long, default_value = "json", value_parser = OutputFormat::from_str)]
+    format: OutputFormat,
+    /// `desc` (newest first) or `asc`.
+    #[arg(long, default_value = "desc")]
+    order: String,
+    /// `instructions`, `balances` or `both` (instructions plus a report of
+    /// transactions where the two disagree).
+    #[arg(long, default_value = "instructions")]
+    strategy: String,
+    /// Also report transfers from transactions that landed with an error.
+    #[arg(long)]
+    include_failed: bool,
+    /// Also report stored transfers that were rolled back after indexing.
+    #[arg(long)]
+    include_orphaned: bool,
+    /// Leave out `minted`/`burned` records.
+    #[arg(long)]
+    exclude_mints: bool,
+    /// Also print moves between the wallet's own token accounts.
+    #[arg(long)]
+    include_internal: bool,
+    /// Only records of this direction (`sent`, `received`, ...).
+    #[arg(long)]
+    direction: Option<String>,
+    /// Inclusive amount bounds in UI units.
+    #[arg(long)]
+    min_amount: Option<String>,
+    #[arg(long)]
+    max_amount: Option<String>,
+    /// Token account or owner wallet on the other side.
+    #[arg(long)]
+    counterparty: Option<String>,
+    /// Only transfers whose memo contains this text, ignoring case.
+    #[arg(long)]
+    memo_contains: Option<String>,
+    /// Page size; the configured default applies when unset.
+    #[arg(long)]
+    limit: Option<usize>,
+    /// `next_cursor` of a previous run, to fetch the following page.
+    #[arg(long)]
+    cursor: Option<String>,
+    /// Add each transfer's `balance_after`, walked back from the current
+    /// balance.
+    #[arg(long)]
+    running_balance: bool,
+    /// `processed`, `confirmed` or `finalized`; the configured commitment
+    /// by default.
+    #[arg(long)]
+    commitment: Option<String>,
+}
 
-        if sigs.is_empty() {
-            break;
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = init_tracing() {
+        eprintln!("invalid tracing configuration: {:#}", e);
+        std::process::exit(1);
+    }
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "invalid configuration");
+            std::process::exit(1);
         }
+    };
+    config.log_effective();
+    let client: Arc<dyn SolanaRpc> = match FailoverRpc::from_config(&config) {
+        Ok(client)
+            if config.rpc_requests_per_second.is_none() && config.rpc_method_limits.is_empty() =>
+        {
+            Arc::new(client)
+        }
+        Ok(client) => Arc::new(ThrottledRpc::new(
+            Arc::new(client),
+            config.rpc_requests_per_second,
+            &config.rpc_method_limits,
+        )),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "invalid RPC configuration");
+            std::process::exit(1);
+        }
+    };
+    // verify_mints = false skips the check, e.g. when the RPC is unreachable at boot.
+    if config.verify_mints {
+        if let Err(e) = indexer::verify_mints(client.as_ref(), &config).await {
+            error!(error = %e, "mint verification failed, refusing to start");
+            std::process::exit(1);
+        }
+    }
+    let store = match store::open(&config).await {
+        Ok(store) => store,
+        Err(e) => {
+            error!(error = %e, "failed to open transfer store");
+            std::process::exit(1);
+        }
+    };
 
-        for sig_info in &sigs {
-            let block_time = match sig_info.block_time {
-                Some(ts) => ts,
-                None => continue,
-            };
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(client, config, store).await,
+        Command::Backfill(args) => backfill_once(client, config, store, *args).await,
+    }
+    telemetry::shutdown();
+}
 
-            if block_time < cutoff_ts {
-                break 'outer;
+/// Runs one backfill through the same validation and rendering as
+/// `GET /backfill` and prints the body to stdout.
+async fn backfill_once(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    args: BackfillArgs,
+) {
+    let query = BackfillQuery {
+        wallet: args.wallet,
+        mint: args.mint,
+        symbol: args.symbol,
+        hours: args.hours,
+        start: args.start,
+        end: args.end,
+        format: Some(args.format.to_string()),
+        order: Some(args.order),
+        strategy: Some(args.strategy),
+        include_failed: args.include_failed,
+        include_orphaned: args.include_orphaned,
+        include_mints: !args.exclude_mints,
+        include_internal: args.include_internal,
+        direction: args.direction,
+        min_amount: args.min_amount,
+        max_amount: args.max_amount,
+        counterparty: args.counterparty,
+        memo_contains: args.memo_contains,
+        limit: args.limit,
+        cursor: args.cursor,
+        running_balance: args.running_balance,
+        commitment: args.commitment,
+    };
+    let result = server::run_backfill(query, client.as_ref(), &config, &store).await;
+    if let Err(e) = store.close().await {
+        error!(error = %e, "failed to flush transfer store");
+    }
+    let body = result
+        .map_err(anyhow::Error::from)
+        .and_then(|(format, response)| {
+            if let Some(cursor) = &response.next_cursor {
+                info!(cursor = %cursor, "more transfers match, continue with --cursor");
             }
+            response.render(format)
+        });
+    match body {
+        Ok(body) => println!("{}", body),
+        Err(e) => {
+            error!(error = %e, "backfill failed");
+            telemetry::shutdown();
+            std::process::exit(1);
+        }
+    }
+}
 
-            let tx = client.get_transaction_with_config(
-                &sig_info.signature.parse()?,
-                RpcTransactionConfig {
-                    encoding: Some(UiTransactionEncoding::JsonParsed),
-                    commitment: None,
-                    max_supported_transaction_version: None,
-                },
-            )?;
-
-            let instructions = match &tx.transaction.transaction {
-                EncodedTransaction::Json(parsed_tx) => match &parsed_tx.message {
-                    UiMessage::Parsed(parsed_msg) => &parsed_msg.instructions,
-                    _ => continue,
+async fn serve(client: Arc<dyn SolanaRpc>, config: Arc<Config>, store: Storage) {
+    // Fail at startup rather than at the first report or export.
+    if let Some(target) = &config.s3 {
+        match s3::check_access(target).await {
+            Ok(()) => {
+                info!(bucket = %target.bucket, dry_run = target.dry_run, "S3 bucket is writable")
+            }
+            Err(e) => {
+                error!(bucket = %target.bucket, error = %format!("{:#}", e), "cannot write to the S3 bucket");
+                std::process::exit(1);
+            }
+        }
+    }
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let watchlist = match Watchlist::start(
+        client.clone(),
+        config.clone(),
+        store.clone(),
+        shutdown_rx.clone(),
+    )
+    .await
+    {
+        Ok(watchlist) => Arc::new(watchlist),
+        Err(e) => {
+            error!(error = %e, "cannot load watched wallets");
+            std::process::exit(1);
+        }
+    };
+    let permits = BackfillPermits::new(config.max_concurrent_backfills);
+    let route = server::routes(
+        client.clone(),
+        config.clone(),
+        store.clone(),
+        watchlist.clone(),
+        permits.clone(),
+    );
+    let server = match (&config.unix_socket, &config.tls) {
+        (Some(_), Some(_)) => unreachable!("the config rejects TLS on a unix socket"),
+        (Some(socket), None) => match unix_socket::bind(route, socket, shutdown_rx.clone()) {
+            Ok(server) => {
+                info!(
+                    path = %socket.path.display(),
+                    mode = format!("{:o}", socket.mode),
+                    transport = "unix",
+                    cluster = config.cluster.name(),
+                    "listening"
+                );
+                server.boxed()
+            }
+            Err(e) => {
+                error!(error = format!("{:#}", e), "cannot bind unix socket");
+                std::process::exit(1);
+            }
+        },
+        (None, Some(files)) => match tls::bind(
+            route,
+            (config.bind_addr, config.port),
+            files.clone(),
+            shutdown_rx.clone(),
+        )
+        .await
+        {
+            Ok((addr, server)) => {
+                // With `port = 0` this is the only place the chosen port shows up.
+                info!(%addr, transport = "https", cluster = config.cluster.name(), "listening");
+                server.boxed()
+            }
+            Err(e) => {
+                error!(error = format!("{:#}", e), "cannot serve HTTPS");
+                std::process::exit(1);
+            }
+        },
+        (None, None) => {
+            let mut server_shutdown = shutdown_rx.clone();
+            match warp::serve(route).try_bind_with_graceful_shutdown(
+                (config.bind_addr, config.port),
+                async move {
+                    let _ = server_shutdown.changed().await;
                 },
-                _ => continue,
-            };
-
-            for ix in instructions {
-                if let UiInstruction::Parsed(ui_parsed) = ix {
-                    if let UiParsedInstruction::Parsed(parsed) = ui_parsed {
-                        if parsed.program != "spl-token" {
-                            continue;
-                        }
-
-                        let instruction_type = parsed.parsed.get("type")
-                            .and_then(|v| v.as_str())
-                            .unwrap_or("");
-                        if instruction_type != "transfer" && instruction_type != "transferChecked" {
-                            continue;
-                        }
-
-                        let info = match parsed.parsed.get("info") {
-                            Some(i) => i,
-                            None => continue,
-                        };
-
-                        if let Some(mint) = info.get("mint").and_then(|v| v.as_str()) {
-                            if mint != USDC_MINT_ADDRESS {
-                                continue;
-                            }
-                        }
-
-                        let source = info.get("source").and_then(|v| v.as_str());
-                        let destination = info.get("destination").and_then(|v| v.as_str());
-
-                        let amount_str = info.get("amount")
-                            .and_then(|v| v.as_str())
-                            .or_else(|| {
-                                info.get("tokenAmount")
-                                    .and_then(|token_amount| token_amount.get("amount").and_then(|v| v.as_str()))
-                            })
-                            .unwrap_or("0");
-
-                        let amount_u64 = amount_str.parse::<u64>().unwrap_or(0);
-                        if amount_u64 == 0 {
-                            continue;
-                        }
-
-                        let amount = amount_u64 as f64 / 1_000_000f64; // USDC has 6 decimals
-
-                        let direction = if let Some(src) = source {
-                            if src == WALLET_ADDRESS {
-                                "sent"
-                            } else if let Some(dest) = destination {
-                                if dest == WALLET_ADDRESS {
-                                    "received"
-                                } else {
-                                    continue;
-                                }
-                            } else {
-                                continue;
-                            }
-                        } else {
-                            continue;
-                        };
-
-                        let date = DateTime::<Utc>::from_timestamp(block_time, 0);
-
-                        transfers.push(format!(
-                            "{} | {}{:.6} USDC | {}",
-                            date.to_rfc3339(),
-                            if direction == "sent" { "-" } else { "+" },
-                            amount,
-                            direction,
-                        ));
-                    }
+            ) {
+                Ok((addr, server)) => {
+                    info!(%addr, transport = "http", cluster = config.cluster.name(), "listening");
+                    server.boxed()
+                }
+                Err(e) => {
+                    error!(bind_addr = %config.bind_addr, port = config.port, error = %e, "cannot bind listener");
+                    std::process::exit(1);
                 }
             }
         }
+    };
+    let server = tokio::spawn(server);
+    let grpc_server = match config.grpc_port {
+        Some(port) => match tokio::net::TcpListener::bind((config.bind_addr, port)).await {
+            Ok(listener) => {
+                if let Ok(addr) = listener.local_addr() {
+                    info!(%addr, "gRPC listening");
+                }
+                let serve = grpc::serve(
+                    listener,
+                    client.clone(),
+                    config.clone(),
+                    store.clone(),
+                    permits,
+                    shutdown_rx.clone(),
+                );
+                Some(tokio::spawn(async move {
+                    if let Err(e) = serve.await {
+                        error!(error = %e, "gRPC server failed");
+                    }
+                }))
+            }
+            Err(e) => {
+                error!(bind_addr = %config.bind_addr, port, error = %e, "cannot bind gRPC listener");
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let mut background = Vec::new();
+    background.extend(webhook::spawn(config.clone(), shutdown_rx.clone()));
+    background.extend(telegram::spawn(config.clone(), shutdown_rx.clone()));
+    background.extend(discord::spawn(config.clone(), shutdown_rx.clone()));
+    background.extend(reports::spawn(
+        client.clone(),
+        config.clone(),
+        store.clone(),
+        shutdown_rx.clone(),
+    ));
+    background.extend(alerts::spawn(client, config, shutdown_rx));
 
-        before_signature = sigs.last().map(|s| s.signature.parse().ok()).flatten();
-    }
-
-    transfers.sort();
-    Ok(transfers.join("\n"))
-}
+    shutdown_signal().await;
+    info!("shutdown requested");
+    let _ = shutdown_tx.send(true);
 
-async fn handle_backfill() -> Result<impl warp::Reply, warp::Rejection> {
-    match backfill_usdc_transfers().await {
-        Ok(data) => Ok(warp::reply::with_status(data, warp::http::StatusCode::OK)),
-        Err(e) => Ok(warp::reply::with_status(
-            format!("Error: {}", e),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        )),
+    // The server stops accepting connections immediately; in-flight requests
+    // and background syncs get until the grace period to finish.
+    let drain = async {
+        let _ = server.await;
+        if let Some(grpc_server) = grpc_server {
+            let _ = grpc_server.await;
+        }
+        watchlist.join().await;
+        for task in background {
+            let _ = task.await;
+        }
+    };
+    if tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, drain)
+        .await
+        .is_err()
+    {
+        warn!(
+            grace_period = ?SHUTDOWN_GRACE_PERIOD,
+            "in-flight work did not finish in time, abandoning it"
+        );
+    }
+    if let Err(e) = store.close().await {
+        error!(error = %e, "failed to flush transfer store");
     }
+    info!("shutdown complete");
 }
 
-#[tokio::main]
-async fn main() {
-    let route = warp::path("backfill").and(warp::get()).and_then(handle_backfill);
-
-    // Render expects binding on 0.0.0.0:10000
-    warp::serve(route).run(([0, 0, 0, 0], 10000)).await;
+/// Resolves on the first SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let sigterm = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!(error = %e, "cannot listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm => {}
     }
-        
+}
@@ -0,0 +1,523 @@
+//! Prometheus collectors shared by the indexer and the HTTP handlers.
+
+use prometheus::core::Collector;
+use prometheus::{
+    GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+};
+use std::sync::LazyLock;
+use std::time::Instant;
+use warp::http::StatusCode;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Prometheus collectors served at `/metrics`.
+pub struct Metrics {
+    pub(crate) registry: Registry,
+    /// Every RPC attempt, labelled by method and how it ended.
+    pub(crate) rpc_calls: IntCounterVec,
+    /// Requests sent to each RPC endpoint, by how they ended.
+    pub(crate) rpc_endpoint_requests: IntCounterVec,
+    /// 1 while an endpoint is in rotation, 0 while it is benched.
+    pub(crate) rpc_endpoint_healthy: IntGaugeVec,
+    /// Times each endpoint was benched.
+    pub(crate) rpc_failovers: IntCounterVec,
+    /// Requests let through the client-side budget, by method; its rate is
+    /// the effective QPS.
+    pub(crate) rpc_requests_sent: IntCounterVec,
+    /// Time requests waited on the budget, by method.
+    pub(crate) rpc_throttle_wait: HistogramVec,
+    /// Configured requests per second, `all` for the shared budget.
+    pub(crate) rpc_budget: GaugeVec,
+    /// Fetched transactions, by whether they came back decodable.
+    pub(crate) transactions_parsed: IntCounterVec,
+    /// Extracted transfers by wallet (see [`Config::wallet_label`]) and
+    /// direction.
+    ///
+    /// [`Config::wallet_label`]: crate::config::Config::wallet_label
+    pub(crate) transfers_found: IntCounterVec,
+    /// Wallet transfers dropped instead of reported, by reason.
+    pub(crate) transfers_skipped: IntCounterVec,
+    /// Listed signatures left unread, by reason.
+    pub(crate) signatures_skipped: IntCounterVec,
+    /// Backfills cut short by `max_signature_pages`.
+    pub(crate) backfills_truncated: IntCounterVec,
+    pub(crate) backfill_duration: HistogramVec,
+    pub(crate) http_requests: IntCounterVec,
+    pub(crate) http_request_duration: HistogramVec,
+    /// gRPC calls by method and status code.
+    pub(crate) grpc_requests: IntCounterVec,
+    /// Chain slot observed at the start of the last successful sync.
+    pub(crate) synced_slot: IntGaugeVec,
+    /// Chain tip at scrape time minus `synced_slot`.
+    pub(crate) indexing_lag: IntGaugeVec,
+    /// Unix time of the last successful sync.
+    pub(crate) synced_at: IntGaugeVec,
+    /// Scrape time minus `synced_at`.
+    pub(crate) indexing_lag_seconds: IntGaugeVec,
+    /// Chain tip as of the last scrape or `/status` call.
+    pub(crate) chain_tip: IntGauge,
+    /// Slot and block time of the newest transaction indexed.
+    pub(crate) newest_indexed_slot: IntGaugeVec,
+    pub(crate) newest_block_time: IntGaugeVec,
+    /// Transfers stored per wallet and mint, as of the last sync.
+    pub(crate) stored_transfers: IntGaugeVec,
+    /// Balance snapshots whose change since the previous one the stored
+    /// transfers don't add up to.
+    pub(crate) balance_mismatches: IntCounterVec,
+    /// Base units of the last snapshot's change the transfers don't explain.
+    pub(crate) balance_unexplained: IntGaugeVec,
+    /// Unix time each background loop (`poll` or `live`) last synced every
+    /// mint of a wallet.
+    pub(crate) loop_last_tick: IntGaugeVec,
+    /// 1 while a wallet's websocket subscription is up.
+    pub(crate) live_connected: IntGaugeVec,
+    /// Stored signatures re-checked below `finalized`, by what was found:
+    /// `finalized`, `pending` or `orphaned`.
+    pub(crate) signature_reverifications: IntCounterVec,
+    /// Webhook delivery attempts by target host and outcome.
+    pub(crate) webhook_deliveries: IntCounterVec,
+    pub(crate) telegram_messages: IntCounterVec,
+    pub(crate) discord_messages: IntCounterVec,
+    /// `/backfill` responses served from the response cache or computed.
+    pub(crate) response_cache: IntCounterVec,
+    /// Hours priced for `price_usd`, by source and outcome: `ok`,
+    /// `missing` (the source had no point) or `failed`.
+    pub(crate) price_lookups: IntCounterVec,
+    /// Alert rule checks by rule and outcome: `quiet`, `fired`, `cooldown`
+    /// (fired but held back) or `error`.
+    pub(crate) alert_evaluations: IntCounterVec,
+    /// Alerts sent by channel and outcome.
+    pub(crate) alert_notifications: IntCounterVec,
+    /// Scheduled report deliveries by report and outcome: `delivered` or
+    /// `failed` (retried on the next tick).
+    pub(crate) report_deliveries: IntCounterVec,
+    /// Requests refused by the IP allowlist, by server: `http` or `grpc`.
+    pub(crate) allowlist_denied: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let rpc_calls = IntCounterVec::new(
+            Opts::new(
+                "indexer_rpc_calls_total",
+                "RPC attempts by method and outcome",
+            ),
+            &["method", "outcome"],
+        )
+        .unwrap();
+        let rpc_endpoint_requests = IntCounterVec::new(
+            Opts::new(
+                "indexer_rpc_endpoint_requests_total",
+                "RPC requests by endpoint and outcome",
+            ),
+            &["endpoint", "outcome"],
+        )
+        .unwrap();
+        let rpc_endpoint_healthy = IntGaugeVec::new(
+            Opts::new(
+                "indexer_rpc_endpoint_healthy",
+                "Whether an RPC endpoint is in rotation",
+            ),
+            &["endpoint"],
+        )
+        .unwrap();
+        let rpc_failovers = IntCounterVec::new(
+            Opts::new(
+                "indexer_rpc_failovers_total",
+                "Times an RPC endpoint was marked unhealthy",
+            ),
+            &["endpoint"],
+        )
+        .unwrap();
+        let rpc_requests_sent = IntCounterVec::new(
+            Opts::new(
+                "indexer_rpc_requests_sent_total",
+                "RPC requests let through the client-side budget, by method",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let rpc_throttle_wait = HistogramVec::new(
+            HistogramOpts::new(
+                "indexer_rpc_throttle_wait_seconds",
+                "Time RPC requests waited on the client-side budget, by method",
+            )
+            .buckets(vec![0.0, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+            &["method"],
+        )
+        .unwrap();
+        let rpc_budget = GaugeVec::new(
+            Opts::new(
+                "indexer_rpc_budget_requests_per_second",
+                "Configured RPC requests per second, by method or all",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let transactions_parsed = IntCounterVec::new(
+            Opts::new(
+                "indexer_transactions_parsed_total",
+                "Fetched transactions by decode outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let transfers_found = IntCounterVec::new(
+            Opts::new(
+                "indexer_transfers_found_total",
+                "Wallet transfers extracted by wallet and direction",
+            ),
+            &["wallet", "direction"],
+        )
+        .unwrap();
+        let transfers_skipped = IntCounterVec::new(
+            Opts::new(
+                "indexer_transfers_skipped_total",
+                "Wallet transfers that could not be recorded, by reason",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let signatures_skipped = IntCounterVec::new(
+            Opts::new(
+                "indexer_signatures_skipped_total",
+                "Listed signatures that could not be read, by reason",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let backfills_truncated = IntCounterVec::new(
+            Opts::new(
+                "indexer_backfills_truncated_total",
+                "Backfills that stopped at the signature page cap",
+            ),
+            &["wallet"],
+        )
+        .unwrap();
+        let backfill_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "indexer_backfill_duration_seconds",
+                "Time spent fetching one backfill window from the RPC, by wallet",
+            )
+            .buckets(vec![
+                0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+            ]),
+            &["wallet"],
+        )
+        .unwrap();
+        let http_requests = IntCounterVec::new(
+            Opts::new(
+                "indexer_http_requests_total",
+                "HTTP requests by route and status",
+            ),
+            &["route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "indexer_http_request_duration_seconds",
+                "HTTP request latency by route",
+            ),
+            &["route"],
+        )
+        .unwrap();
+        let grpc_requests = IntCounterVec::new(
+            Opts::new(
+                "indexer_grpc_requests_total",
+                "gRPC calls by method and status code",
+            ),
+            &["method", "code"],
+        )
+        .unwrap();
+        let synced_slot = IntGaugeVec::new(
+            Opts::new(
+                "indexer_synced_slot",
+                "Chain slot at the start of the last successful sync",
+            ),
+            &["wallet", "mint"],
+        )
+        .unwrap();
+        let indexing_lag = IntGaugeVec::new(
+            Opts::new(
+                "indexer_indexing_lag_slots",
+                "Slots between the chain tip and the last successful sync",
+            ),
+            &["wallet", "mint"],
+        )
+        .unwrap();
+        let synced_at = IntGaugeVec::new(
+            Opts::new(
+                "indexer_synced_timestamp_seconds",
+                "Unix time of the last successful sync",
+            ),
+            &["wallet", "mint"],
+        )
+        .unwrap();
+        let indexing_lag_seconds = IntGaugeVec::new(
+            Opts::new(
+                "indexer_indexing_lag_seconds",
+                "Seconds since the last successful sync",
+            ),
+            &["wallet", "mint"],
+        )
+        .unwrap();
+        let chain_tip = IntGauge::new("indexer_chain_tip_slot", "Latest known chain slot").unwrap();
+        let newest_indexed_slot = IntGaugeVec::new(
+            Opts::new(
+                "indexer_newest_indexed_slot",
+                "Slot of the newest transaction indexed",
+            ),
+            &["wallet", "mint"],
+        )
+        .unwrap();
+        let newest_block_time = IntGaugeVec::new(
+            Opts::new(
+                "indexer_newest_block_time_seconds",
+                "Block time of the newest transaction indexed",
+            ),
+            &["wallet", "mint"],
+        )
+        .unwrap();
+        let stored_transfers = IntGaugeVec::new(
+            Opts::new(
+                "indexer_stored_transfers",
+                "Transfers stored, by wallet and mint",
+            ),
+            &["wallet", "mint"],
+        )
+        .unwrap();
+        let balance_mismatches = IntCounterVec::new(
+            Opts::new(
+                "indexer_balance_reconciliation_mismatches_total",
+                "Balance snapshots not matching the transfers since the previous one, by wallet and mint",
+            ),
+            &["wallet", "mint"],
+        )
+        .unwrap();
+        let balance_unexplained = IntGaugeVec::new(
+            Opts::new(
+                "indexer_balance_unexplained_base_units",
+                "Balance change between the last two snapshots not explained by transfers",
+            ),
+            &["wallet", "mint"],
+        )
+        .unwrap();
+        let loop_last_tick = IntGaugeVec::new(
+            Opts::new(
+                "indexer_loop_last_tick_timestamp_seconds",
+                "Unix time a background loop last synced every mint of a wallet",
+            ),
+            &["wallet", "loop"],
+        )
+        .unwrap();
+        let live_connected = IntGaugeVec::new(
+            Opts::new(
+                "indexer_live_connected",
+                "Whether a wallet's websocket subscription is up",
+            ),
+            &["wallet"],
+        )
+        .unwrap();
+        let signature_reverifications = IntCounterVec::new(
+            Opts::new(
+                "indexer_signature_reverifications_total",
+                "Unfinalized signatures re-checked, by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let webhook_deliveries = IntCounterVec::new(
+            Opts::new(
+                "indexer_webhook_deliveries_total",
+                "Webhook delivery attempts by target and outcome",
+            ),
+            &["target", "outcome"],
+        )
+        .unwrap();
+        let telegram_messages = IntCounterVec::new(
+            Opts::new(
+                "indexer_telegram_messages_total",
+                "Telegram messages by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let discord_messages = IntCounterVec::new(
+            Opts::new(
+                "indexer_discord_messages_total",
+                "Discord webhook posts by target and outcome",
+            ),
+            &["target", "outcome"],
+        )
+        .unwrap();
+
+        let response_cache = IntCounterVec::new(
+            Opts::new(
+                "indexer_response_cache_requests_total",
+                "Backfill responses by cache outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+
+        let price_lookups = IntCounterVec::new(
+            Opts::new(
+                "indexer_price_lookups_total",
+                "Hours priced by source and outcome",
+            ),
+            &["source", "outcome"],
+        )
+        .unwrap();
+
+        let alert_evaluations = IntCounterVec::new(
+            Opts::new(
+                "indexer_alert_evaluations_total",
+                "Alert rule checks by rule and outcome",
+            ),
+            &["rule", "outcome"],
+        )
+        .unwrap();
+        let alert_notifications = IntCounterVec::new(
+            Opts::new(
+                "indexer_alert_notifications_total",
+                "Alert deliveries by channel and outcome",
+            ),
+            &["channel", "outcome"],
+        )
+        .unwrap();
+        let report_deliveries = IntCounterVec::new(
+            Opts::new(
+                "indexer_report_deliveries_total",
+                "Scheduled report deliveries by report and outcome",
+            ),
+            &["report", "outcome"],
+        )
+        .unwrap();
+        let allowlist_denied = IntCounterVec::new(
+            Opts::new(
+                "indexer_allowlist_denied_total",
+                "Requests from addresses outside the IP allowlist, by server",
+            ),
+            &["server"],
+        )
+        .unwrap();
+
+        for collector in [
+            Box::new(rpc_calls.clone()) as Box<dyn Collector>,
+            Box::new(rpc_endpoint_requests.clone()),
+            Box::new(rpc_endpoint_healthy.clone()),
+            Box::new(rpc_failovers.clone()),
+            Box::new(rpc_requests_sent.clone()),
+            Box::new(rpc_throttle_wait.clone()),
+            Box::new(rpc_budget.clone()),
+            Box::new(transactions_parsed.clone()),
+            Box::new(transfers_found.clone()),
+            Box::new(transfers_skipped.clone()),
+            Box::new(signatures_skipped.clone()),
+            Box::new(backfills_truncated.clone()),
+            Box::new(backfill_duration.clone()),
+            Box::new(http_requests.clone()),
+            Box::new(http_request_duration.clone()),
+            Box::new(grpc_requests.clone()),
+            Box::new(synced_slot.clone()),
+            Box::new(indexing_lag.clone()),
+            Box::new(synced_at.clone()),
+            Box::new(indexing_lag_seconds.clone()),
+            Box::new(chain_tip.clone()),
+            Box::new(newest_indexed_slot.clone()),
+            Box::new(newest_block_time.clone()),
+            Box::new(stored_transfers.clone()),
+            Box::new(balance_mismatches.clone()),
+            Box::new(balance_unexplained.clone()),
+            Box::new(loop_last_tick.clone()),
+            Box::new(live_connected.clone()),
+            Box::new(signature_reverifications.clone()),
+            Box::new(webhook_deliveries.clone()),
+            Box::new(telegram_messages.clone()),
+            Box::new(discord_messages.clone()),
+            Box::new(response_cache.clone()),
+            Box::new(price_lookups.clone()),
+            Box::new(alert_evaluations.clone()),
+            Box::new(alert_notifications.clone()),
+            Box::new(report_deliveries.clone()),
+            Box::new(allowlist_denied.clone()),
+        ] {
+            registry.register(collector).unwrap();
+        }
+
+        Self {
+            registry,
+            rpc_calls,
+            rpc_endpoint_requests,
+            rpc_endpoint_healthy,
+            rpc_failovers,
+            rpc_requests_sent,
+            rpc_throttle_wait,
+            rpc_budget,
+            transactions_parsed,
+            transfers_found,
+            transfers_skipped,
+            signatures_skipped,
+            backfills_truncated,
+            backfill_duration,
+            http_requests,
+            http_request_duration,
+            grpc_requests,
+            synced_slot,
+            indexing_lag,
+            synced_at,
+            indexing_lag_seconds,
+            chain_tip,
+            newest_indexed_slot,
+            newest_block_time,
+            stored_transfers,
+            balance_mismatches,
+            balance_unexplained,
+            loop_last_tick,
+            live_connected,
+            signature_reverifications,
+            webhook_deliveries,
+            telegram_messages,
+            discord_messages,
+            response_cache,
+            price_lookups,
+            alert_evaluations,
+            alert_notifications,
+            report_deliveries,
+            allowlist_denied,
+        }
+    }
+
+    pub fn observe_http(&self, route: &str, status: StatusCode, started: Instant) {
+        self.http_requests
+            .with_label_values(&[route, status.as_str()])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[route])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Recomputes every lag gauge against the current chain tip and clock.
+    pub fn update_lag(&self, tip: u64, now: i64) {
+        self.chain_tip.set(tip as i64);
+        for family in self.synced_at.collect() {
+            for metric in family.get_metric() {
+                let labels: Vec<&str> = metric.get_label().iter().map(|l| l.get_value()).collect();
+                let synced_at = metric.get_gauge().get_value() as i64;
+                self.indexing_lag_seconds
+                    .with_label_values(&labels)
+                    .set((now - synced_at).max(0));
+            }
+        }
+        for family in self.synced_slot.collect() {
+            for metric in family.get_metric() {
+                let labels: Vec<&str> = metric.get_label().iter().map(|l| l.get_value()).collect();
+                let synced = metric.get_gauge().get_value() as i64;
+                self.indexing_lag
+                    .with_label_values(&labels)
+                    .set((tip as i64 - synced).max(0));
+            }
+        }
+    }
+}
@@ -0,0 +1,1312 @@
+//! Types shared by the indexer, the store and the HTTP layer.
+
+use anyhow::Result;
+use async_graphql::{ComplexObject, Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use crate::config::MintInfo;
+
+/// Inclusive `[start, end]` range of block times to index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct TimeWindow {
+    pub start: i64,
+    pub end: i64,
+}
+
+/// Where the "now" a window is measured back from came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[graphql(rename_items = "snake_case")]
+pub enum AnchorSource {
+    /// `?end=` was given.
+    Request,
+    /// Block time of the latest finalized slot.
+    Chain,
+    /// The host clock, when the chain couldn't be asked.
+    SystemClock,
+}
+
+/// The time a backfill window ends at unless `?end=` is earlier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct WindowAnchor {
+    pub source: AnchorSource,
+    pub time: i64,
+    /// The finalized slot whose block time this is, for `chain`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[graphql(rename_items = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+    /// Both sides are token accounts owned by the wallet, e.g. consolidating
+    /// ATAs. Stored before the rename as `self`, which still parses.
+    #[serde(alias = "self")]
+    Internal,
+    /// `mintTo` into one of the wallet's token accounts; the mint stands in
+    /// as the source.
+    Minted,
+    /// `burn` from one of the wallet's token accounts; the mint stands in
+    /// as the destination.
+    Burned,
+}
+
+impl FromStr for Direction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sent" => Ok(Direction::Sent),
+            "received" => Ok(Direction::Received),
+            "internal" | "self" => Ok(Direction::Internal),
+            "minted" => Ok(Direction::Minted),
+            "burned" => Ok(Direction::Burned),
+            other => anyhow::bail!("unknown direction '{}'", other),
+        }
+    }
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+            Direction::Internal => "internal",
+            Direction::Minted => "minted",
+            Direction::Burned => "burned",
+        }
+    }
+
+    /// Tokens arrived in the wallet.
+    pub fn is_inflow(&self) -> bool {
+        matches!(self, Direction::Received | Direction::Minted)
+    }
+
+    /// Tokens left the wallet.
+    pub fn is_outflow(&self) -> bool {
+        matches!(self, Direction::Sent | Direction::Burned)
+    }
+
+    /// A change of the mint's supply rather than a movement between holders.
+    pub fn is_supply_change(&self) -> bool {
+        matches!(self, Direction::Minted | Direction::Burned)
+    }
+}
+
+/// A single spl-token movement into or out of the indexed wallet.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject, ToSchema)]
+#[graphql(complex, rename_fields = "snake_case")]
+pub struct Transfer {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: i64,
+    /// Index of the top-level instruction that produced the transfer.
+    pub instruction_index: usize,
+    /// Position within that instruction's CPIs, `None` for top-level transfers.
+    pub inner_index: Option<usize>,
+    pub direction: Direction,
+    /// Amount in the mint's base units. For a Token-2022 transfer with a
+    /// fee this is the gross amount debited from the source; see `fee`.
+    pub amount_raw: u64,
+    /// `amount_raw` rendered as an exact decimal string.
+    pub amount_ui: String,
+    pub source: String,
+    pub destination: String,
+    pub mint: String,
+    pub symbol: String,
+    /// Transfer fee withheld by a Token-2022 mint (`transferCheckedWithFee`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<TransferFee>,
+    /// Wallet owning the [`Transfer::counterparty`] token account, when the
+    /// transaction's token balances name it.
+    #[serde(default)]
+    pub counterparty_owner: Option<String>,
+    /// Account that paid the transaction's SOL fee.
+    #[serde(default)]
+    pub fee_payer: Option<String>,
+    /// The SOL fee, on outgoing records when the wallet was the fee payer;
+    /// `null` when someone else (e.g. a relayer) paid.
+    #[serde(default)]
+    pub network_fee: Option<NetworkFee>,
+    /// Text of the transaction's Memo program instructions, one per line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    /// Commitment the transaction was read at, when below `finalized`:
+    /// such a record can still be rolled back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<String>,
+    /// Wallet the record belongs to, filled in when a response is built;
+    /// tells the streams apart under `?wallet=all`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallet: Option<String>,
+    /// Link to the transaction in a block explorer. Filled in when a
+    /// response is built, so it follows the current `explorer_tx_url`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explorer_url: Option<String>,
+    /// The other side, also filled in when a response is built; absent for
+    /// mints and burns, which have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[graphql(skip)]
+    pub counterparty: Option<Counterparty>,
+    /// The transaction landed but reverted; only present with `include_failed`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub failed: bool,
+    /// Re-checking the transaction found it rolled back or reverted; only
+    /// present with `include_orphaned`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub orphaned: bool,
+    /// A delegate the wallet approved, not the wallet itself, signed for
+    /// the debit of its token account.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub via_delegate: bool,
+    /// Wallet balance of the mint right after this transfer, filled in for
+    /// `?running_balance=true`; see [`apply_running_balance`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance_after_raw: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance_after: Option<String>,
+    /// The running balance could not account for this row, so it has no
+    /// `balance_after`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub balance_excluded: bool,
+    /// USD price of the mint in the hour of `block_time`, filled in when a
+    /// response is built with a `price_source` configured; `null` when the
+    /// source had none.
+    #[serde(default)]
+    pub price_usd: Option<String>,
+    /// `amount_ui` times `price_usd`.
+    #[serde(default)]
+    pub value_usd: Option<String>,
+}
+
+/// Who a transfer was with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct Counterparty {
+    pub token_account: String,
+    /// Wallet owning `token_account`, from the transaction's token balances
+    /// or a lookup of the account.
+    pub owner: Option<String>,
+    pub resolved: bool,
+}
+
+/// A transaction fee in lamports, also rendered in SOL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct NetworkFee {
+    pub lamports: u64,
+    pub sol: String,
+}
+
+pub const SOL_DECIMALS: u8 = 9;
+
+impl NetworkFee {
+    pub fn new(lamports: u64) -> Self {
+        Self {
+            lamports,
+            sol: format_amount(lamports, SOL_DECIMALS),
+        }
+    }
+}
+
+/// The fee part of a Token-2022 transfer: `fee_raw` was withheld from the
+/// gross `amount_raw`, so the destination received `net_amount_raw`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct TransferFee {
+    pub fee_raw: u64,
+    pub fee_ui: String,
+    pub net_amount_raw: u64,
+    pub net_amount_ui: String,
+}
+
+impl TransferFee {
+    /// `None` if the fee exceeds the gross amount.
+    pub fn new(gross_raw: u64, fee_raw: u64, decimals: u8) -> Option<Self> {
+        let net_amount_raw = gross_raw.checked_sub(fee_raw)?;
+        Some(TransferFee {
+            fee_raw,
+            fee_ui: format_amount(fee_raw, decimals),
+            net_amount_raw,
+            net_amount_ui: format_amount(net_amount_raw, decimals),
+        })
+    }
+}
+
+/// The GraphQL side of [`Transfer::counterparty`], whose name the derived
+/// resolver can't take.
+#[ComplexObject]
+impl Transfer {
+    /// The other side; absent for mints and burns, which have none.
+    #[graphql(name = "counterparty")]
+    async fn counterparty_info(&self) -> Option<&Counterparty> {
+        self.counterparty.as_ref()
+    }
+}
+
+impl Transfer {
+    /// What the destination was credited: the gross amount less any fee.
+    pub fn net_amount_raw(&self) -> u64 {
+        self.fee
+            .as_ref()
+            .map_or(self.amount_raw, |fee| fee.net_amount_raw)
+    }
+
+    /// Fills in the fields derived when a response is built.
+    pub fn annotate(&mut self, explorer_url: String) {
+        self.explorer_url = Some(explorer_url);
+        self.counterparty = (!self.direction.is_supply_change()).then(|| Counterparty {
+            token_account: self.counterparty().to_string(),
+            owner: self.counterparty_owner.clone(),
+            resolved: self.counterparty_owner.is_some(),
+        });
+    }
+
+    /// Signed change to the wallet's balance: the destination is credited
+    /// the net amount, the source debited the gross one. `None` for a
+    /// failed transaction, which moved nothing.
+    pub fn balance_effect(&self) -> Option<i128> {
+        if self.failed {
+            return None;
+        }
+        let gross = i128::from(self.amount_raw);
+        let net = i128::from(self.net_amount_raw());
+        Some(match self.direction {
+            Direction::Received | Direction::Minted => net,
+            Direction::Sent | Direction::Burned => -gross,
+            Direction::Internal => net - gross,
+        })
+    }
+
+    /// Uniquely identifies the on-chain event behind this record.
+    pub fn event_key(&self) -> (String, usize, Option<usize>) {
+        (
+            self.signature.clone(),
+            self.instruction_index,
+            self.inner_index,
+        )
+    }
+
+    /// Chain order: block time, then slot, then signature and position in
+    /// the transaction, so records from one block sort the same way on every
+    /// run. The wallet breaks the tie between the two sides of a transfer
+    /// between tracked wallets.
+    pub fn chronological_key(&self) -> (i64, u64, &str, usize, Option<usize>, &str) {
+        (
+            self.block_time,
+            self.slot,
+            &self.signature,
+            self.instruction_index,
+            self.inner_index,
+            self.wallet.as_deref().unwrap_or_default(),
+        )
+    }
+
+    /// The token account on the other side of the movement.
+    pub fn counterparty(&self) -> &str {
+        match self.direction {
+            Direction::Sent | Direction::Internal | Direction::Burned => &self.destination,
+            Direction::Received | Direction::Minted => &self.source,
+        }
+    }
+
+    /// Who the wallet transacted with: the owner wallet when known
+    /// (`true`), else the bare token account (`false`).
+    pub fn counterparty_address(&self) -> (&str, bool) {
+        match &self.counterparty_owner {
+            Some(owner) => (owner, true),
+            None => (self.counterparty(), false),
+        }
+    }
+
+    pub fn timestamp_rfc3339(&self) -> String {
+        DateTime::<Utc>::from_timestamp(self.block_time, 0)
+            .unwrap_or_default()
+            .to_rfc3339()
+    }
+
+    /// Legacy `"<rfc3339> | +1.000000 USDC | received"` line.
+    pub fn to_text_line(&self) -> String {
+        let sign = match self.direction {
+            Direction::Sent | Direction::Burned => "-",
+            Direction::Received | Direction::Minted => "+",
+            Direction::Internal => "",
+        };
+        format!(
+            "{} | {}{} {} | {}",
+            self.timestamp_rfc3339(),
+            sign,
+            self.amount_ui,
+            self.symbol,
+            self.direction.as_str()
+        )
+    }
+}
+
+/// `?order=` for transfer listings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    /// Newest first, the API default.
+    #[default]
+    Desc,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            other => Err(format!(
+                "unsupported order '{}', expected asc or desc",
+                other
+            )),
+        }
+    }
+}
+
+impl SortOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+/// Position after the last record of a page, handed out as an opaque
+/// `next_cursor`. It pins the wallet selection, window and order of the
+/// first page and is keyed on the chain position of the last record rather
+/// than an offset, so transfers arriving at the head don't shift later
+/// pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageCursor {
+    /// `?wallet=` of the first page: an address or `all`.
+    pub wallets: String,
+    pub window: TimeWindow,
+    pub order: SortOrder,
+    block_time: i64,
+    slot: u64,
+    signature: String,
+    instruction_index: usize,
+    inner_index: Option<usize>,
+    wallet: String,
+}
+
+impl PageCursor {
+    const VERSION: &'static str = "v2";
+
+    /// The cursor continuing after `last`.
+    pub fn after(last: &Transfer, wallets: &str, window: TimeWindow, order: SortOrder) -> Self {
+        PageCursor {
+            wallets: wallets.to_string(),
+            window,
+            order,
+            block_time: last.block_time,
+            slot: last.slot,
+            signature: last.signature.clone(),
+            instruction_index: last.instruction_index,
+            inner_index: last.inner_index,
+            wallet: last.wallet.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let inner = self.inner_index.map_or("-".to_string(), |i| i.to_string());
+        let plain = [
+            Self::VERSION,
+            &self.wallets,
+            &self.window.start.to_string(),
+            &self.window.end.to_string(),
+            self.order.as_str(),
+            &self.block_time.to_string(),
+            &self.slot.to_string(),
+            &self.signature,
+            &self.instruction_index.to_string(),
+            &inner,
+            &self.wallet,
+        ]
+        .join("|");
+        solana_sdk::bs58::encode(plain).into_string()
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid cursor '{}'", cursor);
+        let bytes = solana_sdk::bs58::decode(cursor)
+            .into_vec()
+            .map_err(|_| invalid())?;
+        let plain = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let fields: Vec<&str> = plain.split('|').collect();
+        let [version, wallets, start, end, order, block_time, slot, signature, instruction_index, inner, wallet] =
+            fields[..]
+        else {
+            return Err(invalid());
+        };
+        if version != Self::VERSION {
+            return Err(invalid());
+        }
+        let number = |s: &str| s.parse::<i64>().map_err(|_| invalid());
+        Ok(PageCursor {
+            wallets: wallets.to_string(),
+            window: TimeWindow {
+                start: number(start)?,
+                end: number(end)?,
+            },
+            order: order.parse().map_err(|_| invalid())?,
+            block_time: number(block_time)?,
+            slot: slot.parse().map_err(|_| invalid())?,
+            signature: signature.to_string(),
+            instruction_index: instruction_index.parse().map_err(|_| invalid())?,
+            inner_index: match inner {
+                "-" => None,
+                i => Some(i.parse().map_err(|_| invalid())?),
+            },
+            wallet: wallet.to_string(),
+        })
+    }
+
+    /// Whether `transfer` comes after the cursor in its order.
+    pub fn precedes(&self, transfer: &Transfer) -> bool {
+        let position = (
+            self.block_time,
+            self.slot,
+            self.signature.as_str(),
+            self.instruction_index,
+            self.inner_index,
+            self.wallet.as_str(),
+        );
+        match self.order {
+            SortOrder::Asc => transfer.chronological_key() > position,
+            SortOrder::Desc => transfer.chronological_key() < position,
+        }
+    }
+}
+
+/// Sorts `transfers` by [`Transfer::chronological_key`] in `order`.
+pub fn sort_transfers(transfers: &mut [Transfer], order: SortOrder) {
+    transfers.sort_by(|a, b| {
+        let ordering = a.chronological_key().cmp(&b.chronological_key());
+        match order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    });
+}
+
+/// How transfers are extracted from a transaction (`?strategy=`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Decode spl-token transfer instructions, top-level and CPI.
+    #[default]
+    Instructions,
+    /// Net per-account effect from `preTokenBalances`/`postTokenBalances`.
+    /// Catches flows the instruction parser doesn't understand, but knows
+    /// nothing about individual instructions.
+    Balances,
+    /// Report instruction transfers and flag transactions where the two
+    /// strategies disagree.
+    Both,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instructions" => Ok(Strategy::Instructions),
+            "balances" => Ok(Strategy::Balances),
+            "both" => Ok(Strategy::Both),
+            other => Err(format!(
+                "unsupported strategy '{}', expected instructions, balances or both",
+                other
+            )),
+        }
+    }
+}
+
+/// A transaction whose instruction-derived transfers don't add up to its
+/// token balance changes.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Discrepancy {
+    pub signature: String,
+    pub slot: u64,
+    pub accounts: Vec<AccountDiscrepancy>,
+}
+
+/// Net change of one wallet token account according to each strategy,
+/// as signed decimal strings.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AccountDiscrepancy {
+    pub token_account: String,
+    pub instructions: String,
+    pub balances: String,
+}
+
+/// Everything that identifies one backfill run.
+#[derive(Debug, Clone)]
+pub struct BackfillRequest {
+    pub wallet: Pubkey,
+    pub mint: MintInfo,
+    pub window: TimeWindow,
+    /// Stop paginating at this signature (exclusive), e.g. the newest one
+    /// already persisted.
+    pub until: Option<Signature>,
+    pub include_failed: bool,
+    /// Also return stored records re-checking found rolled back.
+    pub include_orphaned: bool,
+    pub strategy: Strategy,
+    /// Commitment the history is read at.
+    pub commitment: CommitmentConfig,
+}
+
+/// A wallet's holdings of one mint, summed over all its token accounts.
+#[derive(Debug, Clone, Serialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct WalletBalance {
+    pub wallet: String,
+    pub mint: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub amount_raw: u64,
+    pub amount_ui: String,
+    pub token_accounts: Vec<TokenAccountBalance>,
+    /// Slot the balances were read at.
+    pub slot: u64,
+    pub commitment: String,
+}
+
+/// What one transaction did for the tracked wallets, from `GET /tx/{signature}`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TransactionEffect {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_payer: Option<String>,
+    /// Fee the transaction paid, whoever paid it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_fee: Option<NetworkFee>,
+    /// Why the transaction failed, if it did; its transfers then moved
+    /// nothing.
+    pub error: Option<String>,
+    /// Every transfer of a tracked mint touching one of the wallets, each
+    /// with its `wallet` set.
+    pub transfers: Vec<Transfer>,
+    /// Only filled with `strategy=both`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+#[derive(Debug, Clone, Serialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct TokenAccountBalance {
+    pub address: String,
+    pub amount_raw: u64,
+    pub amount_ui: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalKind {
+    /// `approve` or `approveChecked`.
+    Approve,
+    Revoke,
+}
+
+/// A delegate approval on, or its revocation from, one of the wallet's
+/// token accounts of the mint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Approval {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: i64,
+    pub instruction_index: usize,
+    pub inner_index: Option<usize>,
+    pub kind: ApprovalKind,
+    pub token_account: String,
+    /// Who may now spend from `token_account`; `None` for a revoke, which
+    /// clears whatever delegate was set.
+    pub delegate: Option<String>,
+    /// The approved allowance in base units; `None` for a revoke.
+    pub amount_raw: Option<u64>,
+    pub amount_ui: Option<String>,
+    pub mint: String,
+    pub symbol: String,
+}
+
+/// A delegate one of the wallet's token accounts currently has.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Delegation {
+    pub token_account: String,
+    pub delegate: String,
+    /// What the delegate may still spend, in base units.
+    pub amount_raw: u64,
+    pub amount_ui: String,
+}
+
+/// Body of `GET /approvals`: the outstanding delegations on a wallet's
+/// token accounts of one mint.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Delegations {
+    pub wallet: String,
+    pub mint: String,
+    pub symbol: String,
+    pub delegations: Vec<Delegation>,
+    /// Slot the accounts were read at.
+    pub slot: u64,
+    pub commitment: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountEventKind {
+    /// The associated-token program's `create` or `createIdempotent`
+    /// actually made the account.
+    Created,
+    /// spl-token `closeAccount`.
+    Closed,
+}
+
+impl FromStr for AccountEventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "created" => Ok(AccountEventKind::Created),
+            "closed" => Ok(AccountEventKind::Closed),
+            other => anyhow::bail!("unknown account event '{}'", other),
+        }
+    }
+}
+
+impl AccountEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountEventKind::Created => "created",
+            AccountEventKind::Closed => "closed",
+        }
+    }
+}
+
+/// One of the wallet's token accounts of the mint being opened or closed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AccountEvent {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: i64,
+    pub instruction_index: usize,
+    pub inner_index: Option<usize>,
+    pub kind: AccountEventKind,
+    pub token_account: String,
+    pub mint: String,
+    /// For `created` the account that paid the rent, often a counterparty
+    /// making a first deposit; for `closed` the one the rent went back to.
+    pub rent_account: Option<String>,
+}
+
+/// A token account the stored lifecycle events show as open.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct OpenTokenAccount {
+    pub address: String,
+    pub created_slot: u64,
+}
+
+/// Replays `events`, oldest first, into the accounts still open at the end,
+/// by address. Accounts created before the indexed history aren't known.
+pub fn open_token_accounts(events: &[AccountEvent]) -> Vec<OpenTokenAccount> {
+    let mut open = BTreeMap::new();
+    for event in events {
+        match event.kind {
+            AccountEventKind::Created => {
+                open.insert(event.token_account.as_str(), event.slot);
+            }
+            AccountEventKind::Closed => {
+                open.remove(event.token_account.as_str());
+            }
+        }
+    }
+    open.into_iter()
+        .map(|(address, created_slot)| OpenTokenAccount {
+            address: address.to_string(),
+            created_slot,
+        })
+        .collect()
+}
+
+/// The balance `?running_balance=true` was reconstructed from, so rows that
+/// don't add up can be explained.
+#[derive(Debug, Clone, Serialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct RunningBalance {
+    /// Slot the anchor balance was read at.
+    pub anchor_slot: u64,
+    pub anchor_amount_raw: u64,
+    pub anchor_amount: String,
+    pub commitment: String,
+    /// Rows left without a `balance_after`.
+    pub excluded: usize,
+    /// `false` when walking back from the anchor went below zero: some
+    /// balance change is missing from the transfers (an instruction the
+    /// indexer doesn't decode, or a gap in the history), and rows older than
+    /// that point are excluded.
+    pub consistent: bool,
+}
+
+/// Fills in `balance_after` on `transfers`, which must include every
+/// transfer between the oldest row and `anchor.slot`. Rows before the anchor
+/// are walked newest to oldest undoing each effect; rows that landed after
+/// it, oldest to newest applying them. Failed rows move nothing and are
+/// excluded.
+pub fn apply_running_balance(transfers: &mut [Transfer], anchor: &WalletBalance) -> RunningBalance {
+    let anchor_raw = i128::from(anchor.amount_raw);
+    let mut order: Vec<usize> = (0..transfers.len()).collect();
+    order.sort_by(|&a, &b| {
+        transfers[a]
+            .chronological_key()
+            .cmp(&transfers[b].chronological_key())
+    });
+    let (before, after): (Vec<usize>, Vec<usize>) = order
+        .into_iter()
+        .partition(|&i| transfers[i].slot <= anchor.slot);
+
+    let mut consistent = true;
+    let mut balance = anchor_raw;
+    for &i in before.iter().rev() {
+        let transfer = &mut transfers[i];
+        let effect = match transfer.balance_effect() {
+            Some(effect) if consistent => effect,
+            _ => {
+                transfer.balance_excluded = true;
+                continue;
+            }
+        };
+        set_balance_after(transfer, balance, anchor.decimals);
+        balance -= effect;
+        if balance < 0 {
+            consistent = false;
+        }
+    }
+    let mut balance = anchor_raw;
+    for &i in &after {
+        let transfer = &mut transfers[i];
+        match transfer.balance_effect() {
+            Some(effect) if balance + effect >= 0 => {
+                balance += effect;
+                set_balance_after(transfer, balance, anchor.decimals);
+            }
+            Some(_) => {
+                consistent = false;
+                transfer.balance_excluded = true;
+            }
+            None => transfer.balance_excluded = true,
+        }
+    }
+
+    RunningBalance {
+        anchor_slot: anchor.slot,
+        anchor_amount_raw: anchor.amount_raw,
+        anchor_amount: anchor.amount_ui.clone(),
+        commitment: anchor.commitment.clone(),
+        excluded: transfers.iter().filter(|t| t.balance_excluded).count(),
+        consistent,
+    }
+}
+
+/// What `/balance_at` replayed transfers from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BalanceAnchorSource {
+    /// The balance read from the chain for the request.
+    Current,
+    /// A balance the background indexer stored earlier.
+    Snapshot,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BalanceAnchor {
+    pub source: BalanceAnchorSource,
+    /// Slot the anchor balance holds as of.
+    pub slot: u64,
+    /// Unix time it was read.
+    pub taken_at: i64,
+    pub amount_raw: u64,
+    pub amount_ui: String,
+}
+
+/// Body of `GET /balance_at`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BalanceAt {
+    pub wallet: String,
+    pub mint: String,
+    pub symbol: String,
+    pub decimals: u8,
+    /// The requested Unix time; the balance is as of the end of that
+    /// second.
+    pub timestamp: i64,
+    pub amount_raw: u64,
+    pub amount_ui: String,
+    pub anchor: BalanceAnchor,
+    /// Transfers between the anchor and `timestamp` that were replayed.
+    pub transfers_applied: usize,
+}
+
+/// The balance at the end of second `ts`, from `anchor_raw` held as of
+/// `anchor_slot`: transfers up to the anchor but after `ts` are undone,
+/// newest first, and ones after the anchor but by `ts` applied, oldest
+/// first. Returns the balance and how many transfers moved it, or `None`
+/// when it would go below zero on the way, which means some change is
+/// missing from `transfers`.
+pub fn replay_balance(
+    anchor_raw: u64,
+    anchor_slot: u64,
+    transfers: &[Transfer],
+    ts: i64,
+) -> Option<(u64, usize)> {
+    let mut ordered: Vec<&Transfer> = transfers.iter().collect();
+    ordered.sort_by(|a, b| a.chronological_key().cmp(&b.chronological_key()));
+    let (undo, apply): (Vec<&Transfer>, Vec<&Transfer>) = ordered
+        .into_iter()
+        .filter(|t| (t.slot <= anchor_slot) != (t.block_time <= ts))
+        .partition(|t| t.slot <= anchor_slot);
+
+    let mut balance = i128::from(anchor_raw);
+    let mut applied = 0;
+    for transfer in undo.iter().rev() {
+        if let Some(effect) = transfer.balance_effect() {
+            balance -= effect;
+            applied += 1;
+            if balance < 0 {
+                return None;
+            }
+        }
+    }
+    for transfer in apply {
+        if let Some(effect) = transfer.balance_effect() {
+            balance += effect;
+            applied += 1;
+            if balance < 0 {
+                return None;
+            }
+        }
+    }
+    Some((u64::try_from(balance).ok()?, applied))
+}
+
+/// A wallet's balance of one mint as the background indexer read it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct BalanceSnapshot {
+    /// Slot the balance was read at.
+    pub slot: u64,
+    /// Unix time it was read.
+    pub taken_at: i64,
+    pub amount_raw: u64,
+    /// Transfers stored for the wallet and mint at the time, for taking the
+    /// next snapshot after enough new ones.
+    pub transfer_count: u64,
+    /// How far the change since the previous snapshot differs from the
+    /// transfers stored in between, in base units; `None` for the first
+    /// snapshot or when the transfers couldn't all be synced.
+    pub unexplained_raw: Option<i64>,
+}
+
+/// The part of the balance change from `previous` to `amount_raw` at `slot`
+/// that `transfers` landing in between don't account for. Anything but 0
+/// means a balance change the parser doesn't recognise, or a gap in the
+/// history.
+pub fn unexplained_balance_change(
+    previous: &BalanceSnapshot,
+    amount_raw: u64,
+    slot: u64,
+    transfers: &[Transfer],
+) -> i128 {
+    let explained: i128 = transfers
+        .iter()
+        .filter(|t| t.slot > previous.slot && t.slot <= slot)
+        .filter_map(Transfer::balance_effect)
+        .sum();
+    i128::from(amount_raw) - i128::from(previous.amount_raw) - explained
+}
+
+fn set_balance_after(transfer: &mut Transfer, balance: i128, decimals: u8) {
+    let balance = u64::try_from(balance).unwrap_or(u64::MAX);
+    transfer.balance_after_raw = Some(balance);
+    transfer.balance_after = Some(format_amount(balance, decimals));
+}
+
+/// Renders `raw` base units with the decimal point inserted `decimals` places
+/// from the right, keeping every digit (no float rounding).
+pub fn format_amount(raw: impl Into<u128>, decimals: u8) -> String {
+    let raw = raw.into();
+    if decimals == 0 {
+        return raw.to_string();
+    }
+    let digits = format!("{:0>width$}", raw, width = decimals as usize + 1);
+    let (int_part, frac_part) = digits.split_at(digits.len() - decimals as usize);
+    format!("{}.{}", int_part, frac_part)
+}
+
+/// [`format_amount`] for signed totals such as net flow.
+pub fn format_signed_amount(raw: i128, decimals: u8) -> String {
+    let magnitude = format_amount(raw.unsigned_abs(), decimals);
+    if raw < 0 {
+        format!("-{}", magnitude)
+    } else {
+        magnitude
+    }
+}
+
+/// Parses a decimal string in UI units (`"1.5"`) into base units, rejecting
+/// signs, exponents and more fractional digits than the mint has.
+pub fn parse_amount(value: &str, decimals: u8) -> Result<u64, String> {
+    let invalid = || format!("'{}' is not a decimal amount", value);
+    let (int_part, frac_part) = value.split_once('.').unwrap_or((value, ""));
+    let digits_only = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if int_part.is_empty() && frac_part.is_empty()
+        || !digits_only(int_part)
+        || !digits_only(frac_part)
+    {
+        return Err(invalid());
+    }
+    if frac_part.len() > decimals as usize {
+        return Err(format!(
+            "'{}' has more than {} decimal places",
+            value, decimals
+        ));
+    }
+    let scaled = format!(
+        "{}{:0<width$}",
+        int_part,
+        frac_part,
+        width = decimals as usize
+    );
+    scaled
+        .trim_start_matches('0')
+        .parse::<u64>()
+        .or_else(|e| match e.kind() {
+            std::num::IntErrorKind::Empty => Ok(0),
+            _ => Err(format!("'{}' is out of range", value)),
+        })
+}
+
+/// Server-side narrowing of a transfer list; every set field must match.
+#[derive(Debug, Clone, Default)]
+pub struct TransferFilter {
+    pub direction: Option<Direction>,
+    /// Inclusive bounds on `amount_raw`.
+    pub min_amount: Option<u64>,
+    pub max_amount: Option<u64>,
+    /// Either the counterparty token account or its resolved owner.
+    pub counterparty: Option<String>,
+    /// Case-insensitive substring of `memo`, stored lowercased.
+    pub memo_contains: Option<String>,
+}
+
+impl TransferFilter {
+    pub fn matches(&self, transfer: &Transfer) -> bool {
+        self.direction.is_none_or(|d| transfer.direction == d)
+            && self.min_amount.is_none_or(|min| transfer.amount_raw >= min)
+            && self.max_amount.is_none_or(|max| transfer.amount_raw <= max)
+            && self.counterparty.as_deref().is_none_or(|counterparty| {
+                transfer.counterparty() == counterparty
+                    || transfer.counterparty_owner.as_deref() == Some(counterparty)
+            })
+            && self.memo_contains.as_deref().is_none_or(|needle| {
+                transfer
+                    .memo
+                    .as_deref()
+                    .is_some_and(|memo| memo.to_lowercase().contains(needle))
+            })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    /// A USDC transfer between `counterparty` and the wallet's ATA.
+    pub(crate) fn transfer(direction: Direction, amount_raw: u64, counterparty: &str) -> Transfer {
+        let (source, destination) = match direction {
+            Direction::Sent | Direction::Burned => ("wallet-ata", counterparty),
+            _ => (counterparty, "wallet-ata"),
+        };
+        Transfer {
+            signature: format!("sig-{}", amount_raw),
+            slot: 1,
+            block_time: 0,
+            instruction_index: 0,
+            inner_index: None,
+            direction,
+            amount_raw,
+            amount_ui: format_amount(amount_raw, 6),
+            source: source.to_string(),
+            destination: destination.to_string(),
+            mint: "mint".to_string(),
+            symbol: "USDC".to_string(),
+            counterparty_owner: None,
+            wallet: None,
+            memo: None,
+            commitment: None,
+            fee_payer: None,
+            network_fee: None,
+            fee: None,
+            explorer_url: None,
+            counterparty: None,
+            failed: false,
+            orphaned: false,
+            via_delegate: false,
+            balance_after_raw: None,
+            balance_after: None,
+            balance_excluded: false,
+            price_usd: None,
+            value_usd: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::transfer;
+    use super::*;
+
+    #[test]
+    fn reads_internal_transfers_stored_as_self() {
+        assert_eq!("self".parse::<Direction>().unwrap(), Direction::Internal);
+        assert_eq!(
+            "internal".parse::<Direction>().unwrap(),
+            Direction::Internal
+        );
+        let legacy: Direction = serde_json::from_str("\"self\"").unwrap();
+        assert_eq!(legacy, Direction::Internal);
+        assert_eq!(
+            serde_json::to_string(&Direction::Internal).unwrap(),
+            "\"internal\""
+        );
+    }
+
+    #[test]
+    fn walks_the_balance_back_from_the_anchor() {
+        let at = |slot, direction, amount_raw| Transfer {
+            slot,
+            block_time: slot as i64,
+            ..transfer(direction, amount_raw, "alice")
+        };
+        let mut failed = at(3, Direction::Sent, 7);
+        failed.failed = true;
+        let mut transfers = vec![
+            at(1, Direction::Received, 100),
+            at(2, Direction::Sent, 30),
+            failed,
+            at(4, Direction::Received, 5),
+            // Landed after the anchor was read.
+            at(11, Direction::Sent, 10),
+        ];
+        let anchor = WalletBalance {
+            wallet: "wallet".to_string(),
+            mint: "mint".to_string(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+            amount_raw: 75,
+            amount_ui: format_amount(75u64, 6),
+            token_accounts: Vec::new(),
+            slot: 10,
+            commitment: "confirmed".to_string(),
+        };
+        let summary = apply_running_balance(&mut transfers, &anchor);
+        let balances: Vec<Option<u64>> = transfers.iter().map(|t| t.balance_after_raw).collect();
+        assert_eq!(
+            balances,
+            vec![Some(100), Some(70), None, Some(75), Some(65)]
+        );
+        assert!(transfers[2].balance_excluded);
+        assert_eq!(transfers[4].balance_after.as_deref(), Some("0.000065"));
+        assert!(summary.consistent);
+        assert_eq!(summary.excluded, 1);
+
+        // Deposits beyond the anchor mean a withdrawal is missing from the
+        // history; rows older than the point it shows up are excluded.
+        let mut transfers = vec![
+            at(0, Direction::Received, 10),
+            at(1, Direction::Received, 100),
+            at(2, Direction::Received, 50),
+        ];
+        let summary = apply_running_balance(&mut transfers, &anchor);
+        assert!(!summary.consistent);
+        assert_eq!(transfers[2].balance_after_raw, Some(75));
+        assert_eq!(transfers[1].balance_after_raw, Some(25));
+        assert!(transfers[0].balance_excluded);
+        assert_eq!(summary.excluded, 1);
+    }
+
+    #[test]
+    fn replays_the_balance_to_a_point_in_time() {
+        let at = |slot, direction, amount_raw| Transfer {
+            slot,
+            block_time: slot as i64 * 10,
+            ..transfer(direction, amount_raw, "alice")
+        };
+        let mut failed = at(3, Direction::Sent, 7);
+        failed.failed = true;
+        let transfers = vec![
+            at(1, Direction::Received, 100),
+            at(2, Direction::Sent, 30),
+            failed,
+            at(4, Direction::Received, 5),
+            at(11, Direction::Sent, 10),
+        ];
+        // Back from a balance of 75 at slot 10 to the end of time 25.
+        assert_eq!(replay_balance(75, 10, &transfers, 25), Some((70, 1)));
+        assert_eq!(replay_balance(75, 10, &transfers, 5), Some((0, 3)));
+        // Forward from 70 at slot 2, past the anchor.
+        assert_eq!(replay_balance(70, 2, &transfers, 200), Some((65, 2)));
+        assert_eq!(replay_balance(75, 10, &transfers, 100), Some((75, 0)));
+        // Undoing more than was there: a deposit is missing.
+        assert_eq!(replay_balance(50, 10, &transfers, 5), None);
+
+        let previous = BalanceSnapshot {
+            slot: 1,
+            taken_at: 10,
+            amount_raw: 100,
+            transfer_count: 1,
+            unexplained_raw: None,
+        };
+        assert_eq!(unexplained_balance_change(&previous, 75, 10, &transfers), 0);
+        // A 3 unit fee taken by something the parser doesn't read.
+        assert_eq!(
+            unexplained_balance_change(&previous, 72, 10, &transfers),
+            -3
+        );
+    }
+
+    #[test]
+    fn parses_ui_amounts_into_base_units() {
+        assert_eq!(parse_amount("100", 6), Ok(100_000_000));
+        assert_eq!(parse_amount("0.5", 6), Ok(500_000));
+        assert_eq!(parse_amount(".000001", 6), Ok(1));
+        assert_eq!(parse_amount("0", 6), Ok(0));
+        for invalid in [
+            "",
+            ".",
+            "-1",
+            "1e3",
+            "1.0000001",
+            "1,5",
+            "99999999999999999999",
+        ] {
+            assert!(parse_amount(invalid, 6).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn filters_compose() {
+        let mut owned = transfer(Direction::Received, 5_000_000, "alice-ata");
+        owned.counterparty_owner = Some("alice".to_string());
+        let transfers = [
+            owned,
+            transfer(Direction::Received, 10, "alice-ata"),
+            transfer(Direction::Sent, 5_000_000, "bob-ata"),
+        ];
+        let filter = TransferFilter {
+            direction: Some(Direction::Received),
+            min_amount: Some(1_000_000),
+            counterparty: Some("alice".to_string()),
+            ..TransferFilter::default()
+        };
+        let matching: Vec<_> = transfers.iter().filter(|t| filter.matches(t)).collect();
+        assert_eq!(matching.len(), 1);
+        assert_eq!(matching[0].amount_raw, 5_000_000);
+
+        let mut tagged = transfer(Direction::Received, 1, "alice-ata");
+        tagged.memo = Some("Deposit REF-4411".to_string());
+        let filter = TransferFilter {
+            memo_contains: Some("ref-4411".to_string()),
+            ..TransferFilter::default()
+        };
+        assert!(filter.matches(&tagged));
+        assert!(!filter.matches(&transfers[0]));
+    }
+
+    #[test]
+    fn cursor_round_trips_and_resumes_after_the_last_record() {
+        let mut transfers: Vec<_> = (1..=3)
+            .map(|n| Transfer {
+                block_time: n,
+                ..transfer(Direction::Received, n as u64, "x")
+            })
+            .collect();
+        sort_transfers(&mut transfers, SortOrder::Desc);
+        let window = TimeWindow { start: 0, end: 10 };
+        let cursor = PageCursor::after(&transfers[0], "all", window, SortOrder::Desc);
+        let decoded = PageCursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded, cursor);
+
+        let rest: Vec<_> = transfers.iter().filter(|t| decoded.precedes(t)).collect();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest[0].block_time, 2);
+        assert!(PageCursor::decode("not-a-cursor").is_err());
+    }
+
+    #[test]
+    fn cursor_tells_apart_both_sides_of_a_transfer_between_tracked_wallets() {
+        let sides: Vec<_> = ["wallet-a", "wallet-b"]
+            .map(|wallet| Transfer {
+                wallet: Some(wallet.to_string()),
+                ..transfer(Direction::Received, 1, "x")
+            })
+            .into();
+        let window = TimeWindow { start: 0, end: 10 };
+        let cursor = PageCursor::after(&sides[0], "all", window, SortOrder::Asc);
+        let decoded = PageCursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.wallets, "all");
+        assert!(!decoded.precedes(&sides[0]));
+        assert!(decoded.precedes(&sides[1]));
+    }
+
+    #[test]
+    fn sorts_by_chain_position_with_stable_ties() {
+        let mut transfers = vec![
+            Transfer {
+                block_time: 10,
+                slot: 5,
+                signature: "b".to_string(),
+                ..transfer(Direction::Received, 1, "x")
+            },
+            Transfer {
+                block_time: 10,
+                slot: 5,
+                signature: "a".to_string(),
+                instruction_index: 1,
+                ..transfer(Direction::Received, 2, "x")
+            },
+            Transfer {
+                block_time: 10,
+                slot: 5,
+                signature: "a".to_string(),
+                ..transfer(Direction::Received, 3, "x")
+            },
+            Transfer {
+                block_time: 9,
+                slot: 6,
+                ..transfer(Direction::Received, 4, "x")
+            },
+        ];
+        sort_transfers(&mut transfers, SortOrder::Asc);
+        let amounts: Vec<u64> = transfers.iter().map(|t| t.amount_raw).collect();
+        assert_eq!(amounts, [4, 3, 2, 1]);
+
+        sort_transfers(&mut transfers, SortOrder::Desc);
+        let amounts: Vec<u64> = transfers.iter().map(|t| t.amount_raw).collect();
+        assert_eq!(amounts, [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,200 @@
+//! The OpenAPI 3 description of the HTTP API, served at `/openapi.json`.
+//! Paths come from the `#[utoipa::path]` attributes on the handlers and
+//! schemas from the types they serialize, so the document changes with the
+//! code.
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::Deprecated;
+use utoipa::{Modify, OpenApi};
+
+use crate::diagnostics::{Diagnostics, SkipCount};
+use crate::error::ErrorCode;
+use crate::export::ExportRow;
+use crate::indexer::{MintReindex, ReindexReport};
+use crate::jobs::{JobError, JobReport, JobResult, JobStatus, ProgressSnapshot};
+use crate::model::{
+    AccountDiscrepancy, AccountEvent, AccountEventKind, AnchorSource, Approval, ApprovalKind,
+    BalanceAnchor, BalanceAnchorSource, BalanceAt, BalanceSnapshot, Counterparty, Delegation,
+    Delegations, Direction, Discrepancy, NetworkFee, OpenTokenAccount, RunningBalance, TimeWindow,
+    TokenAccountBalance, TransactionEffect, Transfer, TransferFee, WalletBalance, WindowAnchor,
+};
+use crate::output::BackfillResponse;
+use crate::server;
+use crate::stats::{BalancePoint, Bucket, CounterpartyTotals, DirectionCounts, Summary};
+use crate::ws::{self, Subscription};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "SPL token transfers of Solana wallets, indexed and served over HTTP."),
+    paths(
+        server::handle_backfill,
+        server::handle_legacy_backfill,
+        server::handle_submit_backfill_job,
+        server::handle_backfill_job,
+        server::handle_cancel_backfill_job,
+        server::handle_summary,
+        server::handle_aggregate,
+        server::handle_counterparties,
+        server::handle_balance,
+        server::handle_balance_at,
+        server::handle_balance_history,
+        server::handle_approvals,
+        server::handle_account_events,
+        server::handle_export,
+        server::handle_s3_export,
+        server::handle_graphql,
+        server::handle_transaction,
+        server::handle_raw_transaction,
+        server::handle_stream,
+        ws::serve,
+        server::handle_list_wallets,
+        server::handle_watch_wallet,
+        server::handle_unwatch_wallet,
+        server::handle_reparse,
+        server::handle_reindex,
+        server::handle_purge_cache,
+        server::handle_status,
+        server::handle_metrics,
+        server::handle_healthz,
+        server::handle_readyz,
+    ),
+    components(schemas(
+        AccountDiscrepancy,
+        AccountEvent,
+        AccountEventKind,
+        AnchorSource,
+        Approval,
+        ApprovalKind,
+        BackfillResponse,
+        BalanceAnchor,
+        BalanceAnchorSource,
+        BalanceAt,
+        BalancePoint,
+        BalanceSnapshot,
+        Bucket,
+        Counterparty,
+        CounterpartyTotals,
+        Delegation,
+        Delegations,
+        Diagnostics,
+        Direction,
+        DirectionCounts,
+        Discrepancy,
+        ExportRow,
+        JobError,
+        JobReport,
+        JobResult,
+        JobStatus,
+        MintReindex,
+        NetworkFee,
+        OpenTokenAccount,
+        ProgressSnapshot,
+        ReindexReport,
+        RunningBalance,
+        SkipCount,
+        Subscription,
+        Summary,
+        TimeWindow,
+        TokenAccountBalance,
+        TransactionEffect,
+        Transfer,
+        TransferFee,
+        WalletBalance,
+        WindowAnchor,
+        ErrorCode,
+        server::ErrorBody,
+        server::ErrorDetail,
+        server::ReindexRequest,
+        server::WatchRequest,
+        server::S3ExportRequest,
+    )),
+    modifiers(&SecuritySchemes, &LegacyRoutes)
+)]
+pub struct ApiDoc;
+
+/// The credentials the `security` entries of the paths refer to.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let bearer =
+            || SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build());
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("bearer", bearer());
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))),
+        );
+        components.add_security_scheme("admin_token", bearer());
+    }
+}
+
+/// Routes kept from before `/v1`, flagged deprecated for client generators.
+struct LegacyRoutes;
+
+impl Modify for LegacyRoutes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(item) = openapi.paths.paths.get_mut("/backfill") {
+            for operation in item.operations.values_mut() {
+                operation.deprecated = Some(Deprecated::True);
+            }
+        }
+    }
+}
+
+/// `/docs` when `swagger_ui` is on. The Swagger UI assets come from a CDN,
+/// so the page needs the browser to reach it.
+pub const SWAGGER_UI_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>API docs</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_every_route_and_the_shared_schemas() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        for path in [
+            "/v1/backfill",
+            "/v1/backfill/{id}",
+            "/v1/export",
+            "/v1/export/s3",
+            "/v1/graphql",
+            "/v1/tx/{signature}",
+            "/v1/ws",
+            "/readyz",
+        ] {
+            assert!(doc["paths"][path].is_object(), "{} is missing", path);
+        }
+        assert_eq!(doc["paths"]["/backfill"]["get"]["deprecated"], true);
+        assert_ne!(doc["paths"]["/v1/backfill"]["get"]["deprecated"], true);
+        let backfill = &doc["paths"]["/v1/backfill"]["get"];
+        assert!(backfill["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|p| p["name"] == "include_failed" && p["in"] == "query"));
+        let schemas = &doc["components"]["schemas"];
+        assert!(schemas["Transfer"]["properties"]["amount_ui"].is_object());
+        assert!(schemas["ExportRow"]["properties"]["memo"].is_object());
+        assert!(schemas["ErrorBody"]["properties"]["error"].is_object());
+        assert!(schemas["ErrorDetail"]["properties"]["retryable"].is_object());
+        let codes = schemas["ErrorCode"]["enum"].as_array().unwrap();
+        assert!(codes.contains(&serde_json::json!("RPC_RATE_LIMITED")));
+        assert!(doc["components"]["securitySchemes"]["api_key"].is_object());
+    }
+}
@@ -0,0 +1,322 @@
+//! Rendering of backfill results, shared by the HTTP endpoint and the CLI so
+//! the two never drift apart.
+
+use anyhow::Result;
+use async_graphql::SimpleObject;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use crate::diagnostics::Diagnostics;
+use crate::model::{Discrepancy, RunningBalance, TimeWindow, Transfer, WindowAnchor};
+
+/// Output formats selectable via `?format=` or `--format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+    /// The legacy pipe-delimited lines, one transfer per line.
+    Text,
+    /// One JSON transfer object per line. The HTTP endpoint streams it.
+    Ndjson,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 4] = [
+        OutputFormat::Json,
+        OutputFormat::Csv,
+        OutputFormat::Text,
+        OutputFormat::Ndjson,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Text => "text",
+            OutputFormat::Ndjson => "ndjson",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Json => "application/json",
+            OutputFormat::Csv => "text/csv; charset=utf-8",
+            OutputFormat::Text => "text/plain; charset=utf-8",
+            OutputFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// `content_type` without its parameters.
+    pub fn media_type(self) -> &'static str {
+        let content_type = self.content_type();
+        content_type.split(';').next().unwrap_or(content_type)
+    }
+
+    /// Whether an `Accept` media range such as `text/*` covers this format.
+    fn accepted_by(self, range: &str) -> bool {
+        let media_type = self.media_type();
+        match range.strip_suffix("/*") {
+            Some("*") => true,
+            Some(kind) => media_type.split('/').next() == Some(kind),
+            None => range == media_type,
+        }
+    }
+
+    /// The first of `supported`, in the client's order of preference, that
+    /// the `Accept` header value allows; `None` if it allows none.
+    pub fn from_accept(accept: &str, supported: &[OutputFormat]) -> Option<OutputFormat> {
+        let mut ranges: Vec<(f32, String)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let media = parts.next()?.trim().to_ascii_lowercase();
+                let quality = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                    Some(q) => q.trim().parse().ok()?,
+                    None => 1.0,
+                };
+                (!media.is_empty() && quality > 0.0).then_some((quality, media))
+            })
+            .collect();
+        // Stable, so equally preferred ranges keep the client's order.
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranges.iter().find_map(|(_, range)| {
+            supported
+                .iter()
+                .copied()
+                .find(|format| format.accepted_by(range))
+        })
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "text" => Ok(OutputFormat::Text),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            other => Err(format!(
+                "unsupported format '{}', expected json, csv, text or ndjson",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct BackfillResponse {
+    pub wallet: String,
+    pub mint: String,
+    pub symbol: String,
+    pub window: TimeWindow,
+    /// What `window` was measured back from.
+    pub window_anchor: WindowAnchor,
+    pub transfers: Vec<Transfer>,
+    pub undecodable_transactions: usize,
+    /// Signatures and instructions left out, by reason, with examples.
+    /// Empty when the request was answered from an up-to-date store.
+    /// REST only, like `discrepancies`: debugging detail for operators.
+    #[graphql(skip)]
+    pub diagnostics: Diagnostics,
+    /// `getSignaturesForAddress` pages this request walked; 0 when it was
+    /// answered from an up-to-date store.
+    pub pages_scanned: usize,
+    /// The signature page cap was hit before the window was covered, so
+    /// older transfers in it may be missing.
+    pub truncated: bool,
+    /// Transactions where `strategy=both` found the two methods disagreeing.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[graphql(skip)]
+    pub discrepancies: Vec<Discrepancy>,
+    /// More records match than this page holds; pass `next_cursor` back as
+    /// `?cursor=` for the next page.
+    pub has_more: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// Anchor of the `balance_after` column, with `?running_balance=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub running_balance: Option<RunningBalance>,
+    /// Where `price_usd` came from, when prices were looked up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price_source: Option<String>,
+}
+
+pub const CSV_HEADER: [&str; 6] = [
+    "timestamp",
+    "signature",
+    "direction",
+    "amount",
+    "counterparty",
+    "mint",
+];
+
+/// Serializes transfers as CSV with a header row. Amounts are written as the
+/// exact decimal string so spreadsheet imports don't round them. A
+/// `balance_after` column is appended when a running balance was computed,
+/// `price_usd` and `value_usd` ones when prices were looked up.
+pub fn transfers_to_csv(transfers: &[Transfer]) -> Result<String> {
+    let with_balance = transfers
+        .iter()
+        .any(|t| t.balance_after.is_some() || t.balance_excluded);
+    let with_prices = transfers.iter().any(|t| t.price_usd.is_some());
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let mut header = CSV_HEADER.to_vec();
+    if with_balance {
+        header.push("balance_after");
+    }
+    if with_prices {
+        header.extend(["price_usd", "value_usd"]);
+    }
+    writer.write_record(header)?;
+    for transfer in transfers {
+        let timestamp = transfer.timestamp_rfc3339();
+        let mut record = vec![
+            timestamp.as_str(),
+            &transfer.signature,
+            transfer.direction.as_str(),
+            &transfer.amount_ui,
+            transfer.counterparty(),
+            &transfer.mint,
+        ];
+        if with_balance {
+            record.push(transfer.balance_after.as_deref().unwrap_or(""));
+        }
+        if with_prices {
+            record.push(transfer.price_usd.as_deref().unwrap_or(""));
+            record.push(transfer.value_usd.as_deref().unwrap_or(""));
+        }
+        writer.write_record(record)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Serializes transfers as newline-terminated JSON lines.
+pub fn transfers_to_ndjson(transfers: &[Transfer]) -> Result<String> {
+    let mut body = String::new();
+    for transfer in transfers {
+        body.push_str(&serde_json::to_string(transfer)?);
+        body.push('\n');
+    }
+    Ok(body)
+}
+
+impl BackfillResponse {
+    /// Serializes the response body in `format`.
+    pub fn render(&self, format: OutputFormat) -> Result<String> {
+        match format {
+            OutputFormat::Json => Ok(serde_json::to_string(self)?),
+            OutputFormat::Csv => transfers_to_csv(&self.transfers),
+            OutputFormat::Text => {
+                let lines: Vec<String> =
+                    self.transfers.iter().map(Transfer::to_text_line).collect();
+                Ok(lines.join("\n"))
+            }
+            OutputFormat::Ndjson => transfers_to_ndjson(&self.transfers),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::transfer;
+    use crate::model::{AnchorSource, Direction};
+
+    fn response() -> BackfillResponse {
+        BackfillResponse {
+            wallet: "wallet".to_string(),
+            mint: "mint".to_string(),
+            symbol: "USDC".to_string(),
+            window: TimeWindow { start: 0, end: 10 },
+            window_anchor: WindowAnchor {
+                source: AnchorSource::Request,
+                time: 10,
+                slot: None,
+            },
+            transfers: vec![Transfer {
+                signature: "sig".to_string(),
+                block_time: 5,
+                ..transfer(Direction::Received, 1_500_000, "alice")
+            }],
+            undecodable_transactions: 0,
+            diagnostics: Diagnostics::default(),
+            pages_scanned: 1,
+            truncated: false,
+            discrepancies: Vec::new(),
+            has_more: false,
+            next_cursor: None,
+            running_balance: None,
+            price_source: None,
+        }
+    }
+
+    #[test]
+    fn every_format_renders_the_same_transfers() {
+        let response = response();
+        let json: serde_json::Value =
+            serde_json::from_str(&response.render(OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["transfers"][0]["amount_ui"], "1.500000");
+
+        let csv = response.render(OutputFormat::Csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER.join(",").as_str()));
+        assert!(lines
+            .next()
+            .unwrap()
+            .contains(",sig,received,1.500000,alice,mint"));
+
+        let text = response.render(OutputFormat::Text).unwrap();
+        assert_eq!(text, response.transfers[0].to_text_line());
+
+        let ndjson = response.render(OutputFormat::Ndjson).unwrap();
+        assert!(ndjson.ends_with('\n'));
+        let line: serde_json::Value = serde_json::from_str(ndjson.trim_end()).unwrap();
+        assert_eq!(line, json["transfers"][0]);
+    }
+
+    #[test]
+    fn negotiates_from_the_accept_header() {
+        let all = &OutputFormat::ALL;
+        assert_eq!(
+            OutputFormat::from_accept("text/csv", all),
+            Some(OutputFormat::Csv)
+        );
+        assert_eq!(
+            OutputFormat::from_accept("application/json;q=0.5, application/x-ndjson", all),
+            Some(OutputFormat::Ndjson)
+        );
+        assert_eq!(
+            OutputFormat::from_accept("Text/Plain; charset=utf-8", all),
+            Some(OutputFormat::Text)
+        );
+        assert_eq!(
+            OutputFormat::from_accept("*/*", all),
+            Some(OutputFormat::Json)
+        );
+        assert_eq!(
+            OutputFormat::from_accept("text/*", &[OutputFormat::Json]),
+            None
+        );
+        assert_eq!(
+            OutputFormat::from_accept("application/xml, text/csv;q=0", all),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_known_formats_only() {
+        assert_eq!("csv".parse(), Ok(OutputFormat::Csv));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
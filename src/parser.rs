@@ -0,0 +1,1781 @@
+//! Turns fetched transactions into [`Transfer`]s for one wallet and mint.
+
+use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+use solana_sdk::pubkey::Pubkey;
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, UiInnerInstructions,
+    UiInstruction, UiLoadedAddresses, UiMessage, UiParsedInstruction, UiTransactionTokenBalance,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::config::{MintInfo, TokenProgram, SPL_TOKEN};
+use crate::diagnostics;
+use crate::metrics::METRICS;
+use crate::model::{
+    format_amount, format_signed_amount, parse_amount, AccountDiscrepancy, AccountEvent,
+    AccountEventKind, Approval, ApprovalKind, Direction, Discrepancy, NetworkFee, Transfer,
+    TransferFee,
+};
+
+pub const SPL_TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: &str = "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL";
+pub const MEMO_V1_PROGRAM_ID: &str = "Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo";
+pub const MEMO_PROGRAM_ID: &str = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr";
+
+/// Derives the associated token account of `wallet` for an spl-token `mint`.
+pub fn associated_token_address(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    associated_token_address_for_program(wallet, mint, &SPL_TOKEN.pubkey())
+}
+
+/// Derives the associated token account of `wallet` for a `mint` owned by
+/// `token_program`; the derivation differs between spl-token and Token-2022.
+pub fn associated_token_address_for_program(
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Pubkey {
+    let ata_program = Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID).expect("valid program id");
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ata_program,
+    )
+    .0
+}
+
+/// What the parser needs to know to decide whether a token account belongs to
+/// the indexed wallet, and which token programs' instructions to read.
+pub struct WalletContext {
+    address: String,
+    /// Token accounts known to be the wallet's without looking at the
+    /// transaction (currently the ATA derived under each token program).
+    token_accounts: HashSet<String>,
+    programs: Vec<TokenProgram>,
+}
+
+impl WalletContext {
+    /// Whether `token_account` is one of the wallet's accounts for the
+    /// indexed mint by derivation alone (and so certainly holds that mint).
+    pub fn is_derived_account(&self, token_account: &str) -> bool {
+        self.token_accounts.contains(token_account)
+    }
+
+    pub fn new(wallet: &Pubkey, mint: &MintInfo, programs: &[TokenProgram]) -> Self {
+        let token_accounts = programs
+            .iter()
+            .map(|program| {
+                associated_token_address_for_program(wallet, &mint.mint, &program.pubkey())
+                    .to_string()
+            })
+            .collect();
+        WalletContext {
+            address: wallet.to_string(),
+            token_accounts,
+            programs: programs.to_vec(),
+        }
+    }
+
+    /// The wallet's ATAs for the mint, one per accepted token program.
+    pub fn derived_accounts(&self) -> impl Iterator<Item = &str> {
+        self.token_accounts.iter().map(String::as_str)
+    }
+
+    pub fn programs(&self) -> &[TokenProgram] {
+        &self.programs
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn owns(&self, token_account: &str, owners: &HashMap<String, String>) -> bool {
+        self.is_derived_account(token_account)
+            || owners
+                .get(token_account)
+                .is_some_and(|owner| *owner == self.address)
+    }
+}
+
+/// Full account key list in index order. For v0 transactions in the raw
+/// message shape the keys loaded from lookup tables follow the static keys
+/// (writable first), matching how `accountIndex` values are assigned; the
+/// jsonParsed shape already lists them inline. Binary encodings are decoded
+/// and read like the raw shape.
+pub fn message_account_keys(tx: &EncodedConfirmedTransactionWithStatusMeta) -> Vec<String> {
+    let static_keys = match &tx.transaction.transaction {
+        EncodedTransaction::Json(ui_tx) => match &ui_tx.message {
+            UiMessage::Parsed(message) => {
+                return message
+                    .account_keys
+                    .iter()
+                    .map(|account| account.pubkey.clone())
+                    .collect()
+            }
+            UiMessage::Raw(message) => message.account_keys.clone(),
+        },
+        encoded => match encoded.decode() {
+            Some(decoded) => decoded
+                .message
+                .static_account_keys()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            None => return Vec::new(),
+        },
+    };
+    let mut keys = static_keys;
+    let loaded: Option<&UiLoadedAddresses> = tx
+        .transaction
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.loaded_addresses.as_ref().into());
+    if let Some(loaded) = loaded {
+        keys.extend(loaded.writable.iter().cloned());
+        keys.extend(loaded.readonly.iter().cloned());
+    }
+    keys
+}
+
+/// Maps token accounts to their owners using the `owner` field of the
+/// transaction's pre/post token balances.
+pub fn token_account_owners(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> HashMap<String, String> {
+    let mut owners = HashMap::new();
+    let Some(meta) = &tx.transaction.meta else {
+        return owners;
+    };
+    let account_keys = message_account_keys(tx);
+
+    let pre: Option<&Vec<UiTransactionTokenBalance>> = meta.pre_token_balances.as_ref().into();
+    let post: Option<&Vec<UiTransactionTokenBalance>> = meta.post_token_balances.as_ref().into();
+    for balance in pre.into_iter().chain(post).flatten() {
+        let Some(owner) = Option::<&String>::from(balance.owner.as_ref()) else {
+            continue;
+        };
+        if let Some(account) = account_keys.get(balance.account_index as usize) {
+            owners.insert(account.clone(), owner.clone());
+        }
+    }
+    owners
+}
+
+/// Maps token accounts to their mint using the transaction's pre/post token
+/// balances.
+pub fn token_account_mints(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> HashMap<String, String> {
+    let mut mints = HashMap::new();
+    let Some(meta) = &tx.transaction.meta else {
+        return mints;
+    };
+    let account_keys = message_account_keys(tx);
+
+    let pre: Option<&Vec<UiTransactionTokenBalance>> = meta.pre_token_balances.as_ref().into();
+    let post: Option<&Vec<UiTransactionTokenBalance>> = meta.post_token_balances.as_ref().into();
+    for balance in pre.into_iter().chain(post).flatten() {
+        if let Some(account) = account_keys.get(balance.account_index as usize) {
+            mints.insert(account.clone(), balance.mint.clone());
+        }
+    }
+    mints
+}
+
+/// Which instruction family a [`TokenMovement`] came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementKind {
+    Transfer,
+    /// `mintTo`/`mintToChecked`: `source` is the mint itself.
+    MintTo,
+    /// `burn`/`burnChecked`: `destination` is the mint itself.
+    Burn,
+}
+
+/// Source, destination and base-unit amount of an spl-token transfer, mint
+/// or burn.
+pub struct TokenMovement<'a> {
+    pub kind: MovementKind,
+    pub source: &'a str,
+    pub destination: &'a str,
+    /// Only `transferChecked` names the mint; plain `transfer` leaves it to
+    /// be resolved from the accounts involved.
+    pub mint: Option<&'a str>,
+    /// `Err` carries the raw value when the amount is missing or isn't a
+    /// `u64`; that's a decoding problem, not a zero-value transfer.
+    pub amount: Result<u64, String>,
+    /// The withheld fee of a Token-2022 `transferCheckedWithFee`, read the
+    /// same way as `amount`.
+    pub fee: Option<Result<u64, String>>,
+    /// Who signed for the debited account: its owner, a delegate, or a
+    /// multisig. Mints name a `mintAuthority` instead and leave it unset.
+    pub authority: Option<&'a str>,
+}
+
+/// Why an instruction yielded no [`TokenMovement`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotAMovement {
+    /// Another program, instruction type or mint.
+    Other,
+    /// A token movement whose parsed `info` lacks the named field.
+    MissingInfo(&'static str),
+}
+
+/// Reads a base-unit amount given either as `key` or as `key.amount` inside
+/// the `ui_key` UI token amount object, as a string or a JSON number. When
+/// neither is readable, the object's `uiAmountString` scaled by its
+/// `decimals` is the last resort.
+fn raw_amount(info: &serde_json::Value, key: &str, ui_key: &str) -> Result<u64, String> {
+    let ui = info.get(ui_key);
+    let raw = info
+        .get(key)
+        .or_else(|| ui.and_then(|t| t.get("amount")))
+        .map_or(Err("<missing>".to_string()), base_units);
+    raw.or_else(|e| match ui.and_then(|t| t.get("uiAmountString")) {
+        Some(serde_json::Value::String(amount)) => {
+            let decimals = ui
+                .and_then(|t| t.get("decimals"))
+                .and_then(|d| base_units(d).ok())
+                .and_then(|d| u8::try_from(d).ok())
+                .ok_or(e)?;
+            parse_amount(amount, decimals).map_err(|_| amount.clone())
+        }
+        _ => Err(e),
+    })
+}
+
+/// A `u64` written as a decimal string or a JSON number; `Err` carries the
+/// value as given.
+fn base_units(value: &serde_json::Value) -> Result<u64, String> {
+    match value {
+        serde_json::Value::String(s) => s.parse::<u64>().map_err(|_| s.clone()),
+        serde_json::Value::Number(n) => n.as_u64().ok_or_else(|| n.to_string()),
+        other => Err(other.to_string()),
+    }
+}
+
+/// Recognizes `transfer`/`transferChecked`/`transferCheckedWithFee`, and
+/// `mintTo`/`burn` with their `Checked` forms, of `mint_address` by one of
+/// `programs` in a parsed instruction. A missing `mint` (plain `transfer`)
+/// is let through with `mint: None` for the caller to resolve.
+pub fn parse_spl_transfer<'a>(
+    ix: &'a UiInstruction,
+    mint_address: &str,
+    programs: &[TokenProgram],
+) -> Result<TokenMovement<'a>, NotAMovement> {
+    let UiInstruction::Parsed(UiParsedInstruction::Parsed(parsed)) = ix else {
+        return Err(NotAMovement::Other);
+    };
+    if !programs.iter().any(|p| p.name == parsed.program) {
+        return Err(NotAMovement::Other);
+    }
+
+    let instruction_type = parsed
+        .parsed
+        .get("type")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+    let (kind, with_fee) = match instruction_type {
+        "transfer" | "transferChecked" => (MovementKind::Transfer, false),
+        "transferCheckedWithFee" => (MovementKind::Transfer, true),
+        "mintTo" | "mintToChecked" => (MovementKind::MintTo, false),
+        "burn" | "burnChecked" => (MovementKind::Burn, false),
+        _ => return Err(NotAMovement::Other),
+    };
+
+    let info = parsed
+        .parsed
+        .get("info")
+        .ok_or(NotAMovement::MissingInfo("info"))?;
+    let field = |name: &'static str| {
+        info.get(name)
+            .and_then(|v| v.as_str())
+            .ok_or(NotAMovement::MissingInfo(name))
+    };
+    let amount = raw_amount(info, "amount", "tokenAmount");
+    let authority = info
+        .get("authority")
+        .or_else(|| info.get("multisigAuthority"))
+        .and_then(|v| v.as_str());
+    if kind != MovementKind::Transfer {
+        let mint = field("mint")?;
+        if mint != mint_address {
+            return Err(NotAMovement::Other);
+        }
+        let account = field("account")?;
+        let (source, destination) = match kind {
+            MovementKind::MintTo => (mint, account),
+            _ => (account, mint),
+        };
+        return Ok(TokenMovement {
+            kind,
+            source,
+            destination,
+            mint: Some(mint),
+            amount,
+            fee: None,
+            authority,
+        });
+    }
+
+    let mint = info.get("mint").and_then(|v| v.as_str());
+    if mint.is_some_and(|mint| mint != mint_address) {
+        return Err(NotAMovement::Other);
+    }
+
+    let source = field("source")?;
+    let destination = field("destination")?;
+
+    Ok(TokenMovement {
+        kind,
+        source,
+        destination,
+        mint,
+        amount,
+        fee: with_fee.then(|| raw_amount(info, "fee", "feeAmount")),
+        authority,
+    })
+}
+
+/// Transaction-level facts shared by every instruction parsed from it.
+pub struct ParseContext<'a> {
+    pub wallet: &'a WalletContext,
+    pub mint: &'a MintInfo,
+    /// Token account → owner, from the transaction's token balances.
+    pub owners: &'a HashMap<String, String>,
+    /// Token account → mint, from the token balances plus any accounts the
+    /// caller looked up.
+    pub mints: &'a HashMap<String, String>,
+    pub signature: &'a str,
+    pub slot: u64,
+    pub block_time: i64,
+    pub failed: bool,
+    /// The transaction's memo, see [`transaction_memo`].
+    pub memo: Option<&'a str>,
+    pub fee_payer: Option<&'a str>,
+    /// `meta.fee` in lamports when the wallet is the fee payer.
+    pub paid_fee: Option<u64>,
+}
+
+/// Turns one instruction into a [`Transfer`] of `ctx.mint` touching the
+/// wallet (including mints into and burns from its accounts), or `None` if
+/// it's anything else: another program, another
+/// instruction type, another mint, a zero or unreadable amount, or a
+/// transfer between two accounts the wallet doesn't own.
+pub fn parse_token_transfer(
+    ix: &UiInstruction,
+    ctx: &ParseContext,
+    instruction_index: usize,
+    inner_index: Option<usize>,
+) -> Option<Transfer> {
+    let mint_address = ctx.mint.mint.to_string();
+    let movement = match parse_spl_transfer(ix, &mint_address, ctx.wallet.programs()) {
+        Ok(movement) => movement,
+        Err(NotAMovement::Other) => return None,
+        Err(NotAMovement::MissingInfo(field)) => {
+            warn!(
+                signature = ctx.signature,
+                instruction_index,
+                ?inner_index,
+                field,
+                "skipping token instruction with incomplete info"
+            );
+            METRICS
+                .transfers_skipped
+                .with_label_values(&[diagnostics::MISSING_INFO])
+                .inc();
+            diagnostics::record(diagnostics::MISSING_INFO, ctx.signature);
+            return None;
+        }
+    };
+
+    let owns_source = ctx.wallet.owns(movement.source, ctx.owners);
+    let owns_destination = ctx.wallet.owns(movement.destination, ctx.owners);
+    let direction = match (movement.kind, owns_source, owns_destination) {
+        (MovementKind::MintTo, _, true) => Direction::Minted,
+        (MovementKind::Burn, true, _) => Direction::Burned,
+        (MovementKind::MintTo | MovementKind::Burn, _, _) => return None,
+        (MovementKind::Transfer, true, true) => Direction::Internal,
+        (MovementKind::Transfer, true, false) => Direction::Sent,
+        (MovementKind::Transfer, false, true) => Direction::Received,
+        (MovementKind::Transfer, false, false) => return None,
+    };
+    // A plain `transfer` only counts once its mint is positively identified.
+    if movement.mint.is_none() {
+        let resolved = [movement.source, movement.destination]
+            .into_iter()
+            .find_map(|account| {
+                if ctx.wallet.is_derived_account(account) {
+                    Some(mint_address.as_str())
+                } else {
+                    ctx.mints.get(account).map(String::as_str)
+                }
+            });
+        match resolved {
+            Some(mint) if mint == mint_address => {}
+            Some(_) => return None,
+            None => {
+                warn!(
+                    signature = ctx.signature,
+                    instruction_index,
+                    ?inner_index,
+                    "skipping transfer whose mint could not be resolved"
+                );
+                METRICS
+                    .transfers_skipped
+                    .with_label_values(&[diagnostics::UNRESOLVED_MINT])
+                    .inc();
+                diagnostics::record(diagnostics::UNRESOLVED_MINT, ctx.signature);
+                return None;
+            }
+        }
+    }
+
+    let unreadable = |raw: &str| {
+        warn!(
+            signature = ctx.signature,
+            instruction_index,
+            ?inner_index,
+            amount = %raw,
+            "skipping transfer with an unreadable amount"
+        );
+        METRICS
+            .transfers_skipped
+            .with_label_values(&[diagnostics::UNPARSABLE_AMOUNT])
+            .inc();
+        diagnostics::record(diagnostics::UNPARSABLE_AMOUNT, ctx.signature);
+    };
+    let amount = match movement.amount {
+        // Zero-value transfers move nothing (and are a favourite of address
+        // poisoning spam), so they're not reported.
+        Ok(0) => return None,
+        Ok(amount) => amount,
+        Err(raw) => {
+            unreadable(&raw);
+            return None;
+        }
+    };
+    let fee = match movement.fee {
+        None => None,
+        Some(Ok(fee_raw)) => match TransferFee::new(amount, fee_raw, ctx.mint.decimals) {
+            Some(fee) => Some(fee),
+            None => {
+                unreadable(&format!("fee {} of {}", fee_raw, amount));
+                return None;
+            }
+        },
+        Some(Err(raw)) => {
+            unreadable(&raw);
+            return None;
+        }
+    };
+    let counterparty = match direction {
+        Direction::Sent | Direction::Internal | Direction::Burned => movement.destination,
+        Direction::Received | Direction::Minted => movement.source,
+    };
+    let via_delegate = owns_source
+        && movement
+            .authority
+            .is_some_and(|a| a != ctx.wallet.address());
+
+    Some(Transfer {
+        signature: ctx.signature.to_string(),
+        slot: ctx.slot,
+        block_time: ctx.block_time,
+        instruction_index,
+        inner_index,
+        direction,
+        amount_raw: amount,
+        amount_ui: format_amount(amount, ctx.mint.decimals),
+        source: movement.source.to_string(),
+        destination: movement.destination.to_string(),
+        mint: mint_address,
+        symbol: ctx.mint.symbol.clone(),
+        fee,
+        counterparty_owner: ctx.owners.get(counterparty).cloned(),
+        wallet: None,
+        memo: ctx.memo.map(str::to_string),
+        commitment: None,
+        orphaned: false,
+        via_delegate,
+        fee_payer: ctx.fee_payer.map(str::to_string),
+        network_fee: ctx
+            .paid_fee
+            .filter(|_| direction.is_outflow())
+            .map(NetworkFee::new),
+        explorer_url: None,
+        counterparty: None,
+        failed: ctx.failed,
+        balance_after_raw: None,
+        balance_after: None,
+        balance_excluded: false,
+        price_usd: None,
+        value_usd: None,
+    })
+}
+
+/// Pulls the wallet's transfers of `mint` out of one fetched transaction,
+/// covering both top-level instructions and CPIs recorded in
+/// `meta.innerInstructions`. Returns `None` when the transaction didn't come
+/// back in the parsed shape, so callers can count it instead of silently
+/// treating it as transfer-free; [`crate::decode::json_parsed`] converts
+/// the other shapes first.
+///
+/// `looked_up_mints` supplements the mints the transaction's token balances
+/// reveal (see [`accounts_missing_mint`]).
+pub fn extract_transfers(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    sig_info: &RpcConfirmedTransactionStatusWithSignature,
+    block_time: i64,
+    wallet: &WalletContext,
+    mint: &MintInfo,
+    looked_up_mints: &HashMap<String, String>,
+) -> Option<Vec<Transfer>> {
+    let owners = token_account_owners(tx);
+    let mut mints = token_account_mints(tx);
+    for (account, mint) in looked_up_mints {
+        mints.entry(account.clone()).or_insert_with(|| mint.clone());
+    }
+    let failed = sig_info.err.is_some()
+        || tx
+            .transaction
+            .meta
+            .as_ref()
+            .is_some_and(|meta| meta.err.is_some());
+    let memo = transaction_memo(tx);
+    let (fee_payer, paid_fee) = transaction_fee(tx, wallet);
+    let ctx = ParseContext {
+        wallet,
+        mint,
+        owners: &owners,
+        mints: &mints,
+        signature: &sig_info.signature,
+        slot: sig_info.slot,
+        block_time,
+        failed,
+        memo: memo.as_deref(),
+        fee_payer: fee_payer.as_deref(),
+        paid_fee,
+    };
+
+    Some(
+        located_instructions(tx)?
+            .into_iter()
+            .filter_map(|(instruction_index, inner_index, ix)| {
+                parse_token_transfer(ix, &ctx, instruction_index, inner_index)
+            })
+            .collect(),
+    )
+}
+
+/// Recognizes `approve`/`approveChecked` and `revoke` of one of the wallet's
+/// token accounts of `ctx.mint`. A plain `approve` or `revoke` doesn't name
+/// the mint, so the account's mint must be known from derivation or the
+/// token balances.
+pub fn parse_approval(
+    ix: &UiInstruction,
+    ctx: &ParseContext,
+    instruction_index: usize,
+    inner_index: Option<usize>,
+) -> Option<Approval> {
+    let UiInstruction::Parsed(UiParsedInstruction::Parsed(parsed)) = ix else {
+        return None;
+    };
+    if !ctx
+        .wallet
+        .programs()
+        .iter()
+        .any(|p| p.name == parsed.program)
+    {
+        return None;
+    }
+    let kind = match parsed.parsed.get("type").and_then(|v| v.as_str())? {
+        "approve" | "approveChecked" => ApprovalKind::Approve,
+        "revoke" => ApprovalKind::Revoke,
+        _ => return None,
+    };
+    let info = parsed.parsed.get("info")?;
+    let source = info.get("source").and_then(|v| v.as_str())?;
+    let owner = info
+        .get("owner")
+        .or_else(|| info.get("multisigOwner"))
+        .and_then(|v| v.as_str());
+    if !ctx.wallet.owns(source, ctx.owners) && owner != Some(ctx.wallet.address()) {
+        return None;
+    }
+    let mint_address = ctx.mint.mint.to_string();
+    let mint = match info.get("mint").and_then(|v| v.as_str()) {
+        Some(mint) => Some(mint),
+        None if ctx.wallet.is_derived_account(source) => Some(mint_address.as_str()),
+        None => ctx.mints.get(source).map(String::as_str),
+    };
+    if mint != Some(mint_address.as_str()) {
+        return None;
+    }
+
+    let (delegate, amount_raw) = match kind {
+        ApprovalKind::Revoke => (None, None),
+        ApprovalKind::Approve => {
+            let delegate = info.get("delegate").and_then(|v| v.as_str())?;
+            match raw_amount(info, "amount", "tokenAmount") {
+                Ok(amount) => (Some(delegate.to_string()), Some(amount)),
+                Err(raw) => {
+                    warn!(
+                        signature = ctx.signature,
+                        instruction_index,
+                        ?inner_index,
+                        amount = %raw,
+                        "skipping approval with an unreadable amount"
+                    );
+                    diagnostics::record(diagnostics::UNPARSABLE_AMOUNT, ctx.signature);
+                    return None;
+                }
+            }
+        }
+    };
+    Some(Approval {
+        signature: ctx.signature.to_string(),
+        slot: ctx.slot,
+        block_time: ctx.block_time,
+        instruction_index,
+        inner_index,
+        kind,
+        token_account: source.to_string(),
+        delegate,
+        amount_raw,
+        amount_ui: amount_raw.map(|amount| format_amount(amount, ctx.mint.decimals)),
+        mint: mint_address,
+        symbol: ctx.mint.symbol.clone(),
+    })
+}
+
+/// The approvals and revokes [`parse_approval`] finds in `tx`, top-level or
+/// CPI. A failed transaction changed no delegate, so it has none.
+pub fn extract_approvals(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    sig_info: &RpcConfirmedTransactionStatusWithSignature,
+    block_time: i64,
+    wallet: &WalletContext,
+    mint: &MintInfo,
+) -> Vec<Approval> {
+    let failed = sig_info.err.is_some()
+        || tx
+            .transaction
+            .meta
+            .as_ref()
+            .is_some_and(|meta| meta.err.is_some());
+    let Some(located) = located_instructions(tx).filter(|_| !failed) else {
+        return Vec::new();
+    };
+    let owners = token_account_owners(tx);
+    let mints = token_account_mints(tx);
+    let ctx = ParseContext {
+        wallet,
+        mint,
+        owners: &owners,
+        mints: &mints,
+        signature: &sig_info.signature,
+        slot: sig_info.slot,
+        block_time,
+        failed,
+        memo: None,
+        fee_payer: None,
+        paid_fee: None,
+    };
+    located
+        .into_iter()
+        .filter_map(|(instruction_index, inner_index, ix)| {
+            parse_approval(ix, &ctx, instruction_index, inner_index)
+        })
+        .collect()
+}
+
+/// Recognizes an associated-token `create`/`createIdempotent` of the
+/// wallet's account of `ctx.mint`, or an spl-token `closeAccount` of one of
+/// its accounts of the mint. `existing` lists the token accounts the
+/// transaction started with: `createIdempotent` of one of those did
+/// nothing.
+pub fn parse_account_event(
+    ix: &UiInstruction,
+    ctx: &ParseContext,
+    existing: &HashSet<String>,
+    instruction_index: usize,
+    inner_index: Option<usize>,
+) -> Option<AccountEvent> {
+    let UiInstruction::Parsed(UiParsedInstruction::Parsed(parsed)) = ix else {
+        return None;
+    };
+    let instruction_type = parsed.parsed.get("type").and_then(|v| v.as_str())?;
+    let info = parsed.parsed.get("info")?;
+    let text = |name: &str| info.get(name).and_then(|v| v.as_str());
+    let mint_address = ctx.mint.mint.to_string();
+    let (kind, token_account, rent_account) = if parsed.program_id == ASSOCIATED_TOKEN_PROGRAM_ID {
+        if !matches!(instruction_type, "create" | "createIdempotent")
+            || text("wallet") != Some(ctx.wallet.address())
+            || text("mint") != Some(mint_address.as_str())
+        {
+            return None;
+        }
+        let account = text("account")?;
+        if existing.contains(account) {
+            return None;
+        }
+        (AccountEventKind::Created, account, text("source"))
+    } else if ctx
+        .wallet
+        .programs()
+        .iter()
+        .any(|p| p.name == parsed.program)
+    {
+        if instruction_type != "closeAccount" {
+            return None;
+        }
+        let account = text("account")?;
+        let owner = text("owner").or_else(|| text("multisigOwner"));
+        if !ctx.wallet.owns(account, ctx.owners) && owner != Some(ctx.wallet.address()) {
+            return None;
+        }
+        let of_mint =
+            ctx.wallet.is_derived_account(account) || ctx.mints.get(account) == Some(&mint_address);
+        if !of_mint {
+            return None;
+        }
+        (AccountEventKind::Closed, account, text("destination"))
+    } else {
+        return None;
+    };
+    Some(AccountEvent {
+        signature: ctx.signature.to_string(),
+        slot: ctx.slot,
+        block_time: ctx.block_time,
+        instruction_index,
+        inner_index,
+        kind,
+        token_account: token_account.to_string(),
+        mint: mint_address,
+        rent_account: rent_account.map(str::to_string),
+    })
+}
+
+/// The lifecycle events [`parse_account_event`] finds in `tx`, top-level or
+/// CPI. A failed transaction opened and closed nothing.
+pub fn extract_account_events(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    sig_info: &RpcConfirmedTransactionStatusWithSignature,
+    block_time: i64,
+    wallet: &WalletContext,
+    mint: &MintInfo,
+) -> Vec<AccountEvent> {
+    let Some(meta) = tx.transaction.meta.as_ref() else {
+        return Vec::new();
+    };
+    if sig_info.err.is_some() || meta.err.is_some() {
+        return Vec::new();
+    }
+    let Some(located) = located_instructions(tx) else {
+        return Vec::new();
+    };
+    let account_keys = message_account_keys(tx);
+    let pre: Option<&Vec<UiTransactionTokenBalance>> = meta.pre_token_balances.as_ref().into();
+    let existing: HashSet<String> = pre
+        .into_iter()
+        .flatten()
+        .filter_map(|balance| account_keys.get(balance.account_index as usize).cloned())
+        .collect();
+    let owners = token_account_owners(tx);
+    let mints = token_account_mints(tx);
+    let ctx = ParseContext {
+        wallet,
+        mint,
+        owners: &owners,
+        mints: &mints,
+        signature: &sig_info.signature,
+        slot: sig_info.slot,
+        block_time,
+        failed: false,
+        memo: None,
+        fee_payer: None,
+        paid_fee: None,
+    };
+    located
+        .into_iter()
+        .filter_map(|(instruction_index, inner_index, ix)| {
+            parse_account_event(ix, &ctx, &existing, instruction_index, inner_index)
+        })
+        .collect()
+}
+
+/// Token accounts of plain `transfer`s touching the wallet whose mint
+/// neither the token balances nor derivation reveal. The caller resolves
+/// them (e.g. via `getAccountInfo`) and passes the result to
+/// [`extract_transfers`].
+pub fn accounts_missing_mint(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    wallet: &WalletContext,
+    mint: &MintInfo,
+) -> Vec<String> {
+    let Some(located) = located_instructions(tx) else {
+        return Vec::new();
+    };
+    let owners = token_account_owners(tx);
+    let mints = token_account_mints(tx);
+    let mint_address = mint.mint.to_string();
+    let mut missing = Vec::new();
+    for (_, _, ix) in located {
+        let Ok(movement) = parse_spl_transfer(ix, &mint_address, wallet.programs()) else {
+            continue;
+        };
+        let accounts = [movement.source, movement.destination];
+        let known =
+            |account: &&str| wallet.is_derived_account(account) || mints.contains_key(*account);
+        if movement.mint.is_some()
+            || accounts.iter().any(known)
+            || !accounts.iter().any(|a| wallet.owns(a, &owners))
+        {
+            continue;
+        }
+        for account in accounts {
+            if !missing.iter().any(|m| m == account) {
+                missing.push(account.to_string());
+            }
+        }
+    }
+    missing
+}
+
+/// The fee payer of `tx` (its first account key) and, when that's the
+/// wallet, the fee it paid in lamports. Fees paid by a relayer aren't the
+/// wallet's cost, so they're not reported.
+pub fn transaction_fee(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    wallet: &WalletContext,
+) -> (Option<String>, Option<u64>) {
+    let fee_payer = message_account_keys(tx).into_iter().next();
+    let paid_fee = tx
+        .transaction
+        .meta
+        .as_ref()
+        .filter(|_| fee_payer.as_deref() == Some(wallet.address()))
+        .map(|meta| meta.fee);
+    (fee_payer, paid_fee)
+}
+
+/// Text of every Memo program (v1 or v2) instruction in `tx`, top-level or
+/// CPI, one per line in execution order.
+pub fn transaction_memo(tx: &EncodedConfirmedTransactionWithStatusMeta) -> Option<String> {
+    let mut located = located_instructions(tx)?;
+    located.sort_by_key(|(index, inner_index, _)| (*index, inner_index.map_or(0, |i| i + 1)));
+    let memos: Vec<String> = located
+        .into_iter()
+        .filter_map(|(_, _, ix)| instruction_memo(ix))
+        .collect();
+    (!memos.is_empty()).then(|| memos.join("\n"))
+}
+
+/// The memo carried by `ix`, if it's a Memo program instruction. The RPC
+/// decodes valid UTF-8 itself; anything else arrives as base58 data and is
+/// converted lossily.
+fn instruction_memo(ix: &UiInstruction) -> Option<String> {
+    let is_memo =
+        |program_id: &str| program_id == MEMO_PROGRAM_ID || program_id == MEMO_V1_PROGRAM_ID;
+    match ix {
+        UiInstruction::Parsed(UiParsedInstruction::Parsed(parsed))
+            if is_memo(&parsed.program_id) =>
+        {
+            parsed.parsed.as_str().map(str::to_string)
+        }
+        UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(decoded))
+            if is_memo(&decoded.program_id) =>
+        {
+            let data = solana_sdk::bs58::decode(&decoded.data).into_vec().ok()?;
+            Some(String::from_utf8_lossy(&data).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Every instruction of `tx` as (outer index, position within that
+/// instruction's CPIs, instruction), or `None` if it isn't jsonParsed.
+fn located_instructions(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Option<Vec<(usize, Option<usize>, &UiInstruction)>> {
+    let instructions = match &tx.transaction.transaction {
+        EncodedTransaction::Json(parsed_tx) => match &parsed_tx.message {
+            UiMessage::Parsed(parsed_msg) => &parsed_msg.instructions,
+            UiMessage::Raw(_) => return None,
+        },
+        _ => return None,
+    };
+
+    let mut located: Vec<(usize, Option<usize>, &UiInstruction)> = instructions
+        .iter()
+        .enumerate()
+        .map(|(index, ix)| (index, None, ix))
+        .collect();
+    if let Some(meta) = &tx.transaction.meta {
+        let inner: Option<&Vec<UiInnerInstructions>> = meta.inner_instructions.as_ref().into();
+        for group in inner.into_iter().flatten() {
+            for (inner_index, ix) in group.instructions.iter().enumerate() {
+                located.push((group.index as usize, Some(inner_index), ix));
+            }
+        }
+    }
+
+    Some(located)
+}
+
+/// The [`Strategy::Balances`](crate::model::Strategy::Balances) extractor:
+/// one record per wallet token account of `mint` whose balance changed,
+/// received or sent according to the sign. The counterparty is the other
+/// account with the exactly opposite change, if there is one. Since there's
+/// no instruction behind a record, `instruction_index` holds the token
+/// account's position in the account list instead. Returns `None` without
+/// transaction metadata.
+pub fn balance_transfers(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    sig_info: &RpcConfirmedTransactionStatusWithSignature,
+    block_time: i64,
+    wallet: &WalletContext,
+    mint: &MintInfo,
+) -> Option<Vec<Transfer>> {
+    let meta = tx.transaction.meta.as_ref()?;
+    let account_keys = message_account_keys(tx);
+    let owners = token_account_owners(tx);
+    let mint_address = mint.mint.to_string();
+
+    // account index → (pre, post) in base units
+    let mut balances: BTreeMap<u8, (u64, u64)> = BTreeMap::new();
+    let pre: Option<&Vec<UiTransactionTokenBalance>> = meta.pre_token_balances.as_ref().into();
+    let post: Option<&Vec<UiTransactionTokenBalance>> = meta.post_token_balances.as_ref().into();
+    for (list, is_post) in [(pre, false), (post, true)] {
+        for balance in list.into_iter().flatten() {
+            if balance.mint != mint_address {
+                continue;
+            }
+            let Ok(amount) = balance.ui_token_amount.amount.parse::<u64>() else {
+                warn!(
+                    signature = %sig_info.signature,
+                    account_index = balance.account_index,
+                    amount = %balance.ui_token_amount.amount,
+                    "skipping token balance with an unreadable amount"
+                );
+                METRICS
+                    .transfers_skipped
+                    .with_label_values(&[diagnostics::UNPARSABLE_AMOUNT])
+                    .inc();
+                diagnostics::record(diagnostics::UNPARSABLE_AMOUNT, &sig_info.signature);
+                continue;
+            };
+            let entry = balances.entry(balance.account_index).or_default();
+            if is_post {
+                entry.1 = amount;
+            } else {
+                entry.0 = amount;
+            }
+        }
+    }
+
+    let deltas: Vec<(usize, &str, i128)> = balances
+        .into_iter()
+        .filter_map(|(index, (pre, post))| {
+            let account = account_keys.get(index as usize)?;
+            let delta = i128::from(post) - i128::from(pre);
+            (delta != 0).then_some((index as usize, account.as_str(), delta))
+        })
+        .collect();
+    let failed = sig_info.err.is_some() || meta.err.is_some();
+    let memo = transaction_memo(tx);
+    let (fee_payer, paid_fee) = transaction_fee(tx, wallet);
+
+    let transfers = deltas
+        .iter()
+        .filter(|(_, account, _)| wallet.owns(account, &owners))
+        .map(|&(index, account, delta)| {
+            let counterparty = deltas
+                .iter()
+                .find(|(_, other, other_delta)| {
+                    *other != account && *other_delta == -delta && !wallet.owns(other, &owners)
+                })
+                .map_or("", |(_, other, _)| *other);
+            let (direction, source, destination) = if delta > 0 {
+                (Direction::Received, counterparty, account)
+            } else {
+                (Direction::Sent, account, counterparty)
+            };
+            let amount = u64::try_from(delta.unsigned_abs()).unwrap_or(u64::MAX);
+            Transfer {
+                signature: sig_info.signature.clone(),
+                slot: sig_info.slot,
+                block_time,
+                instruction_index: index,
+                inner_index: None,
+                direction,
+                amount_raw: amount,
+                amount_ui: format_amount(amount, mint.decimals),
+                source: source.to_string(),
+                destination: destination.to_string(),
+                mint: mint_address.clone(),
+                symbol: mint.symbol.clone(),
+                fee: None,
+                counterparty_owner: owners.get(counterparty).cloned(),
+                wallet: None,
+                memo: memo.clone(),
+                commitment: None,
+                orphaned: false,
+                via_delegate: false,
+                fee_payer: fee_payer.clone(),
+                network_fee: paid_fee
+                    .filter(|_| direction.is_outflow())
+                    .map(NetworkFee::new),
+                explorer_url: None,
+                counterparty: None,
+                failed,
+                balance_after_raw: None,
+                balance_after: None,
+                balance_excluded: false,
+                price_usd: None,
+                value_usd: None,
+            }
+        })
+        .collect();
+    Some(transfers)
+}
+
+/// Compares the per-account net effect of both strategies' transfers for
+/// one transaction, returning the accounts where they differ.
+pub fn find_discrepancy(
+    signature: &str,
+    slot: u64,
+    from_instructions: &[Transfer],
+    from_balances: &[Transfer],
+    wallet: &WalletContext,
+    owners: &HashMap<String, String>,
+    decimals: u8,
+) -> Option<Discrepancy> {
+    let net = |transfers: &[Transfer]| {
+        let mut net: BTreeMap<String, i128> = BTreeMap::new();
+        for transfer in transfers.iter().filter(|t| !t.failed) {
+            if wallet.owns(&transfer.source, owners) {
+                *net.entry(transfer.source.clone()).or_default() -= i128::from(transfer.amount_raw);
+            }
+            // A Token-2022 fee is withheld in the destination account but
+            // isn't part of its balance.
+            if wallet.owns(&transfer.destination, owners) {
+                *net.entry(transfer.destination.clone()).or_default() +=
+                    i128::from(transfer.net_amount_raw());
+            }
+        }
+        net.retain(|_, delta| *delta != 0);
+        net
+    };
+    let instructions = net(from_instructions);
+    let balances = net(from_balances);
+    if instructions == balances {
+        return None;
+    }
+
+    let accounts: BTreeSet<&String> = instructions.keys().chain(balances.keys()).collect();
+    let accounts = accounts
+        .into_iter()
+        .filter_map(|account| {
+            let by_instructions = instructions.get(account).copied().unwrap_or(0);
+            let by_balances = balances.get(account).copied().unwrap_or(0);
+            (by_instructions != by_balances).then(|| AccountDiscrepancy {
+                token_account: account.clone(),
+                instructions: format_signed_amount(by_instructions, decimals),
+                balances: format_signed_amount(by_balances, decimals),
+            })
+        })
+        .collect();
+    Some(Discrepancy {
+        signature: signature.to_string(),
+        slot,
+        accounts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use solana_transaction_status::parse_instruction::ParsedInstruction;
+    use solana_transaction_status::UiPartiallyDecodedInstruction;
+
+    const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+    const WALLET: &str = "7cMEhpt9y3inBNVv8fNnuaEbx7hKHZnLvR1KWKKxuDDU";
+    const WALLET_TOKEN_ACCOUNT: &str = "3emsAVdmGKERbHjmGfQ6oZ1e35dkf5iYcS6U4CPKFVaa";
+    const OTHER_TOKEN_ACCOUNT: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+
+    fn usdc() -> MintInfo {
+        MintInfo {
+            mint: Pubkey::from_str(USDC).unwrap(),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+        }
+    }
+
+    fn instruction(program: &str, parsed: Value) -> UiInstruction {
+        UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+            program: program.to_string(),
+            program_id: TokenProgram::from_str(program)
+                .map_or(SPL_TOKEN_PROGRAM_ID, |p| p.id)
+                .to_string(),
+            parsed,
+            stack_height: None,
+        }))
+    }
+
+    /// Runs `parse_token_transfer` with `WALLET_TOKEN_ACCOUNT` owned by the
+    /// wallet and `OTHER_TOKEN_ACCOUNT` owned by someone else, both holding
+    /// USDC.
+    fn parse(ix: &UiInstruction) -> Option<Transfer> {
+        parse_with_mints(
+            ix,
+            &[(WALLET_TOKEN_ACCOUNT, USDC), (OTHER_TOKEN_ACCOUNT, USDC)],
+        )
+    }
+
+    fn parse_with_mints(ix: &UiInstruction, mints: &[(&str, &str)]) -> Option<Transfer> {
+        with_context(mints, |ctx| parse_token_transfer(ix, ctx, 1, Some(0)))
+    }
+
+    fn with_context<T>(mints: &[(&str, &str)], f: impl FnOnce(&ParseContext) -> T) -> T {
+        let mint = usdc();
+        let wallet = WalletContext::new(
+            &Pubkey::from_str(WALLET).unwrap(),
+            &mint,
+            &TokenProgram::ALL,
+        );
+        let owners = HashMap::from([
+            (WALLET_TOKEN_ACCOUNT.to_string(), WALLET.to_string()),
+            (
+                OTHER_TOKEN_ACCOUNT.to_string(),
+                "11111111111111111111111111111111".to_string(),
+            ),
+        ]);
+        let mints: HashMap<String, String> = mints
+            .iter()
+            .map(|(account, mint)| (account.to_string(), mint.to_string()))
+            .collect();
+        let ctx = ParseContext {
+            wallet: &wallet,
+            mint: &mint,
+            owners: &owners,
+            mints: &mints,
+            signature: "sig",
+            slot: 42,
+            block_time: 1_700_000_000,
+            failed: false,
+            memo: None,
+            fee_payer: None,
+            paid_fee: None,
+        };
+        f(&ctx)
+    }
+
+    fn approval(ix: &UiInstruction, mints: &[(&str, &str)]) -> Option<Approval> {
+        with_context(mints, |ctx| parse_approval(ix, ctx, 0, None))
+    }
+
+    #[test]
+    fn marks_transfers_signed_by_a_delegate() {
+        let transfer = |authority: &str| {
+            instruction(
+                "spl-token",
+                json!({
+                    "type": "transfer",
+                    "info": {
+                        "source": WALLET_TOKEN_ACCOUNT,
+                        "destination": OTHER_TOKEN_ACCOUNT,
+                        "authority": authority,
+                        "amount": "10",
+                    },
+                }),
+            )
+        };
+        assert!(!parse(&transfer(WALLET)).unwrap().via_delegate);
+        let delegated = parse(&transfer(OTHER_TOKEN_ACCOUNT)).unwrap();
+        assert_eq!(delegated.direction, Direction::Sent);
+        assert!(delegated.via_delegate);
+
+        // Whoever signs for the other side's account is none of our concern.
+        let incoming = instruction(
+            "spl-token",
+            json!({
+                "type": "transfer",
+                "info": {
+                    "source": OTHER_TOKEN_ACCOUNT,
+                    "destination": WALLET_TOKEN_ACCOUNT,
+                    "authority": OTHER_TOKEN_ACCOUNT,
+                    "amount": "10",
+                },
+            }),
+        );
+        assert!(!parse(&incoming).unwrap().via_delegate);
+    }
+
+    #[test]
+    fn reads_approvals_and_revokes_of_the_wallets_accounts() {
+        let usdc_accounts = [(WALLET_TOKEN_ACCOUNT, USDC)];
+        let approve = instruction(
+            "spl-token",
+            json!({
+                "type": "approve",
+                "info": {
+                    "source": WALLET_TOKEN_ACCOUNT,
+                    "delegate": OTHER_TOKEN_ACCOUNT,
+                    "owner": WALLET,
+                    "amount": "5000000",
+                },
+            }),
+        );
+        let found = approval(&approve, &usdc_accounts).unwrap();
+        assert_eq!(found.kind, ApprovalKind::Approve);
+        assert_eq!(found.delegate.as_deref(), Some(OTHER_TOKEN_ACCOUNT));
+        assert_eq!(found.amount_ui.as_deref(), Some("5.000000"));
+        // A plain approve of an account of unknown mint can't be attributed.
+        assert!(approval(&approve, &[]).is_none());
+
+        let checked = instruction(
+            "spl-token",
+            json!({
+                "type": "approveChecked",
+                "info": {
+                    "source": WALLET_TOKEN_ACCOUNT,
+                    "mint": USDC,
+                    "delegate": OTHER_TOKEN_ACCOUNT,
+                    "owner": WALLET,
+                    "tokenAmount": { "amount": "7", "decimals": 6 },
+                },
+            }),
+        );
+        assert_eq!(approval(&checked, &[]).unwrap().amount_raw, Some(7));
+
+        let revoke = instruction(
+            "spl-token",
+            json!({
+                "type": "revoke",
+                "info": { "source": WALLET_TOKEN_ACCOUNT, "owner": WALLET },
+            }),
+        );
+        let revoked = approval(&revoke, &usdc_accounts).unwrap();
+        assert_eq!(revoked.kind, ApprovalKind::Revoke);
+        assert_eq!((revoked.delegate, revoked.amount_raw), (None, None));
+
+        let foreign = instruction(
+            "spl-token",
+            json!({
+                "type": "revoke",
+                "info": {
+                    "source": OTHER_TOKEN_ACCOUNT,
+                    "owner": "11111111111111111111111111111111",
+                },
+            }),
+        );
+        assert!(approval(&foreign, &[(OTHER_TOKEN_ACCOUNT, USDC)]).is_none());
+    }
+
+    #[test]
+    fn reads_creations_and_closures_of_the_wallets_accounts() {
+        let create = |kind: &str, wallet: &str| {
+            UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+                program: "spl-associated-token-account".to_string(),
+                program_id: ASSOCIATED_TOKEN_PROGRAM_ID.to_string(),
+                parsed: json!({
+                    "type": kind,
+                    "info": {
+                        "source": OTHER_TOKEN_ACCOUNT,
+                        "account": WALLET_TOKEN_ACCOUNT,
+                        "wallet": wallet,
+                        "mint": USDC,
+                    },
+                }),
+                stack_height: None,
+            }))
+        };
+        let event = |ix: &UiInstruction, existing: &[&str]| {
+            let existing = existing.iter().map(|a| a.to_string()).collect();
+            with_context(&[(WALLET_TOKEN_ACCOUNT, USDC)], |ctx| {
+                parse_account_event(ix, ctx, &existing, 0, None)
+            })
+        };
+
+        let created = event(&create("create", WALLET), &[]).unwrap();
+        assert_eq!(created.kind, AccountEventKind::Created);
+        assert_eq!(created.token_account, WALLET_TOKEN_ACCOUNT);
+        assert_eq!(created.rent_account.as_deref(), Some(OTHER_TOKEN_ACCOUNT));
+        assert!(event(&create("createIdempotent", WALLET), &[]).is_some());
+        // An idempotent create of an account that was already there is a no-op.
+        assert!(event(&create("createIdempotent", WALLET), &[WALLET_TOKEN_ACCOUNT]).is_none());
+        assert!(event(&create("create", OTHER_TOKEN_ACCOUNT), &[]).is_none());
+
+        let close = |account: &str| {
+            instruction(
+                "spl-token",
+                json!({
+                    "type": "closeAccount",
+                    "info": {
+                        "account": account,
+                        "destination": WALLET,
+                        "owner": WALLET,
+                    },
+                }),
+            )
+        };
+        let closed = event(&close(WALLET_TOKEN_ACCOUNT), &[]).unwrap();
+        assert_eq!(closed.kind, AccountEventKind::Closed);
+        assert_eq!(closed.rent_account.as_deref(), Some(WALLET));
+        // An account of another mint closing is someone else's business.
+        assert!(event(&close(OTHER_TOKEN_ACCOUNT), &[]).is_none());
+    }
+
+    #[test]
+    fn reads_memos_from_both_memo_programs() {
+        let parsed = UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+            program: "spl-memo".to_string(),
+            program_id: MEMO_PROGRAM_ID.to_string(),
+            parsed: json!("order 1234"),
+            stack_height: None,
+        }));
+        assert_eq!(instruction_memo(&parsed).as_deref(), Some("order 1234"));
+
+        let raw = UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(
+            UiPartiallyDecodedInstruction {
+                program_id: MEMO_V1_PROGRAM_ID.to_string(),
+                accounts: Vec::new(),
+                data: solana_sdk::bs58::encode(b"ref \xff").into_string(),
+                stack_height: None,
+            },
+        ));
+        assert_eq!(instruction_memo(&raw).as_deref(), Some("ref \u{fffd}"));
+
+        let token = instruction("spl-token", json!({"type": "transfer", "info": {}}));
+        assert_eq!(instruction_memo(&token), None);
+    }
+
+    #[test]
+    fn parses_plain_transfer_as_sent() {
+        let ix = instruction(
+            "spl-token",
+            json!({
+                "type": "transfer",
+                "info": {
+                    "source": WALLET_TOKEN_ACCOUNT,
+                    "destination": OTHER_TOKEN_ACCOUNT,
+                    "authority": WALLET,
+                    "amount": "1500000",
+                },
+            }),
+        );
+        let transfer = parse(&ix).unwrap();
+        assert_eq!(transfer.direction, Direction::Sent);
+        assert_eq!(transfer.amount_raw, 1_500_000);
+        assert_eq!(transfer.amount_ui, "1.500000");
+        assert_eq!(transfer.mint, USDC);
+        assert_eq!(
+            (transfer.instruction_index, transfer.inner_index),
+            (1, Some(0))
+        );
+        assert_eq!((transfer.slot, transfer.block_time), (42, 1_700_000_000));
+    }
+
+    #[test]
+    fn parses_transfer_checked_as_received() {
+        let ix = instruction(
+            "spl-token",
+            json!({
+                "type": "transferChecked",
+                "info": {
+                    "source": OTHER_TOKEN_ACCOUNT,
+                    "destination": WALLET_TOKEN_ACCOUNT,
+                    "mint": USDC,
+                    "tokenAmount": { "amount": "250", "decimals": 6, "uiAmountString": "0.00025" },
+                },
+            }),
+        );
+        let transfer = parse(&ix).unwrap();
+        assert_eq!(transfer.direction, Direction::Received);
+        assert_eq!(transfer.amount_raw, 250);
+        assert_eq!(transfer.amount_ui, "0.000250");
+        assert_eq!(
+            transfer.counterparty_address(),
+            ("11111111111111111111111111111111", true)
+        );
+    }
+
+    #[test]
+    fn parses_token_2022_transfer_with_fee() {
+        let ix = instruction(
+            "spl-token-2022",
+            json!({
+                "type": "transferCheckedWithFee",
+                "info": {
+                    "source": OTHER_TOKEN_ACCOUNT,
+                    "destination": WALLET_TOKEN_ACCOUNT,
+                    "mint": USDC,
+                    "authority": "11111111111111111111111111111111",
+                    "tokenAmount": { "amount": "1000000", "decimals": 6, "uiAmountString": "1" },
+                    "feeAmount": { "amount": "2500", "decimals": 6, "uiAmountString": "0.0025" },
+                },
+            }),
+        );
+        let transfer = parse(&ix).unwrap();
+        assert_eq!(transfer.direction, Direction::Received);
+        assert_eq!(transfer.amount_raw, 1_000_000);
+        assert_eq!(
+            transfer.fee,
+            Some(TransferFee {
+                fee_raw: 2500,
+                fee_ui: "0.002500".to_string(),
+                net_amount_raw: 997_500,
+                net_amount_ui: "0.997500".to_string(),
+            })
+        );
+        assert_eq!(transfer.net_amount_raw(), 997_500);
+
+        // Not read at all once Token-2022 is dropped from the program list.
+        assert_eq!(
+            parse_spl_transfer(&ix, USDC, &[SPL_TOKEN]).err(),
+            Some(NotAMovement::Other)
+        );
+    }
+
+    #[test]
+    fn parses_mints_and_burns_on_wallet_accounts() {
+        let mint_to = instruction(
+            "spl-token",
+            json!({
+                "type": "mintTo",
+                "info": {
+                    "mint": USDC,
+                    "account": WALLET_TOKEN_ACCOUNT,
+                    "mintAuthority": "11111111111111111111111111111111",
+                    "amount": "5000000",
+                },
+            }),
+        );
+        let minted = parse(&mint_to).unwrap();
+        assert_eq!(minted.direction, Direction::Minted);
+        assert_eq!(minted.amount_raw, 5_000_000);
+        assert_eq!(minted.counterparty(), USDC);
+
+        let burn = instruction(
+            "spl-token",
+            json!({
+                "type": "burnChecked",
+                "info": {
+                    "account": WALLET_TOKEN_ACCOUNT,
+                    "mint": USDC,
+                    "authority": WALLET,
+                    "tokenAmount": { "amount": "70", "decimals": 6, "uiAmountString": "0.00007" },
+                },
+            }),
+        );
+        let burned = parse(&burn).unwrap();
+        assert_eq!(burned.direction, Direction::Burned);
+        assert_eq!(burned.source, WALLET_TOKEN_ACCOUNT);
+        assert_eq!(burned.amount_raw, 70);
+
+        let elsewhere = instruction(
+            "spl-token",
+            json!({
+                "type": "mintTo",
+                "info": { "mint": USDC, "account": OTHER_TOKEN_ACCOUNT, "amount": "1" },
+            }),
+        );
+        assert!(parse(&elsewhere).is_none());
+    }
+
+    #[test]
+    fn transfer_between_wallet_accounts_is_self() {
+        let ata = associated_token_address(&Pubkey::from_str(WALLET).unwrap(), &usdc().mint);
+        let ix = instruction(
+            "spl-token",
+            json!({
+                "type": "transfer",
+                "info": {
+                    "source": ata.to_string(),
+                    "destination": WALLET_TOKEN_ACCOUNT,
+                    "amount": "1",
+                },
+            }),
+        );
+        assert_eq!(parse(&ix).unwrap().direction, Direction::Internal);
+    }
+
+    #[test]
+    fn rejects_transfer_checked_of_another_mint() {
+        let ix = instruction(
+            "spl-token",
+            json!({
+                "type": "transferChecked",
+                "info": {
+                    "source": OTHER_TOKEN_ACCOUNT,
+                    "destination": WALLET_TOKEN_ACCOUNT,
+                    "mint": "Es9vMFrzaCERH16Cdv83hA5KaM6rDx8JEX5Rk3z3aZ9o",
+                    "tokenAmount": { "amount": "250", "decimals": 6 },
+                },
+            }),
+        );
+        assert!(parse(&ix).is_none());
+    }
+
+    #[test]
+    fn plain_transfer_needs_a_resolved_mint() {
+        let ix = instruction(
+            "spl-token",
+            json!({
+                "type": "transfer",
+                "info": {
+                    "source": OTHER_TOKEN_ACCOUNT,
+                    "destination": WALLET_TOKEN_ACCOUNT,
+                    "amount": "10",
+                },
+            }),
+        );
+        let usdt = "Es9vMFrzaCERH16Cdv83hA5KaM6rDx8JEX5Rk3z3aZ9o";
+        assert!(parse_with_mints(&ix, &[(WALLET_TOKEN_ACCOUNT, usdt)]).is_none());
+
+        let unresolved = || {
+            METRICS
+                .transfers_skipped
+                .with_label_values(&["unresolved_mint"])
+                .get()
+        };
+        let before = unresolved();
+        assert!(parse_with_mints(&ix, &[]).is_none());
+        assert!(unresolved() > before);
+
+        // Either side is enough to identify the mint.
+        assert!(parse_with_mints(&ix, &[(OTHER_TOKEN_ACCOUNT, USDC)]).is_some());
+    }
+
+    #[test]
+    fn rejects_missing_info() {
+        let ix = instruction("spl-token", json!({ "type": "transfer" }));
+        assert!(parse(&ix).is_none());
+        let ix = instruction(
+            "spl-token",
+            json!({ "type": "transfer", "info": { "source": WALLET_TOKEN_ACCOUNT } }),
+        );
+        assert_eq!(
+            parse_spl_transfer(&ix, USDC, &[SPL_TOKEN]).err(),
+            Some(NotAMovement::MissingInfo("destination"))
+        );
+    }
+
+    #[test]
+    fn rejects_unreadable_and_zero_amounts() {
+        let skipped = || {
+            METRICS
+                .transfers_skipped
+                .with_label_values(&["unparsable_amount"])
+                .get()
+        };
+        let before = skipped();
+        for amount in [json!(-1), json!(1.5), json!(null), json!("abc"), json!("0")] {
+            let ix = instruction(
+                "spl-token",
+                json!({
+                    "type": "transfer",
+                    "info": {
+                        "source": WALLET_TOKEN_ACCOUNT,
+                        "destination": OTHER_TOKEN_ACCOUNT,
+                        "amount": amount,
+                    },
+                }),
+            );
+            assert!(parse(&ix).is_none(), "amount {} should be rejected", amount);
+        }
+        // Everything but the zero amount is a decoding failure worth counting.
+        assert!(skipped() >= before + 4);
+    }
+
+    #[test]
+    fn reads_amounts_as_strings_or_numbers() {
+        let transfer = |amount: serde_json::Value| {
+            let ix = instruction(
+                "spl-token",
+                json!({
+                    "type": "transfer",
+                    "info": {
+                        "source": OTHER_TOKEN_ACCOUNT,
+                        "destination": WALLET_TOKEN_ACCOUNT,
+                        "amount": amount,
+                    },
+                }),
+            );
+            parse(&ix).map(|t| t.amount_raw)
+        };
+        assert_eq!(transfer(json!("1500000")), Some(1_500_000));
+        assert_eq!(transfer(json!(1500000)), Some(1_500_000));
+    }
+
+    #[test]
+    fn reads_checked_amounts_in_every_shape() {
+        let checked = |token_amount: serde_json::Value| {
+            let ix = instruction(
+                "spl-token",
+                json!({
+                    "type": "transferChecked",
+                    "info": {
+                        "source": OTHER_TOKEN_ACCOUNT,
+                        "destination": WALLET_TOKEN_ACCOUNT,
+                        "mint": USDC,
+                        "tokenAmount": token_amount,
+                    },
+                }),
+            );
+            parse(&ix).map(|t| t.amount_raw)
+        };
+        assert_eq!(
+            checked(json!({ "amount": "250", "decimals": 6 })),
+            Some(250)
+        );
+        assert_eq!(checked(json!({ "amount": 250, "decimals": 6 })), Some(250));
+        // Without a readable `amount`, the UI string is scaled by the
+        // decimals, which may themselves be a string.
+        assert_eq!(
+            checked(json!({ "uiAmountString": "1.5", "decimals": 6 })),
+            Some(1_500_000)
+        );
+        assert_eq!(
+            checked(json!({ "amount": null, "uiAmountString": "0.25", "decimals": "6" })),
+            Some(250_000)
+        );
+        assert_eq!(checked(json!({ "uiAmountString": "1.5" })), None);
+        assert_eq!(
+            checked(json!({ "uiAmountString": "1.5", "decimals": 300 })),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_other_programs_and_instruction_types() {
+        let info = json!({
+            "source": WALLET_TOKEN_ACCOUNT,
+            "destination": OTHER_TOKEN_ACCOUNT,
+            "amount": "10",
+        });
+        let system = instruction("system", json!({ "type": "transfer", "info": info }));
+        assert!(parse(&system).is_none());
+        let approve = instruction("spl-token", json!({ "type": "approve", "info": info }));
+        assert!(parse(&approve).is_none());
+    }
+
+    #[test]
+    fn rejects_transfers_not_touching_the_wallet() {
+        let ix = instruction(
+            "spl-token",
+            json!({
+                "type": "transfer",
+                "info": {
+                    "source": OTHER_TOKEN_ACCOUNT,
+                    "destination": OTHER_TOKEN_ACCOUNT,
+                    "amount": "10",
+                },
+            }),
+        );
+        assert!(parse(&ix).is_none());
+    }
+
+    /// A transaction moving 1.5 USDC from `OTHER_TOKEN_ACCOUNT` into
+    /// `WALLET_TOKEN_ACCOUNT`, as seen only through its token balances.
+    fn balance_only_transaction() -> EncodedConfirmedTransactionWithStatusMeta {
+        let balance = |index: u8, owner: &str, amount: &str| {
+            json!({
+                "accountIndex": index,
+                "mint": USDC,
+                "owner": owner,
+                "uiTokenAmount": {
+                    "amount": amount,
+                    "decimals": 6,
+                    "uiAmount": null,
+                    "uiAmountString": "0",
+                },
+            })
+        };
+        serde_json::from_value(json!({
+            "slot": 42,
+            "blockTime": 1_700_000_000,
+            "meta": {
+                "err": null,
+                "status": { "Ok": null },
+                "fee": 5000,
+                "preBalances": [0, 0, 0],
+                "postBalances": [0, 0, 0],
+                "preTokenBalances": [
+                    balance(1, WALLET, "1000000"),
+                    balance(2, "11111111111111111111111111111111", "2000000"),
+                ],
+                "postTokenBalances": [
+                    balance(1, WALLET, "2500000"),
+                    balance(2, "11111111111111111111111111111111", "500000"),
+                ],
+            },
+            "transaction": {
+                "signatures": ["sig"],
+                "message": {
+                    "accountKeys": [
+                        { "pubkey": WALLET, "writable": true, "signer": true, "source": "transaction" },
+                        { "pubkey": WALLET_TOKEN_ACCOUNT, "writable": true, "signer": false, "source": "transaction" },
+                        { "pubkey": OTHER_TOKEN_ACCOUNT, "writable": true, "signer": false, "source": "transaction" },
+                    ],
+                    "recentBlockhash": "11111111111111111111111111111111",
+                    "instructions": [],
+                },
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn derives_transfers_from_balance_deltas_and_reports_discrepancies() {
+        let tx = balance_only_transaction();
+        let sig_info = RpcConfirmedTransactionStatusWithSignature {
+            signature: "sig".to_string(),
+            slot: 42,
+            err: None,
+            memo: None,
+            block_time: Some(1_700_000_000),
+            confirmation_status: None,
+        };
+        let mint = usdc();
+        let wallet = WalletContext::new(
+            &Pubkey::from_str(WALLET).unwrap(),
+            &mint,
+            &TokenProgram::ALL,
+        );
+
+        let transfers = balance_transfers(&tx, &sig_info, 1_700_000_000, &wallet, &mint).unwrap();
+        assert_eq!(transfers.len(), 1);
+        let transfer = &transfers[0];
+        assert_eq!(transfer.direction, Direction::Received);
+        assert_eq!(transfer.amount_raw, 1_500_000);
+        assert_eq!(transfer.source, OTHER_TOKEN_ACCOUNT);
+        assert_eq!(transfer.destination, WALLET_TOKEN_ACCOUNT);
+        // The wallet paid, but only outgoing records carry the fee.
+        assert_eq!(transfer.fee_payer.as_deref(), Some(WALLET));
+        assert_eq!(transfer.network_fee, None);
+        assert_eq!(
+            transaction_fee(&tx, &wallet),
+            (Some(WALLET.to_string()), Some(5000))
+        );
+        let relayed = WalletContext::new(&Pubkey::new_unique(), &mint, &TokenProgram::ALL);
+        assert_eq!(transaction_fee(&tx, &relayed).1, None);
+
+        let owners = token_account_owners(&tx);
+        assert!(find_discrepancy("sig", 42, &transfers, &transfers, &wallet, &owners, 6).is_none());
+
+        // The transaction has no instructions, so the instruction strategy
+        // finds nothing and the whole change is unexplained.
+        let discrepancy =
+            find_discrepancy("sig", 42, &[], &transfers, &wallet, &owners, 6).unwrap();
+        assert_eq!(discrepancy.accounts.len(), 1);
+        let account = &discrepancy.accounts[0];
+        assert_eq!(account.token_account, WALLET_TOKEN_ACCOUNT);
+        assert_eq!(account.instructions, "0.000000");
+        assert_eq!(account.balances, "1.500000");
+    }
+}
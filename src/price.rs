@@ -0,0 +1,293 @@
+//! USD prices at transfer time, for `price_usd` and `value_usd`. Prices
+//! come from CoinGecko's per-contract market chart or Pyth Benchmarks'
+//! hourly candles and are cached per (mint, hour): a historical hour
+//! doesn't change, so entries are kept until the cache fills. A source
+//! that can't be reached leaves the fields `null`; it never fails the
+//! request.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::Config;
+use crate::metrics::METRICS;
+use crate::model::Transfer;
+
+const HOUR: i64 = 3600;
+/// Longest span fetched in one request; CoinGecko serves hourly points up
+/// to 90 days and coarser ones beyond.
+const MAX_SPAN: i64 = 30 * 24 * HOUR;
+/// A point further than this from the hour isn't its price.
+const MAX_DISTANCE: i64 = HOUR;
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceProvider {
+    CoinGecko,
+    Pyth,
+}
+
+impl PriceProvider {
+    pub fn name(self) -> &'static str {
+        match self {
+            PriceProvider::CoinGecko => "coingecko",
+            PriceProvider::Pyth => "pyth",
+        }
+    }
+
+    pub fn default_url(self) -> &'static str {
+        match self {
+            PriceProvider::CoinGecko => "https://api.coingecko.com/api/v3",
+            PriceProvider::Pyth => "https://benchmarks.pyth.network",
+        }
+    }
+}
+
+impl FromStr for PriceProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "coingecko" => Ok(PriceProvider::CoinGecko),
+            "pyth" => Ok(PriceProvider::Pyth),
+            other => Err(format!(
+                "unknown price source '{}', expected coingecko or pyth",
+                other
+            )),
+        }
+    }
+}
+
+/// Where prices are looked up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriceSource {
+    pub provider: PriceProvider,
+    /// API base URL, the provider's public one unless overridden.
+    pub api_url: String,
+    /// CoinGecko API key, sent as the pro or demo key header depending on
+    /// the host.
+    pub api_key: Option<String>,
+}
+
+/// (mint, start of hour) → USD price.
+static PRICES: LazyLock<Mutex<HashMap<(String, i64), f64>>> = LazyLock::new(Default::default);
+const PRICES_CAPACITY: usize = 100_000;
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(LOOKUP_TIMEOUT)
+        .build()
+        .unwrap_or_default()
+});
+
+fn hour_of(block_time: i64) -> i64 {
+    block_time - block_time.rem_euclid(HOUR)
+}
+
+/// Sets `price_usd` and `value_usd` on each of `transfers` when a price
+/// source is configured, fetching the hours not cached yet.
+pub async fn enrich(config: &Config, transfers: &mut [Transfer]) {
+    let Some(source) = &config.price_source else {
+        return;
+    };
+    let mut missing: HashMap<(String, String), BTreeSet<i64>> = HashMap::new();
+    {
+        let prices = PRICES.lock().unwrap();
+        for transfer in transfers.iter() {
+            let hour = hour_of(transfer.block_time);
+            if !prices.contains_key(&(transfer.mint.clone(), hour)) {
+                missing
+                    .entry((transfer.mint.clone(), transfer.symbol.clone()))
+                    .or_default()
+                    .insert(hour);
+            }
+        }
+    }
+    for ((mint, symbol), hours) in missing {
+        for span in spans(&hours) {
+            let (from, to) = (span[0], span[span.len() - 1] + HOUR);
+            let points = match history(source, &mint, &symbol, from, to).await {
+                Ok(points) => points,
+                Err(e) => {
+                    warn!(source = source.provider.name(), %mint, error = %e, "price lookup failed");
+                    count(source, "failed", span.len());
+                    continue;
+                }
+            };
+            let mut prices = PRICES.lock().unwrap();
+            if prices.len() >= PRICES_CAPACITY {
+                prices.clear();
+            }
+            let mut found = 0;
+            for &hour in &span {
+                if let Some(price) = price_at(&points, hour) {
+                    prices.insert((mint.clone(), hour), price);
+                    found += 1;
+                }
+            }
+            count(source, "ok", found);
+            count(source, "missing", span.len() - found);
+        }
+    }
+    let prices = PRICES.lock().unwrap();
+    for transfer in transfers {
+        let price = prices.get(&(transfer.mint.clone(), hour_of(transfer.block_time)));
+        apply(transfer, price.copied());
+    }
+}
+
+fn count(source: &PriceSource, outcome: &str, hours: usize) {
+    METRICS
+        .price_lookups
+        .with_label_values(&[source.provider.name(), outcome])
+        .inc_by(hours as u64);
+}
+
+fn apply(transfer: &mut Transfer, price: Option<f64>) {
+    transfer.price_usd = price.map(|price| price.to_string());
+    transfer.value_usd = price.and_then(|price| {
+        let amount: f64 = transfer.amount_ui.parse().ok()?;
+        Some(format!("{:.6}", amount * price))
+    });
+}
+
+/// `hours`, in order, cut into runs no longer than [`MAX_SPAN`].
+fn spans(hours: &BTreeSet<i64>) -> Vec<Vec<i64>> {
+    let mut spans: Vec<Vec<i64>> = Vec::new();
+    for &hour in hours {
+        match spans.last_mut() {
+            Some(span) if hour - span[0] < MAX_SPAN => span.push(hour),
+            _ => spans.push(vec![hour]),
+        }
+    }
+    spans
+}
+
+/// The point closest to `hour`, if one is within [`MAX_DISTANCE`].
+fn price_at(points: &[(i64, f64)], hour: i64) -> Option<f64> {
+    points
+        .iter()
+        .filter(|(time, _)| (time - hour).abs() <= MAX_DISTANCE)
+        .min_by_key(|(time, _)| (time - hour).abs())
+        .map(|&(_, price)| price)
+}
+
+/// (unix time, USD price) points for `mint` between `from` and `to`.
+async fn history(
+    source: &PriceSource,
+    mint: &str,
+    symbol: &str,
+    from: i64,
+    to: i64,
+) -> Result<Vec<(i64, f64)>> {
+    let base = source.api_url.trim_end_matches('/');
+    let request = match source.provider {
+        PriceProvider::CoinGecko => {
+            let url = format!("{}/coins/solana/contract/{}/market_chart/range", base, mint);
+            let request = CLIENT.get(url).query(&[
+                ("vs_currency", "usd".to_string()),
+                ("from", from.to_string()),
+                ("to", to.to_string()),
+            ]);
+            match &source.api_key {
+                Some(key) if base.contains("pro-api.") => request.header("x-cg-pro-api-key", key),
+                Some(key) => request.header("x-cg-demo-api-key", key),
+                None => request,
+            }
+        }
+        PriceProvider::Pyth => CLIENT
+            .get(format!("{}/v1/shims/tradingview/history", base))
+            .query(&[
+                ("symbol", format!("Crypto.{}/USD", symbol.to_uppercase())),
+                ("resolution", "60".to_string()),
+                ("from", from.to_string()),
+                ("to", to.to_string()),
+            ]),
+    };
+    let body: Value = request.send().await?.error_for_status()?.json().await?;
+    match source.provider {
+        PriceProvider::CoinGecko => coingecko_points(&body),
+        PriceProvider::Pyth => pyth_points(&body),
+    }
+}
+
+/// `{"prices": [[unix_ms, price], ...]}`.
+fn coingecko_points(body: &Value) -> Result<Vec<(i64, f64)>> {
+    let prices = body["prices"]
+        .as_array()
+        .context("response has no prices")?;
+    prices
+        .iter()
+        .map(|point| {
+            let time = point[0].as_i64().context("point without a time")?;
+            let price = point[1].as_f64().context("point without a price")?;
+            Ok((time / 1000, price))
+        })
+        .collect()
+}
+
+/// `{"s": "ok", "t": [unix, ...], "c": [close, ...]}`, or `"s": "no_data"`.
+fn pyth_points(body: &Value) -> Result<Vec<(i64, f64)>> {
+    match body["s"].as_str() {
+        Some("ok") => {}
+        Some("no_data") => return Ok(Vec::new()),
+        other => anyhow::bail!("candle status {:?}", other),
+    }
+    let times = body["t"].as_array().context("response has no times")?;
+    let closes = body["c"].as_array().context("response has no closes")?;
+    times
+        .iter()
+        .zip(closes)
+        .map(|(time, close)| {
+            let time = time.as_i64().context("candle without a time")?;
+            let close = close.as_f64().context("candle without a close")?;
+            Ok((time, close))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::transfer;
+    use crate::model::Direction;
+    use serde_json::json;
+
+    #[test]
+    fn reads_both_providers_and_picks_the_hours_point() {
+        let body =
+            json!({ "prices": [[1_700_000_000_000i64, 1.0001], [1_700_003_600_000i64, 0.9998]] });
+        let points = coingecko_points(&body).unwrap();
+        assert_eq!(
+            points,
+            vec![(1_700_000_000, 1.0001), (1_700_003_600, 0.9998)]
+        );
+        assert_eq!(price_at(&points, 1_700_003_000), Some(0.9998));
+        assert_eq!(price_at(&points, 1_700_100_000), None);
+
+        let body = json!({ "s": "ok", "t": [1_700_000_000i64], "c": [142.5] });
+        assert_eq!(pyth_points(&body).unwrap(), vec![(1_700_000_000, 142.5)]);
+        assert!(pyth_points(&json!({ "s": "no_data" })).unwrap().is_empty());
+        assert!(pyth_points(&json!({ "s": "error", "errmsg": "bad symbol" })).is_err());
+    }
+
+    #[test]
+    fn values_the_amount_and_splits_long_ranges() {
+        let mut priced = transfer(Direction::Received, 2_500_000, "alice");
+        apply(&mut priced, Some(0.5));
+        assert_eq!(priced.price_usd.as_deref(), Some("0.5"));
+        assert_eq!(priced.value_usd.as_deref(), Some("1.250000"));
+        apply(&mut priced, None);
+        assert_eq!((priced.price_usd, priced.value_usd), (None, None));
+
+        let hours: BTreeSet<i64> = [0, HOUR, MAX_SPAN, MAX_SPAN + HOUR].into();
+        assert_eq!(
+            spans(&hours),
+            vec![vec![0, HOUR], vec![MAX_SPAN, MAX_SPAN + HOUR]]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! How far each wallet's index has got in this process, for `/status` and
+//! the lag gauges: per mint the chain tip at the last sync and the newest
+//! transaction indexed, per wallet the last successful tick of the poller
+//! and the live subscription. Nothing here is persisted, so after a restart
+//! these stay empty until the first sync.
+
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use utoipa::ToSchema;
+
+use crate::metrics::METRICS;
+
+/// One wallet/mint's last sync.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MintProgress {
+    /// Chain slot at the start of the last successful sync.
+    pub synced_slot: u64,
+    /// Slot and block time of the newest transaction indexed so far.
+    pub newest: Option<(u64, i64)>,
+}
+
+/// The background loops of one wallet.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct LoopHealth {
+    /// Unix time the poller last finished a tick with every mint synced.
+    pub last_poll: Option<i64>,
+    /// Whether the websocket subscription is up right now.
+    pub live_connected: bool,
+    /// Unix time the live indexer last synced every mint.
+    pub last_live_sync: Option<i64>,
+}
+
+static MINTS: LazyLock<Mutex<HashMap<(String, String), MintProgress>>> =
+    LazyLock::new(Default::default);
+static LOOPS: LazyLock<Mutex<HashMap<String, LoopHealth>>> = LazyLock::new(Default::default);
+
+/// Records a successful sync of `wallet`/`mint` that started at `tip`.
+/// `newest` is what the sync found; `None` keeps the previous newest.
+pub fn record_sync(wallet: &str, mint: &str, tip: u64, newest: Option<(u64, i64)>) {
+    let mut mints = MINTS.lock().unwrap();
+    let progress = mints
+        .entry((wallet.to_string(), mint.to_string()))
+        .or_default();
+    progress.synced_slot = tip;
+    if newest.is_some() {
+        progress.newest = newest;
+    }
+}
+
+pub fn mint(wallet: &str, mint: &str) -> Option<MintProgress> {
+    MINTS
+        .lock()
+        .unwrap()
+        .get(&(wallet.to_string(), mint.to_string()))
+        .copied()
+}
+
+pub fn loops(wallet: &str) -> LoopHealth {
+    LOOPS
+        .lock()
+        .unwrap()
+        .get(wallet)
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn record_poll(wallet: &str) {
+    let now = Utc::now().timestamp();
+    LOOPS
+        .lock()
+        .unwrap()
+        .entry(wallet.to_string())
+        .or_default()
+        .last_poll = Some(now);
+    METRICS
+        .loop_last_tick
+        .with_label_values(&[wallet, "poll"])
+        .set(now);
+}
+
+pub fn record_live_sync(wallet: &str) {
+    let now = Utc::now().timestamp();
+    LOOPS
+        .lock()
+        .unwrap()
+        .entry(wallet.to_string())
+        .or_default()
+        .last_live_sync = Some(now);
+    METRICS
+        .loop_last_tick
+        .with_label_values(&[wallet, "live"])
+        .set(now);
+}
+
+pub fn set_live_connected(wallet: &str, connected: bool) {
+    LOOPS
+        .lock()
+        .unwrap()
+        .entry(wallet.to_string())
+        .or_default()
+        .live_connected = connected;
+    METRICS
+        .live_connected
+        .with_label_values(&[wallet])
+        .set(connected as i64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_transaction_across_empty_syncs() {
+        record_sync("progress-wallet", "mint", 100, Some((90, 1_700_000_000)));
+        record_sync("progress-wallet", "mint", 120, None);
+        assert_eq!(
+            mint("progress-wallet", "mint"),
+            Some(MintProgress {
+                synced_slot: 120,
+                newest: Some((90, 1_700_000_000)),
+            })
+        );
+        assert_eq!(mint("progress-wallet", "other"), None);
+
+        set_live_connected("progress-wallet", true);
+        record_poll("progress-wallet");
+        let health = loops("progress-wallet");
+        assert!(health.live_connected && health.last_poll.is_some());
+        assert_eq!(health.last_live_sync, None);
+    }
+}
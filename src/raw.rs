@@ -0,0 +1,74 @@
+//! Raw `getTransaction` responses, kept gzip-compressed in the store when
+//! `store_raw_transactions` is on. They answer `/tx/{signature}/raw` when the
+//! parser's reading of a transaction is in doubt, and let a reparse
+//! re-extract transfers after a parser fix without going back to the RPC.
+
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+use std::io::Read;
+
+/// One stored transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawTransaction {
+    pub signature: String,
+    /// Decides which transactions are dropped first once the store holds
+    /// more than `raw_transaction_limit`.
+    pub slot: u64,
+    /// The gzip-compressed JSON of the response.
+    pub data: Vec<u8>,
+}
+
+impl RawTransaction {
+    pub fn encode(signature: &str, tx: &EncodedConfirmedTransactionWithStatusMeta) -> Result<Self> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        serde_json::to_writer(&mut encoder, tx)?;
+        Ok(RawTransaction {
+            signature: signature.to_string(),
+            slot: tx.slot,
+            data: encoder.finish()?,
+        })
+    }
+
+    /// The response as it came from the RPC.
+    pub fn json(&self) -> Result<Vec<u8>> {
+        let mut json = Vec::new();
+        GzDecoder::new(self.data.as_slice()).read_to_end(&mut json)?;
+        Ok(json)
+    }
+
+    pub fn decode(&self) -> Result<EncodedConfirmedTransactionWithStatusMeta> {
+        Ok(serde_json::from_slice(&self.json()?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trips_a_transaction() {
+        let tx: EncodedConfirmedTransactionWithStatusMeta = serde_json::from_value(json!({
+            "slot": 42,
+            "blockTime": 1_700_000_000,
+            "meta": null,
+            "transaction": {
+                "signatures": ["sig"],
+                "message": {
+                    "accountKeys": [],
+                    "recentBlockhash": "11111111111111111111111111111111",
+                    "instructions": [],
+                },
+            },
+        }))
+        .unwrap();
+        let raw = RawTransaction::encode("sig", &tx).unwrap();
+        assert_eq!(raw.slot, 42);
+        let json: serde_json::Value = serde_json::from_slice(&raw.json().unwrap()).unwrap();
+        assert_eq!(json["blockTime"], 1_700_000_000);
+        assert_eq!(raw.decode().unwrap().block_time, Some(1_700_000_000));
+    }
+}
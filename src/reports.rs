@@ -0,0 +1,505 @@
+//! Daily statements from the `[[reports]]` tables. Each time a report's cron
+//! schedule comes round, the previous UTC day is summarized per wallet and
+//! delivered with its transfers as CSV: posted to a webhook, written to a
+//! directory or the S3 bucket, or attached to a Telegram or Discord
+//! message. A day's
+//! statement always has the same id, which names the files and goes out as
+//! the webhook's `Idempotency-Key`, so running it again overwrites it; a
+//! failed delivery is retried on every scheduler tick, and alerted on once
+//! it has failed [`FAILURES_BEFORE_ALERT`] times.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, Utc};
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::alerts::{self, Alert};
+use crate::config::{Config, MintInfo};
+use crate::metrics::METRICS;
+use crate::model::{Direction, TimeWindow, Transfer};
+use crate::output::transfers_to_csv;
+use crate::rpc::SolanaRpc;
+use crate::server::{backfill_for_query, BackfillQuery};
+use crate::stats::Summary;
+use crate::store::Storage;
+use crate::watchlist::tracked_wallets;
+use crate::webhook::{self, WebhookTarget};
+use crate::{discord, s3, telegram};
+
+/// How often due and failed reports are looked for.
+const TICK: Duration = Duration::from_secs(30);
+/// Files can take longer than an alert to upload.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(60);
+/// Failed deliveries of one statement before an alert goes out.
+pub const FAILURES_BEFORE_ALERT: u32 = 3;
+
+/// One `[[reports]]` entry.
+#[derive(Debug, Clone)]
+pub struct ReportSchedule {
+    pub name: String,
+    /// When to run, in UTC.
+    pub schedule: cron::Schedule,
+    /// Every tracked wallet when `None`.
+    pub wallet: Option<Pubkey>,
+    pub mint: MintInfo,
+    pub delivery: ReportDelivery,
+}
+
+#[derive(Debug, Clone)]
+pub enum ReportDelivery {
+    /// A JSON body with the summary and the CSV.
+    Webhook(WebhookTarget),
+    /// `<id>.csv` and `<id>.json` files in the directory.
+    Directory(PathBuf),
+    /// A document sent to the `[telegram]` chat.
+    Telegram,
+    /// A file posted to this Discord channel webhook.
+    Discord { url: String },
+    /// CSV and JSON objects in the `[s3]` bucket, under
+    /// `reports/<name>/` in the [`s3::object_key`] layout.
+    S3,
+}
+
+impl ReportDelivery {
+    fn channel(&self) -> &'static str {
+        match self {
+            ReportDelivery::Webhook(_) => "webhook",
+            ReportDelivery::Directory(_) => "directory",
+            ReportDelivery::Telegram => "telegram",
+            ReportDelivery::Discord { .. } => "discord",
+            ReportDelivery::S3 => "s3",
+        }
+    }
+}
+
+/// The UTC days whose reports are due: the day before each time `schedule`
+/// came round after `since`, up to and including `now`.
+pub fn due_days(
+    schedule: &cron::Schedule,
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Vec<NaiveDate> {
+    let mut days: Vec<NaiveDate> = schedule
+        .after(&since)
+        .take_while(|fire| *fire <= now)
+        .map(|fire| (fire - ChronoDuration::days(1)).date_naive())
+        .collect();
+    days.dedup();
+    days
+}
+
+/// Inclusive block time bounds of a UTC day.
+pub fn day_window(day: NaiveDate) -> TimeWindow {
+    let start = day.and_hms_opt(0, 0, 0).expect("midnight exists").and_utc();
+    TimeWindow {
+        start: start.timestamp(),
+        end: start.timestamp() + 24 * 3600 - 1,
+    }
+}
+
+/// One wallet's report for one day.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub report: String,
+    pub wallet: Pubkey,
+    pub mint: MintInfo,
+    pub day: NaiveDate,
+    pub summary: Summary,
+    /// The day's transfers, internal ones left out.
+    pub csv: String,
+}
+
+impl Statement {
+    pub fn new(
+        report: &str,
+        wallet: Pubkey,
+        mint: &MintInfo,
+        day: NaiveDate,
+        transfers: &[Transfer],
+    ) -> Result<Self> {
+        let external: Vec<Transfer> = transfers
+            .iter()
+            .filter(|t| t.direction != Direction::Internal)
+            .cloned()
+            .collect();
+        Ok(Statement {
+            report: report.to_string(),
+            wallet,
+            mint: mint.clone(),
+            day,
+            summary: Summary::from_transfers(transfers, mint.decimals),
+            csv: transfers_to_csv(&external)?,
+        })
+    }
+
+    /// The same for every run of this report, wallet, mint and day.
+    pub fn id(&self) -> String {
+        format!(
+            "{}-{}-{}-{}",
+            self.report, self.wallet, self.mint.symbol, self.day
+        )
+    }
+
+    /// Everything but the CSV.
+    fn summary_json(&self) -> Value {
+        json!({
+            "type": "report",
+            "id": self.id(),
+            "report": self.report,
+            "wallet": self.wallet.to_string(),
+            "mint": self.mint.mint.to_string(),
+            "symbol": self.mint.symbol,
+            "day": self.day.to_string(),
+            "window": day_window(self.day),
+            "summary": self.summary,
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        let mut body = self.summary_json();
+        body["csv"] = Value::String(self.csv.clone());
+        body
+    }
+
+    fn to_text(&self) -> String {
+        let counts = &self.summary.counts;
+        format!(
+            "Report: {} for {}\nReceived {} {}, sent {} {}, net {}\n{} transfers\nWallet: {}",
+            self.report,
+            self.day,
+            self.summary.total_received,
+            self.mint.symbol,
+            self.summary.total_sent,
+            self.mint.symbol,
+            self.summary.net_flow,
+            counts.received + counts.sent + counts.minted + counts.burned,
+            self.wallet
+        )
+    }
+}
+
+/// Starts the scheduler if any report is configured. It stops at
+/// `shutdown`.
+pub fn spawn(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    shutdown: watch::Receiver<bool>,
+) -> Option<JoinHandle<()>> {
+    if config.reports.is_empty() {
+        return None;
+    }
+    Some(tokio::spawn(run(client, config, store, shutdown)))
+}
+
+async fn run(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let http = match reqwest::Client::builder().timeout(DELIVERY_TIMEOUT).build() {
+        Ok(http) => http,
+        Err(e) => {
+            error!(error = %e, "cannot build report client, reports disabled");
+            return;
+        }
+    };
+    // Statements still to deliver, by report index, wallet and day, with
+    // how often delivering them has failed.
+    let mut pending: BTreeMap<(usize, Pubkey, NaiveDate), u32> = BTreeMap::new();
+    let mut since = Utc::now();
+    let mut tick = tokio::time::interval(TICK);
+    info!(reports = config.reports.len(), "report scheduler started");
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {}
+            _ = shutdown.changed() => return,
+        }
+        let now = Utc::now();
+        for (index, report) in config.reports.iter().enumerate() {
+            let days = due_days(&report.schedule, since, now);
+            if days.is_empty() {
+                continue;
+            }
+            let wallets = match report.wallet {
+                Some(wallet) => vec![wallet],
+                None => match tracked_wallets(&config, &store).await {
+                    Ok(tracked) => tracked.into_iter().map(|t| t.wallet).collect(),
+                    Err(e) => {
+                        warn!(report = %report.name, error = %e, "cannot list tracked wallets, reporting on the configured ones");
+                        config.wallets.clone()
+                    }
+                },
+            };
+            for day in days {
+                for &wallet in &wallets {
+                    pending.entry((index, wallet, day)).or_insert(0);
+                }
+            }
+        }
+        since = now;
+
+        let keys: Vec<_> = pending.keys().copied().collect();
+        for key in keys {
+            let (index, wallet, day) = key;
+            let report = &config.reports[index];
+            let outcome = tokio::select! {
+                outcome = run_report(&http, client.as_ref(), &config, &store, report, wallet, day) => outcome,
+                _ = shutdown.changed() => return,
+            };
+            match outcome {
+                Ok(id) => {
+                    info!(report = %report.name, %id, channel = report.delivery.channel(), "report delivered");
+                    pending.remove(&key);
+                    record(&report.name, "delivered");
+                }
+                Err(e) => {
+                    warn!(report = %report.name, %wallet, %day, error = %e, "report failed, retrying on the next tick");
+                    record(&report.name, "failed");
+                    let failures = pending.get_mut(&key).expect("key is pending");
+                    *failures += 1;
+                    if *failures == FAILURES_BEFORE_ALERT {
+                        let alert = failure_alert(report, wallet, day, *failures, &e);
+                        alerts::notify(&http, &config, &alert).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Builds and delivers one statement, returning its id.
+async fn run_report(
+    http: &reqwest::Client,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    report: &ReportSchedule,
+    wallet: Pubkey,
+    day: NaiveDate,
+) -> Result<String> {
+    let window = day_window(day);
+    let query = BackfillQuery {
+        wallet: Some(wallet.to_string()),
+        mint: Some(report.mint.mint.to_string()),
+        symbol: None,
+        hours: None,
+        start: Some(window.start),
+        end: Some(window.end),
+        format: None,
+        order: Some("asc".to_string()),
+        strategy: None,
+        include_failed: false,
+        include_orphaned: false,
+        include_mints: true,
+        // Internal volume is summarized on its own.
+        include_internal: true,
+        direction: None,
+        min_amount: None,
+        max_amount: None,
+        counterparty: None,
+        memo_contains: None,
+        limit: None,
+        cursor: None,
+        running_balance: false,
+        commitment: None,
+    };
+    let (_, response) = backfill_for_query(&query, client, config, store).await?;
+    if response.truncated {
+        anyhow::bail!("the day's history was truncated");
+    }
+    let statement = Statement::new(&report.name, wallet, &report.mint, day, &response.transfers)?;
+    deliver(http, config, &report.delivery, &statement).await?;
+    Ok(statement.id())
+}
+
+async fn deliver(
+    http: &reqwest::Client,
+    config: &Config,
+    delivery: &ReportDelivery,
+    statement: &Statement,
+) -> Result<()> {
+    let id = statement.id();
+    let file_name = format!("{}.csv", id);
+    match delivery {
+        ReportDelivery::Webhook(target) => {
+            webhook::post_report(http, target, &id, &statement.to_json()).await
+        }
+        ReportDelivery::Directory(directory) => write_files(directory, statement).await,
+        ReportDelivery::Telegram => {
+            let target = config
+                .telegram
+                .as_ref()
+                .context("no [telegram] chat is configured")?;
+            let caption = statement.to_text();
+            telegram::post_document(
+                http,
+                target,
+                &caption,
+                &file_name,
+                statement.csv.clone().into(),
+            )
+            .await
+        }
+        ReportDelivery::Discord { url } => {
+            let text = statement.to_text();
+            discord::post_file(http, url, &text, &file_name, statement.csv.clone().into()).await
+        }
+        ReportDelivery::S3 => {
+            let target = config.s3.as_ref().context("no [s3] bucket is configured")?;
+            let key = |extension| {
+                format!(
+                    "reports/{}/{}",
+                    statement.report,
+                    s3::object_key(
+                        &statement.wallet.to_string(),
+                        &statement.mint.symbol,
+                        day_window(statement.day),
+                        extension
+                    )
+                )
+            };
+            let summary = serde_json::to_vec_pretty(&statement.summary_json())?;
+            s3::put(target, &key("csv"), "text/csv", statement.csv.as_bytes()).await?;
+            s3::put(target, &key("json"), "application/json", &summary).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Writes `<id>.csv` and `<id>.json`, each through a temporary file
+/// renamed over the old one, so readers never see half a report.
+pub async fn write_files(directory: &Path, statement: &Statement) -> Result<()> {
+    tokio::fs::create_dir_all(directory)
+        .await
+        .with_context(|| format!("cannot create {}", directory.display()))?;
+    let id = statement.id();
+    let summary = serde_json::to_vec_pretty(&statement.summary_json())?;
+    for (extension, contents) in [("csv", statement.csv.as_bytes()), ("json", &summary[..])] {
+        let path = directory.join(format!("{}.{}", id, extension));
+        let partial = directory.join(format!(".{}.{}.partial", id, extension));
+        tokio::fs::write(&partial, contents)
+            .await
+            .with_context(|| format!("cannot write {}", partial.display()))?;
+        tokio::fs::rename(&partial, &path)
+            .await
+            .with_context(|| format!("cannot replace {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn failure_alert(
+    report: &ReportSchedule,
+    wallet: Pubkey,
+    day: NaiveDate,
+    failures: u32,
+    error: &anyhow::Error,
+) -> Alert {
+    Alert {
+        rule: report.name.clone(),
+        kind: "report",
+        wallet,
+        mint: report.mint.mint.to_string(),
+        symbol: report.mint.symbol.clone(),
+        message: format!(
+            "Report for {} failed {} times via {}: {:#}",
+            day,
+            failures,
+            report.delivery.channel(),
+            error
+        ),
+        signature: None,
+    }
+}
+
+fn record(report: &str, outcome: &str) {
+    METRICS
+        .report_deliveries
+        .with_label_values(&[report, outcome])
+        .inc();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MintRegistry, DEFAULT_MINTS};
+    use crate::model::fixtures::transfer;
+    use chrono::TimeZone;
+    use std::str::FromStr;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn reports_on_the_day_before_each_run() {
+        let schedule = cron::Schedule::from_str("0 5 0 * * *").unwrap();
+        assert!(due_days(&schedule, at(1, 0, 6), at(2, 0, 4)).is_empty());
+        let day = |d| NaiveDate::from_ymd_opt(2024, 3, d).unwrap();
+        assert_eq!(due_days(&schedule, at(1, 0, 6), at(2, 0, 5)), [day(1)]);
+        // A scheduler that was held up catches up on every missed run.
+        assert_eq!(
+            due_days(&schedule, at(1, 0, 4), at(3, 12, 0)),
+            [
+                NaiveDate::from_ymd_opt(2024, 2, 29).unwrap(),
+                day(1),
+                day(2)
+            ]
+        );
+        assert_eq!(
+            day_window(day(1)),
+            TimeWindow {
+                start: at(1, 0, 0).timestamp(),
+                end: at(2, 0, 0).timestamp() - 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rewrites_the_same_files_for_a_day() {
+        let usdc = MintRegistry::parse(DEFAULT_MINTS).unwrap().mints[0].clone();
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let transfers = [
+            transfer(Direction::Received, 2_500_000, "alice"),
+            transfer(Direction::Internal, 7_000_000, "wallet-ata-2"),
+        ];
+        let statement = Statement::new("daily", Pubkey::default(), &usdc, day, &transfers).unwrap();
+        assert_eq!(
+            statement.id(),
+            format!("daily-{}-USDC-2024-03-01", Pubkey::default())
+        );
+        assert_eq!(statement.summary.total_received, "2.500000");
+        assert_eq!(statement.csv.lines().count(), 2);
+
+        let directory = std::env::temp_dir().join(format!("reports-{}", std::process::id()));
+        write_files(&directory, &statement).await.unwrap();
+        write_files(&directory, &statement).await.unwrap();
+        let mut names: Vec<String> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                format!("{}.csv", statement.id()),
+                format!("{}.json", statement.id())
+            ]
+        );
+        let summary: Value = serde_json::from_slice(
+            &std::fs::read(directory.join(format!("{}.json", statement.id()))).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(summary["summary"]["internal_volume"], "7.000000");
+        assert!(summary.get("csv").is_none());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! Request ids and the access log. Every request gets an id, the client's
+//! `X-Request-Id` when it sent a usable one: the request's span carries it
+//! into each log line written on its behalf, and it comes back in the
+//! `X-Request-Id` response header, in error bodies and in the access-log
+//! line, so a user quoting it leads to the request's whole trail.
+
+use rand::RngCore;
+use std::convert::Infallible;
+use std::time::Instant;
+use tracing::{field, info, info_span, Span};
+use warp::http::{HeaderValue, Method};
+use warp::hyper::body::HttpBody;
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::Filter;
+
+pub const HEADER: &str = "x-request-id";
+
+/// Longest incoming id that is kept.
+const MAX_LEN: usize = 128;
+
+/// `incoming` when it is usable as an id, else a fresh one. Ids end up in
+/// headers and logs verbatim, so only short, printable ASCII is kept.
+pub fn resolve(incoming: Option<&str>) -> String {
+    match incoming {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            id.to_string()
+        }
+        _ => {
+            let mut bytes = [0u8; 16];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            hex::encode(bytes)
+        }
+    }
+}
+
+/// The span a request runs in, for `warp::trace`. [`filter`] fills in
+/// `request_id` once it has picked one.
+pub fn span(info: warp::trace::Info<'_>) -> Span {
+    info_span!(
+        "request",
+        method = %info.method(),
+        path = info.path(),
+        request_id = field::Empty,
+    )
+}
+
+/// What the access log needs to know about a request.
+#[derive(Debug, Clone)]
+pub struct RequestLog {
+    pub id: String,
+    method: Method,
+    path: String,
+    started: Instant,
+}
+
+/// Picks the request's id and records it on the current span.
+pub fn filter() -> impl Filter<Extract = (RequestLog,), Error = Infallible> + Clone {
+    warp::header::headers_cloned()
+        .and(warp::method())
+        .and(warp::path::full())
+        .map(
+            |headers: warp::http::HeaderMap, method: Method, path: FullPath| {
+                let incoming = headers.get(HEADER).and_then(|value| value.to_str().ok());
+                let id = resolve(incoming);
+                Span::current().record("request_id", id.as_str());
+                RequestLog {
+                    id,
+                    method,
+                    path: path.as_str().to_string(),
+                    started: Instant::now(),
+                }
+            },
+        )
+}
+
+impl RequestLog {
+    /// Adds the id to `response`'s headers and writes the access-log line.
+    /// Streamed bodies are logged when their head is sent, without a size.
+    pub fn finish(self, mut response: Response) -> Response {
+        if let Ok(value) = HeaderValue::from_str(&self.id) {
+            response.headers_mut().insert(HEADER, value);
+        }
+        info!(
+            target: "access",
+            method = %self.method,
+            path = %self.path,
+            status = response.status().as_u16(),
+            latency_ms = self.started.elapsed().as_secs_f64() * 1000.0,
+            bytes = response.body().size_hint().exact(),
+            request_id = %self.id,
+            "request"
+        );
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_usable_incoming_ids() {
+        assert_eq!(resolve(Some("req-42.a:b")), "req-42.a:b");
+    }
+
+    #[test]
+    fn replaces_missing_or_unusable_ids() {
+        for incoming in [
+            None,
+            Some(""),
+            Some("has space"),
+            Some(&*"x".repeat(MAX_LEN + 1)),
+        ] {
+            let id = resolve(incoming);
+            assert_eq!(id.len(), 32, "{:?}", incoming);
+            assert!(id.bytes().all(|b| b.is_ascii_hexdigit()));
+        }
+        assert_ne!(resolve(None), resolve(None));
+    }
+}
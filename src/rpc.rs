@@ -0,0 +1,421 @@
+//! The RPC surface the indexer depends on, and the retry policy around it.
+
+use async_trait::async_trait;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_client::rpc_custom_error::{
+    JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+};
+use solana_client::rpc_request::RpcError;
+use solana_client::rpc_request::TokenAccountsFilter;
+use solana_client::rpc_response::{
+    Response as RpcResponse, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount,
+};
+use solana_sdk::account::Account;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, TransactionStatus};
+use std::future::Future;
+use std::time::Duration;
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
+
+use crate::metrics::METRICS;
+
+pub const RETRY_BASE_DELAY: Duration = Duration::from_millis(250);
+pub const RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// The JSON-RPC calls the indexer makes. Implemented by the nonblocking
+/// [`RpcClient`]; tests substitute canned responses.
+#[async_trait]
+pub trait SolanaRpc: Send + Sync {
+    async fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, ClientError>;
+
+    async fn get_transaction(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError>;
+
+    async fn get_account(
+        &self,
+        address: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<Account>, ClientError>;
+
+    async fn get_slot(&self) -> Result<u64, ClientError>;
+
+    /// Latest slot the cluster has finalized.
+    async fn get_finalized_slot(&self) -> Result<u64, ClientError>;
+
+    /// Estimated production time of `slot`, in Unix seconds.
+    async fn get_block_time(&self, slot: u64) -> Result<i64, ClientError>;
+
+    /// Token accounts owned by `owner` that hold `mint`, `jsonParsed`.
+    async fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<RpcResponse<Vec<RpcKeyedAccount>>, ClientError>;
+
+    /// Statuses of `signatures`, searching the node's full history; `None`
+    /// for a signature the cluster doesn't know.
+    async fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> Result<Vec<Option<TransactionStatus>>, ClientError>;
+}
+
+#[async_trait]
+impl SolanaRpc for RpcClient {
+    async fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, ClientError> {
+        self.get_signatures_for_address_with_config(address, config)
+            .await
+    }
+
+    async fn get_transaction(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+        self.get_transaction_with_config(signature, config).await
+    }
+
+    async fn get_account(
+        &self,
+        address: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<Account>, ClientError> {
+        Ok(self
+            .get_account_with_commitment(address, commitment)
+            .await?
+            .value)
+    }
+
+    async fn get_slot(&self) -> Result<u64, ClientError> {
+        RpcClient::get_slot(self).await
+    }
+
+    async fn get_finalized_slot(&self) -> Result<u64, ClientError> {
+        RpcClient::get_slot_with_commitment(self, CommitmentConfig::finalized()).await
+    }
+
+    async fn get_block_time(&self, slot: u64) -> Result<i64, ClientError> {
+        RpcClient::get_block_time(self, slot).await
+    }
+
+    async fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<RpcResponse<Vec<RpcKeyedAccount>>, ClientError> {
+        self.get_token_accounts_by_owner_with_commitment(
+            owner,
+            TokenAccountsFilter::Mint(*mint),
+            commitment,
+        )
+        .await
+    }
+
+    async fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> Result<Vec<Option<TransactionStatus>>, ClientError> {
+        Ok(self
+            .get_signature_statuses_with_history(signatures)
+            .await?
+            .value)
+    }
+}
+
+/// How an RPC failure should be handled by [`with_retry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcFailure {
+    /// HTTP 429 from the provider; back off harder than for other failures.
+    RateLimited,
+    /// Timeouts, connection resets, 5xx and unhealthy-node responses.
+    Transient,
+    /// Anything retrying won't fix (bad params, unknown signature, ...).
+    Permanent,
+}
+
+pub fn classify_rpc_error(err: &ClientError) -> RpcFailure {
+    match err.kind() {
+        ClientErrorKind::Reqwest(e) => match e.status() {
+            Some(status) if status.as_u16() == 429 => RpcFailure::RateLimited,
+            Some(status) if status.is_server_error() => RpcFailure::Transient,
+            Some(_) => RpcFailure::Permanent,
+            None if e.is_timeout() || e.is_connect() || e.is_request() => RpcFailure::Transient,
+            None => RpcFailure::Permanent,
+        },
+        ClientErrorKind::Io(_) => RpcFailure::Transient,
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, message, .. }) => {
+            if *code == 429 || message.contains("Too Many Requests") {
+                RpcFailure::RateLimited
+            } else if *code == JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY
+                || *code == JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE
+            {
+                RpcFailure::Transient
+            } else {
+                RpcFailure::Permanent
+            }
+        }
+        ClientErrorKind::RpcError(RpcError::RpcRequestError(message)) => {
+            if message.contains("429") || message.contains("Too Many Requests") {
+                RpcFailure::RateLimited
+            } else {
+                RpcFailure::Transient
+            }
+        }
+        _ => RpcFailure::Permanent,
+    }
+}
+
+/// Exponential backoff with "equal jitter": half the delay is fixed, the
+/// other half random, so concurrent fetches don't retry in lockstep.
+pub fn backoff_delay(attempt: u32, failure: RpcFailure) -> Duration {
+    let base = match failure {
+        RpcFailure::RateLimited => RETRY_BASE_DELAY * 4,
+        _ => RETRY_BASE_DELAY,
+    };
+    let delay = base
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(RETRY_MAX_DELAY);
+    let half = delay / 2;
+    half + half.mul_f64(rand::random::<f64>())
+}
+
+/// Runs `op` until it succeeds, fails permanently, or `max_attempts` is hit.
+/// The call, retries included, is one `rpc` span; the endpoint that
+/// answered is filled in by [`crate::failover`].
+pub async fn with_retry<T, F, Fut>(
+    method: &str,
+    max_attempts: u32,
+    mut op: F,
+) -> std::result::Result<T, ClientError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, ClientError>>,
+{
+    let span = info_span!(
+        "rpc",
+        otel.kind = "client",
+        otel.status_code = field::Empty,
+        method,
+        endpoint = field::Empty,
+        retries = field::Empty,
+    );
+    let call = async {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Ok(value) => {
+                    METRICS.rpc_calls.with_label_values(&[method, "ok"]).inc();
+                    Span::current().record("retries", attempt - 1);
+                    if attempt > 1 {
+                        info!(
+                            method,
+                            retries = attempt - 1,
+                            "RPC call succeeded after retrying"
+                        );
+                    }
+                    return Ok(value);
+                }
+                Err(err) => {
+                    let failure = classify_rpc_error(&err);
+                    let outcome = match failure {
+                        RpcFailure::RateLimited => "rate_limited",
+                        RpcFailure::Transient => "transient",
+                        RpcFailure::Permanent => "permanent",
+                    };
+                    METRICS
+                        .rpc_calls
+                        .with_label_values(&[method, outcome])
+                        .inc();
+                    if failure == RpcFailure::Permanent || attempt >= max_attempts {
+                        let span = Span::current();
+                        span.record("retries", attempt - 1);
+                        span.record("otel.status_code", "ERROR");
+                        if attempt == 1 {
+                            error!(method, error = %err, "RPC call failed");
+                        } else {
+                            error!(method, retries = attempt - 1, error = %err, "RPC call giving up");
+                        }
+                        return Err(err);
+                    }
+                    let delay = backoff_delay(attempt, failure);
+                    warn!(
+                        method,
+                        ?failure,
+                        attempt,
+                        max_attempts,
+                        ?delay,
+                        error = %err,
+                        "RPC call failed, retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    };
+    call.instrument(span).await
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use solana_client::client_error::ClientErrorKind;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Serves a fixed signature history (newest first) and transaction
+    /// fixtures, honouring `before`/`until`/`limit` like the real RPC.
+    #[derive(Default)]
+    pub struct MockRpc {
+        /// History served for any address without an entry in
+        /// `signatures_by_address`.
+        pub signatures: Vec<RpcConfirmedTransactionStatusWithSignature>,
+        pub signatures_by_address: HashMap<Pubkey, Vec<RpcConfirmedTransactionStatusWithSignature>>,
+        /// `getTransaction` results in their JSON wire form, by signature.
+        pub transactions: HashMap<String, serde_json::Value>,
+        /// Makes every `getSignaturesForAddress` call fail.
+        pub fail_signatures: bool,
+        /// Makes every `getSignaturesForAddress` call time out instead, a
+        /// failure worth retrying.
+        pub signatures_time_out: bool,
+        pub slot: u64,
+        /// `getTokenAccountsByOwner` result, reported at `slot`.
+        pub token_accounts: Vec<RpcKeyedAccount>,
+        /// `getAccountInfo` results; anything else doesn't exist.
+        pub accounts: HashMap<Pubkey, Account>,
+        pub accounts_requested: Mutex<Vec<Pubkey>>,
+        /// `before` cursor of each `getSignaturesForAddress` call, in order.
+        pub pages_requested: Mutex<Vec<Option<Signature>>>,
+        pub transactions_requested: Mutex<Vec<String>>,
+        /// `getSignatureStatuses` results; anything else is unknown.
+        pub signature_statuses: HashMap<String, TransactionStatus>,
+        /// `getBlockTime` results; any other slot has no time.
+        pub block_times: HashMap<u64, i64>,
+        pub block_times_requested: Mutex<Vec<u64>>,
+    }
+
+    fn error(message: &str) -> ClientError {
+        ClientErrorKind::Custom(message.to_string()).into()
+    }
+
+    #[async_trait]
+    impl SolanaRpc for MockRpc {
+        async fn get_signatures_for_address(
+            &self,
+            address: &Pubkey,
+            config: GetConfirmedSignaturesForAddress2Config,
+        ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, ClientError> {
+            self.pages_requested.lock().unwrap().push(config.before);
+            if self.fail_signatures {
+                return Err(error("getSignaturesForAddress failed"));
+            }
+            if self.signatures_time_out {
+                return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into());
+            }
+            let signatures = self
+                .signatures_by_address
+                .get(address)
+                .unwrap_or(&self.signatures);
+            let position = |signature: Option<Signature>| {
+                signature.and_then(|signature| {
+                    signatures
+                        .iter()
+                        .position(|s| s.signature == signature.to_string())
+                })
+            };
+            let from = position(config.before).map_or(0, |i| i + 1);
+            let to = position(config.until).unwrap_or(signatures.len());
+            let limit = config.limit.unwrap_or(1000);
+            Ok(signatures[from.min(to)..to]
+                .iter()
+                .take(limit)
+                .cloned()
+                .collect())
+        }
+
+        async fn get_transaction(
+            &self,
+            signature: &Signature,
+            _config: RpcTransactionConfig,
+        ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+            let signature = signature.to_string();
+            self.transactions_requested
+                .lock()
+                .unwrap()
+                .push(signature.clone());
+            let fixture = self
+                .transactions
+                .get(&signature)
+                .ok_or_else(|| error("transaction not found"))?;
+            Ok(serde_json::from_value(fixture.clone())?)
+        }
+
+        async fn get_account(
+            &self,
+            address: &Pubkey,
+            _commitment: CommitmentConfig,
+        ) -> Result<Option<Account>, ClientError> {
+            self.accounts_requested.lock().unwrap().push(*address);
+            Ok(self.accounts.get(address).cloned())
+        }
+
+        async fn get_slot(&self) -> Result<u64, ClientError> {
+            Ok(self.slot)
+        }
+
+        async fn get_finalized_slot(&self) -> Result<u64, ClientError> {
+            Ok(self.slot)
+        }
+
+        async fn get_block_time(&self, slot: u64) -> Result<i64, ClientError> {
+            self.block_times_requested.lock().unwrap().push(slot);
+            self.block_times
+                .get(&slot)
+                .copied()
+                .ok_or_else(|| error("block time not available"))
+        }
+
+        async fn get_token_accounts_by_owner(
+            &self,
+            _owner: &Pubkey,
+            _mint: &Pubkey,
+            _commitment: CommitmentConfig,
+        ) -> Result<RpcResponse<Vec<RpcKeyedAccount>>, ClientError> {
+            Ok(RpcResponse {
+                context: solana_client::rpc_response::RpcResponseContext {
+                    slot: self.slot,
+                    api_version: None,
+                },
+                value: self.token_accounts.clone(),
+            })
+        }
+
+        async fn get_signature_statuses(
+            &self,
+            signatures: &[Signature],
+        ) -> Result<Vec<Option<TransactionStatus>>, ClientError> {
+            Ok(signatures
+                .iter()
+                .map(|s| self.signature_statuses.get(&s.to_string()).cloned())
+                .collect())
+        }
+    }
+}
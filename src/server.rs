@@ -0,0 +1,3552 @@
+//! HTTP routes and handlers.
+
+use anyhow::Result;
+use chrono::{FixedOffset, Utc};
+use futures::stream::{self, StreamExt};
+use prometheus::TextEncoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn, Instrument};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::filters::BoxedFilter;
+use warp::http::{HeaderValue, Method, StatusCode};
+use warp::hyper::body::Bytes;
+use warp::reply::{Reply, Response};
+use warp::Filter;
+
+use crate::auth::ApiKeys;
+use crate::cache::ResponseCache;
+use crate::compression;
+use crate::config::{
+    commitment_name, parse_commitment, resolve_filter, Config, MintInfo, DEFAULT_PAGE_SIZE,
+    MAX_WINDOW_SECS,
+};
+use crate::deadline;
+use crate::diagnostics::{self, Diagnostics};
+use crate::error::{ErrorCode, IndexerError};
+use crate::etag;
+use crate::events;
+use crate::export::{ExportFormat, FileExport, ParquetExport, PARQUET_CONTENT_TYPE};
+use crate::graphql::{self, IndexerSchema};
+use crate::indexer::{
+    backfill_with_store, balance_at, fetch_balance, fetch_delegations, reindex, reparse,
+    transaction_effect, window_anchor,
+};
+use crate::jobs::{JobRegistry, JobReport, JobResult, ProgressSnapshot};
+use crate::limits::{BackfillPermits, RateLimiter};
+use crate::metrics::METRICS;
+use crate::model::{
+    apply_running_balance, open_token_accounts, parse_amount, sort_transfers, BackfillRequest,
+    Direction, OpenTokenAccount, PageCursor, SortOrder, Strategy, TimeWindow, TransferFilter,
+    WalletBalance,
+};
+use crate::openapi::{ApiDoc, SWAGGER_UI_HTML};
+use crate::output::{transfers_to_ndjson, BackfillResponse, OutputFormat};
+use crate::price;
+use crate::progress::{self, LoopHealth};
+use crate::request_id::{self, RequestLog};
+use crate::rpc::SolanaRpc;
+use crate::s3::{self, Upload};
+use crate::stats::{
+    aggregate, balance_series, parse_tz_offset, top_counterparties, BucketSize, Summary,
+};
+use crate::store::Storage;
+use crate::tls;
+use crate::watchlist::{tracked_wallets, WalletSource, Watchlist};
+use crate::ws;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BackfillQuery {
+    /// Wallet address, or `all` to merge every tracked wallet; defaults to
+    /// the configured wallet.
+    pub wallet: Option<String>,
+    /// Mint pubkey to index; alternatively pick one by `symbol`.
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+    /// Lookback from `end` (or now) in hours. Mutually exclusive with `start`.
+    pub hours: Option<i64>,
+    /// Inclusive window bounds as unix timestamps. Without `end` the window
+    /// ends at the block time of the latest finalized slot.
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    /// `json` (default), `csv`, `text` for the legacy pipe-delimited lines,
+    /// or `ndjson` to stream the whole window one transfer per line.
+    pub format: Option<String>,
+    /// `desc` (newest first, the default) or `asc`.
+    pub order: Option<String>,
+    /// `instructions` (default), `balances` or `both`.
+    pub strategy: Option<String>,
+    /// Also report transfers from transactions that landed with an error.
+    #[serde(default)]
+    pub include_failed: bool,
+    /// Also return stored records whose transaction was rolled back by a
+    /// fork or reverted after they were indexed.
+    #[serde(default)]
+    pub include_orphaned: bool,
+    /// `false` drops `minted`/`burned` records.
+    #[serde(default = "included_by_default")]
+    pub include_mints: bool,
+    /// Also return `internal` records, moves between the wallet's own token
+    /// accounts. Implied by `direction=internal`.
+    #[serde(default)]
+    pub include_internal: bool,
+    /// Only `sent`, `received`, `internal`, `minted` or `burned` records.
+    pub direction: Option<String>,
+    /// Inclusive amount bounds in UI units, e.g. `min_amount=100`.
+    pub min_amount: Option<String>,
+    pub max_amount: Option<String>,
+    /// Token account or owner wallet on the other side.
+    pub counterparty: Option<String>,
+    /// Only records whose memo contains this text, ignoring case.
+    pub memo_contains: Option<String>,
+    /// Page size, 100 by default and at most `max_page_size`.
+    pub limit: Option<usize>,
+    /// `next_cursor` of the previous page. It carries that page's window and
+    /// order, which take precedence over `hours`/`start`/`end`.
+    pub cursor: Option<String>,
+    /// Add each row's `balance_after`, reconstructed from the current
+    /// on-chain balance.
+    #[serde(default)]
+    pub running_balance: bool,
+    /// `processed`, `confirmed` or `finalized`; the configured commitment
+    /// by default.
+    pub commitment: Option<String>,
+}
+
+fn included_by_default() -> bool {
+    true
+}
+
+/// `/aggregate` parameters: the `/backfill` selection plus bucketing.
+/// (Serde's `flatten` doesn't work with numeric query values, hence the
+/// repeated fields.)
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AggregateQuery {
+    pub wallet: Option<String>,
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+    pub hours: Option<i64>,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    #[serde(default)]
+    pub include_failed: bool,
+    #[serde(default = "included_by_default")]
+    pub include_mints: bool,
+    /// `hour` or `day`.
+    pub bucket: String,
+    /// Offset buckets are aligned to, `±HH:MM` or minutes; UTC by default.
+    pub tz_offset: Option<String>,
+    pub commitment: Option<String>,
+}
+
+/// `/counterparties` parameters: the `/backfill` selection plus `limit`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CounterpartiesQuery {
+    pub wallet: Option<String>,
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+    pub hours: Option<i64>,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    #[serde(default)]
+    pub include_failed: bool,
+    #[serde(default = "included_by_default")]
+    pub include_mints: bool,
+    /// Number of entries to return, 20 by default.
+    pub limit: Option<usize>,
+    pub commitment: Option<String>,
+}
+
+impl CounterpartiesQuery {
+    fn backfill_query(&self) -> BackfillQuery {
+        BackfillQuery {
+            wallet: self.wallet.clone(),
+            mint: self.mint.clone(),
+            symbol: self.symbol.clone(),
+            hours: self.hours,
+            start: self.start,
+            end: self.end,
+            format: None,
+            order: None,
+            strategy: None,
+            include_failed: self.include_failed,
+            include_orphaned: false,
+            include_mints: self.include_mints,
+            include_internal: false,
+            direction: None,
+            min_amount: None,
+            max_amount: None,
+            counterparty: None,
+            memo_contains: None,
+            limit: None,
+            cursor: None,
+            running_balance: false,
+            commitment: self.commitment.clone(),
+        }
+    }
+}
+
+impl AggregateQuery {
+    fn backfill_query(&self) -> BackfillQuery {
+        BackfillQuery {
+            wallet: self.wallet.clone(),
+            mint: self.mint.clone(),
+            symbol: self.symbol.clone(),
+            hours: self.hours,
+            start: self.start,
+            end: self.end,
+            format: None,
+            order: None,
+            strategy: None,
+            include_failed: self.include_failed,
+            include_orphaned: false,
+            include_mints: self.include_mints,
+            include_internal: false,
+            direction: None,
+            min_amount: None,
+            max_amount: None,
+            counterparty: None,
+            memo_contains: None,
+            limit: None,
+            cursor: None,
+            running_balance: false,
+            commitment: self.commitment.clone(),
+        }
+    }
+}
+
+/// `/export` parameters: the `/backfill` selection of one wallet.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    pub wallet: Option<String>,
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+    /// `parquet`, the default and so far the only one.
+    pub format: Option<String>,
+    pub hours: Option<i64>,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    #[serde(default)]
+    pub include_failed: bool,
+    #[serde(default = "included_by_default")]
+    pub include_mints: bool,
+    pub commitment: Option<String>,
+}
+
+impl ExportQuery {
+    fn backfill_query(&self) -> BackfillQuery {
+        BackfillQuery {
+            wallet: self.wallet.clone(),
+            mint: self.mint.clone(),
+            symbol: self.symbol.clone(),
+            hours: self.hours,
+            start: self.start,
+            end: self.end,
+            format: None,
+            order: Some(SortOrder::Asc.as_str().to_string()),
+            strategy: None,
+            include_failed: self.include_failed,
+            include_orphaned: false,
+            include_mints: self.include_mints,
+            include_internal: false,
+            direction: None,
+            min_amount: None,
+            max_amount: None,
+            counterparty: None,
+            memo_contains: None,
+            limit: None,
+            cursor: None,
+            running_balance: false,
+            commitment: self.commitment.clone(),
+        }
+    }
+}
+
+impl TimeWindow {
+    /// Resolves the requested window measured back from `now`, the
+    /// [`window_anchor`]; with neither `hours` nor `start` it covers the last
+    /// `default_hours`.
+    pub fn from_query(query: &BackfillQuery, now: i64, default_hours: i64) -> Result<Self, String> {
+        if query.hours.is_some() && query.start.is_some() {
+            return Err("'hours' and 'start' cannot be combined".to_string());
+        }
+
+        let end = query.end.unwrap_or(now).min(now);
+        let start = match (query.start, query.hours) {
+            (Some(start), _) => start,
+            (None, Some(hours)) if hours <= 0 => {
+                return Err(format!("'hours' must be positive, got {}", hours));
+            }
+            (None, Some(hours)) => end.saturating_sub(hours.saturating_mul(3600)),
+            (None, None) => end.saturating_sub(default_hours.saturating_mul(3600)),
+        };
+
+        if start > end {
+            return Err(format!("'start' ({}) is after 'end' ({})", start, end));
+        }
+
+        Ok(TimeWindow {
+            start: start.max(end.saturating_sub(MAX_WINDOW_SECS)),
+            end,
+        })
+    }
+
+    /// Consecutive non-overlapping windows of at most `secs` covering this
+    /// one, in `order`.
+    fn split(self, secs: i64, order: SortOrder) -> Vec<TimeWindow> {
+        let mut chunks = Vec::new();
+        let mut start = self.start;
+        while start <= self.end {
+            let end = start.saturating_add(secs - 1).min(self.end);
+            chunks.push(TimeWindow { start, end });
+            start = end + 1;
+        }
+        if order == SortOrder::Desc {
+            chunks.reverse();
+        }
+        chunks
+    }
+}
+
+/// Span of block time each step of an NDJSON stream fetches and holds in
+/// memory.
+const NDJSON_CHUNK_SECS: i64 = 6 * 3600;
+
+/// Span of block time each row group of an `/export` file covers at most.
+const EXPORT_CHUNK_SECS: i64 = 24 * 3600;
+
+const SLOT_CACHE_TTL: Duration = Duration::from_secs(30);
+/// Comment line sent on an idle `/stream` so proxies don't time it out.
+const STREAM_KEEPALIVE: Duration = Duration::from_secs(15);
+const MAX_ADMIN_BODY_BYTES: u64 = 4 * 1024;
+
+/// Cap on a `POST /graphql` body; queries are text, not uploads.
+const MAX_GRAPHQL_BODY_BYTES: u64 = 64 * 1024;
+
+/// One persisted cursor as reported by `/status`.
+#[derive(Debug, Serialize, ToSchema)]
+struct CursorStatus {
+    wallet: String,
+    mint: String,
+    indexed_from: i64,
+    indexed_until: i64,
+    newest_signature: Option<String>,
+}
+
+/// How far one wallet/mint's index trails the chain, as reported by
+/// `/status`. The slot and lag fields are only known once this process has
+/// synced the pair; the tip ones also need `getSlot` to have answered.
+#[derive(Debug, Serialize, ToSchema)]
+struct MintLag {
+    mint: String,
+    symbol: String,
+    newest_signature: Option<String>,
+    newest_slot: Option<u64>,
+    newest_block_time: Option<i64>,
+    /// Unix time the stored history reaches up to.
+    indexed_until: Option<i64>,
+    /// Chain slot at the start of the last successful sync.
+    synced_slot: Option<u64>,
+    lag_slots: Option<u64>,
+    /// Seconds since the last successful sync.
+    lag_seconds: Option<i64>,
+    transfer_count: u64,
+    /// The wallet's token accounts for this mint that the indexed history
+    /// saw created and not yet closed.
+    token_accounts: Vec<OpenTokenAccount>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct WalletLag {
+    wallet: String,
+    loops: LoopHealth,
+    mints: Vec<MintLag>,
+}
+
+/// Formats of the endpoints that only answer in JSON.
+const JSON_ONLY: &[OutputFormat] = &[OutputFormat::Json];
+
+/// The body format of a data endpoint: `?format=` when given, else the
+/// client's preference among `supported` per `Accept`, else the first of
+/// `supported`. An `Accept` allowing none of them is a 406 listing them.
+fn response_format(
+    format: Option<&str>,
+    accept: Option<&str>,
+    supported: &[OutputFormat],
+) -> Result<OutputFormat, IndexerError> {
+    let listed = || {
+        supported
+            .iter()
+            .map(|format| format.media_type())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    if let Some(format) = format {
+        let format: OutputFormat = format.parse().map_err(IndexerError::InvalidParameter)?;
+        if !supported.contains(&format) {
+            return Err(IndexerError::InvalidParameter(format!(
+                "format {} is not available here, only {}",
+                format,
+                listed()
+            )));
+        }
+        return Ok(format);
+    }
+    match accept.map(str::trim).filter(|accept| !accept.is_empty()) {
+        Some(accept) => OutputFormat::from_accept(accept, supported).ok_or_else(|| {
+            IndexerError::NotAcceptable(format!(
+                "cannot respond with '{}'; supported: {}",
+                accept,
+                listed()
+            ))
+        }),
+        None => Ok(supported[0]),
+    }
+}
+
+/// Records the request in the HTTP metrics and turns failures into
+/// rejections for [`handle_rejection`].
+fn finish(
+    route: &str,
+    started: Instant,
+    result: Result<Response, IndexerError>,
+) -> Result<Response, warp::Rejection> {
+    let status = match &result {
+        Ok(response) => response.status(),
+        Err(e) => e.status(),
+    };
+    METRICS.observe_http(route, status, started);
+    result.map_err(warp::reject::custom)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/status",
+    responses((status = 200, description = "Cluster, the persisted cursors and how far each tracked wallet trails the chain tip", body = serde_json::Value)),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_status(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    slots: Arc<SlotCache>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    finish(
+        "status",
+        started,
+        status_response(client.as_ref(), &config, &store, &slots).await,
+    )
+}
+
+async fn status_response(
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    slots: &SlotCache,
+) -> Result<Response, IndexerError> {
+    let now = Utc::now().timestamp();
+    let tip = match slots.get(client).await {
+        Ok((tip, _)) => {
+            METRICS.update_lag(tip, now);
+            Some(tip)
+        }
+        Err(e) => {
+            warn!(error = %e, "getSlot failed, lag not computed");
+            None
+        }
+    };
+    let mut wallets = Vec::new();
+    for tracked in tracked_wallets(config, store).await? {
+        let name = tracked.wallet.to_string();
+        let mut mints = Vec::new();
+        for mint in &config.mints.mints {
+            let state = store.sync_state(&tracked.wallet, &mint.mint).await?;
+            let progress = progress::mint(&name, &mint.mint.to_string());
+            let synced_slot = progress.map(|p| p.synced_slot);
+            let newest = progress.and_then(|p| p.newest);
+            let all_time = TimeWindow {
+                start: 0,
+                end: i64::MAX,
+            };
+            let account_events = store
+                .account_events(&tracked.wallet, &mint.mint, &all_time)
+                .await?;
+            mints.push(MintLag {
+                mint: mint.mint.to_string(),
+                symbol: mint.symbol.clone(),
+                newest_signature: state.as_ref().and_then(|s| s.newest_signature.clone()),
+                newest_slot: newest.map(|(slot, _)| slot),
+                newest_block_time: newest.map(|(_, block_time)| block_time),
+                indexed_until: state.as_ref().map(|s| s.indexed_until),
+                synced_slot,
+                lag_slots: tip
+                    .zip(synced_slot)
+                    .map(|(tip, synced)| tip.saturating_sub(synced)),
+                lag_seconds: state.map(|s| (now - s.indexed_until).max(0)),
+                transfer_count: store.count_transfers(&tracked.wallet, &mint.mint).await?,
+                token_accounts: open_token_accounts(&account_events),
+            });
+        }
+        wallets.push(WalletLag {
+            loops: progress::loops(&name),
+            wallet: name,
+            mints,
+        });
+    }
+
+    let cursors: Vec<CursorStatus> = store
+        .list_sync_states()
+        .await?
+        .into_iter()
+        .map(|(wallet, mint, state)| CursorStatus {
+            wallet,
+            mint,
+            indexed_from: state.indexed_from,
+            indexed_until: state.indexed_until,
+            newest_signature: state.newest_signature,
+        })
+        .collect();
+    Ok(warp::reply::json(&serde_json::json!({
+        "cluster": config.cluster.name(),
+        "tip_slot": tip,
+        "cursors": cursors,
+        "wallets": wallets,
+    }))
+    .into_response())
+}
+
+/// Serves every collector in Prometheus text format. The lag gauges are
+/// brought up to the tip in [`SlotCache`], so scrapes don't each cost a
+/// `getSlot` call; if the tip can't be had the previous lag values are
+/// served unchanged.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain")),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_metrics(
+    client: Arc<dyn SolanaRpc>,
+    slots: Arc<SlotCache>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    match slots.get(client.as_ref()).await {
+        Ok((tip, _)) => METRICS.update_lag(tip, Utc::now().timestamp()),
+        Err(e) => warn!(error = %e, "getSlot failed, lag not updated"),
+    }
+    let mut body = String::new();
+    let result = match TextEncoder::new().encode_utf8(&METRICS.registry.gather(), &mut body) {
+        Ok(()) => Ok(
+            warp::reply::with_header(body, "Content-Type", prometheus::TEXT_FORMAT).into_response(),
+        ),
+        Err(e) => Err(IndexerError::Internal(e.to_string())),
+    };
+    finish("metrics", started, result)
+}
+
+/// Most recent `getSlot` result, so readiness probes, `/status` and metric
+/// scrapes don't each cost an RPC call.
+#[derive(Debug, Default)]
+struct SlotCache {
+    latest: std::sync::Mutex<Option<(Instant, u64)>>,
+}
+
+impl SlotCache {
+    /// Returns the cached slot and its age if younger than [`SLOT_CACHE_TTL`],
+    /// otherwise asks the RPC and caches the answer.
+    async fn get(&self, client: &dyn SolanaRpc) -> Result<(u64, Duration)> {
+        if let Some((fetched_at, slot)) = *self.latest.lock().unwrap() {
+            if fetched_at.elapsed() <= SLOT_CACHE_TTL {
+                return Ok((slot, fetched_at.elapsed()));
+            }
+        }
+        let slot = client.get_slot().await?;
+        *self.latest.lock().unwrap() = Some((Instant::now(), slot));
+        Ok((slot, Duration::ZERO))
+    }
+}
+
+/// Outcome of one readiness check.
+#[derive(Debug, Serialize, ToSchema)]
+struct ReadinessCheck {
+    ok: bool,
+    detail: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses((status = 200, description = "The process is up", body = serde_json::Value))
+)]
+async fn handle_healthz() -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let response = warp::reply::json(&serde_json::json!({ "status": "ok" })).into_response();
+    METRICS.observe_http("healthz", response.status(), started);
+    Ok(response)
+}
+
+/// Reports 200 only if the RPC answers and, when a background indexer is
+/// running, every mint of the default wallet was synced within
+/// `max_index_age`; 503 otherwise, with each check's result in the body.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "RPC and index checks passed", body = serde_json::Value),
+        (status = 503, description = "A check failed", body = serde_json::Value),
+    )
+)]
+async fn handle_readyz(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    slots: Arc<SlotCache>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let mut checks = serde_json::Map::new();
+
+    let rpc = match slots.get(client.as_ref()).await {
+        Ok((slot, age)) => ReadinessCheck {
+            ok: true,
+            detail: format!("slot {} ({}s old)", slot, age.as_secs()),
+        },
+        Err(e) => {
+            let e = IndexerError::from(e);
+            warn!(error = %e, "Readiness check could not reach the RPC provider");
+            ReadinessCheck {
+                ok: false,
+                detail: e.public_message(),
+            }
+        }
+    };
+    checks.insert("rpc".to_string(), serde_json::json!(rpc));
+
+    if config.poll_interval.is_some() || config.live_ws_url.is_some() {
+        let index = index_freshness(&config, &store).await;
+        checks.insert("index".to_string(), serde_json::json!(index));
+    }
+
+    let ready = checks.values().all(|check| check["ok"] == true);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let response = warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "ready": ready, "checks": checks })),
+        status,
+    )
+    .into_response();
+    METRICS.observe_http("readyz", response.status(), started);
+    Ok(response)
+}
+
+async fn index_freshness(config: &Config, store: &Storage) -> ReadinessCheck {
+    let now = Utc::now().timestamp();
+    let wallets = match tracked_wallets(config, store).await {
+        Ok(wallets) => wallets,
+        Err(e) => {
+            return ReadinessCheck {
+                ok: false,
+                detail: format!("store error: {}", e),
+            }
+        }
+    };
+    let mut stale = Vec::new();
+    for tracked in &wallets {
+        let wallet = &tracked.wallet;
+        // Only name the wallet when there's more than one to tell apart.
+        let name = |mint: &MintInfo| match wallets.len() {
+            1 => mint.symbol.clone(),
+            _ => format!("{} {}", wallet, mint.symbol),
+        };
+        for mint in &config.mints.mints {
+            match store.sync_state(wallet, &mint.mint).await {
+                Ok(Some(state))
+                    if now - state.indexed_until <= config.max_index_age.as_secs() as i64 => {}
+                Ok(Some(state)) => stale.push(format!(
+                    "{} last synced {}s ago",
+                    name(mint),
+                    now - state.indexed_until
+                )),
+                Ok(None) => stale.push(format!("{} never synced", name(mint))),
+                Err(e) => {
+                    return ReadinessCheck {
+                        ok: false,
+                        detail: format!("store error: {}", e),
+                    }
+                }
+            }
+        }
+    }
+    if stale.is_empty() {
+        ReadinessCheck {
+            ok: true,
+            detail: format!(
+                "all mints synced within {}s",
+                config.max_index_age.as_secs()
+            ),
+        }
+    } else {
+        ReadinessCheck {
+            ok: false,
+            detail: stale.join(", "),
+        }
+    }
+}
+
+/// `/backfill` results by normalized query; see [`cache_key`].
+type BackfillCache = ResponseCache<BackfillQuery, Arc<(OutputFormat, BackfillResponse)>>;
+
+#[utoipa::path(
+    get,
+    path = "/v1/backfill",
+    params(BackfillQuery),
+    responses(
+        (status = 200, description = "Transfers in the window, in the requested `format`", content(
+            ("application/json" = BackfillResponse),
+            ("text/csv" = String),
+            ("text/plain" = String),
+            ("application/x-ndjson" = Transfer),
+        )),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+        (status = 504, description = "Ran past `request_timeout`; the body says how far the scan got", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_backfill(
+    mut query: BackfillQuery,
+    accept: Option<String>,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    cache: Option<Arc<BackfillCache>>,
+    permits: BackfillPermits,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = async {
+        let format = response_format(
+            query.format.as_deref(),
+            accept.as_deref(),
+            &OutputFormat::ALL,
+        )?;
+        query.format = Some(format.to_string());
+        // Boxed: together these futures outgrow a worker thread's stack in
+        // unoptimized builds.
+        if format == OutputFormat::Ndjson {
+            Box::pin(stream_backfill(query, client, config, store, &permits)).await
+        } else {
+            Box::pin(deadline::run(
+                config.request_timeout,
+                backfill_response(
+                    query,
+                    client.as_ref(),
+                    &config,
+                    &store,
+                    cache.as_deref(),
+                    &permits,
+                ),
+            ))
+            .await
+        }
+    }
+    .await;
+    finish("backfill", started, result)
+}
+
+#[utoipa::path(
+    get,
+    path = "/backfill",
+    params(BackfillQuery),
+    responses(
+        (status = 200, description = "`/v1/backfill`, as pipe-delimited `text` lines unless `format` asks otherwise; `Accept` is ignored", content(
+            ("text/plain" = String),
+            ("application/json" = BackfillResponse),
+            ("text/csv" = String),
+            ("application/x-ndjson" = Transfer),
+        )),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+        (status = 504, description = "Ran past `request_timeout`", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_legacy_backfill(
+    mut query: BackfillQuery,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    cache: Option<Arc<BackfillCache>>,
+    permits: BackfillPermits,
+) -> Result<Response, warp::Rejection> {
+    query
+        .format
+        .get_or_insert_with(|| OutputFormat::Text.to_string());
+    handle_backfill(query, None, client, config, store, cache, permits).await
+}
+
+/// `?format=ndjson`: the window is walked in [`NDJSON_CHUNK_SECS`] steps
+/// and each step's transfers are written out before the next is fetched,
+/// so memory stays flat however long the window is. The first step runs
+/// before the response starts, so bad parameters still get an error
+/// status; a failure after that ends the body with an
+/// [`ErrorBody`] line. Pages and running balances need the
+/// whole result up front, so `cursor`, `limit` and `running_balance` don't
+/// apply.
+async fn stream_backfill(
+    query: BackfillQuery,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: &BackfillPermits,
+) -> Result<Response, IndexerError> {
+    if query.cursor.is_some() || query.running_balance {
+        return Err(IndexerError::InvalidParameter(
+            "format=ndjson streams the whole window; cursor and running_balance don't apply"
+                .to_string(),
+        ));
+    }
+    let permit = permits.try_acquire()?;
+    // Every chunk gets an explicit `end`, so the chain is asked once.
+    let anchor = window_anchor(client.as_ref(), &config, query.end).await;
+    let window = TimeWindow::from_query(&query, anchor.time, config.window_hours)
+        .map_err(IndexerError::InvalidWindow)?;
+    let order = match query.order.as_deref() {
+        Some(order) => order.parse().map_err(IndexerError::InvalidParameter)?,
+        None => SortOrder::default(),
+    };
+    let chunk_query = move |chunk: TimeWindow| BackfillQuery {
+        hours: None,
+        start: Some(chunk.start),
+        end: Some(chunk.end),
+        ..query.clone()
+    };
+    let mut chunks = window.split(NDJSON_CHUNK_SECS, order).into_iter();
+    let first = match chunks.next() {
+        Some(chunk) => {
+            deadline::run(config.request_timeout, async {
+                let (_, response) =
+                    backfill_for_query(&chunk_query(chunk), client.as_ref(), &config, &store)
+                        .await?;
+                Ok::<_, IndexerError>(transfers_to_ndjson(&response.transfers)?)
+            })
+            .await?
+        }
+        None => String::new(),
+    };
+
+    let (sender, body, disconnected) = spawned_body();
+    let task = deadline::scope(disconnected, async move {
+        let _permit = permit;
+        if sender.send(Ok(first.into())).await.is_err() {
+            return;
+        }
+        for chunk in chunks {
+            let lines =
+                match backfill_for_query(&chunk_query(chunk), client.as_ref(), &config, &store)
+                    .await
+                    .and_then(|(_, response)| Ok(transfers_to_ndjson(&response.transfers)?))
+                {
+                    Ok(lines) => lines,
+                    Err(IndexerError::Cancelled) => return,
+                    Err(e) => {
+                        warn!(error = %e, "NDJSON backfill stream failed");
+                        let line = serde_json::to_string(&ErrorBody::from(&e))
+                            .expect("error bodies serialize");
+                        let _ = sender.send(Ok(format!("{}\n", line).into())).await;
+                        return;
+                    }
+                };
+            if sender.send(Ok(lines.into())).await.is_err() {
+                return;
+            }
+        }
+    });
+    tokio::spawn(task.in_current_span());
+    let mut reply = Response::new(body);
+    reply.headers_mut().insert(
+        "Content-Type",
+        HeaderValue::from_static(OutputFormat::Ndjson.content_type()),
+    );
+    Ok(reply)
+}
+
+/// A body fed chunk by chunk from a spawned task, and a token cancelled
+/// once hyper drops the body, which is how a client going away shows.
+/// Running the task in [`deadline::scope`] of the token stops its fetches
+/// at the next page instead of when the next chunk fails to send; an `Err`
+/// sent down the channel aborts the response.
+fn spawned_body() -> (
+    mpsc::Sender<std::io::Result<Bytes>>,
+    warp::hyper::Body,
+    CancellationToken,
+) {
+    let (sender, receiver) = mpsc::channel(1);
+    let disconnected = CancellationToken::new();
+    let guard = disconnected.clone().drop_guard();
+    let chunks = stream::unfold((receiver, guard), |(mut receiver, guard)| async move {
+        let chunk = receiver.recv().await?;
+        Some((chunk, (receiver, guard)))
+    });
+    (sender, warp::hyper::Body::wrap_stream(chunks), disconnected)
+}
+
+/// `query` with defaults filled in, so spellings of the same request (an
+/// explicit default wallet, a symbol instead of a mint) share a cache entry.
+fn cache_key(query: &BackfillQuery, config: &Config) -> Result<BackfillQuery, IndexerError> {
+    let mut key = query.clone();
+    if key.wallet.as_deref() != Some(ALL_WALLETS) {
+        key.wallet = Some(wallet_param(query.wallet.as_deref(), config)?.to_string());
+    }
+    let mint = config
+        .mints
+        .select(query.mint.as_deref(), query.symbol.as_deref())
+        .map_err(IndexerError::InvalidParameter)?;
+    key.mint = Some(mint.mint.to_string());
+    key.symbol = None;
+    key.format
+        .get_or_insert_with(|| OutputFormat::Json.to_string());
+    key.order
+        .get_or_insert_with(|| SortOrder::default().as_str().to_string());
+    key.commitment =
+        Some(commitment_name(commitment_param(query.commitment.as_deref(), config)?).to_string());
+    Ok(key)
+}
+
+async fn backfill_response(
+    query: BackfillQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    cache: Option<&BackfillCache>,
+    permits: &BackfillPermits,
+) -> Result<Response, IndexerError> {
+    let cached = match cache {
+        Some(cache) => Some((cache, cache_key(&query, config)?)),
+        None => None,
+    };
+    // Cache hits and requests sharing another's computation take no slot.
+    let compute = move || async move {
+        let _permit = permits.try_acquire()?;
+        run_backfill(query, client, config, store)
+            .await
+            .map(Arc::new)
+    };
+    let (result, cache_status) = match cached {
+        Some((cache, key)) => {
+            let (result, hit) = cache.get_or_compute(key, compute).await?;
+            let outcome = if hit { "hit" } else { "miss" };
+            METRICS.response_cache.with_label_values(&[outcome]).inc();
+            (result, Some(if hit { "HIT" } else { "MISS" }))
+        }
+        None => (compute().await?, None),
+    };
+    let (format, response) = result.as_ref();
+    let format = *format;
+    let body = response.render(format)?;
+    let mut reply =
+        warp::reply::with_header(body, "Content-Type", format.content_type()).into_response();
+    let headers = reply.headers_mut();
+    if let Some(status) = cache_status {
+        headers.insert("X-Cache", HeaderValue::from_static(status));
+    }
+    // CSV and text bodies have nowhere else to carry the cursor.
+    if let Some(cursor) = &response.next_cursor {
+        if let Ok(value) = HeaderValue::from_str(cursor) {
+            headers.insert("X-Next-Cursor", value);
+        }
+    }
+    headers.insert("X-Pages-Scanned", HeaderValue::from(response.pages_scanned));
+    if response.truncated {
+        headers.insert("X-Truncated", HeaderValue::from_static("true"));
+    }
+    if format == OutputFormat::Csv {
+        let filename = format!(
+            "transfers-{}-{}-{}.csv",
+            response.wallet, response.window.start, response.window.end
+        );
+        if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+        {
+            headers.insert("Content-Disposition", value);
+        }
+    }
+    Ok(reply)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/backfill",
+    params(BackfillQuery),
+    responses(
+        (status = 202, description = "Job accepted; poll the `Location`", body = JobReport),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_submit_backfill_job(
+    query: BackfillQuery,
+    accept: Option<String>,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    jobs: Arc<JobRegistry>,
+    permits: BackfillPermits,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = response_format(None, accept.as_deref(), JSON_ONLY)
+        .and_then(|_| submit_backfill_job(query, client, config, store, &jobs, permits));
+    finish("backfill_jobs", started, result)
+}
+
+/// Starts `query` as a job; its result is the JSON `/backfill` body.
+fn submit_backfill_job(
+    query: BackfillQuery,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    jobs: &Arc<JobRegistry>,
+    permits: BackfillPermits,
+) -> Result<Response, IndexerError> {
+    if query
+        .format
+        .as_deref()
+        .is_some_and(|format| format != "json")
+    {
+        return Err(IndexerError::InvalidParameter(
+            "backfill jobs only report json".to_string(),
+        ));
+    }
+    let report = jobs.submit(async move {
+        // Jobs queue for a slot instead of being turned away.
+        let _permit = permits.acquire().await;
+        let (_, response) = run_backfill(query, client.as_ref(), &config, &store).await?;
+        Ok(response)
+    })?;
+    Ok(job_accepted(&report))
+}
+
+/// `202` with the submitted job, pointing at where it can be polled.
+fn job_accepted(report: &JobReport) -> Response {
+    let mut reply =
+        warp::reply::with_status(warp::reply::json(report), StatusCode::ACCEPTED).into_response();
+    if let Ok(value) = HeaderValue::from_str(&format!("/v1/backfill/{}", report.id)) {
+        reply.headers_mut().insert("Location", value);
+    }
+    reply
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/backfill/{id}",
+    params(("id" = String, Path, description = "Job id returned when it was submitted")),
+    responses(
+        (status = 200, description = "The job, with its result once done", body = JobReport),
+        (status = 404, description = "No such job", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_backfill_job(
+    id: String,
+    jobs: Arc<JobRegistry>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = jobs
+        .get(&id)
+        .map(|report| warp::reply::json(&report).into_response())
+        .ok_or_else(|| unknown_job(&id));
+    finish("backfill_jobs", started, result)
+}
+
+/// Cancels a pending or running job; a finished one is forgotten.
+#[utoipa::path(
+    delete,
+    path = "/v1/backfill/{id}",
+    params(("id" = String, Path, description = "Job id returned when it was submitted")),
+    responses(
+        (status = 200, description = "The job as cancelled", body = JobReport),
+        (status = 404, description = "No such job", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_cancel_backfill_job(
+    id: String,
+    jobs: Arc<JobRegistry>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = jobs
+        .cancel(&id)
+        .map(|report| warp::reply::json(&report).into_response())
+        .ok_or_else(|| unknown_job(&id));
+    finish("backfill_jobs", started, result)
+}
+
+fn unknown_job(id: &str) -> IndexerError {
+    IndexerError::NotFound(format!("no backfill job '{}'", id))
+}
+
+/// Validates `query` against `config` and runs the backfill it describes.
+/// The CLI goes through here too, so both reject the same inputs.
+pub async fn run_backfill(
+    query: BackfillQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+) -> Result<(OutputFormat, BackfillResponse), IndexerError> {
+    let format = match query.format.as_deref() {
+        Some(format) => format.parse().map_err(IndexerError::InvalidParameter)?,
+        None => OutputFormat::Json,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > config.max_page_size {
+        return Err(IndexerError::InvalidParameter(format!(
+            "limit must be between 1 and {}",
+            config.max_page_size
+        )));
+    }
+    let mut query = query;
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(PageCursor::decode)
+        .transpose()
+        .map_err(IndexerError::InvalidParameter)?;
+    let order = match &cursor {
+        Some(cursor) => {
+            query.wallet = Some(cursor.wallets.clone());
+            query.hours = None;
+            query.start = Some(cursor.window.start);
+            query.end = Some(cursor.window.end);
+            query.order = Some(cursor.order.as_str().to_string());
+            cursor.order
+        }
+        None => match query.order.as_deref() {
+            Some(order) => order.parse().map_err(IndexerError::InvalidParameter)?,
+            None => SortOrder::default(),
+        },
+    };
+
+    let (_, mut response) = backfill_for_query(&query, client, config, store).await?;
+    if let Some(cursor) = &cursor {
+        response.transfers.retain(|t| cursor.precedes(t));
+    }
+    response.has_more = response.transfers.len() > limit;
+    if response.has_more {
+        response.transfers.truncate(limit);
+        response.next_cursor = response
+            .transfers
+            .last()
+            .map(|last| PageCursor::after(last, &response.wallet, response.window, order).encode());
+    }
+    Ok((format, response))
+}
+
+/// Resolves the wallet, window and mint of `query` and runs the backfill,
+/// returning the selected mint alongside the result. `?wallet=all` merges
+/// the streams of every tracked wallet, tagging each record with its
+/// wallet.
+pub(crate) async fn backfill_for_query(
+    query: &BackfillQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+) -> Result<(MintInfo, BackfillResponse), IndexerError> {
+    let merged = query.wallet.as_deref() == Some(ALL_WALLETS);
+    let wallets = if merged {
+        if query.running_balance {
+            return Err(IndexerError::InvalidParameter(
+                "running_balance needs a single wallet".to_string(),
+            ));
+        }
+        tracked_wallets(config, store)
+            .await?
+            .into_iter()
+            .map(|tracked| tracked.wallet)
+            .collect()
+    } else {
+        vec![wallet_param(query.wallet.as_deref(), config)?]
+    };
+
+    let anchor = window_anchor(client, config, query.end).await;
+    let window = TimeWindow::from_query(query, anchor.time, config.window_hours)
+        .map_err(IndexerError::InvalidWindow)?;
+
+    let mint = config
+        .mints
+        .select(query.mint.as_deref(), query.symbol.as_deref())
+        .map_err(IndexerError::InvalidParameter)?;
+
+    let order = match query.order.as_deref() {
+        Some(order) => order.parse().map_err(IndexerError::InvalidParameter)?,
+        None => SortOrder::default(),
+    };
+
+    let strategy = match query.strategy.as_deref() {
+        Some(strategy) => strategy.parse().map_err(IndexerError::InvalidParameter)?,
+        None => Strategy::default(),
+    };
+
+    let commitment = commitment_param(query.commitment.as_deref(), config)?;
+
+    let filter = transfer_filter(query, mint.decimals)?;
+
+    let mut transfers = Vec::new();
+    let mut undecodable_transactions = 0;
+    let mut diagnostics = Diagnostics::default();
+    let mut pages_scanned = 0;
+    let mut truncated = false;
+    let mut discrepancies = Vec::new();
+    let mut running_balance = None;
+    for wallet in &wallets {
+        // The balance is read first, so everything it reflects is in the
+        // history fetched next. Walking back from it needs every transfer
+        // up to now, not just the requested window; the host clock may trail
+        // the chain, hence the later of the two.
+        let balance = if query.running_balance {
+            Some(fetch_balance(client, config, wallet, mint, commitment).await?)
+        } else {
+            None
+        };
+        let request = BackfillRequest {
+            wallet: *wallet,
+            mint: mint.clone(),
+            window: match balance {
+                Some(_) => TimeWindow {
+                    start: window.start,
+                    end: Utc::now().timestamp().max(anchor.time),
+                },
+                None => window,
+            },
+            until: None,
+            include_failed: query.include_failed,
+            include_orphaned: query.include_orphaned,
+            strategy,
+            commitment,
+        };
+        let (outcome, skipped) =
+            diagnostics::collect(backfill_with_store(client, config, store, &request)).await;
+        let outcome = outcome?;
+        diagnostics.merge(skipped);
+
+        let mut found = outcome.transfers;
+        if let Some(balance) = balance {
+            running_balance = Some(apply_running_balance(&mut found, &balance));
+            found.retain(|t| t.block_time <= window.end);
+        }
+        if merged {
+            for transfer in &mut found {
+                transfer.wallet = Some(wallet.to_string());
+            }
+        }
+        transfers.extend(found);
+        undecodable_transactions += outcome.undecodable_transactions;
+        pages_scanned += outcome.pages_scanned;
+        truncated |= outcome.truncated;
+        discrepancies.extend(outcome.discrepancies);
+    }
+    if !query.include_mints {
+        transfers.retain(|t| !t.direction.is_supply_change());
+    }
+    if !query.include_internal && filter.direction != Some(Direction::Internal) {
+        transfers.retain(|t| t.direction != Direction::Internal);
+    }
+    transfers.retain(|t| filter.matches(t));
+    sort_transfers(&mut transfers, order);
+    for transfer in &mut transfers {
+        let explorer_url = config.explorer_link(&transfer.signature);
+        transfer.annotate(explorer_url);
+    }
+    price::enrich(config, &mut transfers).await;
+    let response = BackfillResponse {
+        wallet: if merged {
+            ALL_WALLETS.to_string()
+        } else {
+            wallets[0].to_string()
+        },
+        mint: mint.mint.to_string(),
+        symbol: mint.symbol.clone(),
+        window,
+        window_anchor: anchor,
+        transfers,
+        undecodable_transactions,
+        diagnostics,
+        pages_scanned,
+        truncated,
+        discrepancies,
+        has_more: false,
+        next_cursor: None,
+        running_balance,
+        price_source: config
+            .price_source
+            .as_ref()
+            .map(|source| source.provider.name().to_string()),
+    };
+    Ok((mint.clone(), response))
+}
+
+/// Validates the filter parameters of `query`; amounts are scaled by the
+/// selected mint's `decimals`.
+fn transfer_filter(query: &BackfillQuery, decimals: u8) -> Result<TransferFilter, IndexerError> {
+    let invalid =
+        |name: &str, e: String| IndexerError::InvalidParameter(format!("{}: {}", name, e));
+    let direction = query
+        .direction
+        .as_deref()
+        .map(|d| {
+            d.parse::<Direction>()
+                .map_err(|e| invalid("direction", e.to_string()))
+        })
+        .transpose()?;
+    let min_amount = query
+        .min_amount
+        .as_deref()
+        .map(|a| parse_amount(a, decimals).map_err(|e| invalid("min_amount", e)))
+        .transpose()?;
+    let max_amount = query
+        .max_amount
+        .as_deref()
+        .map(|a| parse_amount(a, decimals).map_err(|e| invalid("max_amount", e)))
+        .transpose()?;
+    if let (Some(min), Some(max)) = (min_amount, max_amount) {
+        if min > max {
+            return Err(IndexerError::InvalidParameter(
+                "min_amount is larger than max_amount".to_string(),
+            ));
+        }
+    }
+    let counterparty = query
+        .counterparty
+        .as_deref()
+        .map(|c| {
+            Pubkey::from_str(c).map(|_| c.to_string()).map_err(|e| {
+                invalid(
+                    "counterparty",
+                    format!("'{}' is not a valid pubkey: {}", c, e),
+                )
+            })
+        })
+        .transpose()?;
+    Ok(TransferFilter {
+        direction,
+        min_amount,
+        max_amount,
+        counterparty,
+        memo_contains: query
+            .memo_contains
+            .as_deref()
+            .filter(|needle| !needle.is_empty())
+            .map(str::to_lowercase),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/aggregate",
+    params(AggregateQuery),
+    responses(
+        (status = 200, description = "Totals per hour or day", body = serde_json::Value),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+        (status = 504, description = "Ran past `request_timeout`; the body says how far the scan got", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_aggregate(
+    query: AggregateQuery,
+    accept: Option<String>,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: BackfillPermits,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = async {
+        response_format(None, accept.as_deref(), JSON_ONLY)?;
+        let _permit = permits.try_acquire()?;
+        deadline::run(
+            config.request_timeout,
+            aggregate_response(query, client.as_ref(), &config, &store),
+        )
+        .await
+    }
+    .await;
+    finish("aggregate", started, result)
+}
+
+async fn aggregate_response(
+    query: AggregateQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+) -> Result<Response, IndexerError> {
+    let size: BucketSize = query
+        .bucket
+        .parse()
+        .map_err(IndexerError::InvalidParameter)?;
+    let offset = match query.tz_offset.as_deref() {
+        Some(value) => parse_tz_offset(value).map_err(IndexerError::InvalidParameter)?,
+        None => FixedOffset::east_opt(0).expect("zero offset is valid"),
+    };
+    let (mint, response) =
+        backfill_for_query(&query.backfill_query(), client, config, store).await?;
+    let buckets = aggregate(
+        &response.transfers,
+        response.window,
+        size,
+        offset,
+        mint.decimals,
+    );
+    Ok(warp::reply::json(&serde_json::json!({
+        "wallet": response.wallet,
+        "mint": response.mint,
+        "symbol": response.symbol,
+        "window": response.window,
+        "bucket": query.bucket,
+        "tz_offset": offset.to_string(),
+        "buckets": buckets,
+    }))
+    .into_response())
+}
+
+const DEFAULT_COUNTERPARTIES_LIMIT: usize = 20;
+pub(crate) const MAX_COUNTERPARTIES_LIMIT: usize = 1000;
+
+#[utoipa::path(
+    get,
+    path = "/v1/counterparties",
+    params(CounterpartiesQuery),
+    responses(
+        (status = 200, description = "Counterparties by volume", body = serde_json::Value),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+        (status = 504, description = "Ran past `request_timeout`; the body says how far the scan got", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_counterparties(
+    query: CounterpartiesQuery,
+    accept: Option<String>,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: BackfillPermits,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = async {
+        response_format(None, accept.as_deref(), JSON_ONLY)?;
+        let _permit = permits.try_acquire()?;
+        deadline::run(
+            config.request_timeout,
+            counterparties_response(query, client.as_ref(), &config, &store),
+        )
+        .await
+    }
+    .await;
+    finish("counterparties", started, result)
+}
+
+async fn counterparties_response(
+    query: CounterpartiesQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+) -> Result<Response, IndexerError> {
+    let limit = query.limit.unwrap_or(DEFAULT_COUNTERPARTIES_LIMIT);
+    if limit == 0 || limit > MAX_COUNTERPARTIES_LIMIT {
+        return Err(IndexerError::InvalidParameter(format!(
+            "'limit' must be between 1 and {}, got {}",
+            MAX_COUNTERPARTIES_LIMIT, limit
+        )));
+    }
+    let (mint, response) =
+        backfill_for_query(&query.backfill_query(), client, config, store).await?;
+    let counterparties = top_counterparties(&response.transfers, mint.decimals, limit);
+    Ok(warp::reply::json(&serde_json::json!({
+        "wallet": response.wallet,
+        "mint": response.mint,
+        "symbol": response.symbol,
+        "window": response.window,
+        "counterparties": counterparties,
+    }))
+    .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/summary",
+    params(BackfillQuery),
+    responses(
+        (status = 200, description = "Totals over the window", body = Summary),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+        (status = 504, description = "Ran past `request_timeout`; the body says how far the scan got", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_summary(
+    query: BackfillQuery,
+    accept: Option<String>,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: BackfillPermits,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = async {
+        response_format(query.format.as_deref(), accept.as_deref(), JSON_ONLY)?;
+        let _permit = permits.try_acquire()?;
+        deadline::run(
+            config.request_timeout,
+            summary_response(query, client.as_ref(), &config, &store),
+        )
+        .await
+    }
+    .await;
+    finish("summary", started, result)
+}
+
+async fn summary_response(
+    query: BackfillQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+) -> Result<Response, IndexerError> {
+    let (response, summary) = summary_for_query(query, client, config, store).await?;
+    Ok(warp::reply::json(&serde_json::json!({
+        "wallet": response.wallet,
+        "mint": response.mint,
+        "symbol": response.symbol,
+        "window": response.window,
+        "summary": summary,
+    }))
+    .into_response())
+}
+
+/// Runs the backfill `query` describes and totals it, for every API that
+/// serves a summary.
+pub(crate) async fn summary_for_query(
+    query: BackfillQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+) -> Result<(BackfillResponse, Summary), IndexerError> {
+    // Internal volume is reported on its own, so it's always fetched.
+    let query = BackfillQuery {
+        include_internal: true,
+        ..query
+    };
+    let (mint, response) = backfill_for_query(&query, client, config, store).await?;
+    let summary = Summary::from_transfers(&response.transfers, mint.decimals);
+    Ok((response, summary))
+}
+
+/// Body of `POST /wallets`.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WatchRequest {
+    pub address: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UnwatchQuery {
+    /// Also delete the wallet's stored transfers and cursors.
+    #[serde(default)]
+    pub purge: bool,
+}
+
+/// One mint of a wallet as reported by `GET /wallets`.
+#[derive(Debug, Serialize, ToSchema)]
+struct MintIndexStatus {
+    mint: String,
+    symbol: String,
+    newest_signature: Option<String>,
+    /// Unix time the stored history reaches up to, if synced at all.
+    indexed_until: Option<i64>,
+    transfer_count: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct WalletStatus {
+    address: String,
+    source: WalletSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    added_at: Option<i64>,
+    mints: Vec<MintIndexStatus>,
+}
+
+/// Admin endpoints take `Authorization: Bearer <admin_token>` and are
+/// refused outright while no token is configured.
+fn authorize(config: &Config, authorization: Option<&str>) -> Result<(), IndexerError> {
+    let Some(token) = &config.admin_token else {
+        return Err(IndexerError::Unauthorized(
+            "admin endpoints are disabled; set ADMIN_TOKEN to enable them".to_string(),
+        ));
+    };
+    let given = authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Comparing digests keeps the time taken independent of how much of
+    // the token matched.
+    if Sha256::digest(given.as_bytes()) == Sha256::digest(token.as_bytes()) {
+        Ok(())
+    } else {
+        Err(IndexerError::Unauthorized(
+            "missing or wrong admin token".to_string(),
+        ))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/wallets",
+    responses((status = 200, description = "Tracked wallets and their index state", body = serde_json::Value)),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_list_wallets(
+    config: Arc<Config>,
+    store: Storage,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    finish(
+        "wallets",
+        started,
+        list_wallets_response(&config, &store).await,
+    )
+}
+
+async fn list_wallets_response(config: &Config, store: &Storage) -> Result<Response, IndexerError> {
+    let mut wallets = Vec::new();
+    for tracked in tracked_wallets(config, store).await? {
+        let mut mints = Vec::new();
+        for mint in &config.mints.mints {
+            let state = store.sync_state(&tracked.wallet, &mint.mint).await?;
+            mints.push(MintIndexStatus {
+                mint: mint.mint.to_string(),
+                symbol: mint.symbol.clone(),
+                newest_signature: state.as_ref().and_then(|s| s.newest_signature.clone()),
+                indexed_until: state.map(|s| s.indexed_until),
+                transfer_count: store.count_transfers(&tracked.wallet, &mint.mint).await?,
+            });
+        }
+        wallets.push(WalletStatus {
+            address: tracked.wallet.to_string(),
+            source: tracked.source,
+            added_at: tracked.added_at,
+            mints,
+        });
+    }
+    Ok(warp::reply::json(&serde_json::json!({ "wallets": wallets })).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/wallets",
+    request_body = WatchRequest,
+    responses(
+        (status = 201, description = "Now tracked", body = serde_json::Value),
+        (status = 200, description = "Already tracked", body = serde_json::Value),
+        (status = 400, description = "Invalid address", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+async fn handle_watch_wallet(
+    authorization: Option<String>,
+    request: WatchRequest,
+    config: Arc<Config>,
+    watchlist: Arc<Watchlist>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = watch_wallet_response(authorization, request, &config, &watchlist).await;
+    finish("wallets", started, result)
+}
+
+async fn watch_wallet_response(
+    authorization: Option<String>,
+    request: WatchRequest,
+    config: &Config,
+    watchlist: &Watchlist,
+) -> Result<Response, IndexerError> {
+    authorize(config, authorization.as_deref())?;
+    let wallet = wallet_param(Some(&request.address), config)?;
+    let (status, added_at) = match watchlist.watch(wallet).await? {
+        Some(watched) => (StatusCode::CREATED, Some(watched.added_at)),
+        None => (StatusCode::OK, None),
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "address": wallet.to_string(),
+            "added": added_at.is_some(),
+            "added_at": added_at,
+        })),
+        status,
+    )
+    .into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/wallets/{address}",
+    params(("address" = String, Path, description = "Wallet to stop tracking"), UnwatchQuery),
+    responses(
+        (status = 200, description = "No longer tracked", body = serde_json::Value),
+        (status = 400, description = "Invalid address", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+async fn handle_unwatch_wallet(
+    address: String,
+    authorization: Option<String>,
+    query: UnwatchQuery,
+    config: Arc<Config>,
+    watchlist: Arc<Watchlist>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = unwatch_wallet_response(address, authorization, query, &config, &watchlist).await;
+    finish("wallets", started, result)
+}
+
+async fn unwatch_wallet_response(
+    address: String,
+    authorization: Option<String>,
+    query: UnwatchQuery,
+    config: &Config,
+    watchlist: &Watchlist,
+) -> Result<Response, IndexerError> {
+    authorize(config, authorization.as_deref())?;
+    let wallet = wallet_param(Some(&address), config)?;
+    watchlist.unwatch(&wallet, query.purge).await?;
+    Ok(warp::reply::json(&serde_json::json!({
+        "address": wallet.to_string(),
+        "purged": query.purge,
+    }))
+    .into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReparseQuery {
+    /// Wallet whose transfers are reparsed, or `all` for every tracked
+    /// wallet; defaults to the configured wallet.
+    pub wallet: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/reparse",
+    params(ReparseQuery),
+    responses(
+        (status = 200, description = "Transfers re-extracted from the stored raw transactions", body = serde_json::Value),
+        (status = 400, description = "Invalid address", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 404, description = "Raw storage is off", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+async fn handle_reparse(
+    authorization: Option<String>,
+    query: ReparseQuery,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = reparse_response(authorization, query, client.as_ref(), &config, &store).await;
+    finish("reparse", started, result)
+}
+
+async fn reparse_response(
+    authorization: Option<String>,
+    query: ReparseQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+) -> Result<Response, IndexerError> {
+    authorize(config, authorization.as_deref())?;
+    if config.raw_transaction_limit.is_none() {
+        return Err(IndexerError::NotFound(
+            "raw transactions are not stored; set STORE_RAW_TRANSACTIONS to keep them".to_string(),
+        ));
+    }
+    let wallets = match query.wallet.as_deref() {
+        Some(ALL_WALLETS) => tracked_wallets(config, store)
+            .await?
+            .into_iter()
+            .map(|tracked| tracked.wallet)
+            .collect(),
+        wallet => vec![wallet_param(wallet, config)?],
+    };
+    let mut reports = Vec::new();
+    for wallet in wallets {
+        let report = reparse(client, config, store, &wallet).await?;
+        reports.push(serde_json::json!({
+            "wallet": wallet.to_string(),
+            "transactions": report.transactions,
+            "undecodable_transactions": report.undecodable_transactions,
+            "transfers": report.transfers,
+        }));
+    }
+    Ok(warp::reply::json(&serde_json::json!({ "wallets": reports })).into_response())
+}
+
+/// Body of `POST /admin/reindex`.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReindexRequest {
+    pub wallet: String,
+    /// Inclusive window bounds as unix timestamps.
+    pub start: i64,
+    pub end: i64,
+    /// Mint pubkey to rebuild, or pick one by `symbol`; every mint by
+    /// default.
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/reindex",
+    request_body = ReindexRequest,
+    responses(
+        (status = 202, description = "Reindex job started; poll it at `/v1/backfill/{id}`", body = JobReport),
+        (status = 400, description = "Invalid address, window or mint", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 503, description = "Too many jobs in progress", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::too_many_arguments)]
+async fn handle_reindex(
+    authorization: Option<String>,
+    request: ReindexRequest,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    jobs: Arc<JobRegistry>,
+    permits: BackfillPermits,
+    cache: Option<Arc<BackfillCache>>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = submit_reindex(
+        authorization,
+        request,
+        client,
+        config,
+        store,
+        &jobs,
+        permits,
+        cache,
+    );
+    finish("admin", started, result)
+}
+
+/// Starts a reindex job. The response cache is dropped once it is done,
+/// so nothing computed from the old records is served again.
+#[allow(clippy::too_many_arguments)]
+fn submit_reindex(
+    authorization: Option<String>,
+    request: ReindexRequest,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    jobs: &Arc<JobRegistry>,
+    permits: BackfillPermits,
+    cache: Option<Arc<BackfillCache>>,
+) -> Result<Response, IndexerError> {
+    authorize(&config, authorization.as_deref())?;
+    let wallet = wallet_param(Some(&request.wallet), &config)?;
+    if request.start > request.end {
+        return Err(IndexerError::InvalidWindow(
+            "start must not be after end".to_string(),
+        ));
+    }
+    let window = TimeWindow {
+        start: request.start,
+        end: request.end,
+    };
+    let mints = match (&request.mint, &request.symbol) {
+        (None, None) => config.mints.mints.clone(),
+        (mint, symbol) => vec![config
+            .mints
+            .select(mint.as_deref(), symbol.as_deref())
+            .map_err(IndexerError::InvalidParameter)?
+            .clone()],
+    };
+    let report = jobs.submit(async move {
+        let _permit = permits.acquire().await;
+        let report = reindex(client.as_ref(), &config, &store, &wallet, &mints, window).await?;
+        if let Some(cache) = &cache {
+            cache.clear();
+        }
+        Ok(JobResult::Reindex(report))
+    })?;
+    Ok(job_accepted(&report))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/purge-cache",
+    responses(
+        (status = 202, description = "Purge job started; its result counts the entries dropped", body = JobReport),
+        (status = 401, description = "Missing or wrong admin token", body = ErrorBody),
+        (status = 503, description = "Too many jobs in progress", body = ErrorBody),
+    ),
+    security(("admin_token" = []))
+)]
+async fn handle_purge_cache(
+    authorization: Option<String>,
+    config: Arc<Config>,
+    jobs: Arc<JobRegistry>,
+    cache: Option<Arc<BackfillCache>>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = purge_cache(authorization, &config, &jobs, cache);
+    finish("admin", started, result)
+}
+
+fn purge_cache(
+    authorization: Option<String>,
+    config: &Config,
+    jobs: &Arc<JobRegistry>,
+    cache: Option<Arc<BackfillCache>>,
+) -> Result<Response, IndexerError> {
+    authorize(config, authorization.as_deref())?;
+    let report = jobs.submit(async move {
+        let purged_entries = cache.as_ref().map_or(0, |cache| cache.clear());
+        Ok(JobResult::CachePurge { purged_entries })
+    })?;
+    Ok(job_accepted(&report))
+}
+
+/// `?wallet=` value selecting every tracked wallet at once.
+const ALL_WALLETS: &str = "all";
+
+/// `?wallet=` if given, else the configured wallet.
+pub(crate) fn wallet_param(param: Option<&str>, config: &Config) -> Result<Pubkey, IndexerError> {
+    match param {
+        Some(param) => Pubkey::from_str(param).map_err(|e| IndexerError::InvalidAddress {
+            address: param.to_string(),
+            reason: e.to_string(),
+        }),
+        None => Ok(config.wallet),
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BalanceQuery {
+    pub wallet: Option<String>,
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+    /// `processed`, `confirmed` or `finalized`; the configured commitment
+    /// by default.
+    pub commitment: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/balance",
+    params(BalanceQuery),
+    responses(
+        (status = 200, description = "Current on-chain balance", body = WalletBalance),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_balance(
+    query: BalanceQuery,
+    accept: Option<String>,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = match response_format(None, accept.as_deref(), JSON_ONLY) {
+        Ok(_) => balance_response(query, client.as_ref(), &config).await,
+        Err(e) => Err(e),
+    };
+    finish("balance", started, result)
+}
+
+async fn balance_response(
+    query: BalanceQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+) -> Result<Response, IndexerError> {
+    let balance = balance_for_query(&query, client, config).await?;
+    Ok(warp::reply::json(&balance).into_response())
+}
+
+/// Resolves the wallet, mint and commitment of `query` and reads the
+/// balance.
+pub(crate) async fn balance_for_query(
+    query: &BalanceQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+) -> Result<WalletBalance, IndexerError> {
+    let wallet = wallet_param(query.wallet.as_deref(), config)?;
+    let mint = config
+        .mints
+        .select(query.mint.as_deref(), query.symbol.as_deref())
+        .map_err(IndexerError::InvalidParameter)?;
+    let commitment = commitment_param(query.commitment.as_deref(), config)?;
+    fetch_balance(client, config, &wallet, mint, commitment).await
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BalanceAtQuery {
+    pub wallet: Option<String>,
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+    /// Unix time to reconstruct the balance at; required.
+    pub ts: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/balance_at",
+    params(BalanceAtQuery),
+    responses(
+        (status = 200, description = "The balance at the end of the requested second", body = BalanceAt),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 422, description = "The indexed history doesn't cover the time, or has a gap", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_balance_at(
+    query: BalanceAtQuery,
+    accept: Option<String>,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = match response_format(None, accept.as_deref(), JSON_ONLY) {
+        Ok(_) => balance_at_response(query, client.as_ref(), &config, &store).await,
+        Err(e) => Err(e),
+    };
+    finish("balance_at", started, result)
+}
+
+async fn balance_at_response(
+    query: BalanceAtQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+) -> Result<Response, IndexerError> {
+    let ts = query
+        .ts
+        .ok_or_else(|| IndexerError::InvalidParameter("'ts' is required".to_string()))?;
+    let wallet = wallet_param(query.wallet.as_deref(), config)?;
+    let mint = config
+        .mints
+        .select(query.mint.as_deref(), query.symbol.as_deref())
+        .map_err(IndexerError::InvalidParameter)?;
+    let balance = balance_at(client, config, store, &wallet, mint, ts).await?;
+    Ok(warp::reply::json(&balance).into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BalanceHistoryQuery {
+    pub wallet: Option<String>,
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+    /// `hour` or `day`.
+    pub bucket: String,
+    /// Offset buckets are aligned to, `±HH:MM` or minutes; UTC by default.
+    pub tz_offset: Option<String>,
+    /// Inclusive Unix time bounds; every stored snapshot by default.
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/balance/history",
+    params(BalanceHistoryQuery),
+    responses(
+        (status = 200, description = "The balance snapshots the background indexer stored, one per bucket", body = serde_json::Value),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_balance_history(
+    query: BalanceHistoryQuery,
+    accept: Option<String>,
+    config: Arc<Config>,
+    store: Storage,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = match response_format(None, accept.as_deref(), JSON_ONLY) {
+        Ok(_) => balance_history_response(query, &config, &store).await,
+        Err(e) => Err(e),
+    };
+    finish("balance_history", started, result)
+}
+
+async fn balance_history_response(
+    query: BalanceHistoryQuery,
+    config: &Config,
+    store: &Storage,
+) -> Result<Response, IndexerError> {
+    let size: BucketSize = query
+        .bucket
+        .parse()
+        .map_err(IndexerError::InvalidParameter)?;
+    let offset = match query.tz_offset.as_deref() {
+        Some(value) => parse_tz_offset(value).map_err(IndexerError::InvalidParameter)?,
+        None => FixedOffset::east_opt(0).expect("zero offset is valid"),
+    };
+    let wallet = wallet_param(query.wallet.as_deref(), config)?;
+    let mint = config
+        .mints
+        .select(query.mint.as_deref(), query.symbol.as_deref())
+        .map_err(IndexerError::InvalidParameter)?;
+    let window = TimeWindow {
+        start: query.start.unwrap_or(0),
+        end: query.end.unwrap_or(i64::MAX),
+    };
+    if window.start > window.end {
+        return Err(IndexerError::InvalidParameter(format!(
+            "'start' ({}) is after 'end' ({})",
+            window.start, window.end
+        )));
+    }
+    let snapshots = store
+        .balance_snapshots(&wallet, &mint.mint, &window)
+        .await?;
+    Ok(warp::reply::json(&serde_json::json!({
+        "wallet": wallet.to_string(),
+        "mint": mint.mint.to_string(),
+        "symbol": mint.symbol,
+        "bucket": query.bucket,
+        "tz_offset": offset.to_string(),
+        "points": balance_series(&snapshots, size, offset, mint.decimals),
+    }))
+    .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/approvals",
+    params(BalanceQuery),
+    responses(
+        (status = 200, description = "Outstanding delegations on the wallet's token accounts", body = Delegations),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_approvals(
+    query: BalanceQuery,
+    accept: Option<String>,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = match response_format(None, accept.as_deref(), JSON_ONLY) {
+        Ok(_) => approvals_response(query, client.as_ref(), &config).await,
+        Err(e) => Err(e),
+    };
+    finish("approvals", started, result)
+}
+
+/// The delegations as the token accounts hold them now; past approvals
+/// that were used up or revoked aren't listed.
+async fn approvals_response(
+    query: BalanceQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+) -> Result<Response, IndexerError> {
+    let wallet = wallet_param(query.wallet.as_deref(), config)?;
+    let mint = config
+        .mints
+        .select(query.mint.as_deref(), query.symbol.as_deref())
+        .map_err(IndexerError::InvalidParameter)?;
+    let commitment = commitment_param(query.commitment.as_deref(), config)?;
+    let delegations = fetch_delegations(client, config, &wallet, mint, commitment).await?;
+    Ok(warp::reply::json(&delegations).into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccountEventsQuery {
+    pub wallet: Option<String>,
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+    /// Inclusive Unix time bounds; all of the stored history by default.
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/accounts/events",
+    params(AccountEventsQuery),
+    responses(
+        (status = 200, description = "Creations and closures of the wallet's token accounts, oldest first", body = serde_json::Value),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_account_events(
+    query: AccountEventsQuery,
+    accept: Option<String>,
+    config: Arc<Config>,
+    store: Storage,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = match response_format(None, accept.as_deref(), JSON_ONLY) {
+        Ok(_) => account_events_response(query, &config, &store).await,
+        Err(e) => Err(e),
+    };
+    finish("account_events", started, result)
+}
+
+/// Only what the indexer has come across: an account created before the
+/// indexed history shows up just when it is closed.
+async fn account_events_response(
+    query: AccountEventsQuery,
+    config: &Config,
+    store: &Storage,
+) -> Result<Response, IndexerError> {
+    let wallet = wallet_param(query.wallet.as_deref(), config)?;
+    let mint = config
+        .mints
+        .select(query.mint.as_deref(), query.symbol.as_deref())
+        .map_err(IndexerError::InvalidParameter)?;
+    let window = TimeWindow {
+        start: query.start.unwrap_or(0),
+        end: query.end.unwrap_or(i64::MAX),
+    };
+    if window.start > window.end {
+        return Err(IndexerError::InvalidParameter(format!(
+            "'start' ({}) is after 'end' ({})",
+            window.start, window.end
+        )));
+    }
+    let events = store.account_events(&wallet, &mint.mint, &window).await?;
+    Ok(warp::reply::json(&serde_json::json!({
+        "wallet": wallet.to_string(),
+        "mint": mint.mint.to_string(),
+        "symbol": mint.symbol,
+        "events": events,
+    }))
+    .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Parquet file of the window's transfers, oldest first, a row group per day of block time; the columns are those of ExportRow", body = [ExportRow], content_type = "application/vnd.apache.parquet"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_export(
+    query: ExportQuery,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: BackfillPermits,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = export_response(query, client, config, store, &permits).await;
+    finish("export", started, result)
+}
+
+/// Streams the file a day of block time at a time, each day written and
+/// sent as its own row groups before the next is fetched, like the NDJSON
+/// backfill. The first day is fetched before the response starts; a
+/// failure after that aborts the body, leaving the client a file without
+/// its footer rather than a silently shorter one.
+async fn export_response(
+    query: ExportQuery,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: &BackfillPermits,
+) -> Result<Response, IndexerError> {
+    if let Some(format) = query.format.as_deref().filter(|f| *f != "parquet") {
+        return Err(IndexerError::InvalidParameter(format!(
+            "format {} is not available here, only parquet",
+            format
+        )));
+    }
+    if query.wallet.as_deref() == Some(ALL_WALLETS) {
+        return Err(IndexerError::InvalidParameter(
+            "export needs a single wallet".to_string(),
+        ));
+    }
+    let decimals = config
+        .mints
+        .select(query.mint.as_deref(), query.symbol.as_deref())
+        .map_err(IndexerError::InvalidParameter)?
+        .decimals;
+    let permit = permits.try_acquire()?;
+    let query = query.backfill_query();
+    let anchor = window_anchor(client.as_ref(), &config, query.end).await;
+    let window = TimeWindow::from_query(&query, anchor.time, config.window_hours)
+        .map_err(IndexerError::InvalidWindow)?;
+    let chunk_query = move |chunk: TimeWindow| BackfillQuery {
+        hours: None,
+        start: Some(chunk.start),
+        end: Some(chunk.end),
+        ..query.clone()
+    };
+    let mut export = ParquetExport::new(decimals)?;
+    let mut chunks = window.split(EXPORT_CHUNK_SECS, SortOrder::Asc).into_iter();
+    let first = match chunks.next() {
+        Some(chunk) => {
+            let (_, response) = deadline::run(
+                config.request_timeout,
+                backfill_for_query(&chunk_query(chunk), client.as_ref(), &config, &store),
+            )
+            .await?;
+            export.write(&response.transfers)?
+        }
+        None => Vec::new(),
+    };
+
+    let (sender, body, disconnected) = spawned_body();
+    let task = deadline::scope(disconnected, async move {
+        let _permit = permit;
+        if sender.send(Ok(first.into())).await.is_err() {
+            return;
+        }
+        for chunk in chunks {
+            let bytes =
+                match backfill_for_query(&chunk_query(chunk), client.as_ref(), &config, &store)
+                    .await
+                    .and_then(|(_, response)| Ok(export.write(&response.transfers)?))
+                {
+                    Ok(bytes) => bytes,
+                    Err(IndexerError::Cancelled) => return,
+                    Err(e) => {
+                        warn!(error = %e, "Parquet export failed");
+                        let _ = sender.send(Err(std::io::Error::other(e.to_string()))).await;
+                        return;
+                    }
+                };
+            if sender.send(Ok(bytes.into())).await.is_err() {
+                return;
+            }
+        }
+        match export.finish() {
+            Ok(footer) => {
+                let _ = sender.send(Ok(footer.into())).await;
+            }
+            Err(e) => {
+                warn!(error = %e, "Parquet export failed");
+                let _ = sender.send(Err(std::io::Error::other(e.to_string()))).await;
+            }
+        }
+    });
+    tokio::spawn(task.in_current_span());
+    let mut reply = Response::new(body);
+    let headers = reply.headers_mut();
+    headers.insert(
+        "Content-Type",
+        HeaderValue::from_static(PARQUET_CONTENT_TYPE),
+    );
+    headers.insert(
+        "Content-Disposition",
+        HeaderValue::from_static("attachment; filename=\"transfers.parquet\""),
+    );
+    Ok(reply)
+}
+
+/// Body of `POST /export/s3`.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct S3ExportRequest {
+    pub wallet: Option<String>,
+    pub mint: Option<String>,
+    pub symbol: Option<String>,
+    /// Inclusive Unix time bounds.
+    pub start: i64,
+    pub end: i64,
+    /// `parquet` (the default) or `csv`.
+    pub format: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/export/s3",
+    request_body = S3ExportRequest,
+    responses(
+        (status = 200, description = "The object written, its `bucket`, `key`, `bytes` and `rows`; with `dry_run` nothing was uploaded", body = serde_json::Value),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 404, description = "No S3 bucket is configured", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider or the bucket failed", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_s3_export(
+    request: S3ExportRequest,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    permits: BackfillPermits,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = s3_export_response(request, client.as_ref(), &config, &store, &permits).await;
+    finish("export_s3", started, result)
+}
+
+/// Encodes and uploads the window a day at a time like `/export`, so the
+/// file is never held whole; past [`s3::PART_SIZE`] it goes up as a
+/// multipart upload.
+async fn s3_export_response(
+    request: S3ExportRequest,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+    permits: &BackfillPermits,
+) -> Result<Response, IndexerError> {
+    let Some(target) = &config.s3 else {
+        return Err(IndexerError::NotFound(
+            "no [s3] bucket is configured".to_string(),
+        ));
+    };
+    let format: ExportFormat = request
+        .format
+        .as_deref()
+        .unwrap_or("parquet")
+        .parse()
+        .map_err(IndexerError::InvalidParameter)?;
+    if request.wallet.as_deref() == Some(ALL_WALLETS) {
+        return Err(IndexerError::InvalidParameter(
+            "export needs a single wallet".to_string(),
+        ));
+    }
+    let wallet = wallet_param(request.wallet.as_deref(), config)?;
+    let mint = config
+        .mints
+        .select(request.mint.as_deref(), request.symbol.as_deref())
+        .map_err(IndexerError::InvalidParameter)?;
+    let _permit = permits.try_acquire()?;
+    let query = ExportQuery {
+        wallet: Some(wallet.to_string()),
+        mint: Some(mint.mint.to_string()),
+        symbol: None,
+        format: None,
+        hours: None,
+        start: Some(request.start),
+        end: Some(request.end),
+        include_failed: false,
+        include_mints: true,
+        commitment: None,
+    }
+    .backfill_query();
+    let anchor = window_anchor(client, config, query.end).await;
+    let window = TimeWindow::from_query(&query, anchor.time, config.window_hours)
+        .map_err(IndexerError::InvalidWindow)?;
+
+    let object_store = |e: anyhow::Error| IndexerError::ObjectStore(format!("{:#}", e));
+    let key = s3::object_key(
+        &wallet.to_string(),
+        &mint.symbol,
+        window,
+        format.extension(),
+    );
+    let mut file = FileExport::new(format, mint.decimals)?;
+    let mut upload = Upload::new(target, &key, format.content_type());
+    let written = async {
+        let mut rows = 0;
+        for chunk in window.split(EXPORT_CHUNK_SECS, SortOrder::Asc) {
+            let chunk_query = BackfillQuery {
+                hours: None,
+                start: Some(chunk.start),
+                end: Some(chunk.end),
+                ..query.clone()
+            };
+            let (_, response) = backfill_for_query(&chunk_query, client, config, store).await?;
+            rows += response.transfers.len();
+            let bytes = file.write(&response.transfers)?;
+            upload.write(&bytes).await.map_err(object_store)?;
+        }
+        Ok::<_, IndexerError>(rows)
+    }
+    .await;
+    let rows = match written {
+        Ok(rows) => rows,
+        Err(e) => {
+            upload.abort().await;
+            return Err(e);
+        }
+    };
+    if let Err(e) = upload.write(&file.finish()?).await {
+        upload.abort().await;
+        return Err(object_store(e));
+    }
+    let object = upload.finish().await.map_err(object_store)?;
+    info!(bucket = %object.bucket, key = %object.key, bytes = object.bytes, rows, "exported to S3");
+    Ok(warp::reply::json(&serde_json::json!({
+        "wallet": wallet.to_string(),
+        "mint": mint.mint.to_string(),
+        "symbol": mint.symbol,
+        "window": window,
+        "format": format.extension(),
+        "bucket": object.bucket,
+        "key": object.key,
+        "bytes": object.bytes,
+        "parts": object.parts,
+        "rows": rows,
+        "dry_run": target.dry_run,
+    }))
+    .into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TransactionQuery {
+    /// Only this wallet's transfers; every tracked wallet's by default.
+    pub wallet: Option<String>,
+    /// `instructions` (default), `balances` or `both`.
+    pub strategy: Option<String>,
+    pub commitment: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/tx/{signature}",
+    params(("signature" = String, Path, description = "Transaction signature"), TransactionQuery),
+    responses(
+        (status = 200, description = "The transaction's transfers", body = TransactionEffect),
+        (status = 404, description = "Unknown signature", body = ErrorBody),
+        (status = 422, description = "No transfer for the tracked wallets", body = ErrorBody),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 400, description = "Invalid parameter", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 406, description = "Accept allows none of the formats", body = ErrorBody),
+        (status = 429, description = "Rate limited or at capacity", body = ErrorBody),
+        (status = 502, description = "The RPC provider failed", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_transaction(
+    signature: String,
+    query: TransactionQuery,
+    accept: Option<String>,
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = match response_format(None, accept.as_deref(), JSON_ONLY) {
+        Ok(_) => transaction_response(&signature, query, client.as_ref(), &config, &store).await,
+        Err(e) => Err(e),
+    };
+    finish("tx", started, result)
+}
+
+/// 404 when the cluster doesn't know `signature`, 422 when it moved none
+/// of the tracked mints for the wallets asked about.
+async fn transaction_response(
+    signature: &str,
+    query: TransactionQuery,
+    client: &dyn SolanaRpc,
+    config: &Config,
+    store: &Storage,
+) -> Result<Response, IndexerError> {
+    let wallets = match query.wallet.as_deref() {
+        Some(wallet) => vec![wallet_param(Some(wallet), config)?],
+        None => tracked_wallets(config, store)
+            .await?
+            .into_iter()
+            .map(|tracked| tracked.wallet)
+            .collect(),
+    };
+    let strategy = match query.strategy.as_deref() {
+        Some(strategy) => strategy.parse().map_err(IndexerError::InvalidParameter)?,
+        None => Strategy::default(),
+    };
+    let commitment = commitment_param(query.commitment.as_deref(), config)?;
+    let mut effect =
+        transaction_effect(client, config, signature, &wallets, strategy, commitment).await?;
+    if effect.transfers.is_empty() {
+        return Err(IndexerError::NoRelevantTransfers(format!(
+            "transaction {} moved no tracked mint for the requested wallets",
+            signature
+        )));
+    }
+    for transfer in &mut effect.transfers {
+        let explorer_url = config.explorer_link(&transfer.signature);
+        transfer.annotate(explorer_url);
+    }
+    price::enrich(config, &mut effect.transfers).await;
+    Ok(warp::reply::json(&effect).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/tx/{signature}/raw",
+    params(("signature" = String, Path, description = "Transaction signature")),
+    responses(
+        (status = 200, description = "The `getTransaction` response the stored transfers were parsed from", body = serde_json::Value),
+        (status = 304, description = "Unchanged since the `If-None-Match` tag"),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 404, description = "Not stored, or raw storage is off", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_raw_transaction(
+    signature: String,
+    config: Arc<Config>,
+    store: Storage,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let result = raw_transaction_response(&signature, &config, &store).await;
+    finish("tx_raw", started, result)
+}
+
+async fn raw_transaction_response(
+    signature: &str,
+    config: &Config,
+    store: &Storage,
+) -> Result<Response, IndexerError> {
+    if config.raw_transaction_limit.is_none() {
+        return Err(IndexerError::NotFound(
+            "raw transactions are not stored; set STORE_RAW_TRANSACTIONS to keep them".to_string(),
+        ));
+    }
+    let Some(raw) = store.raw_transaction(signature).await? else {
+        return Err(IndexerError::NotFound(format!(
+            "transaction {} is not stored",
+            signature
+        )));
+    };
+    let json = raw
+        .json()
+        .map_err(|e| IndexerError::Decode(format!("stored transaction {}: {}", signature, e)))?;
+    Ok(warp::reply::with_header(json, "Content-Type", "application/json").into_response())
+}
+
+/// `/stream` filters; unset ones match everything.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    pub wallet: Option<String>,
+    /// `sent`, `received`, `internal`, `minted` or `burned`.
+    pub direction: Option<String>,
+    /// Inclusive lower bound in UI units, e.g. `min_amount=100`.
+    pub min_amount: Option<String>,
+    /// Mint pubkey or symbol.
+    pub mint: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/stream",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "Server-sent `transfer` events carrying a Transfer each", body = Transfer, content_type = "text/event-stream"),
+        (status = 400, description = "Invalid filter", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_stream(
+    query: StreamQuery,
+    last_event_id: Option<u64>,
+    config: Arc<Config>,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    finish(
+        "stream",
+        started,
+        stream_response(query, last_event_id, config),
+    )
+}
+
+/// Server-sent `transfer` events for transfers the background indexer
+/// finds from now on, or, with `Last-Event-ID`, from just after that event
+/// as far as the replay buffer reaches.
+fn stream_response(
+    query: StreamQuery,
+    last_event_id: Option<u64>,
+    config: Arc<Config>,
+) -> Result<Response, IndexerError> {
+    let wallet = match query.wallet.as_deref() {
+        Some(wallet) => Some(wallet_param(Some(wallet), &config)?),
+        None => None,
+    };
+    let filter = resolve_filter(
+        query.direction.as_deref(),
+        query.min_amount.as_deref(),
+        query.mint.as_deref(),
+        &config.mints,
+    )
+    .map_err(|e| IndexerError::InvalidParameter(e.to_string()))?;
+
+    let (missed, feed) = match last_event_id {
+        Some(id) => events::subscribe_after(id),
+        None => (Vec::new(), events::subscribe()),
+    };
+    let events = stream::iter(missed)
+        .chain(events::live(feed))
+        .filter(move |event| {
+            let wanted =
+                wallet.is_none_or(|wallet| event.wallet == wallet) && filter.matches(event);
+            async move { wanted }
+        })
+        .map(move |event| {
+            let mut transfer = event.transfer;
+            transfer.wallet = Some(event.wallet.to_string());
+            let explorer_url = config.explorer_link(&transfer.signature);
+            transfer.annotate(explorer_url);
+            warp::sse::Event::default()
+                .id(event.id.to_string())
+                .event("transfer")
+                .json_data(&transfer)
+        });
+    let keep_alive = warp::sse::keep_alive().interval(STREAM_KEEPALIVE);
+    Ok(warp::sse::reply(keep_alive.stream(events)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/graphql",
+    request_body(content = serde_json::Value, description = "`{query, variables, operationName}`; `GET` takes the same as query parameters"),
+    responses(
+        (status = 200, description = "`{data, errors}`; each error carries the REST error `code` under `extensions`. A websocket upgrade on the same path serves the `transferAdded` subscription", body = serde_json::Value),
+        (status = 400, description = "Not a GraphQL request", body = ErrorBody),
+        (status = 401, description = "Missing or wrong API key", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("api_key" = []))
+)]
+async fn handle_graphql(
+    schema: IndexerSchema,
+    request: async_graphql::Request,
+) -> Result<Response, warp::Rejection> {
+    let started = Instant::now();
+    let response = async_graphql_warp::GraphQLResponse::from(schema.execute(request).await);
+    finish("graphql", started, Ok(response.into_response()))
+}
+
+/// `?commitment=`, defaulting to the configured one.
+fn commitment_param(
+    commitment: Option<&str>,
+    config: &Config,
+) -> Result<CommitmentConfig, IndexerError> {
+    match commitment {
+        Some(level) => parse_commitment(level)
+            .map_err(|e| IndexerError::InvalidParameter(format!("commitment {}", e))),
+        None => Ok(config.commitment),
+    }
+}
+
+/// Body of every error response: `{"error": {code, message, retryable}}`.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ErrorDetail {
+    code: ErrorCode,
+    message: String,
+    /// Whether the same request may succeed later unchanged.
+    retryable: bool,
+    /// How far the scan got before a `TIMEOUT`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<ProgressSnapshot>,
+    /// The request's `X-Request-Id`, to quote when reporting the failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl ErrorBody {
+    fn new(code: ErrorCode, message: String) -> Self {
+        ErrorBody {
+            error: ErrorDetail {
+                code,
+                message,
+                retryable: code.retryable(),
+                progress: None,
+                request_id: None,
+            },
+        }
+    }
+}
+
+impl From<&IndexerError> for ErrorBody {
+    fn from(e: &IndexerError) -> Self {
+        let mut body = ErrorBody::new(e.code(), e.public_message());
+        body.error.progress = e.progress();
+        body
+    }
+}
+
+/// Turns [`IndexerError`] rejections, and warp's own (unknown route, bad
+/// query string or body, wrong method, oversized body), into an
+/// [`ErrorBody`] with the matching status. Rate-limit errors carry a
+/// `Retry-After` header, and timeouts the progress the scan made. What
+/// failed on our side is logged in full and only named in the body.
+async fn handle_rejection(rejection: warp::Rejection) -> Result<Response, warp::Rejection> {
+    let (status, body, retry_after) = if let Some(e) = rejection.find::<IndexerError>() {
+        if e.status().is_server_error() {
+            warn!(code = %e.code(), error = %e, "Request failed");
+        }
+        (e.status(), ErrorBody::from(e), e.retry_after())
+    } else {
+        let (status, code, message) = warp_rejection(&rejection);
+        (status, ErrorBody::new(code, message), None)
+    };
+
+    let reply = warp::reply::with_status(warp::reply::json(&body), status);
+    let mut response = reply.into_response();
+    // Kept for `with_request_id`, as the id isn't known here.
+    response.extensions_mut().insert(body);
+    if status == StatusCode::UNAUTHORIZED {
+        response
+            .headers_mut()
+            .insert("WWW-Authenticate", HeaderValue::from_static("Bearer"));
+    }
+    if let Some(retry_after) = retry_after {
+        response.headers_mut().insert(
+            "Retry-After",
+            retry_after.as_secs().max(1).to_string().parse().unwrap(),
+        );
+    }
+    Ok(response)
+}
+
+/// Puts the request's id into an error response's body.
+fn with_request_id(mut response: Response, id: &str) -> Response {
+    if let Some(mut body) = response.extensions_mut().remove::<ErrorBody>() {
+        body.error.request_id = Some(id.to_string());
+        if let Ok(json) = serde_json::to_vec(&body) {
+            *response.body_mut() = json.into();
+        }
+    }
+    response
+}
+
+/// Status, code and message for the rejections warp's own filters raise.
+fn warp_rejection(rejection: &warp::Rejection) -> (StatusCode, ErrorCode, String) {
+    use warp::reject;
+    if rejection.is_not_found() {
+        (
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound,
+            "no such route".to_string(),
+        )
+    } else if let Some(e) = rejection.find::<reject::InvalidQuery>() {
+        (
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidParameter,
+            e.to_string(),
+        )
+    } else if let Some(e) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+        (
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidBody,
+            e.to_string(),
+        )
+    } else if let Some(e) = rejection.find::<async_graphql_warp::GraphQLBadRequest>() {
+        (
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidParameter,
+            e.0.to_string(),
+        )
+    } else if let Some(e) = rejection.find::<reject::MissingHeader>() {
+        (
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidHeader,
+            e.to_string(),
+        )
+    } else if let Some(e) = rejection.find::<reject::InvalidHeader>() {
+        (
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidHeader,
+            e.to_string(),
+        )
+    } else if rejection.find::<reject::LengthRequired>().is_some() {
+        (
+            StatusCode::LENGTH_REQUIRED,
+            ErrorCode::InvalidHeader,
+            "a Content-Length header is required".to_string(),
+        )
+    } else if rejection.find::<reject::PayloadTooLarge>().is_some() {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::PayloadTooLarge,
+            "request body is too large".to_string(),
+        )
+    } else if let Some(e) = rejection.find::<reject::UnsupportedMediaType>() {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::UnsupportedMediaType,
+            e.to_string(),
+        )
+    } else if let Some(e) = rejection.find::<warp::cors::CorsForbidden>() {
+        (
+            StatusCode::FORBIDDEN,
+            ErrorCode::CorsForbidden,
+            e.to_string(),
+        )
+    } else if rejection.find::<reject::MethodNotAllowed>().is_some() {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::MethodNotAllowed,
+            "method not allowed".to_string(),
+        )
+    } else {
+        warn!(?rejection, "Unhandled rejection");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::InternalError,
+            "internal error".to_string(),
+        )
+    }
+}
+
+/// CORS for the configured origins, or `None` to send no CORS headers.
+/// Preflights are answered here, before routing and authentication.
+fn cors(config: &Config) -> Option<warp::cors::Builder> {
+    if config.cors_origins.is_empty() {
+        return None;
+    }
+    let cors = warp::cors()
+        .allow_methods(["GET", "POST", "DELETE", "OPTIONS"])
+        .allow_headers([
+            "accept",
+            "accept-encoding",
+            "authorization",
+            "content-type",
+            "if-none-match",
+            "last-event-id",
+            "x-api-key",
+            "x-request-id",
+        ])
+        .expose_headers([
+            "deprecation",
+            "etag",
+            "link",
+            "location",
+            "retry-after",
+            "x-cache",
+            "x-next-cursor",
+            "x-pages-scanned",
+            "x-request-id",
+            "x-truncated",
+        ])
+        .max_age(config.cors_max_age);
+    Some(if config.cors_origins.iter().any(|origin| origin == "*") {
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(config.cors_origins.iter().map(String::as_str))
+    })
+}
+
+/// Passes requests carrying one of `keys`, or every request when there are
+/// none.
+fn api_key(keys: ApiKeys) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and_then(
+            move |authorization: Option<String>, api_key: Option<String>| {
+                let checked = keys
+                    .authorize(authorization.as_deref(), api_key.as_deref())
+                    .map_err(warp::reject::custom);
+                async move { checked }
+            },
+        )
+        .untuple_one()
+}
+
+/// Passes requests within the client's rate, when a limit is configured.
+fn rate_limit(config: Arc<Config>) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    let limiter = config
+        .rate_limit_per_minute
+        .map(|rate| Arc::new(RateLimiter::new(rate, config.rate_limit_burst)));
+    client_ip(config)
+        .and_then(move |client: Option<IpAddr>| {
+            let limiter = limiter.clone();
+            async move {
+                let Some(limiter) = limiter else {
+                    return Ok(());
+                };
+                match client {
+                    Some(client) => limiter
+                        .check(client, Instant::now())
+                        .map_err(warp::reject::custom),
+                    None => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// The address a request came from, as [`Allowlist::client`] tells it from
+/// the peer and the headers of the `trusted_proxies`.
+///
+/// [`Allowlist::client`]: crate::allowlist::Allowlist::client
+fn client_ip(
+    config: Arc<Config>,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = warp::Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<tls::Peer>())
+        .and(warp::header::optional::<String>("forwarded"))
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(
+            move |remote: Option<SocketAddr>,
+                  tls: Option<tls::Peer>,
+                  forwarded: Option<String>,
+                  forwarded_for: Option<String>| {
+                let peer = remote.or(tls.map(|tls::Peer(peer)| peer));
+                config
+                    .allowlist
+                    .client(peer, forwarded.as_deref(), forwarded_for.as_deref())
+            },
+        )
+}
+
+/// Passes requests from the allowed networks, or every request when none
+/// are configured.
+fn ip_allowlist(config: Arc<Config>) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    client_ip(config.clone())
+        .and_then(move |client: Option<IpAddr>| {
+            let checked = config
+                .allowlist
+                .check(client, "http")
+                .map_err(warp::reject::custom);
+            async move { checked }
+        })
+        .untuple_one()
+}
+
+/// Answers conditional GETs with `304` and compresses what is sent, for
+/// the routes that return transfers.
+fn negotiated(
+    data: BoxedFilter<(Response,)>,
+    compression_min_bytes: Option<u64>,
+) -> BoxedFilter<(Response,)> {
+    let data = warp::method()
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(data)
+        .then(
+            |method: Method, if_none_match: Option<String>, response: Response| async move {
+                if method == Method::GET {
+                    etag::tag(response, if_none_match.as_deref()).await
+                } else {
+                    response
+                }
+            },
+        );
+    warp::header::optional::<String>("accept-encoding")
+        .and(data)
+        .map(
+            move |accept_encoding: Option<String>, response: Response| match compression_min_bytes {
+                Some(min_bytes) => {
+                    compression::compress(response, accept_encoding.as_deref(), min_bytes)
+                }
+                None => response,
+            },
+        )
+        .boxed()
+}
+
+/// Marks a response of a route kept from before `/v1` as deprecated, with
+/// a `Link` to the one replacing it, and logs the call so the clients still
+/// using it can be found.
+fn deprecated(mut response: Response, successor: &'static str) -> Response {
+    warn!(successor, "deprecated route called");
+    let headers = response.headers_mut();
+    headers.insert("Deprecation", HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor))
+    {
+        headers.insert("Link", link);
+    }
+    response
+}
+
+/// Every route the service exposes, each request in its own span and
+/// recorded in the access log. `permits` are shared with the gRPC server,
+/// so the cap on backfills holds across both.
+pub fn routes(
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    watchlist: Arc<Watchlist>,
+    permits: BackfillPermits,
+) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    let backfill_cache = config
+        .response_cache_ttl
+        .map(|ttl| Arc::new(BackfillCache::new(ttl)));
+    let with_backfill_cache = warp::any().map(move || backfill_cache.clone());
+    let with_admin = warp::header::optional::<String>("authorization");
+    let schema = graphql::schema(
+        client.clone(),
+        config.clone(),
+        store.clone(),
+        permits.clone(),
+    );
+    let with_permits = warp::any().map(move || permits.clone());
+    let rate_limit = rate_limit(config.clone());
+    let allowed = ip_allowlist(config.clone());
+    // Admin routes take the admin token instead, and probes stay open.
+    let authenticated = api_key(config.api_keys.clone());
+    let metrics_authenticated = api_key(config.metrics_api_key.clone());
+    let with_client = warp::any().map(move || client.clone());
+    let with_store = warp::any().map(move || store.clone());
+    let swagger_ui = config.swagger_ui;
+    let compression_min_bytes = config.compression_min_bytes;
+    let cors = cors(&config);
+    let with_config = warp::any().map(move || config.clone());
+    let slot_cache = Arc::new(SlotCache::default());
+    let with_slot_cache = warp::any().map(move || slot_cache.clone());
+    let with_watchlist = warp::any().map(move || watchlist.clone());
+
+    let with_accept = warp::header::optional::<String>("accept");
+    let jobs = Arc::new(JobRegistry::default());
+    let with_jobs = warp::any().map(move || jobs.clone());
+
+    let backfill = warp::path!("backfill")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<BackfillQuery>())
+        .and(with_accept)
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and(with_backfill_cache.clone())
+        .and(with_permits.clone())
+        .and_then(handle_backfill);
+    // `/backfill` from before the API was versioned, kept for the scripts
+    // reading its text lines. Its errors are recovered here so they carry
+    // the deprecation headers too.
+    let legacy_backfill = warp::path!("backfill")
+        .and(warp::get())
+        .and(
+            authenticated
+                .clone()
+                .and(rate_limit.clone())
+                .and(warp::query::<BackfillQuery>())
+                .and(with_client.clone())
+                .and(with_config.clone())
+                .and(with_store.clone())
+                .and(with_backfill_cache.clone())
+                .and(with_permits.clone())
+                .and_then(handle_legacy_backfill)
+                .recover(handle_rejection)
+                .unify(),
+        )
+        .boxed();
+    let submit_backfill_job = warp::path!("backfill")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<BackfillQuery>())
+        .and(with_accept)
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and(with_jobs.clone())
+        .and(with_permits.clone())
+        .and_then(handle_submit_backfill_job);
+    let backfill_job = warp::path!("backfill" / String)
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(with_jobs.clone())
+        .and_then(handle_backfill_job);
+    let cancel_backfill_job = warp::path!("backfill" / String)
+        .and(warp::delete())
+        .and(authenticated.clone())
+        .and(with_jobs.clone())
+        .and_then(handle_cancel_backfill_job);
+    let summary = warp::path("summary")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<BackfillQuery>())
+        .and(with_accept)
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and(with_permits.clone())
+        .and_then(handle_summary);
+    let aggregate = warp::path("aggregate")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<AggregateQuery>())
+        .and(with_accept)
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and(with_permits.clone())
+        .and_then(handle_aggregate);
+    let counterparties = warp::path("counterparties")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<CounterpartiesQuery>())
+        .and(with_accept)
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and(with_permits.clone())
+        .and_then(handle_counterparties);
+    let balance_history = warp::path!("balance" / "history")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<BalanceHistoryQuery>())
+        .and(with_accept)
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and_then(handle_balance_history);
+    let balance = warp::path("balance")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<BalanceQuery>())
+        .and(with_accept)
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and_then(handle_balance);
+    let balance_at = warp::path("balance_at")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<BalanceAtQuery>())
+        .and(with_accept)
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and_then(handle_balance_at);
+    let approvals = warp::path("approvals")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<BalanceQuery>())
+        .and(with_accept)
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and_then(handle_approvals);
+    let account_events = warp::path!("accounts" / "events")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<AccountEventsQuery>())
+        .and(with_accept)
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and_then(handle_account_events);
+    let s3_export = warp::path!("export" / "s3")
+        .and(warp::post())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::body::content_length_limit(MAX_ADMIN_BODY_BYTES))
+        .and(warp::body::json::<S3ExportRequest>())
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and(with_permits.clone())
+        .and_then(handle_s3_export);
+    let export = warp::path("export")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<ExportQuery>())
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and(with_permits.clone())
+        .and_then(handle_export);
+    let transaction = warp::path!("tx" / String)
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(rate_limit.clone())
+        .and(warp::query::<TransactionQuery>())
+        .and(with_accept)
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and_then(handle_transaction);
+    let raw_transaction = warp::path!("tx" / String / "raw")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and_then(handle_raw_transaction);
+    let stream = warp::path!("stream")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(warp::query::<StreamQuery>())
+        .and(warp::sse::last_event_id::<u64>())
+        .and(with_config.clone())
+        .and_then(handle_stream);
+    let graphql_query = warp::get()
+        .and(async_graphql_warp::graphql(schema.clone()))
+        .or(warp::post()
+            .and(warp::body::content_length_limit(MAX_GRAPHQL_BODY_BYTES))
+            .and(async_graphql_warp::graphql(schema.clone())));
+    let graphql = warp::path!("graphql")
+        .and(authenticated.clone())
+        .and(rate_limit)
+        .and(graphql_query.unify())
+        .and_then(|(schema, request)| handle_graphql(schema, request))
+        .boxed();
+    // Checked first: a websocket upgrade is a GET to the same path.
+    let graphql_subscription = warp::path!("graphql")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(async_graphql_warp::graphql_subscription(schema))
+        .map(Reply::into_response)
+        .boxed();
+    let websocket = warp::path!("ws")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(warp::ws())
+        .and(with_config.clone())
+        .map(|ws: warp::ws::Ws, config: Arc<Config>| {
+            ws.max_message_size(ws::MAX_MESSAGE_BYTES)
+                .on_upgrade(move |socket| ws::serve(socket, config))
+        });
+    let list_wallets = warp::path!("wallets")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and_then(handle_list_wallets);
+    let watch_wallet = warp::path!("wallets")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(MAX_ADMIN_BODY_BYTES))
+        .and(warp::body::json::<WatchRequest>())
+        .and(with_config.clone())
+        .and(with_watchlist.clone())
+        .and_then(handle_watch_wallet);
+    let unwatch_wallet = warp::path!("wallets" / String)
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::<UnwatchQuery>())
+        .and(with_config.clone())
+        .and(with_watchlist)
+        .and_then(handle_unwatch_wallet);
+    let reparse = warp::path!("admin" / "reparse")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::<ReparseQuery>())
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and_then(handle_reparse);
+    let reindex = warp::path!("admin" / "reindex")
+        .and(warp::post())
+        .and(with_admin)
+        .and(warp::body::content_length_limit(MAX_ADMIN_BODY_BYTES))
+        .and(warp::body::json::<ReindexRequest>())
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and(with_jobs.clone())
+        .and(with_permits.clone())
+        .and(with_backfill_cache.clone())
+        .and_then(handle_reindex);
+    let purge_cache = warp::path!("admin" / "purge-cache")
+        .and(warp::post())
+        .and(with_admin)
+        .and(with_config.clone())
+        .and(with_jobs)
+        .and(with_backfill_cache)
+        .and_then(handle_purge_cache);
+    let status = warp::path("status")
+        .and(warp::get())
+        .and(authenticated.clone())
+        .and(with_client.clone())
+        .and(with_config.clone())
+        .and(with_store.clone())
+        .and(with_slot_cache.clone())
+        .and_then(handle_status);
+    let metrics = warp::path("metrics")
+        .and(warp::get())
+        .and(metrics_authenticated)
+        .and(with_client.clone())
+        .and(with_slot_cache.clone())
+        .and_then(handle_metrics);
+    // Documentation stays open, like the probes.
+    let spec = Arc::new(ApiDoc::openapi());
+    let openapi = warp::path!("openapi.json")
+        .and(warp::get())
+        .map(move || warp::reply::json(spec.as_ref()).into_response());
+    let docs = warp::path!("docs")
+        .and(warp::get())
+        .and_then(move || async move {
+            if swagger_ui {
+                Ok(warp::reply::html(SWAGGER_UI_HTML).into_response())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        });
+    let healthz = warp::path("healthz")
+        .and(warp::get())
+        .and_then(handle_healthz);
+    let readyz = warp::path("readyz")
+        .and(warp::get())
+        .and(with_client)
+        .and(with_config)
+        .and(with_store)
+        .and(with_slot_cache)
+        .and_then(handle_readyz);
+
+    // The routes that return transfers; the rest answer with a few bytes or
+    // manage their own framing.
+    let data = backfill_job
+        .or(cancel_backfill_job)
+        .unify()
+        .or(submit_backfill_job)
+        .unify()
+        .or(backfill)
+        .unify()
+        .or(summary)
+        .unify()
+        .or(aggregate)
+        .unify()
+        .or(counterparties)
+        .unify()
+        .or(balance_history)
+        .unify()
+        .or(balance)
+        .unify()
+        .or(balance_at)
+        .unify()
+        .or(approvals)
+        .unify()
+        .or(account_events)
+        .unify()
+        .or(s3_export)
+        .unify()
+        .or(export)
+        .unify()
+        .or(transaction)
+        .unify()
+        .or(raw_transaction)
+        .unify()
+        .or(graphql)
+        .unify()
+        // Boxed to keep the composed filter type within the compiler's limits.
+        .boxed();
+    let data = negotiated(data, compression_min_bytes);
+    let legacy_backfill = negotiated(legacy_backfill, compression_min_bytes)
+        .map(|response: Response| deprecated(response, "/v1/backfill"));
+    // Everything but the probes, metrics, docs and admin routes is
+    // versioned. A `/v2` would mount the routes it changes in front of a
+    // clone of `v1` under its own prefix, reusing the others as they are.
+    let v1 = graphql_subscription
+        .or(data)
+        .or(stream)
+        .or(websocket)
+        .or(list_wallets)
+        .or(watch_wallet)
+        .or(unwatch_wallet)
+        .or(status)
+        .map(Reply::into_response)
+        .boxed();
+    // Probes answer from anywhere, so the platform's health checks don't
+    // have to be allowlisted.
+    let routes = allowed
+        .and(
+            warp::path("v1")
+                .and(v1)
+                .or(legacy_backfill)
+                .or(reparse)
+                .or(reindex)
+                .or(purge_cache)
+                .or(metrics)
+                .or(openapi)
+                .or(docs),
+        )
+        .or(healthz)
+        .or(readyz)
+        .recover(handle_rejection)
+        .map(Reply::into_response);
+    // Outside the recovery so error responses are readable cross-origin too.
+    let routes = match cors {
+        Some(cors) => routes
+            .with(cors)
+            .map(Reply::into_response)
+            .recover(handle_rejection)
+            .unify()
+            .boxed(),
+        None => routes.boxed(),
+    };
+    request_id::filter()
+        .and(routes)
+        .map(|request: RequestLog, response: Response| {
+            let response = with_request_id(response, &request.id);
+            request.finish(response)
+        })
+        .with(warp::trace(request_id::span))
+}
@@ -0,0 +1,483 @@
+//! Totals computed over indexed transfers. Everything is summed in base
+//! units and only converted to decimal strings at the end.
+
+use async_graphql::SimpleObject;
+use chrono::{FixedOffset, TimeZone};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use crate::model::{
+    format_amount, format_signed_amount, BalanceSnapshot, Direction, NetworkFee, TimeWindow,
+    Transfer,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct DirectionCounts {
+    pub received: usize,
+    pub sent: usize,
+    pub internal: usize,
+    pub minted: usize,
+    pub burned: usize,
+}
+
+/// Body of `GET /summary`. Internal transfers are counted and summed on
+/// their own but don't move the totals; mints count as received and burns
+/// as sent, without a counterparty.
+#[derive(Debug, Clone, Serialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct Summary {
+    pub total_received: String,
+    pub total_sent: String,
+    /// `total_received - total_sent`; negative when the wallet paid out more.
+    pub net_flow: String,
+    /// Moved between the wallet's own token accounts.
+    pub internal_volume: String,
+    pub counts: DirectionCounts,
+    pub largest_transfer: Option<Transfer>,
+    pub distinct_counterparties: usize,
+    /// SOL fees the wallet paid for its outgoing transfers, each
+    /// transaction counted once.
+    pub network_fees: NetworkFee,
+}
+
+impl Summary {
+    pub fn from_transfers(transfers: &[Transfer], decimals: u8) -> Self {
+        let mut received: u128 = 0;
+        let mut sent: u128 = 0;
+        let mut internal: u128 = 0;
+        let mut counts = DirectionCounts::default();
+        let mut counterparties = HashSet::new();
+        let mut fee_paying = HashMap::new();
+        for transfer in transfers {
+            if let Some(fee) = &transfer.network_fee {
+                fee_paying.insert(transfer.signature.as_str(), fee.lamports);
+            }
+            match transfer.direction {
+                Direction::Received => {
+                    received += u128::from(transfer.amount_raw);
+                    counts.received += 1;
+                }
+                Direction::Sent => {
+                    sent += u128::from(transfer.amount_raw);
+                    counts.sent += 1;
+                }
+                Direction::Internal => {
+                    internal += u128::from(transfer.amount_raw);
+                    counts.internal += 1;
+                    continue;
+                }
+                Direction::Minted => {
+                    received += u128::from(transfer.amount_raw);
+                    counts.minted += 1;
+                    continue;
+                }
+                Direction::Burned => {
+                    sent += u128::from(transfer.amount_raw);
+                    counts.burned += 1;
+                    continue;
+                }
+            }
+            counterparties.insert(transfer.counterparty_address().0);
+        }
+        let largest_transfer = transfers
+            .iter()
+            .filter(|t| t.direction != Direction::Internal)
+            .max_by_key(|t| t.amount_raw)
+            .cloned();
+
+        Summary {
+            total_received: format_amount(received, decimals),
+            total_sent: format_amount(sent, decimals),
+            net_flow: format_signed_amount(received as i128 - sent as i128, decimals),
+            internal_volume: format_amount(internal, decimals),
+            counts,
+            largest_transfer,
+            distinct_counterparties: counterparties.len(),
+            network_fees: NetworkFee::new(fee_paying.values().sum()),
+        }
+    }
+}
+
+/// One row of `GET /counterparties`.
+#[derive(Debug, Clone, Serialize, SimpleObject, ToSchema)]
+#[graphql(rename_fields = "snake_case")]
+pub struct CounterpartyTotals {
+    pub address: String,
+    /// `address` is the owner wallet; `false` means the owner was unknown
+    /// and `address` is the token account itself.
+    pub is_owner: bool,
+    pub received: String,
+    pub sent: String,
+    /// `received + sent`, which the list is sorted by.
+    pub volume: String,
+    pub received_count: usize,
+    pub sent_count: usize,
+}
+
+/// Groups transfers with another holder by counterparty and returns the `limit`
+/// largest by volume (ties broken by address for a stable order).
+pub fn top_counterparties(
+    transfers: &[Transfer],
+    decimals: u8,
+    limit: usize,
+) -> Vec<CounterpartyTotals> {
+    #[derive(Default)]
+    struct Totals {
+        received: u128,
+        sent: u128,
+        received_count: usize,
+        sent_count: usize,
+    }
+    let mut by_address: HashMap<(&str, bool), Totals> = HashMap::new();
+    for transfer in transfers {
+        if transfer.direction == Direction::Internal || transfer.direction.is_supply_change() {
+            continue;
+        }
+        let totals = by_address
+            .entry(transfer.counterparty_address())
+            .or_default();
+        if transfer.direction == Direction::Received {
+            totals.received += u128::from(transfer.amount_raw);
+            totals.received_count += 1;
+        } else {
+            totals.sent += u128::from(transfer.amount_raw);
+            totals.sent_count += 1;
+        }
+    }
+
+    let mut rows: Vec<_> = by_address.into_iter().collect();
+    rows.sort_by(|((a, _), x), ((b, _), y)| {
+        (y.received + y.sent)
+            .cmp(&(x.received + x.sent))
+            .then_with(|| a.cmp(b))
+    });
+    rows.into_iter()
+        .take(limit)
+        .map(|((address, is_owner), totals)| CounterpartyTotals {
+            address: address.to_string(),
+            is_owner,
+            received: format_amount(totals.received, decimals),
+            sent: format_amount(totals.sent, decimals),
+            volume: format_amount(totals.received + totals.sent, decimals),
+            received_count: totals.received_count,
+            sent_count: totals.sent_count,
+        })
+        .collect()
+}
+
+/// Width of an `/aggregate` bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BucketSize {
+    Hour,
+    Day,
+}
+
+impl BucketSize {
+    pub fn seconds(self) -> i64 {
+        match self {
+            BucketSize::Hour => 3600,
+            BucketSize::Day => 86_400,
+        }
+    }
+}
+
+impl FromStr for BucketSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(BucketSize::Hour),
+            "day" => Ok(BucketSize::Day),
+            other => Err(format!(
+                "unsupported bucket '{}', expected hour or day",
+                other
+            )),
+        }
+    }
+}
+
+/// One `/balance/history` point: the last snapshot taken in its bucket.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BalancePoint {
+    /// Start of the bucket in the requested offset, RFC 3339.
+    pub bucket_start: String,
+    pub slot: u64,
+    pub taken_at: i64,
+    pub amount_raw: u64,
+    pub amount: String,
+    /// Base units of the bucket's balance changes its transfers don't
+    /// explain, summed over its snapshots; `null` when none was checked.
+    pub unexplained_raw: Option<i64>,
+}
+
+/// The snapshots, oldest first, as one point per bucket that has any.
+pub fn balance_series(
+    snapshots: &[BalanceSnapshot],
+    size: BucketSize,
+    offset: FixedOffset,
+    decimals: u8,
+) -> Vec<BalancePoint> {
+    let width = size.seconds();
+    let shift = i64::from(offset.local_minus_utc());
+    let mut points: Vec<(i64, BalancePoint)> = Vec::new();
+    for snapshot in snapshots {
+        let bucket = (snapshot.taken_at + shift).div_euclid(width);
+        let unexplained = match points.last() {
+            Some((last, point)) if *last == bucket => {
+                match (point.unexplained_raw, snapshot.unexplained_raw) {
+                    (Some(a), Some(b)) => Some(a.saturating_add(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+            _ => snapshot.unexplained_raw,
+        };
+        let point = BalancePoint {
+            bucket_start: offset
+                .timestamp_opt(bucket * width - shift, 0)
+                .single()
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_default(),
+            slot: snapshot.slot,
+            taken_at: snapshot.taken_at,
+            amount_raw: snapshot.amount_raw,
+            amount: format_amount(snapshot.amount_raw, decimals),
+            unexplained_raw: unexplained,
+        };
+        match points.last_mut() {
+            Some((last, previous)) if *last == bucket => *previous = point,
+            _ => points.push((bucket, point)),
+        }
+    }
+    points.into_iter().map(|(_, point)| point).collect()
+}
+
+/// Parses a `tz_offset` of either `±HH:MM` or whole minutes east of UTC.
+/// A leading space is read as `+`, since that's what an unescaped `+`
+/// decodes to in a query string.
+pub fn parse_tz_offset(value: &str) -> Result<FixedOffset, String> {
+    let invalid = || {
+        format!(
+            "invalid tz_offset '{}', expected ±HH:MM or minutes east of UTC",
+            value
+        )
+    };
+    let value = match value.strip_prefix(' ') {
+        Some(rest) => format!("+{}", rest),
+        None => value.to_string(),
+    };
+    let offset = match value.parse::<i32>() {
+        Ok(minutes) => minutes.checked_mul(60).and_then(FixedOffset::east_opt),
+        Err(_) => FixedOffset::from_str(&value).ok(),
+    };
+    offset.ok_or_else(invalid)
+}
+
+/// One `/aggregate` bucket. `count` includes internal transfers when they
+/// were asked for; the amounts never do.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Bucket {
+    /// Start of the bucket in the requested offset, RFC 3339.
+    pub bucket_start: String,
+    pub received: String,
+    pub sent: String,
+    pub net: String,
+    pub count: usize,
+}
+
+/// Buckets `transfers` by block time, aligned to midnight/the hour in
+/// `offset`. Every bucket overlapping `window` is emitted, empty or not.
+pub fn aggregate(
+    transfers: &[Transfer],
+    window: TimeWindow,
+    size: BucketSize,
+    offset: FixedOffset,
+    decimals: u8,
+) -> Vec<Bucket> {
+    let width = size.seconds();
+    let shift = i64::from(offset.local_minus_utc());
+    let index = |timestamp: i64| (timestamp + shift).div_euclid(width);
+    let first = index(window.start);
+    let last = index(window.end);
+
+    let mut totals = vec![(0u128, 0u128, 0usize); (last - first + 1) as usize];
+    for transfer in transfers {
+        let bucket = index(transfer.block_time);
+        if bucket < first || bucket > last {
+            continue;
+        }
+        let entry = &mut totals[(bucket - first) as usize];
+        if transfer.direction.is_inflow() {
+            entry.0 += u128::from(transfer.amount_raw);
+        } else if transfer.direction.is_outflow() {
+            entry.1 += u128::from(transfer.amount_raw);
+        }
+        entry.2 += 1;
+    }
+
+    totals
+        .into_iter()
+        .enumerate()
+        .map(|(i, (received, sent, count))| {
+            let start = (first + i as i64) * width - shift;
+            Bucket {
+                bucket_start: offset
+                    .timestamp_opt(start, 0)
+                    .single()
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_default(),
+                received: format_amount(received, decimals),
+                sent: format_amount(sent, decimals),
+                net: format_signed_amount(received as i128 - sent as i128, decimals),
+                count,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::transfer;
+
+    #[test]
+    fn totals_are_exact_and_net_can_go_negative() {
+        let paid = Transfer {
+            network_fee: Some(NetworkFee::new(5000)),
+            ..transfer(Direction::Sent, 3_000_000, "bob")
+        };
+        let transfers = [
+            transfer(Direction::Received, 1_000_001, "alice"),
+            paid,
+            transfer(Direction::Received, 2, "alice"),
+            transfer(Direction::Internal, 9_000_000, "wallet-other-ata"),
+        ];
+        let summary = Summary::from_transfers(&transfers, 6);
+        assert_eq!(summary.total_received, "1.000003");
+        assert_eq!(summary.total_sent, "3.000000");
+        assert_eq!(summary.net_flow, "-1.999997");
+        assert_eq!(summary.internal_volume, "9.000000");
+        assert_eq!(summary.network_fees, NetworkFee::new(5000));
+        assert_eq!(summary.network_fees.sol, "0.000005000");
+        assert_eq!(
+            summary.counts,
+            DirectionCounts {
+                received: 2,
+                sent: 1,
+                internal: 1,
+                ..DirectionCounts::default()
+            }
+        );
+        assert_eq!(summary.largest_transfer.unwrap().amount_raw, 3_000_000);
+        assert_eq!(summary.distinct_counterparties, 2);
+    }
+
+    #[test]
+    fn buckets_by_local_day_and_fills_gaps() {
+        let day = 86_400;
+        let mut early = transfer(Direction::Received, 5, "alice");
+        early.block_time = 2 * day + 3600;
+        let mut late = transfer(Direction::Sent, 2, "bob");
+        late.block_time = 4 * day - 1800;
+        let window = TimeWindow {
+            start: 2 * day,
+            end: 4 * day - 1,
+        };
+
+        let utc = aggregate(
+            &[early.clone(), late.clone()],
+            window,
+            BucketSize::Day,
+            FixedOffset::east_opt(0).unwrap(),
+            6,
+        );
+        assert_eq!(utc.len(), 2);
+        assert_eq!(utc[0].bucket_start, "1970-01-03T00:00:00+00:00");
+        assert_eq!((utc[0].received.as_str(), utc[0].count), ("0.000005", 1));
+        assert_eq!((utc[1].net.as_str(), utc[1].count), ("-0.000002", 1));
+
+        // At UTC-02:00 the first transfer falls on the previous local day
+        // and the middle day is empty.
+        let offset = parse_tz_offset("-02:00").unwrap();
+        let local = aggregate(&[early, late], window, BucketSize::Day, offset, 6);
+        assert_eq!(local.len(), 3);
+        assert_eq!(local[0].bucket_start, "1970-01-02T00:00:00-02:00");
+        assert_eq!(local[0].count, 1);
+        assert_eq!((local[1].count, local[1].net.as_str()), (0, "0.000000"));
+        assert_eq!(local[2].count, 1);
+    }
+
+    #[test]
+    fn keeps_the_last_snapshot_of_each_bucket() {
+        let snapshot = |taken_at, amount_raw, unexplained_raw| BalanceSnapshot {
+            slot: taken_at as u64,
+            taken_at,
+            amount_raw,
+            transfer_count: 0,
+            unexplained_raw,
+        };
+        let snapshots = [
+            snapshot(3600, 10, None),
+            snapshot(3 * 3600 + 60, 20, Some(0)),
+            snapshot(3 * 3600 + 120, 25, Some(-3)),
+            snapshot(3 * 3600 + 180, 30, Some(1)),
+        ];
+        let points = balance_series(
+            &snapshots,
+            BucketSize::Hour,
+            FixedOffset::east_opt(0).unwrap(),
+            6,
+        );
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].bucket_start, "1970-01-01T01:00:00+00:00");
+        assert_eq!(points[0].unexplained_raw, None);
+        assert_eq!(points[1].bucket_start, "1970-01-01T03:00:00+00:00");
+        assert_eq!(
+            (points[1].amount.as_str(), points[1].slot),
+            ("0.000030", 10_980)
+        );
+        assert_eq!(points[1].unexplained_raw, Some(-2));
+    }
+
+    #[test]
+    fn ranks_counterparties_by_volume_grouping_by_owner() {
+        let mut first = transfer(Direction::Received, 5, "alice-ata-1");
+        first.counterparty_owner = Some("alice".to_string());
+        let mut second = transfer(Direction::Sent, 3, "alice-ata-2");
+        second.counterparty_owner = Some("alice".to_string());
+        let transfers = [
+            first,
+            second,
+            transfer(Direction::Received, 7, "unknown-ata"),
+            transfer(Direction::Internal, 100, "wallet-other-ata"),
+        ];
+
+        let top = top_counterparties(&transfers, 6, 20);
+        assert_eq!(top.len(), 2);
+        assert_eq!((top[0].address.as_str(), top[0].is_owner), ("alice", true));
+        assert_eq!(top[0].volume, "0.000008");
+        assert_eq!((top[0].received_count, top[0].sent_count), (1, 1));
+        assert_eq!(
+            (top[1].address.as_str(), top[1].is_owner),
+            ("unknown-ata", false)
+        );
+
+        assert_eq!(top_counterparties(&transfers, 6, 1).len(), 1);
+    }
+
+    #[test]
+    fn parses_tz_offsets() {
+        assert_eq!(parse_tz_offset(" 05:30").unwrap().local_minus_utc(), 19_800);
+        assert_eq!(parse_tz_offset("-480").unwrap().local_minus_utc(), -28_800);
+        assert!(parse_tz_offset("EST").is_err());
+    }
+
+    #[test]
+    fn empty_window_has_zero_totals() {
+        let summary = Summary::from_transfers(&[], 6);
+        assert_eq!(summary.net_flow, "0.000000");
+        assert!(summary.largest_transfer.is_none());
+    }
+}
@@ -0,0 +1,1147 @@
+//! Persistence for indexed transfers, token account lifecycle events,
+//! balance snapshots and per-wallet sync cursors.
+//!
+//! Everything above this module goes through [`TransferStore`]; the backend
+//! is picked by [`open`] from the configuration.
+
+mod postgres;
+mod sqlite;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::FixedOffset;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::{ColumnIndex, Decode, Row, Type};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub use postgres::PostgresStore;
+pub use sqlite::SqliteStore;
+
+use crate::config::{Config, MintInfo, MAX_WINDOW_SECS};
+use crate::model::{
+    format_amount, sort_transfers, AccountEvent, BackfillRequest, BalanceSnapshot, NetworkFee,
+    SortOrder, TimeWindow, Transfer, TransferFee,
+};
+use crate::raw::RawTransaction;
+use crate::stats::{self, Bucket, BucketSize};
+
+/// Time range of chain history already persisted for one wallet/mint pair.
+/// Everything between `indexed_from` and `indexed_until` is in the store;
+/// `newest_signature` is the cursor where the next incremental fetch stops.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncState {
+    pub indexed_from: i64,
+    pub indexed_until: i64,
+    pub newest_signature: Option<String>,
+}
+
+/// A wallet added through `POST /wallets`, kept so it is indexed again
+/// after a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedWallet {
+    pub wallet: String,
+    /// Unix time it was added.
+    pub added_at: i64,
+}
+
+/// A stored transaction read below `finalized`, due to be checked again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnfinalizedSignature {
+    pub signature: String,
+    pub slot: u64,
+    pub commitment: String,
+    pub failed: bool,
+    pub orphaned: bool,
+}
+
+/// Where indexed transfers, sync cursors and runtime wallets are kept.
+#[async_trait]
+pub trait TransferStore: Send + Sync + fmt::Debug {
+    /// Stores `transfers` of `wallet`/`mint`, replacing any earlier record
+    /// of the same event.
+    async fn insert_batch(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        transfers: &[Transfer],
+    ) -> Result<()>;
+
+    /// Stored transfers matching `request`'s wallet, mint, window and
+    /// failed/orphaned filters, oldest first.
+    async fn query_transfers(&self, request: &BackfillRequest) -> Result<Vec<Transfer>>;
+
+    async fn sync_state(&self, wallet: &Pubkey, mint: &Pubkey) -> Result<Option<SyncState>>;
+
+    async fn set_sync_state(&self, wallet: &Pubkey, mint: &Pubkey, state: &SyncState)
+        -> Result<()>;
+
+    /// Every cursor as `(wallet, mint, state)`.
+    async fn list_sync_states(&self) -> Result<Vec<(String, String, SyncState)>>;
+
+    async fn count_transfers(&self, wallet: &Pubkey, mint: &Pubkey) -> Result<u64>;
+
+    /// Which of `signatures` a sync of `wallet`/`mint` has already read and
+    /// stored the transfers of.
+    async fn processed_signatures(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        signatures: &[String],
+    ) -> Result<HashSet<String>>;
+
+    /// Records `(signature, block_time)` pairs as read for `wallet`/`mint`.
+    /// Deleting the transfers of a window or purging the wallet forgets
+    /// them again.
+    async fn mark_processed(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        signatures: &[(String, i64)],
+    ) -> Result<()>;
+
+    /// Stores lifecycle events of `wallet`'s token accounts of `mint`,
+    /// replacing any earlier record of the same instruction.
+    async fn insert_account_events(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        events: &[AccountEvent],
+    ) -> Result<()>;
+
+    /// Stored lifecycle events of `wallet`'s token accounts of `mint` inside
+    /// `window`, oldest first. They're few and say which accounts are open,
+    /// so unlike transfers they're kept past the longest window.
+    async fn account_events(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        window: &TimeWindow,
+    ) -> Result<Vec<AccountEvent>>;
+
+    async fn insert_balance_snapshot(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        snapshot: &BalanceSnapshot,
+    ) -> Result<()>;
+
+    /// Stored balance snapshots of `wallet`/`mint` taken inside `window`,
+    /// oldest first. Like account events they're kept past the longest
+    /// window.
+    async fn balance_snapshots(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        window: &TimeWindow,
+    ) -> Result<Vec<BalanceSnapshot>>;
+
+    async fn latest_balance_snapshot(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<Option<BalanceSnapshot>>;
+
+    /// `/aggregate` buckets over the stored transfers `request` selects.
+    async fn aggregate(
+        &self,
+        request: &BackfillRequest,
+        size: BucketSize,
+        offset: FixedOffset,
+    ) -> Result<Vec<Bucket>> {
+        let transfers = self.query_transfers(request).await?;
+        Ok(stats::aggregate(
+            &transfers,
+            request.window,
+            size,
+            offset,
+            request.mint.decimals,
+        ))
+    }
+
+    /// One entry per signature of `wallet` stored below `finalized`,
+    /// oldest first.
+    async fn unfinalized_signatures(&self, wallet: &Pubkey) -> Result<Vec<UnfinalizedSignature>>;
+
+    /// Records what re-checking `signature` found, on every transfer it
+    /// produced: `commitment` is `None` once finalized, and `orphaned` marks
+    /// a transaction the cluster no longer has or that landed with an error.
+    async fn set_confirmation(
+        &self,
+        wallet: &Pubkey,
+        signature: &str,
+        commitment: Option<&str>,
+        orphaned: bool,
+    ) -> Result<()>;
+
+    /// Replaces every transfer of `wallet`/`mint` stored from one of
+    /// `signatures` with `transfers`, so records a reparse no longer finds
+    /// go away.
+    async fn replace_transfers(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        signatures: &[String],
+        transfers: &[Transfer],
+    ) -> Result<()>;
+
+    /// Deletes `wallet`'s transfers of `mint` inside `window`, cursor left
+    /// alone, and returns how many there were.
+    async fn delete_transfers(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        window: &TimeWindow,
+    ) -> Result<u64>;
+
+    /// Deletes every transfer, account event and cursor stored for `wallet`.
+    async fn purge_wallet(&self, wallet: &Pubkey) -> Result<()>;
+
+    /// Wallets added at runtime, oldest first. Without a database or
+    /// STATE_PATH they are forgotten on restart.
+    async fn watched_wallets(&self) -> Result<Vec<WatchedWallet>>;
+
+    async fn add_watched_wallet(&self, watched: &WatchedWallet) -> Result<()>;
+
+    async fn remove_watched_wallet(&self, wallet: &Pubkey) -> Result<()>;
+
+    /// Stores `raw`, replacing any transaction kept under the same
+    /// signature, then drops the lowest slots beyond `limit`.
+    async fn put_raw_transactions(&self, raw: &[RawTransaction], limit: usize) -> Result<()>;
+
+    async fn raw_transaction(&self, signature: &str) -> Result<Option<RawTransaction>>;
+
+    /// Up to `limit` stored transactions whose signature sorts after
+    /// `after`, in signature order, for paging through all of them.
+    async fn raw_transactions(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<RawTransaction>>;
+
+    /// Persists anything still buffered and releases the backing store.
+    async fn close(&self) -> Result<()>;
+}
+
+/// The shared handle the server and background tasks hold.
+pub type Storage = Arc<dyn TransferStore>;
+
+/// Opens the store the configuration asks for: Postgres or SQLite by the
+/// DATABASE_URL scheme, SQLite at DATABASE_PATH, or memory otherwise.
+pub async fn open(config: &Config) -> Result<Storage> {
+    Ok(match (&config.database_url, &config.database_path) {
+        (Some(url), _) if url.starts_with("postgres://") || url.starts_with("postgresql://") => {
+            Arc::new(PostgresStore::open(url).await?)
+        }
+        (Some(url), _) => Arc::new(SqliteStore::open_url(url).await?),
+        (None, Some(path)) => Arc::new(SqliteStore::open(path).await?),
+        (None, None) => Arc::new(MemoryStore::open(config.state_path.clone()).await?),
+    })
+}
+
+/// A `transfers` row, as both SQL backends select it.
+fn transfer_from_row<'r, R>(row: &'r R, mint: &MintInfo) -> Result<Transfer>
+where
+    R: Row,
+    &'static str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    bool: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+{
+    let inner_index: i64 = row.try_get("inner_index")?;
+    let amount_raw = u64::try_from(row.try_get::<i64, _>("amount_raw")?)?;
+    let direction: String = row.try_get("direction")?;
+    let fee = match row.try_get::<Option<i64>, _>("fee_raw")? {
+        Some(fee_raw) => Some(
+            TransferFee::new(amount_raw, u64::try_from(fee_raw)?, mint.decimals)
+                .ok_or_else(|| anyhow::anyhow!("stored fee exceeds the amount"))?,
+        ),
+        None => None,
+    };
+    Ok(Transfer {
+        signature: row.try_get("signature")?,
+        slot: u64::try_from(row.try_get::<i64, _>("slot")?)?,
+        block_time: row.try_get("block_time")?,
+        instruction_index: usize::try_from(row.try_get::<i64, _>("instruction_index")?)?,
+        inner_index: usize::try_from(inner_index).ok(),
+        direction: direction.parse()?,
+        amount_raw,
+        amount_ui: format_amount(amount_raw, mint.decimals),
+        source: row.try_get("source")?,
+        destination: row.try_get("destination")?,
+        mint: row.try_get("mint")?,
+        symbol: mint.symbol.clone(),
+        fee,
+        counterparty_owner: row.try_get("counterparty_owner")?,
+        wallet: None,
+        memo: row.try_get("memo")?,
+        commitment: row.try_get("commitment")?,
+        fee_payer: row.try_get("fee_payer")?,
+        network_fee: row
+            .try_get::<Option<i64>, _>("network_fee_lamports")?
+            .map(u64::try_from)
+            .transpose()?
+            .map(NetworkFee::new),
+        explorer_url: None,
+        counterparty: None,
+        failed: row.try_get("failed")?,
+        orphaned: row.try_get("orphaned")?,
+        via_delegate: row.try_get("via_delegate")?,
+        balance_after_raw: None,
+        balance_after: None,
+        balance_excluded: false,
+        price_usd: None,
+        value_usd: None,
+    })
+}
+
+fn sync_state_from_row<'r, R>(row: &'r R) -> Result<SyncState>
+where
+    R: Row,
+    &'static str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+{
+    Ok(SyncState {
+        indexed_from: row.try_get("indexed_from")?,
+        indexed_until: row.try_get("indexed_until")?,
+        newest_signature: row.try_get("newest_signature")?,
+    })
+}
+
+fn unfinalized_from_row<'r, R>(row: &'r R) -> Result<UnfinalizedSignature>
+where
+    R: Row,
+    &'static str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    bool: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+{
+    Ok(UnfinalizedSignature {
+        signature: row.try_get("signature")?,
+        slot: u64::try_from(row.try_get::<i64, _>("slot")?)?,
+        commitment: row.try_get("commitment")?,
+        failed: row.try_get("failed")?,
+        orphaned: row.try_get("orphaned")?,
+    })
+}
+
+fn raw_transaction_from_row<'r, R>(row: &'r R) -> Result<RawTransaction>
+where
+    R: Row,
+    &'static str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+    Vec<u8>: Decode<'r, R::Database> + Type<R::Database>,
+{
+    Ok(RawTransaction {
+        signature: row.try_get("signature")?,
+        slot: u64::try_from(row.try_get::<i64, _>("slot")?)?,
+        data: row.try_get("data")?,
+    })
+}
+
+fn account_event_from_row<'r, R>(row: &'r R) -> Result<AccountEvent>
+where
+    R: Row,
+    &'static str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+{
+    let kind: String = row.try_get("kind")?;
+    let inner_index: i64 = row.try_get("inner_index")?;
+    Ok(AccountEvent {
+        signature: row.try_get("signature")?,
+        slot: u64::try_from(row.try_get::<i64, _>("slot")?)?,
+        block_time: row.try_get("block_time")?,
+        instruction_index: usize::try_from(row.try_get::<i64, _>("instruction_index")?)?,
+        inner_index: usize::try_from(inner_index).ok(),
+        kind: kind.parse()?,
+        token_account: row.try_get("token_account")?,
+        mint: row.try_get("mint")?,
+        rent_account: row.try_get("rent_account")?,
+    })
+}
+
+fn balance_snapshot_from_row<'r, R>(row: &'r R) -> Result<BalanceSnapshot>
+where
+    R: Row,
+    &'static str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    Option<i64>: Decode<'r, R::Database> + Type<R::Database>,
+{
+    Ok(BalanceSnapshot {
+        slot: u64::try_from(row.try_get::<i64, _>("slot")?)?,
+        taken_at: row.try_get("taken_at")?,
+        amount_raw: u64::try_from(row.try_get::<i64, _>("amount_raw")?)?,
+        transfer_count: u64::try_from(row.try_get::<i64, _>("transfer_count")?)?,
+        unexplained_raw: row.try_get("unexplained_raw")?,
+    })
+}
+
+/// Orders account events for [`TransferStore::account_events`].
+fn sort_account_events(events: &mut [AccountEvent]) {
+    events.sort_by_key(|e| {
+        (
+            e.slot,
+            e.instruction_index,
+            e.inner_index.map_or(0, |i| i + 1),
+        )
+    });
+}
+
+fn watched_wallet_from_row<'r, R>(row: &'r R) -> Result<WatchedWallet>
+where
+    R: Row,
+    &'static str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+{
+    Ok(WatchedWallet {
+        wallet: row.try_get("wallet")?,
+        added_at: row.try_get("added_at")?,
+    })
+}
+
+/// Cursor plus transfers for one wallet/mint pair in the in-memory store.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MemoryEntry {
+    wallet: String,
+    mint: String,
+    state: SyncState,
+    transfers: Vec<Transfer>,
+    /// Processed signature → block time.
+    #[serde(default)]
+    processed: HashMap<String, i64>,
+    #[serde(default)]
+    account_events: Vec<AccountEvent>,
+    /// Oldest first.
+    #[serde(default)]
+    balance_snapshots: Vec<BalanceSnapshot>,
+}
+
+impl MemoryEntry {
+    fn new(wallet: &Pubkey, mint: &Pubkey) -> Self {
+        MemoryEntry {
+            wallet: wallet.to_string(),
+            mint: mint.to_string(),
+            state: SyncState::default(),
+            transfers: Vec::new(),
+            processed: HashMap::new(),
+            account_events: Vec::new(),
+            balance_snapshots: Vec::new(),
+        }
+    }
+}
+
+/// Contents of the STATE_PATH file.
+#[derive(Debug, Serialize)]
+struct Snapshot<'a> {
+    entries: Vec<&'a MemoryEntry>,
+    watched_wallets: &'a [WatchedWallet],
+}
+
+/// A STATE_PATH file as read back; snapshots written before wallets could
+/// be added at runtime are a bare list of entries.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SavedSnapshot {
+    Current {
+        entries: Vec<MemoryEntry>,
+        #[serde(default)]
+        watched_wallets: Vec<WatchedWallet>,
+    },
+    Entries(Vec<MemoryEntry>),
+}
+
+/// Process-local store used when no database is configured. When STATE_PATH
+/// is set the whole index is snapshotted to that JSON file after every sync
+/// and reloaded at startup, so restarts resume from the saved cursor. Raw
+/// transactions are not part of the snapshot.
+#[derive(Debug)]
+pub struct MemoryStore {
+    state_path: Option<PathBuf>,
+    entries: RwLock<HashMap<(String, String), MemoryEntry>>,
+    watched_wallets: RwLock<Vec<WatchedWallet>>,
+    raw_transactions: RwLock<BTreeMap<String, RawTransaction>>,
+}
+
+impl MemoryStore {
+    pub async fn open(state_path: Option<PathBuf>) -> Result<Self> {
+        let mut entries = HashMap::new();
+        let mut watched_wallets = Vec::new();
+        if let Some(path) = &state_path {
+            match tokio::fs::read(path).await {
+                Ok(bytes) => {
+                    let saved = match serde_json::from_slice(&bytes)? {
+                        SavedSnapshot::Current {
+                            entries,
+                            watched_wallets: watched,
+                        } => {
+                            watched_wallets = watched;
+                            entries
+                        }
+                        SavedSnapshot::Entries(entries) => entries,
+                    };
+                    for entry in saved {
+                        entries.insert((entry.wallet.clone(), entry.mint.clone()), entry);
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(MemoryStore {
+            state_path,
+            entries: RwLock::new(entries),
+            watched_wallets: RwLock::new(watched_wallets),
+            raw_transactions: RwLock::default(),
+        })
+    }
+
+    /// Writes the snapshot file, if configured.
+    pub async fn flush(&self) -> Result<()> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+        let snapshot = {
+            let entries = self.entries.read().await;
+            let watched_wallets = self.watched_wallets.read().await;
+            serde_json::to_vec(&Snapshot {
+                entries: entries.values().collect(),
+                watched_wallets: &watched_wallets,
+            })?
+        };
+        // Write-then-rename so a crash mid-write can't corrupt the cursor.
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, snapshot).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransferStore for MemoryStore {
+    async fn insert_batch(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        transfers: &[Transfer],
+    ) -> Result<()> {
+        let mut entries = self.entries.write().await;
+        let entry = entries
+            .entry((wallet.to_string(), mint.to_string()))
+            .or_insert_with(|| MemoryEntry::new(wallet, mint));
+        let incoming: HashSet<_> = transfers.iter().map(Transfer::event_key).collect();
+        entry
+            .transfers
+            .retain(|t| !incoming.contains(&t.event_key()));
+        entry.transfers.extend(transfers.iter().cloned());
+        Ok(())
+    }
+
+    async fn query_transfers(&self, request: &BackfillRequest) -> Result<Vec<Transfer>> {
+        let entries = self.entries.read().await;
+        let key = (request.wallet.to_string(), request.mint.mint.to_string());
+        let Some(entry) = entries.get(&key) else {
+            return Ok(Vec::new());
+        };
+        let mut transfers: Vec<Transfer> = entry
+            .transfers
+            .iter()
+            .filter(|t| t.block_time >= request.window.start && t.block_time <= request.window.end)
+            .filter(|t| request.include_failed || !t.failed)
+            .filter(|t| request.include_orphaned || !t.orphaned)
+            .cloned()
+            .collect();
+        sort_transfers(&mut transfers, SortOrder::Asc);
+        Ok(transfers)
+    }
+
+    async fn sync_state(&self, wallet: &Pubkey, mint: &Pubkey) -> Result<Option<SyncState>> {
+        let entries = self.entries.read().await;
+        Ok(entries
+            .get(&(wallet.to_string(), mint.to_string()))
+            .map(|entry| entry.state.clone())
+            .filter(|state| state.indexed_until > 0))
+    }
+
+    /// Records the new cursor, drops history older than the longest window
+    /// anyone can query, and writes the snapshot file if configured.
+    async fn set_sync_state(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        state: &SyncState,
+    ) -> Result<()> {
+        {
+            let mut entries = self.entries.write().await;
+            let horizon = state.indexed_until - MAX_WINDOW_SECS;
+            if let Some(entry) = entries.get_mut(&(wallet.to_string(), mint.to_string())) {
+                entry.state = state.clone();
+                entry.state.indexed_from = entry.state.indexed_from.max(horizon);
+                entry.transfers.retain(|t| t.block_time >= horizon);
+                entry
+                    .processed
+                    .retain(|_, block_time| *block_time >= horizon);
+            }
+        }
+        self.flush().await
+    }
+
+    async fn list_sync_states(&self) -> Result<Vec<(String, String, SyncState)>> {
+        let entries = self.entries.read().await;
+        Ok(entries
+            .values()
+            .map(|entry| {
+                (
+                    entry.wallet.clone(),
+                    entry.mint.clone(),
+                    entry.state.clone(),
+                )
+            })
+            .collect())
+    }
+
+    async fn count_transfers(&self, wallet: &Pubkey, mint: &Pubkey) -> Result<u64> {
+        let entries = self.entries.read().await;
+        Ok(entries
+            .get(&(wallet.to_string(), mint.to_string()))
+            .map_or(0, |entry| entry.transfers.len() as u64))
+    }
+
+    async fn processed_signatures(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        signatures: &[String],
+    ) -> Result<HashSet<String>> {
+        let entries = self.entries.read().await;
+        let Some(entry) = entries.get(&(wallet.to_string(), mint.to_string())) else {
+            return Ok(HashSet::new());
+        };
+        Ok(signatures
+            .iter()
+            .filter(|s| entry.processed.contains_key(*s))
+            .cloned()
+            .collect())
+    }
+
+    async fn mark_processed(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        signatures: &[(String, i64)],
+    ) -> Result<()> {
+        if signatures.is_empty() {
+            return Ok(());
+        }
+        let mut entries = self.entries.write().await;
+        let entry = entries
+            .entry((wallet.to_string(), mint.to_string()))
+            .or_insert_with(|| MemoryEntry::new(wallet, mint));
+        entry.processed.extend(signatures.iter().cloned());
+        Ok(())
+    }
+
+    async fn insert_account_events(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        events: &[AccountEvent],
+    ) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let mut entries = self.entries.write().await;
+        let entry = entries
+            .entry((wallet.to_string(), mint.to_string()))
+            .or_insert_with(|| MemoryEntry::new(wallet, mint));
+        let key = |e: &AccountEvent| (e.signature.clone(), e.instruction_index, e.inner_index);
+        let incoming: HashSet<_> = events.iter().map(key).collect();
+        entry.account_events.retain(|e| !incoming.contains(&key(e)));
+        entry.account_events.extend(events.iter().cloned());
+        sort_account_events(&mut entry.account_events);
+        Ok(())
+    }
+
+    async fn account_events(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        window: &TimeWindow,
+    ) -> Result<Vec<AccountEvent>> {
+        let entries = self.entries.read().await;
+        Ok(entries
+            .get(&(wallet.to_string(), mint.to_string()))
+            .map(|entry| {
+                entry
+                    .account_events
+                    .iter()
+                    .filter(|e| e.block_time >= window.start && e.block_time <= window.end)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn insert_balance_snapshot(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        snapshot: &BalanceSnapshot,
+    ) -> Result<()> {
+        let mut entries = self.entries.write().await;
+        let entry = entries
+            .entry((wallet.to_string(), mint.to_string()))
+            .or_insert_with(|| MemoryEntry::new(wallet, mint));
+        entry.balance_snapshots.retain(|s| s.slot != snapshot.slot);
+        entry.balance_snapshots.push(snapshot.clone());
+        entry.balance_snapshots.sort_by_key(|s| s.slot);
+        Ok(())
+    }
+
+    async fn balance_snapshots(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        window: &TimeWindow,
+    ) -> Result<Vec<BalanceSnapshot>> {
+        let entries = self.entries.read().await;
+        Ok(entries
+            .get(&(wallet.to_string(), mint.to_string()))
+            .map(|entry| {
+                entry
+                    .balance_snapshots
+                    .iter()
+                    .filter(|s| s.taken_at >= window.start && s.taken_at <= window.end)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn latest_balance_snapshot(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<Option<BalanceSnapshot>> {
+        let entries = self.entries.read().await;
+        Ok(entries
+            .get(&(wallet.to_string(), mint.to_string()))
+            .and_then(|entry| entry.balance_snapshots.last().cloned()))
+    }
+
+    async fn unfinalized_signatures(&self, wallet: &Pubkey) -> Result<Vec<UnfinalizedSignature>> {
+        let entries = self.entries.read().await;
+        let wallet = wallet.to_string();
+        let mut found: HashMap<&str, UnfinalizedSignature> = HashMap::new();
+        for (transfer, commitment) in entries
+            .values()
+            .filter(|entry| entry.wallet == wallet)
+            .flat_map(|entry| &entry.transfers)
+            .filter_map(|t| Some((t, t.commitment.as_ref()?)))
+        {
+            found
+                .entry(&transfer.signature)
+                .or_insert_with(|| UnfinalizedSignature {
+                    signature: transfer.signature.clone(),
+                    slot: transfer.slot,
+                    commitment: commitment.clone(),
+                    failed: transfer.failed,
+                    orphaned: transfer.orphaned,
+                });
+        }
+        let mut found: Vec<_> = found.into_values().collect();
+        found.sort_by_key(|s| s.slot);
+        Ok(found)
+    }
+
+    async fn set_confirmation(
+        &self,
+        wallet: &Pubkey,
+        signature: &str,
+        commitment: Option<&str>,
+        orphaned: bool,
+    ) -> Result<()> {
+        {
+            let mut entries = self.entries.write().await;
+            let wallet = wallet.to_string();
+            for transfer in entries
+                .values_mut()
+                .filter(|entry| entry.wallet == wallet)
+                .flat_map(|entry| &mut entry.transfers)
+                .filter(|t| t.signature == signature)
+            {
+                transfer.commitment = commitment.map(str::to_string);
+                transfer.orphaned = orphaned;
+            }
+        }
+        self.flush().await
+    }
+
+    async fn replace_transfers(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        signatures: &[String],
+        transfers: &[Transfer],
+    ) -> Result<()> {
+        {
+            let mut entries = self.entries.write().await;
+            if let Some(entry) = entries.get_mut(&(wallet.to_string(), mint.to_string())) {
+                let replaced: HashSet<&str> = signatures.iter().map(String::as_str).collect();
+                entry
+                    .transfers
+                    .retain(|t| !replaced.contains(t.signature.as_str()));
+            }
+        }
+        self.insert_batch(wallet, mint, transfers).await?;
+        self.flush().await
+    }
+
+    async fn delete_transfers(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        window: &TimeWindow,
+    ) -> Result<u64> {
+        let removed = {
+            let mut entries = self.entries.write().await;
+            let Some(entry) = entries.get_mut(&(wallet.to_string(), mint.to_string())) else {
+                return Ok(0);
+            };
+            let before = entry.transfers.len();
+            entry
+                .transfers
+                .retain(|t| t.block_time < window.start || t.block_time > window.end);
+            entry
+                .processed
+                .retain(|_, block_time| *block_time < window.start || *block_time > window.end);
+            (before - entry.transfers.len()) as u64
+        };
+        self.flush().await?;
+        Ok(removed)
+    }
+
+    async fn purge_wallet(&self, wallet: &Pubkey) -> Result<()> {
+        let wallet = wallet.to_string();
+        self.entries
+            .write()
+            .await
+            .retain(|(entry_wallet, _), _| *entry_wallet != wallet);
+        self.flush().await
+    }
+
+    async fn watched_wallets(&self) -> Result<Vec<WatchedWallet>> {
+        Ok(self.watched_wallets.read().await.clone())
+    }
+
+    async fn add_watched_wallet(&self, watched: &WatchedWallet) -> Result<()> {
+        {
+            let mut watched_wallets = self.watched_wallets.write().await;
+            if watched_wallets.iter().any(|w| w.wallet == watched.wallet) {
+                return Ok(());
+            }
+            watched_wallets.push(watched.clone());
+        }
+        self.flush().await
+    }
+
+    async fn remove_watched_wallet(&self, wallet: &Pubkey) -> Result<()> {
+        let wallet = wallet.to_string();
+        self.watched_wallets
+            .write()
+            .await
+            .retain(|w| w.wallet != wallet);
+        self.flush().await
+    }
+
+    async fn put_raw_transactions(&self, raw: &[RawTransaction], limit: usize) -> Result<()> {
+        let mut stored = self.raw_transactions.write().await;
+        for tx in raw {
+            stored.insert(tx.signature.clone(), tx.clone());
+        }
+        if stored.len() > limit {
+            let mut by_slot: Vec<(u64, String)> = stored
+                .values()
+                .map(|tx| (tx.slot, tx.signature.clone()))
+                .collect();
+            by_slot.sort();
+            for (_, signature) in &by_slot[..by_slot.len() - limit] {
+                stored.remove(signature);
+            }
+        }
+        Ok(())
+    }
+
+    async fn raw_transaction(&self, signature: &str) -> Result<Option<RawTransaction>> {
+        Ok(self.raw_transactions.read().await.get(signature).cloned())
+    }
+
+    async fn raw_transactions(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<RawTransaction>> {
+        let stored = self.raw_transactions.read().await;
+        let range = match after {
+            Some(after) => stored.range::<str, _>((Bound::Excluded(after), Bound::Unbounded)),
+            None => stored.range::<str, _>(..),
+        };
+        Ok(range.take(limit).map(|(_, tx)| tx.clone()).collect())
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::transfer;
+    use crate::model::{AccountEventKind, Direction, Strategy};
+    use solana_sdk::commitment_config::CommitmentConfig;
+
+    /// Runs the same round trip against every backend that needs no server.
+    async fn round_trip(store: Storage) {
+        let mint = crate::config::MintRegistry::parse(crate::config::DEFAULT_MINTS)
+            .unwrap()
+            .mints
+            .remove(0);
+        let wallet = Pubkey::new_unique();
+        let mut early = transfer(Direction::Received, 1_500_000, "alice");
+        early.block_time = 100;
+        let mut late = transfer(Direction::Sent, 500_000, "bob");
+        late.block_time = 200;
+        late.failed = true;
+        for t in [&mut early, &mut late] {
+            t.mint = mint.mint.to_string();
+        }
+        store
+            .insert_batch(&wallet, &mint.mint, &[early.clone(), late])
+            .await
+            .unwrap();
+        // Inserting a record again replaces it.
+        store
+            .insert_batch(&wallet, &mint.mint, &[early])
+            .await
+            .unwrap();
+        assert_eq!(store.count_transfers(&wallet, &mint.mint).await.unwrap(), 2);
+
+        let request = BackfillRequest {
+            wallet,
+            mint: mint.clone(),
+            window: TimeWindow {
+                start: 0,
+                end: 3600,
+            },
+            until: None,
+            include_failed: false,
+            include_orphaned: false,
+            strategy: Strategy::Instructions,
+            commitment: CommitmentConfig::confirmed(),
+        };
+        let found = store.query_transfers(&request).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].amount_raw, 1_500_000);
+        let buckets = store
+            .aggregate(
+                &BackfillRequest {
+                    include_failed: true,
+                    ..request.clone()
+                },
+                BucketSize::Hour,
+                FixedOffset::east_opt(0).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].count, 2);
+        assert_eq!(buckets[0].net, "1.000000");
+
+        let state = SyncState {
+            indexed_from: 0,
+            indexed_until: 3600,
+            newest_signature: Some("sig-500000".to_string()),
+        };
+        store
+            .set_sync_state(&wallet, &mint.mint, &state)
+            .await
+            .unwrap();
+        let stored = store
+            .sync_state(&wallet, &mint.mint)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.newest_signature.as_deref(), Some("sig-500000"));
+        assert_eq!(store.list_sync_states().await.unwrap().len(), 1);
+
+        let mut reparsed = found[0].clone();
+        reparsed.instruction_index = 1;
+        store
+            .replace_transfers(
+                &wallet,
+                &mint.mint,
+                &[found[0].signature.clone()],
+                &[reparsed],
+            )
+            .await
+            .unwrap();
+        let found = store.query_transfers(&request).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].instruction_index, 1);
+        store
+            .mark_processed(
+                &wallet,
+                &mint.mint,
+                &[("early".to_string(), 100), ("late".to_string(), 200)],
+            )
+            .await
+            .unwrap();
+        let asked = ["early".to_string(), "late".to_string(), "new".to_string()];
+        let processed = store
+            .processed_signatures(&wallet, &mint.mint, &asked)
+            .await
+            .unwrap();
+        assert_eq!(processed.len(), 2);
+        assert!(!processed.contains("new"));
+
+        let outside = TimeWindow {
+            start: 150,
+            end: 3600,
+        };
+        assert_eq!(
+            store
+                .delete_transfers(&wallet, &mint.mint, &outside)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(store.count_transfers(&wallet, &mint.mint).await.unwrap(), 1);
+        // Deleting a window forgets its signatures, so they are read again.
+        let processed = store
+            .processed_signatures(&wallet, &mint.mint, &asked)
+            .await
+            .unwrap();
+        assert_eq!(processed, HashSet::from(["early".to_string()]));
+
+        let lifecycle = |signature: &str, slot, kind, account: &str| AccountEvent {
+            signature: signature.to_string(),
+            slot,
+            block_time: slot as i64 * 100,
+            instruction_index: 0,
+            inner_index: None,
+            kind,
+            token_account: account.to_string(),
+            mint: mint.mint.to_string(),
+            rent_account: Some(wallet.to_string()),
+        };
+        let events = [
+            lifecycle("open-a", 1, AccountEventKind::Created, "a"),
+            lifecycle("open-b", 2, AccountEventKind::Created, "b"),
+            lifecycle("close-a", 3, AccountEventKind::Closed, "a"),
+        ];
+        store
+            .insert_account_events(&wallet, &mint.mint, &events)
+            .await
+            .unwrap();
+        store
+            .insert_account_events(&wallet, &mint.mint, &events[..1])
+            .await
+            .unwrap();
+        let all_time = TimeWindow {
+            start: 0,
+            end: i64::MAX,
+        };
+        let stored = store
+            .account_events(&wallet, &mint.mint, &all_time)
+            .await
+            .unwrap();
+        assert_eq!(stored, events);
+        let open = crate::model::open_token_accounts(&stored);
+        assert_eq!(open.len(), 1);
+        assert_eq!((open[0].address.as_str(), open[0].created_slot), ("b", 2));
+
+        let snapshot = |slot, taken_at| BalanceSnapshot {
+            slot,
+            taken_at,
+            amount_raw: 1_000,
+            transfer_count: 1,
+            unexplained_raw: Some(-5),
+        };
+        for s in [snapshot(20, 2000), snapshot(10, 1000), snapshot(10, 1100)] {
+            store
+                .insert_balance_snapshot(&wallet, &mint.mint, &s)
+                .await
+                .unwrap();
+        }
+        let snapshots = store
+            .balance_snapshots(&wallet, &mint.mint, &all_time)
+            .await
+            .unwrap();
+        assert_eq!(snapshots, [snapshot(10, 1100), snapshot(20, 2000)]);
+        assert_eq!(
+            store
+                .latest_balance_snapshot(&wallet, &mint.mint)
+                .await
+                .unwrap(),
+            Some(snapshot(20, 2000))
+        );
+
+        let raw = |signature: &str, slot| RawTransaction {
+            signature: signature.to_string(),
+            slot,
+            data: vec![slot as u8],
+        };
+        store
+            .put_raw_transactions(&[raw("c", 3), raw("a", 1), raw("b", 2)], 2)
+            .await
+            .unwrap();
+        assert_eq!(store.raw_transaction("a").await.unwrap(), None);
+        assert_eq!(store.raw_transaction("c").await.unwrap(), Some(raw("c", 3)));
+        let page = store.raw_transactions(None, 1).await.unwrap();
+        assert_eq!(page, [raw("b", 2)]);
+        let page = store.raw_transactions(Some("b"), 10).await.unwrap();
+        assert_eq!(page, [raw("c", 3)]);
+
+        store.purge_wallet(&wallet).await.unwrap();
+        assert_eq!(store.count_transfers(&wallet, &mint.mint).await.unwrap(), 0);
+        assert!(store
+            .processed_signatures(&wallet, &mint.mint, &asked)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .account_events(&wallet, &mint.mint, &all_time)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store
+                .latest_balance_snapshot(&wallet, &mint.mint)
+                .await
+                .unwrap(),
+            None
+        );
+        store.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn memory_and_sqlite_stores_behave_alike() {
+        round_trip(Arc::new(MemoryStore::open(None).await.unwrap())).await;
+        let path = std::env::temp_dir().join(format!("store-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sqlite = SqliteStore::open_url(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        round_trip(Arc::new(sqlite)).await;
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,652 @@
+//! Postgres backend, for hosts with ephemeral disks and a managed database.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use solana_sdk::pubkey::Pubkey;
+use sqlx::postgres::{PgPool, PgPoolOptions, Postgres};
+use sqlx::Transaction;
+use std::collections::HashSet;
+
+use super::{
+    account_event_from_row, balance_snapshot_from_row, raw_transaction_from_row,
+    sync_state_from_row, transfer_from_row, unfinalized_from_row, watched_wallet_from_row,
+    SyncState, TransferStore, UnfinalizedSignature, WatchedWallet,
+};
+use crate::model::{AccountEvent, BackfillRequest, BalanceSnapshot, TimeWindow, Transfer};
+use crate::raw::RawTransaction;
+
+/// Schema changes applied in order on startup, tracked in `schema_version`
+/// like the SQLite ones. The first one is the SQLite schema as of when
+/// Postgres support was added.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE transfers (
+        wallet TEXT NOT NULL,
+        signature TEXT NOT NULL,
+        instruction_index BIGINT NOT NULL,
+        inner_index BIGINT NOT NULL,
+        slot BIGINT NOT NULL,
+        block_time BIGINT NOT NULL,
+        direction TEXT NOT NULL,
+        amount_raw BIGINT NOT NULL,
+        source TEXT NOT NULL,
+        destination TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        failed BOOLEAN NOT NULL,
+        counterparty_owner TEXT,
+        fee_raw BIGINT,
+        memo TEXT,
+        fee_payer TEXT,
+        network_fee_lamports BIGINT,
+        commitment TEXT,
+        orphaned BOOLEAN NOT NULL DEFAULT FALSE,
+        PRIMARY KEY (wallet, signature, instruction_index, inner_index)
+    );
+    CREATE INDEX transfers_by_time ON transfers (wallet, mint, block_time);
+    CREATE TABLE sync_state (
+        wallet TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        indexed_from BIGINT NOT NULL,
+        indexed_until BIGINT NOT NULL,
+        newest_signature TEXT,
+        PRIMARY KEY (wallet, mint)
+    );
+    CREATE TABLE watched_wallets (
+        wallet TEXT PRIMARY KEY,
+        added_at BIGINT NOT NULL
+    );",
+    "CREATE TABLE raw_transactions (
+        signature TEXT PRIMARY KEY,
+        slot BIGINT NOT NULL,
+        data BYTEA NOT NULL
+    );
+    CREATE INDEX raw_transactions_by_slot ON raw_transactions (slot);",
+    "CREATE TABLE processed_signatures (
+        wallet TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        signature TEXT NOT NULL,
+        block_time BIGINT NOT NULL,
+        PRIMARY KEY (wallet, mint, signature)
+    );
+    CREATE INDEX processed_signatures_by_time
+        ON processed_signatures (wallet, mint, block_time);",
+    "ALTER TABLE transfers ADD COLUMN via_delegate BOOLEAN NOT NULL DEFAULT FALSE;",
+    "CREATE TABLE account_events (
+        wallet TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        signature TEXT NOT NULL,
+        instruction_index BIGINT NOT NULL,
+        inner_index BIGINT NOT NULL,
+        slot BIGINT NOT NULL,
+        block_time BIGINT NOT NULL,
+        kind TEXT NOT NULL,
+        token_account TEXT NOT NULL,
+        rent_account TEXT,
+        PRIMARY KEY (wallet, signature, instruction_index, inner_index)
+    );
+    CREATE INDEX account_events_by_time ON account_events (wallet, mint, block_time);",
+    "CREATE TABLE balance_snapshots (
+        wallet TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        slot BIGINT NOT NULL,
+        taken_at BIGINT NOT NULL,
+        amount_raw BIGINT NOT NULL,
+        transfer_count BIGINT NOT NULL,
+        unexplained_raw BIGINT,
+        PRIMARY KEY (wallet, mint, slot)
+    );
+    CREATE INDEX balance_snapshots_by_time ON balance_snapshots (wallet, mint, taken_at);",
+];
+
+/// Postgres-backed transfer store, enabled by a `postgres://` DATABASE_URL.
+#[derive(Debug, Clone)]
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub async fn open(url: &str) -> Result<Self> {
+        let pool = PgPoolOptions::new().max_connections(8).connect(url).await?;
+        let store = PostgresStore { pool };
+        store.migrate().await?;
+        Ok(store)
+    }
+
+    pub async fn migrate(&self) -> Result<()> {
+        sqlx::query("CREATE TABLE IF NOT EXISTS schema_version (version BIGINT NOT NULL)")
+            .execute(&self.pool)
+            .await?;
+        let current: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_version")
+            .fetch_one(&self.pool)
+            .await?;
+        let current = current.unwrap_or(0) as usize;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            let mut tx = self.pool.begin().await?;
+            sqlx::raw_sql(migration).execute(&mut *tx).await?;
+            sqlx::query("INSERT INTO schema_version (version) VALUES ($1)")
+                .bind(index as i64 + 1)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Upserts `transfers` of `wallet` as part of `tx`.
+async fn insert_rows(
+    tx: &mut Transaction<'_, Postgres>,
+    wallet: &str,
+    transfers: &[Transfer],
+) -> Result<()> {
+    for transfer in transfers {
+        sqlx::query(
+            "INSERT INTO transfers (wallet, signature, instruction_index, inner_index, slot,
+                    block_time, direction, amount_raw, source, destination, mint, failed,
+                    counterparty_owner, fee_raw, memo, fee_payer, network_fee_lamports,
+                    commitment, orphaned, via_delegate)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16,
+                    $17, $18, $19, $20)
+                 ON CONFLICT (wallet, signature, instruction_index, inner_index) DO UPDATE SET
+                    slot = EXCLUDED.slot, block_time = EXCLUDED.block_time,
+                    direction = EXCLUDED.direction, amount_raw = EXCLUDED.amount_raw,
+                    source = EXCLUDED.source, destination = EXCLUDED.destination,
+                    mint = EXCLUDED.mint, failed = EXCLUDED.failed,
+                    counterparty_owner = EXCLUDED.counterparty_owner,
+                    fee_raw = EXCLUDED.fee_raw, memo = EXCLUDED.memo,
+                    fee_payer = EXCLUDED.fee_payer,
+                    network_fee_lamports = EXCLUDED.network_fee_lamports,
+                    commitment = EXCLUDED.commitment, orphaned = EXCLUDED.orphaned,
+                    via_delegate = EXCLUDED.via_delegate",
+        )
+        .bind(wallet)
+        .bind(&transfer.signature)
+        .bind(transfer.instruction_index as i64)
+        .bind(transfer.inner_index.map_or(-1, |i| i as i64))
+        .bind(i64::try_from(transfer.slot)?)
+        .bind(transfer.block_time)
+        .bind(transfer.direction.as_str())
+        .bind(i64::try_from(transfer.amount_raw)?)
+        .bind(&transfer.source)
+        .bind(&transfer.destination)
+        .bind(&transfer.mint)
+        .bind(transfer.failed)
+        .bind(&transfer.counterparty_owner)
+        .bind(
+            transfer
+                .fee
+                .as_ref()
+                .map(|fee| i64::try_from(fee.fee_raw))
+                .transpose()?,
+        )
+        .bind(&transfer.memo)
+        .bind(&transfer.fee_payer)
+        .bind(
+            transfer
+                .network_fee
+                .as_ref()
+                .map(|fee| i64::try_from(fee.lamports))
+                .transpose()?,
+        )
+        .bind(&transfer.commitment)
+        .bind(transfer.orphaned)
+        .bind(transfer.via_delegate)
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl TransferStore for PostgresStore {
+    async fn insert_batch(
+        &self,
+        wallet: &Pubkey,
+        _mint: &Pubkey,
+        transfers: &[Transfer],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        insert_rows(&mut tx, &wallet.to_string(), transfers).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn query_transfers(&self, request: &BackfillRequest) -> Result<Vec<Transfer>> {
+        let rows = sqlx::query(
+            "SELECT signature, instruction_index, inner_index, slot, block_time, direction,
+                amount_raw, source, destination, mint, failed, counterparty_owner, fee_raw,
+                memo, fee_payer, network_fee_lamports, commitment, orphaned, via_delegate
+             FROM transfers
+             WHERE wallet = $1 AND mint = $2 AND block_time BETWEEN $3 AND $4
+                AND ($5 OR NOT failed) AND ($6 OR NOT orphaned)
+             ORDER BY block_time, slot, signature, instruction_index, inner_index",
+        )
+        .bind(request.wallet.to_string())
+        .bind(request.mint.mint.to_string())
+        .bind(request.window.start)
+        .bind(request.window.end)
+        .bind(request.include_failed)
+        .bind(request.include_orphaned)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| transfer_from_row(row, &request.mint))
+            .collect()
+    }
+
+    async fn sync_state(&self, wallet: &Pubkey, mint: &Pubkey) -> Result<Option<SyncState>> {
+        let row = sqlx::query(
+            "SELECT indexed_from, indexed_until, newest_signature FROM sync_state
+             WHERE wallet = $1 AND mint = $2",
+        )
+        .bind(wallet.to_string())
+        .bind(mint.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+        row.as_ref().map(sync_state_from_row).transpose()
+    }
+
+    async fn list_sync_states(&self) -> Result<Vec<(String, String, SyncState)>> {
+        let rows = sqlx::query(
+            "SELECT wallet, mint, indexed_from, indexed_until, newest_signature FROM sync_state",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                Ok((
+                    sqlx::Row::try_get(row, "wallet")?,
+                    sqlx::Row::try_get(row, "mint")?,
+                    sync_state_from_row(row)?,
+                ))
+            })
+            .collect()
+    }
+
+    async fn set_sync_state(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        state: &SyncState,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO sync_state (wallet, mint, indexed_from, indexed_until, newest_signature)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (wallet, mint) DO UPDATE SET
+                indexed_from = EXCLUDED.indexed_from,
+                indexed_until = EXCLUDED.indexed_until,
+                newest_signature = EXCLUDED.newest_signature",
+        )
+        .bind(wallet.to_string())
+        .bind(mint.to_string())
+        .bind(state.indexed_from)
+        .bind(state.indexed_until)
+        .bind(&state.newest_signature)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn count_transfers(&self, wallet: &Pubkey, mint: &Pubkey) -> Result<u64> {
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM transfers WHERE wallet = $1 AND mint = $2")
+                .bind(wallet.to_string())
+                .bind(mint.to_string())
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(count as u64)
+    }
+
+    async fn processed_signatures(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        signatures: &[String],
+    ) -> Result<HashSet<String>> {
+        if signatures.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let found: Vec<String> = sqlx::query_scalar(
+            "SELECT signature FROM processed_signatures
+             WHERE wallet = $1 AND mint = $2 AND signature = ANY($3)",
+        )
+        .bind(wallet.to_string())
+        .bind(mint.to_string())
+        .bind(signatures)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(found.into_iter().collect())
+    }
+
+    async fn mark_processed(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        signatures: &[(String, i64)],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (signature, block_time) in signatures {
+            sqlx::query(
+                "INSERT INTO processed_signatures (wallet, mint, signature, block_time)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (wallet, mint, signature) DO UPDATE SET
+                    block_time = excluded.block_time",
+            )
+            .bind(wallet.to_string())
+            .bind(mint.to_string())
+            .bind(signature)
+            .bind(block_time)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn insert_account_events(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        events: &[AccountEvent],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for event in events {
+            sqlx::query(
+                "INSERT INTO account_events (wallet, mint, signature, instruction_index,
+                    inner_index, slot, block_time, kind, token_account, rent_account)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                 ON CONFLICT (wallet, signature, instruction_index, inner_index) DO UPDATE SET
+                    mint = EXCLUDED.mint, slot = EXCLUDED.slot, block_time = EXCLUDED.block_time,
+                    kind = EXCLUDED.kind, token_account = EXCLUDED.token_account,
+                    rent_account = EXCLUDED.rent_account",
+            )
+            .bind(wallet.to_string())
+            .bind(mint.to_string())
+            .bind(&event.signature)
+            .bind(event.instruction_index as i64)
+            .bind(event.inner_index.map_or(-1, |i| i as i64))
+            .bind(i64::try_from(event.slot)?)
+            .bind(event.block_time)
+            .bind(event.kind.as_str())
+            .bind(&event.token_account)
+            .bind(&event.rent_account)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn account_events(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        window: &TimeWindow,
+    ) -> Result<Vec<AccountEvent>> {
+        let rows = sqlx::query(
+            "SELECT signature, instruction_index, inner_index, slot, block_time, kind,
+                token_account, mint, rent_account
+             FROM account_events
+             WHERE wallet = $1 AND mint = $2 AND block_time >= $3 AND block_time <= $4
+             ORDER BY slot, instruction_index, inner_index",
+        )
+        .bind(wallet.to_string())
+        .bind(mint.to_string())
+        .bind(window.start)
+        .bind(window.end)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(account_event_from_row).collect()
+    }
+
+    async fn insert_balance_snapshot(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        snapshot: &BalanceSnapshot,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO balance_snapshots (wallet, mint, slot, taken_at, amount_raw,
+                transfer_count, unexplained_raw)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (wallet, mint, slot) DO UPDATE SET
+                taken_at = excluded.taken_at, amount_raw = excluded.amount_raw,
+                transfer_count = excluded.transfer_count,
+                unexplained_raw = excluded.unexplained_raw",
+        )
+        .bind(wallet.to_string())
+        .bind(mint.to_string())
+        .bind(i64::try_from(snapshot.slot)?)
+        .bind(snapshot.taken_at)
+        .bind(i64::try_from(snapshot.amount_raw)?)
+        .bind(i64::try_from(snapshot.transfer_count)?)
+        .bind(snapshot.unexplained_raw)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn balance_snapshots(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        window: &TimeWindow,
+    ) -> Result<Vec<BalanceSnapshot>> {
+        let rows = sqlx::query(
+            "SELECT slot, taken_at, amount_raw, transfer_count, unexplained_raw
+             FROM balance_snapshots
+             WHERE wallet = $1 AND mint = $2 AND taken_at >= $3 AND taken_at <= $4
+             ORDER BY slot",
+        )
+        .bind(wallet.to_string())
+        .bind(mint.to_string())
+        .bind(window.start)
+        .bind(window.end)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(balance_snapshot_from_row).collect()
+    }
+
+    async fn latest_balance_snapshot(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<Option<BalanceSnapshot>> {
+        let row = sqlx::query(
+            "SELECT slot, taken_at, amount_raw, transfer_count, unexplained_raw
+             FROM balance_snapshots
+             WHERE wallet = $1 AND mint = $2
+             ORDER BY slot DESC
+             LIMIT 1",
+        )
+        .bind(wallet.to_string())
+        .bind(mint.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+        row.as_ref().map(balance_snapshot_from_row).transpose()
+    }
+
+    async fn unfinalized_signatures(&self, wallet: &Pubkey) -> Result<Vec<UnfinalizedSignature>> {
+        let rows = sqlx::query(
+            "SELECT signature, MAX(slot) AS slot, MAX(commitment) AS commitment,
+                BOOL_OR(failed) AS failed, BOOL_OR(orphaned) AS orphaned
+             FROM transfers
+             WHERE wallet = $1 AND commitment IS NOT NULL
+             GROUP BY signature
+             ORDER BY slot",
+        )
+        .bind(wallet.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(unfinalized_from_row).collect()
+    }
+
+    async fn set_confirmation(
+        &self,
+        wallet: &Pubkey,
+        signature: &str,
+        commitment: Option<&str>,
+        orphaned: bool,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE transfers SET commitment = $1, orphaned = $2
+             WHERE wallet = $3 AND signature = $4",
+        )
+        .bind(commitment)
+        .bind(orphaned)
+        .bind(wallet.to_string())
+        .bind(signature)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn replace_transfers(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        signatures: &[String],
+        transfers: &[Transfer],
+    ) -> Result<()> {
+        let wallet = wallet.to_string();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM transfers WHERE wallet = $1 AND mint = $2 AND signature = ANY($3)",
+        )
+        .bind(&wallet)
+        .bind(mint.to_string())
+        .bind(signatures)
+        .execute(&mut *tx)
+        .await?;
+        insert_rows(&mut tx, &wallet, transfers).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_transfers(
+        &self,
+        wallet: &Pubkey,
+        mint: &Pubkey,
+        window: &TimeWindow,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let mut removed = 0;
+        for table in ["transfers", "processed_signatures"] {
+            let result = sqlx::query(&format!(
+                "DELETE FROM {} WHERE wallet = $1 AND mint = $2 AND block_time BETWEEN $3 AND $4",
+                table
+            ))
+            .bind(wallet.to_string())
+            .bind(mint.to_string())
+            .bind(window.start)
+            .bind(window.end)
+            .execute(&mut *tx)
+            .await?;
+            if table == "transfers" {
+                removed = result.rows_affected();
+            }
+        }
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    async fn purge_wallet(&self, wallet: &Pubkey) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for table in [
+            "transfers",
+            "sync_state",
+            "processed_signatures",
+            "account_events",
+            "balance_snapshots",
+        ] {
+            sqlx::query(&format!("DELETE FROM {} WHERE wallet = $1", table))
+                .bind(wallet.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn watched_wallets(&self) -> Result<Vec<WatchedWallet>> {
+        let rows = sqlx::query("SELECT wallet, added_at FROM watched_wallets ORDER BY added_at")
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(watched_wallet_from_row).collect()
+    }
+
+    async fn add_watched_wallet(&self, watched: &WatchedWallet) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO watched_wallets (wallet, added_at) VALUES ($1, $2)
+             ON CONFLICT (wallet) DO NOTHING",
+        )
+        .bind(&watched.wallet)
+        .bind(watched.added_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_watched_wallet(&self, wallet: &Pubkey) -> Result<()> {
+        sqlx::query("DELETE FROM watched_wallets WHERE wallet = $1")
+            .bind(wallet.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn put_raw_transactions(&self, raw: &[RawTransaction], limit: usize) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for stored in raw {
+            sqlx::query(
+                "INSERT INTO raw_transactions (signature, slot, data) VALUES ($1, $2, $3)
+                 ON CONFLICT (signature) DO UPDATE SET slot = EXCLUDED.slot, data = EXCLUDED.data",
+            )
+            .bind(&stored.signature)
+            .bind(i64::try_from(stored.slot)?)
+            .bind(&stored.data)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query(
+            "DELETE FROM raw_transactions WHERE signature NOT IN (
+                SELECT signature FROM raw_transactions ORDER BY slot DESC, signature DESC LIMIT $1
+             )",
+        )
+        .bind(i64::try_from(limit)?)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn raw_transaction(&self, signature: &str) -> Result<Option<RawTransaction>> {
+        let row =
+            sqlx::query("SELECT signature, slot, data FROM raw_transactions WHERE signature = $1")
+                .bind(signature)
+                .fetch_optional(&self.pool)
+                .await?;
+        row.as_ref().map(raw_transaction_from_row).transpose()
+    }
+
+    async fn raw_transactions(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<RawTransaction>> {
+        let rows = sqlx::query(
+            "SELECT signature, slot, data FROM raw_transactions
+             WHERE $1::TEXT IS NULL OR signature > $1
+             ORDER BY signature LIMIT $2",
+        )
+        .bind(after)
+        .bind(i64::try_from(limit)?)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(raw_transaction_from_row).collect()
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.pool.close().await;
+        Ok(())
+    }
+}
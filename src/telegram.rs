@@ -0,0 +1,371 @@
+//! Posts a chat message to Telegram for each new transfer, configured under
+//! `[telegram]`. Messages go out one at a time through a per-minute limit,
+//! so a burst of dust transfers is summarized instead of flooding the chat.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::events::{self, TransferEvent};
+use crate::metrics::METRICS;
+use crate::model::parse_amount;
+
+const API_URL: &str = "https://api.telegram.org";
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Total tries per message, the first one included.
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_MIN_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound on a `retry_after` Telegram asks us to wait.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// The `[telegram]` table, or the `TELEGRAM_*` environment variables. The
+/// bot token stays out of `Debug` output.
+#[derive(Clone)]
+pub struct TelegramTarget {
+    pub bot_token: String,
+    /// Numeric chat id or `@channelname`.
+    pub chat_id: String,
+    /// Inclusive lower bound in UI units, checked against each registered
+    /// mint's decimals when the config is loaded.
+    pub min_amount: Option<String>,
+    /// Messages sent per minute at most; the rest are counted and reported
+    /// in the next message that goes out.
+    pub max_messages_per_minute: u32,
+}
+
+impl fmt::Debug for TelegramTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TelegramTarget")
+            .field("chat_id", &self.chat_id)
+            .field("min_amount", &self.min_amount)
+            .field("max_messages_per_minute", &self.max_messages_per_minute)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TelegramTarget {
+    pub fn matches(&self, event: &TransferEvent) -> bool {
+        self.min_amount.as_deref().is_none_or(|min| {
+            parse_amount(min, event.mint.decimals).is_ok_and(|min| event.transfer.amount_raw >= min)
+        })
+    }
+}
+
+/// Fixed one-minute window of at most `limit` messages.
+#[derive(Debug)]
+struct RateLimiter {
+    limit: u32,
+    window_start: Instant,
+    sent: u32,
+}
+
+impl RateLimiter {
+    fn new(limit: u32, now: Instant) -> Self {
+        Self {
+            limit,
+            window_start: now,
+            sent: 0,
+        }
+    }
+
+    /// Takes a slot in the current window, opening a new one once a minute
+    /// has passed.
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.window_start) >= RATE_WINDOW {
+            self.window_start = now;
+            self.sent = 0;
+        }
+        if self.sent >= self.limit {
+            return false;
+        }
+        self.sent += 1;
+        true
+    }
+}
+
+/// Plain-text message for `event`; `suppressed` transfers that the rate
+/// limit held back since the last message are mentioned at the end.
+fn format_message(event: &TransferEvent, explorer_url: &str, suppressed: u64) -> String {
+    let (counterparty, _) = event.transfer.counterparty_address();
+    let mut text = format!(
+        "{}\n{}: {}\nWallet: {}\n{}",
+        event.headline(),
+        event.counterparty_label(),
+        counterparty,
+        event.wallet,
+        explorer_url
+    );
+    if suppressed > 0 {
+        text.push_str(&format!(
+            "\n\n({} more transfer(s) not shown, rate limit reached)",
+            suppressed
+        ));
+    }
+    text
+}
+
+/// Starts the notifier if a bot token and chat id are configured. It stops
+/// at `shutdown`, abandoning the message being retried, if any.
+pub fn spawn(config: Arc<Config>, shutdown: watch::Receiver<bool>) -> Option<JoinHandle<()>> {
+    let target = config.telegram.clone()?;
+    Some(tokio::spawn(run(config, target, shutdown)))
+}
+
+async fn run(config: Arc<Config>, target: TelegramTarget, mut shutdown: watch::Receiver<bool>) {
+    let client = match reqwest::Client::builder().timeout(DELIVERY_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!(error = %e, "cannot build Telegram client, Telegram alerts disabled");
+            return;
+        }
+    };
+    let mut limiter = RateLimiter::new(target.max_messages_per_minute, Instant::now());
+    let mut suppressed = 0u64;
+    let mut feed = events::subscribe();
+    info!(chat_id = %target.chat_id, "Telegram notifier started");
+    loop {
+        let event = tokio::select! {
+            event = feed.recv() => event,
+            _ = shutdown.changed() => return,
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(missed, "Telegram notifier fell behind, events dropped");
+                suppressed += missed;
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if !target.matches(&event) {
+            continue;
+        }
+        if !limiter.try_acquire(Instant::now()) {
+            suppressed += 1;
+            METRICS
+                .telegram_messages
+                .with_label_values(&["rate_limited"])
+                .inc();
+            continue;
+        }
+
+        let explorer_url = config.explorer_link(&event.transfer.signature);
+        let text = format_message(&event, &explorer_url, suppressed);
+        suppressed = 0;
+        tokio::select! {
+            _ = deliver(&client, &target, &event.transfer.signature, &text) => {}
+            _ = shutdown.changed() => return,
+        }
+    }
+}
+
+/// Sends `text` until Telegram accepts it or the attempts run out.
+async fn deliver(client: &reqwest::Client, target: &TelegramTarget, signature: &str, text: &str) {
+    let mut delay = RETRY_MIN_DELAY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        match send_message(client, target, text).await {
+            Ok(()) => {
+                info!(%signature, attempt, "Telegram message sent");
+                METRICS.telegram_messages.with_label_values(&["sent"]).inc();
+                return;
+            }
+            Err(failure) => {
+                if let Some(retry_after) = failure.retry_after {
+                    delay = retry_after.min(RETRY_MAX_DELAY);
+                }
+                warn!(
+                    %signature,
+                    attempt,
+                    ?delay,
+                    error = %failure.error,
+                    "Telegram message failed"
+                );
+                METRICS
+                    .telegram_messages
+                    .with_label_values(&["failed"])
+                    .inc();
+            }
+        }
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(RETRY_MAX_DELAY);
+        }
+    }
+    error!(
+        %signature,
+        attempts = MAX_ATTEMPTS,
+        "giving up on Telegram message"
+    );
+    METRICS
+        .telegram_messages
+        .with_label_values(&["abandoned"])
+        .inc();
+}
+
+struct SendFailure {
+    error: anyhow::Error,
+    /// How long Telegram asked us to back off (HTTP 429).
+    retry_after: Option<Duration>,
+}
+
+impl<E: Into<anyhow::Error>> From<E> for SendFailure {
+    fn from(error: E) -> Self {
+        Self {
+            error: error.into(),
+            retry_after: None,
+        }
+    }
+}
+
+/// The parts of a Bot API reply we look at.
+#[derive(Debug, Deserialize)]
+struct ApiReply {
+    ok: bool,
+    description: Option<String>,
+    parameters: Option<ApiReplyParameters>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiReplyParameters {
+    retry_after: Option<u64>,
+}
+
+/// Sends an alert once; see [`crate::alerts`].
+pub(crate) async fn post_alert(
+    client: &reqwest::Client,
+    target: &TelegramTarget,
+    text: &str,
+) -> Result<()> {
+    send_message(client, target, text)
+        .await
+        .map_err(|failure| failure.error)
+}
+
+async fn send_message(
+    client: &reqwest::Client,
+    target: &TelegramTarget,
+    text: &str,
+) -> Result<(), SendFailure> {
+    let body = serde_json::json!({
+        "chat_id": target.chat_id,
+        "text": text,
+        "disable_web_page_preview": true,
+    });
+    // The token is part of the URL, which reqwest errors would print.
+    let response = client
+        .post(format!("{}/bot{}/sendMessage", API_URL, target.bot_token))
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    check_reply(response).await
+}
+
+/// Sends a scheduled report as a document with `caption`, once; see
+/// [`crate::reports`].
+pub(crate) async fn post_document(
+    client: &reqwest::Client,
+    target: &TelegramTarget,
+    caption: &str,
+    file_name: &str,
+    contents: Vec<u8>,
+) -> Result<()> {
+    let document = reqwest::multipart::Part::bytes(contents)
+        .file_name(file_name.to_string())
+        .mime_str("text/csv")?;
+    let form = reqwest::multipart::Form::new()
+        .text("chat_id", target.chat_id.clone())
+        .text("caption", caption.to_string())
+        .part("document", document);
+    let response = client
+        .post(format!("{}/bot{}/sendDocument", API_URL, target.bot_token))
+        .multipart(form)
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    check_reply(response).await.map_err(|failure| failure.error)
+}
+
+async fn check_reply(response: reqwest::Response) -> Result<(), SendFailure> {
+    let status = response.status();
+    let reply: ApiReply = response.json().await.map_err(|e| e.without_url())?;
+    if reply.ok {
+        return Ok(());
+    }
+    Err(SendFailure {
+        error: anyhow::anyhow!(
+            "HTTP {}: {}",
+            status,
+            reply.description.as_deref().unwrap_or("no description")
+        ),
+        retry_after: reply
+            .parameters
+            .and_then(|p| p.retry_after)
+            .map(Duration::from_secs),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MintRegistry, DEFAULT_MINTS};
+    use crate::model::fixtures::transfer;
+    use crate::model::Direction;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn event(direction: Direction, amount_raw: u64) -> TransferEvent {
+        TransferEvent {
+            id: 0,
+            wallet: Pubkey::default(),
+            mint: MintRegistry::parse(DEFAULT_MINTS).unwrap().mints[0].clone(),
+            transfer: transfer(direction, amount_raw, "alice"),
+        }
+    }
+
+    #[test]
+    fn formats_amount_direction_counterparty_and_link() {
+        let text = format_message(
+            &event(Direction::Received, 1_500_000),
+            "https://x/tx/sig",
+            0,
+        );
+        assert_eq!(
+            text,
+            format!(
+                "Received 1.500000 USDC\nFrom: alice\nWallet: {}\nhttps://x/tx/sig",
+                Pubkey::default()
+            )
+        );
+
+        let text = format_message(&event(Direction::Sent, 1), "https://x/tx/sig", 3);
+        assert!(text.starts_with("Sent 0.000001 USDC\nTo: alice\n"));
+        assert!(text.ends_with("(3 more transfer(s) not shown, rate limit reached)"));
+    }
+
+    #[test]
+    fn rate_limits_per_minute_and_filters_dust() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2, start);
+        assert!(limiter.try_acquire(start));
+        assert!(limiter.try_acquire(start + Duration::from_secs(1)));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(59)));
+        assert!(limiter.try_acquire(start + RATE_WINDOW));
+
+        let target = TelegramTarget {
+            bot_token: "token".to_string(),
+            chat_id: "1".to_string(),
+            min_amount: Some("1".to_string()),
+            max_messages_per_minute: 2,
+        };
+        assert!(target.matches(&event(Direction::Received, 1_000_000)));
+        assert!(!target.matches(&event(Direction::Received, 999_999)));
+    }
+}
@@ -0,0 +1,126 @@
+//! Optional OpenTelemetry export of the tracing spans: requests, backfill
+//! phases (signature pages, transaction fetches, parsing) and each RPC
+//! call. Configured like any OpenTelemetry SDK, from the environment:
+//! export is on once `OTEL_EXPORTER_OTLP_ENDPOINT` (or
+//! `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`) names an OTLP/gRPC collector, and
+//! `OTEL_TRACES_SAMPLER` with `OTEL_TRACES_SAMPLER_ARG` picks the sampler,
+//! as a full backfill makes thousands of spans.
+
+use anyhow::{bail, Context, Result};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// `service.name` unless `OTEL_SERVICE_NAME` says otherwise.
+const SERVICE_NAME: &str = "solana-usdc-indexer";
+
+/// The layer exporting spans, or `None` when no collector is configured.
+/// Must be called inside the Tokio runtime, which runs the batch exporter.
+pub fn layer<S>() -> Result<Option<OpenTelemetryLayer<S, Tracer>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let configured = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|name| std::env::var(name).is_ok_and(|value| !value.is_empty()));
+    if !configured {
+        return Ok(None);
+    }
+    let sampler = sampler(
+        std::env::var("OTEL_TRACES_SAMPLER").ok().as_deref(),
+        std::env::var("OTEL_TRACES_SAMPLER_ARG").ok().as_deref(),
+    )?;
+    let mut resource = Resource::default();
+    if std::env::var("OTEL_SERVICE_NAME").is_err() {
+        resource = resource.merge(&Resource::new([KeyValue::new(
+            "service.name",
+            SERVICE_NAME,
+        )]));
+    }
+    // The exporter reads the endpoint, headers and timeout from the
+    // standard variables itself.
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .with_trace_config(
+            trace::config()
+                .with_sampler(sampler)
+                .with_resource(resource),
+        )
+        .install_batch(runtime::Tokio)
+        .context("starting the OTLP trace exporter")?;
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// The sampler `OTEL_TRACES_SAMPLER` names, with `OTEL_TRACES_SAMPLER_ARG`
+/// as the ratio of the `traceidratio` ones. Unset means
+/// `parentbased_always_on`, as the specification has it.
+pub fn sampler(name: Option<&str>, arg: Option<&str>) -> Result<Sampler> {
+    let ratio = || -> Result<f64> {
+        let Some(arg) = arg else {
+            return Ok(1.0);
+        };
+        let ratio: f64 = arg
+            .trim()
+            .parse()
+            .with_context(|| format!("OTEL_TRACES_SAMPLER_ARG '{}' is not a number", arg))?;
+        if !(0.0..=1.0).contains(&ratio) {
+            bail!(
+                "OTEL_TRACES_SAMPLER_ARG must be between 0 and 1, got {}",
+                ratio
+            );
+        }
+        Ok(ratio)
+    };
+    let parent_based = |root| Sampler::ParentBased(Box::new(root));
+    Ok(match name.unwrap_or("parentbased_always_on") {
+        "always_on" => Sampler::AlwaysOn,
+        "always_off" => Sampler::AlwaysOff,
+        "traceidratio" => Sampler::TraceIdRatioBased(ratio()?),
+        "parentbased_always_on" => parent_based(Sampler::AlwaysOn),
+        "parentbased_always_off" => parent_based(Sampler::AlwaysOff),
+        "parentbased_traceidratio" => parent_based(Sampler::TraceIdRatioBased(ratio()?)),
+        other => bail!(
+            "OTEL_TRACES_SAMPLER '{}' is not one of always_on, always_off, traceidratio, \
+             parentbased_always_on, parentbased_always_off or parentbased_traceidratio",
+            other
+        ),
+    })
+}
+
+/// Flushes the spans still batched; call before exiting.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_everything_by_default() {
+        let chosen = sampler(None, None).unwrap();
+        assert_eq!(
+            format!("{:?}", chosen),
+            format!("{:?}", Sampler::ParentBased(Box::new(Sampler::AlwaysOn)))
+        );
+    }
+
+    #[test]
+    fn reads_the_ratio_for_ratio_samplers() {
+        let chosen = sampler(Some("traceidratio"), Some("0.05")).unwrap();
+        assert_eq!(
+            format!("{:?}", chosen),
+            format!("{:?}", Sampler::TraceIdRatioBased(0.05))
+        );
+        assert!(sampler(Some("traceidratio"), Some("1.5")).is_err());
+        assert!(sampler(Some("traceidratio"), Some("half")).is_err());
+        assert!(sampler(Some("sometimes"), None).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Client-side budget for RPC requests, so the indexer stays inside its
+//! provider's plan instead of finding the limit through 429s. Every call
+//! takes a token from the shared bucket and, if the method has one, from
+//! its own; callers wait for tokens rather than failing.
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use solana_client::client_error::ClientError;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_client::rpc_response::{
+    Response as RpcResponse, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount,
+};
+use solana_sdk::account::Account;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, TransactionStatus};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::metrics::METRICS;
+use crate::rpc::{classify_rpc_error, RpcFailure, SolanaRpc};
+
+/// The methods [`SolanaRpc`] sends, by their JSON-RPC names.
+pub const RPC_METHODS: [&str; 7] = [
+    "getSignaturesForAddress",
+    "getTransaction",
+    "getAccountInfo",
+    "getSlot",
+    "getBlockTime",
+    "getTokenAccountsByOwner",
+    "getSignatureStatuses",
+];
+
+/// 429s answered by waiting on the budget before one is handed to the
+/// caller's retry loop.
+const MAX_THROTTLED_RETRIES: u32 = 3;
+
+/// Token bucket holding up to a second's worth of requests. Tokens may go
+/// negative: each caller reserves its token up front and sleeps off the
+/// debt, so waiters are served in arrival order.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Bucket {
+    fn new(per_second: f64) -> Self {
+        let capacity = per_second.max(1.0);
+        Bucket {
+            rate: per_second,
+            capacity,
+            state: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Reserves a token, returning how long to wait before using it.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut state = self.state.lock().expect("rpc budget lock poisoned");
+        let (tokens, refilled_at) = &mut *state;
+        let elapsed = now.saturating_duration_since(*refilled_at).as_secs_f64();
+        *tokens = (*tokens + elapsed * self.rate).min(self.capacity) - 1.0;
+        *refilled_at = now;
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / self.rate)
+        }
+    }
+
+    /// Empties the bucket after the provider said we went too fast.
+    fn drain(&self) {
+        let mut state = self.state.lock().expect("rpc budget lock poisoned");
+        state.0 = state.0.min(0.0);
+    }
+}
+
+/// Wraps the RPC client every indexer call goes through.
+pub struct ThrottledRpc {
+    inner: Arc<dyn SolanaRpc>,
+    shared: Option<Bucket>,
+    per_method: HashMap<&'static str, Bucket>,
+}
+
+impl ThrottledRpc {
+    /// `per_second` budgets all calls together; `per_method` adds a budget
+    /// for individual methods, by JSON-RPC name.
+    pub fn new(
+        inner: Arc<dyn SolanaRpc>,
+        per_second: Option<f64>,
+        per_method: &BTreeMap<String, f64>,
+    ) -> Self {
+        let per_method = RPC_METHODS
+            .iter()
+            .filter_map(|&method| {
+                let rate = *per_method.get(method)?;
+                Some((method, Bucket::new(rate)))
+            })
+            .collect::<HashMap<_, _>>();
+        if let Some(rate) = per_second {
+            METRICS.rpc_budget.with_label_values(&["all"]).set(rate);
+        }
+        for (method, bucket) in &per_method {
+            METRICS
+                .rpc_budget
+                .with_label_values(&[method])
+                .set(bucket.rate);
+        }
+        ThrottledRpc {
+            inner,
+            shared: per_second.map(Bucket::new),
+            per_method,
+        }
+    }
+
+    async fn acquire(&self, method: &str) {
+        let now = Instant::now();
+        let wait = [self.shared.as_ref(), self.per_method.get(method)]
+            .into_iter()
+            .flatten()
+            .map(|bucket| bucket.reserve(now))
+            .max()
+            .unwrap_or_default();
+        METRICS
+            .rpc_throttle_wait
+            .with_label_values(&[method])
+            .observe(wait.as_secs_f64());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        METRICS.rpc_requests_sent.with_label_values(&[method]).inc();
+    }
+
+    fn drain(&self, method: &str) {
+        for bucket in [self.shared.as_ref(), self.per_method.get(method)]
+            .into_iter()
+            .flatten()
+        {
+            bucket.drain();
+        }
+    }
+
+    async fn call<'a, T>(
+        &'a self,
+        method: &'static str,
+        op: impl Fn(&'a dyn SolanaRpc) -> BoxFuture<'a, Result<T, ClientError>>,
+    ) -> Result<T, ClientError> {
+        let mut throttled = 0;
+        loop {
+            self.acquire(method).await;
+            match op(self.inner.as_ref()).await {
+                Err(err)
+                    if classify_rpc_error(&err) == RpcFailure::RateLimited
+                        && throttled < MAX_THROTTLED_RETRIES =>
+                {
+                    // Everyone sharing the bucket slows down, not just us.
+                    debug!(method, "RPC rate limited, waiting on the budget");
+                    self.drain(method);
+                    throttled += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SolanaRpc for ThrottledRpc {
+    async fn get_signatures_for_address(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, ClientError> {
+        self.call("getSignaturesForAddress", |rpc| {
+            rpc.get_signatures_for_address(
+                address,
+                GetConfirmedSignaturesForAddress2Config {
+                    before: config.before,
+                    until: config.until,
+                    limit: config.limit,
+                    commitment: config.commitment,
+                },
+            )
+        })
+        .await
+    }
+
+    async fn get_transaction(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> Result<EncodedConfirmedTransactionWithStatusMeta, ClientError> {
+        self.call("getTransaction", |rpc| {
+            rpc.get_transaction(signature, config)
+        })
+        .await
+    }
+
+    async fn get_account(
+        &self,
+        address: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<Account>, ClientError> {
+        self.call("getAccountInfo", |rpc| rpc.get_account(address, commitment))
+            .await
+    }
+
+    async fn get_slot(&self) -> Result<u64, ClientError> {
+        self.call("getSlot", |rpc| rpc.get_slot()).await
+    }
+
+    async fn get_finalized_slot(&self) -> Result<u64, ClientError> {
+        self.call("getSlot", |rpc| rpc.get_finalized_slot()).await
+    }
+
+    async fn get_block_time(&self, slot: u64) -> Result<i64, ClientError> {
+        self.call("getBlockTime", |rpc| rpc.get_block_time(slot))
+            .await
+    }
+
+    async fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> Result<RpcResponse<Vec<RpcKeyedAccount>>, ClientError> {
+        self.call("getTokenAccountsByOwner", |rpc| {
+            rpc.get_token_accounts_by_owner(owner, mint, commitment)
+        })
+        .await
+    }
+
+    async fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> Result<Vec<Option<TransactionStatus>>, ClientError> {
+        self.call("getSignatureStatuses", |rpc| {
+            rpc.get_signature_statuses(signatures)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queues_callers_once_the_burst_is_spent() {
+        let bucket = Bucket::new(2.0);
+        let start = Instant::now();
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(start), Duration::from_secs(1));
+        // Two seconds later the debt is paid and a token is back.
+        assert_eq!(
+            bucket.reserve(start + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn applies_the_method_budget_on_top_of_the_shared_one() {
+        let inner: Arc<dyn SolanaRpc> = Arc::new(crate::rpc::mock::MockRpc::default());
+        let per_method = BTreeMap::from([("getSlot".to_string(), 10.0)]);
+        let rpc = ThrottledRpc::new(inner, Some(1000.0), &per_method);
+        let started = Instant::now();
+        // Ten go out at once, the other two wait 100ms each.
+        for _ in 0..12 {
+            rpc.get_slot().await.unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        rpc.get_account(&Pubkey::new_unique(), CommitmentConfig::confirmed())
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
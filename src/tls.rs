@@ -0,0 +1,327 @@
+//! HTTPS for the built-in server, for hosts with no proxy in front. The
+//! server terminates TLS itself with the configured certificate and key. A
+//! watcher notices when either file changes, as on a Let's Encrypt
+//! renewal, and once the new pair checks out swaps it in: handshakes from
+//! then on use it, open connections carry on, and the port stays open.
+
+use anyhow::{anyhow, bail, Context, Result};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    Certificate, ClientConfig, ClientConnection, Connection, PrivateKey, ServerConfig,
+    ServerConnection, ServerName,
+};
+use rustls_pemfile::Item;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{debug, error, info, warn};
+use warp::hyper::server::accept;
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+use warp::hyper::{Body, Request};
+use warp::{Filter, Rejection, Reply};
+
+/// How often the certificate and key files are checked for changes.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A handshake takes two round trips; anything longer is stuck.
+const MAX_HANDSHAKE_ROUNDS: usize = 8;
+
+/// A client that hasn't finished its handshake by then is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections through their handshake that the server hasn't picked up.
+const ACCEPTED_BACKLOG: usize = 128;
+
+/// PEM files with the certificate chain, leaf first, and its private key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// The address an HTTPS connection came from, on each of its requests.
+/// warp only knows the peer of connections it accepts itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer(pub SocketAddr);
+
+/// The pair handshakes are made with, replaced when the files change.
+struct Certificates(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+/// A server config handing out whatever `certificates` holds.
+fn server_config(certificates: Arc<Certificates>) -> ServerConfig {
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(certificates);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config
+}
+
+/// Reads the pair, failing unless the files are readable, hold a
+/// certificate chain and a key, and the key belongs to the certificate.
+/// The last is found out by a handshake between the pair and a client in
+/// memory: a foreign key signs it with the wrong key, and the client
+/// refuses.
+fn load(files: &TlsFiles) -> Result<Arc<CertifiedKey>> {
+    let certs = read_certs(&files.cert)?;
+    let key = read_key(&files.key)?;
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|_| anyhow!("{} holds a key of an unsupported type", files.key.display()))?;
+    let pair = Arc::new(CertifiedKey::new(certs, key));
+    let server = server_config(Arc::new(Certificates(RwLock::new(pair.clone()))));
+    let client = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate))
+        .with_no_client_auth();
+    let name = ServerName::try_from("localhost").expect("localhost is a valid server name");
+    let mut client = Connection::Client(ClientConnection::new(Arc::new(client), name)?);
+    let mut server = Connection::Server(ServerConnection::new(Arc::new(server))?);
+    for _ in 0..MAX_HANDSHAKE_ROUNDS {
+        if !client.is_handshaking() && !server.is_handshaking() {
+            return Ok(pair);
+        }
+        relay(&mut client, &mut server)?;
+        relay(&mut server, &mut client)?;
+    }
+    bail!("the TLS handshake with the certificate did not finish")
+}
+
+/// Passes what `from` has to send on to `to`.
+fn relay(from: &mut Connection, to: &mut Connection) -> Result<()> {
+    let mut records = Vec::new();
+    while from.wants_write() {
+        from.write_tls(&mut records)?;
+    }
+    let mut records = &records[..];
+    while !records.is_empty() {
+        to.read_tls(&mut records)?;
+        to.process_new_packets().map_err(|e| {
+            anyhow!(
+                "a TLS handshake with the certificate failed ({}); does the key belong to it?",
+                e
+            )
+        })?;
+    }
+    Ok(())
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("reading TLS certificate {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .with_context(|| format!("parsing TLS certificate {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("{} holds no PEM certificate", path.display());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// The first key in the file: PKCS#8, PKCS#1 or SEC1.
+fn read_key(path: &Path) -> Result<PrivateKey> {
+    let pem = std::fs::read(path).with_context(|| format!("reading TLS key {}", path.display()))?;
+    let mut pem = &pem[..];
+    loop {
+        match rustls_pemfile::read_one(&mut pem)
+            .with_context(|| format!("parsing TLS key {}", path.display()))?
+        {
+            Some(Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key)) => {
+                return Ok(PrivateKey(key))
+            }
+            Some(_) => {}
+            None => bail!("{} holds no PEM private key", path.display()),
+        }
+    }
+}
+
+/// Accepts whatever certificate the server presents. The handshake
+/// signature is still checked against it, which is all [`load`] is after.
+struct AnyCertificate;
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// When the files were last written, to notice renewals.
+fn modified(files: &TlsFiles) -> Option<(SystemTime, SystemTime)> {
+    let cert = std::fs::metadata(&files.cert).ok()?.modified().ok()?;
+    let key = std::fs::metadata(&files.key).ok()?.modified().ok()?;
+    Some((cert, key))
+}
+
+/// Accepts connections on `listener` and passes them on once through
+/// their handshake, until `accepted` is dropped. Handshakes run apart from
+/// the accept loop, so a client that stalls in one holds up no one else.
+async fn accept_loop(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    accepted: mpsc::Sender<TlsStream<TcpStream>>,
+) {
+    loop {
+        let (stream, peer) = tokio::select! {
+            _ = accepted.closed() => return,
+            connection = listener.accept() => match connection {
+                Ok(connection) => connection,
+                Err(e) => {
+                    // Out of file descriptors, most likely; let some close.
+                    warn!(error = %e, "cannot accept an HTTPS connection");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+        };
+        let acceptor = acceptor.clone();
+        let accepted = accepted.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = accepted.send(stream).await;
+                }
+                Ok(Err(e)) => debug!(%peer, error = %e, "TLS handshake failed"),
+                Err(_) => debug!(%peer, "TLS handshake timed out"),
+            }
+        });
+    }
+}
+
+/// Swaps in the pair whenever the files change and it checks out, until
+/// `shutdown` flips. A changed pair that doesn't is logged and the old
+/// one kept.
+async fn reload(
+    files: TlsFiles,
+    certificates: Arc<Certificates>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut seen = modified(&files);
+    let mut checks = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+    checks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    checks.tick().await;
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => return,
+            _ = checks.tick() => {}
+        }
+        let current = modified(&files);
+        if current == seen {
+            continue;
+        }
+        seen = current;
+        match load(&files) {
+            Ok(pair) => {
+                *certificates.0.write().unwrap() = pair;
+                info!(cert = %files.cert.display(), "TLS certificate reloaded");
+            }
+            Err(e) => warn!(
+                cert = %files.cert.display(),
+                error = format!("{:#}", e),
+                "TLS certificate changed but can't be used, keeping the old one"
+            ),
+        }
+    }
+}
+
+/// Serves `filter` over TLS on `addr` until `shutdown` flips, like
+/// `warp::serve(..).bind_with_graceful_shutdown`, reloading the pair when
+/// the files change. Fails when the pair can't be used or `addr` can't be
+/// bound. Each request carries its connection's [`Peer`].
+pub async fn bind<F>(
+    filter: F,
+    addr: impl Into<SocketAddr>,
+    files: TlsFiles,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(SocketAddr, impl Future<Output = ()>)>
+where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let certificates = Arc::new(Certificates(RwLock::new(load(&files)?)));
+    let acceptor = TlsAcceptor::from(Arc::new(server_config(certificates.clone())));
+    let addr = addr.into();
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding {}", addr))?;
+    let addr = listener.local_addr()?;
+
+    let (accepted, incoming) = mpsc::channel(ACCEPTED_BACKLOG);
+    tokio::spawn(accept_loop(listener, acceptor, accepted));
+    let service = warp::service(filter);
+    let make_service = make_service_fn(move |stream: &TlsStream<TcpStream>| {
+        let peer = stream.get_ref().0.peer_addr().ok().map(Peer);
+        let mut service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                if let Some(peer) = peer {
+                    request.extensions_mut().insert(peer);
+                }
+                service.call(request)
+            }))
+        }
+    });
+    let incoming = ReceiverStream::new(incoming).map(Ok::<_, Infallible>);
+    let reloads = reload(files, certificates, shutdown.clone());
+    let server = warp::hyper::Server::builder(accept::from_stream(incoming))
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.changed().await;
+        });
+    let serve = async move {
+        let (served, ()) = tokio::join!(server, reloads);
+        if let Err(e) = served {
+            error!(error = %e, "HTTPS server failed");
+        }
+    };
+    Ok((addr, serve))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unreadable_or_empty_files() {
+        let dir = std::env::temp_dir().join(format!("tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = TlsFiles {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        };
+        let err = load(&files).err().unwrap();
+        assert!(
+            format!("{:#}", err).contains("reading TLS certificate"),
+            "{:#}",
+            err
+        );
+
+        std::fs::write(&files.cert, "not a certificate").unwrap();
+        std::fs::write(&files.key, "not a key").unwrap();
+        let err = load(&files).err().unwrap();
+        assert!(err.to_string().contains("no PEM certificate"), "{:#}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
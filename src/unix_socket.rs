@@ -0,0 +1,154 @@
+//! Listening on a Unix domain socket instead of a TCP port, for hosts where
+//! a proxy on the same machine is the only client and no port should be
+//! open at all. `BIND=unix:/run/indexer/indexer.sock` selects it; the
+//! routes, health checks included, are the same as over TCP.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::Permissions;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::UnixListener;
+use tokio::sync::watch;
+use tokio_stream::wrappers::UnixListenerStream;
+use tracing::warn;
+use warp::{Filter, Rejection, Reply};
+
+/// Owner and group may connect: a proxy running as another user is put in
+/// the socket's group.
+pub const DEFAULT_MODE: u32 = 0o660;
+
+const PREFIX: &str = "unix:";
+
+/// Where the socket is created and the permissions it is given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+    pub mode: u32,
+}
+
+/// The socket path of a `BIND` value, which reads `unix:<path>`.
+pub fn parse_bind(value: &str) -> Result<PathBuf> {
+    match value.strip_prefix(PREFIX) {
+        Some(path) if !path.is_empty() => Ok(PathBuf::from(path)),
+        _ => bail!(
+            "bind '{}' must be unix:<path>; use bind_addr and port for TCP",
+            value
+        ),
+    }
+}
+
+/// Permissions in octal, as `chmod` takes them: `660` or `0660`.
+pub fn parse_mode(value: &str) -> Result<u32> {
+    let mode = u32::from_str_radix(value.trim(), 8)
+        .map_err(|_| anyhow!("unix_socket_mode '{}' is not an octal mode", value))?;
+    if mode > 0o777 {
+        bail!("unix_socket_mode '{}' is not an octal mode", value);
+    }
+    Ok(mode)
+}
+
+/// Creates the socket. A socket file left behind by a run that didn't shut
+/// down cleanly is removed first; anything else at the path, or a socket
+/// another process still accepts on, is left alone and an error.
+fn listen(socket: &UnixSocket) -> Result<UnixListener> {
+    let path = &socket.path;
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                bail!("{} is in use by another process", path.display());
+            }
+            std::fs::remove_file(path)
+                .with_context(|| format!("removing stale socket {}", path.display()))?;
+        }
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("checking {}", path.display())),
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("binding {}", path.display()))?;
+    std::fs::set_permissions(path, Permissions::from_mode(socket.mode))
+        .with_context(|| format!("setting the permissions of {}", path.display()))?;
+    Ok(listener)
+}
+
+/// Serves `filter` on the socket until `shutdown` flips, like
+/// `warp::serve(..).bind_with_graceful_shutdown`, then removes the socket
+/// file once open connections have finished.
+pub fn bind<F>(
+    filter: F,
+    socket: &UnixSocket,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<impl Future<Output = ()>>
+where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let listener = listen(socket)?;
+    let server = warp::serve(filter).serve_incoming_with_graceful_shutdown(
+        UnixListenerStream::new(listener),
+        async move {
+            let _ = shutdown.changed().await;
+        },
+    );
+    let path = socket.path.clone();
+    Ok(async move {
+        server.await;
+        remove(&path);
+    })
+}
+
+fn remove(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != ErrorKind::NotFound {
+            warn!(path = %path.display(), error = %e, "cannot remove the socket");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bind_and_mode() {
+        assert_eq!(
+            parse_bind("unix:/run/indexer.sock").unwrap(),
+            PathBuf::from("/run/indexer.sock")
+        );
+        assert!(parse_bind("unix:").is_err());
+        assert!(parse_bind("0.0.0.0:10000").is_err());
+        assert_eq!(parse_mode("660").unwrap(), 0o660);
+        assert_eq!(parse_mode("0600").unwrap(), 0o600);
+        assert!(parse_mode("999").is_err());
+        assert!(parse_mode("7777").is_err());
+    }
+
+    #[tokio::test]
+    async fn replaces_a_stale_socket_but_not_a_live_one() {
+        let dir = std::env::temp_dir().join(format!("unix-socket-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = UnixSocket {
+            path: dir.join("indexer.sock"),
+            mode: 0o600,
+        };
+        drop(std::os::unix::net::UnixListener::bind(&socket.path).unwrap());
+
+        let listener = listen(&socket).unwrap();
+        let mode = std::fs::metadata(&socket.path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let err = listen(&socket).unwrap_err();
+        assert!(err.to_string().contains("in use"), "{:#}", err);
+        drop(listener);
+
+        std::fs::remove_file(&socket.path).unwrap();
+        std::fs::write(&socket.path, "not a socket").unwrap();
+        let err = listen(&socket).unwrap_err();
+        assert!(err.to_string().contains("not a socket"), "{:#}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
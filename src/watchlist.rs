@@ -0,0 +1,244 @@
+//! Wallets the background indexer follows: the configured ones plus any
+//! added at runtime through `POST /wallets`. Runtime additions are recorded
+//! in the store, so they are followed again after a restart.
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::config::Config;
+use crate::error::IndexerError;
+use crate::indexer;
+use crate::rpc::SolanaRpc;
+use crate::store::{Storage, WatchedWallet};
+
+/// Where a tracked wallet comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WalletSource {
+    Config,
+    Api,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackedWallet {
+    pub wallet: Pubkey,
+    pub source: WalletSource,
+    /// Unix time a runtime addition was made.
+    pub added_at: Option<i64>,
+}
+
+/// Every tracked wallet: the configured ones in config order, then those
+/// added at runtime, oldest first.
+pub async fn tracked_wallets(config: &Config, store: &Storage) -> Result<Vec<TrackedWallet>> {
+    let mut tracked: Vec<TrackedWallet> = config
+        .wallets
+        .iter()
+        .map(|&wallet| TrackedWallet {
+            wallet,
+            source: WalletSource::Config,
+            added_at: None,
+        })
+        .collect();
+    for watched in store.watched_wallets().await? {
+        let wallet = match Pubkey::from_str(&watched.wallet) {
+            Ok(wallet) => wallet,
+            Err(e) => {
+                warn!(wallet = %watched.wallet, error = %e, "skipping unreadable watched wallet");
+                continue;
+            }
+        };
+        if !config.wallets.contains(&wallet) {
+            tracked.push(TrackedWallet {
+                wallet,
+                source: WalletSource::Api,
+                added_at: Some(watched.added_at),
+            });
+        }
+    }
+    Ok(tracked)
+}
+
+/// The background tasks of one wallet and the switch that stops them.
+struct Follower {
+    stop: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+/// Runs the background indexing of every tracked wallet and starts or
+/// stops it as wallets are added or removed.
+pub struct Watchlist {
+    client: Arc<dyn SolanaRpc>,
+    config: Arc<Config>,
+    store: Storage,
+    shutdown: watch::Receiver<bool>,
+    followers: Mutex<HashMap<Pubkey, Follower>>,
+}
+
+impl Watchlist {
+    /// Starts following every tracked wallet. Everything stops once
+    /// `shutdown` flips; [`Watchlist::join`] then waits for it.
+    pub async fn start(
+        client: Arc<dyn SolanaRpc>,
+        config: Arc<Config>,
+        store: Storage,
+        shutdown: watch::Receiver<bool>,
+    ) -> Result<Self> {
+        let watchlist = Watchlist {
+            client,
+            config,
+            store,
+            shutdown,
+            followers: Mutex::new(HashMap::new()),
+        };
+        let tracked = tracked_wallets(&watchlist.config, &watchlist.store).await?;
+        let mut followers = watchlist.followers.lock().await;
+        for tracked in tracked {
+            followers.insert(tracked.wallet, watchlist.follow(tracked.wallet, false));
+        }
+        drop(followers);
+        Ok(watchlist)
+    }
+
+    fn follow(&self, wallet: Pubkey, initial_sync: bool) -> Follower {
+        let (stop, stopped) = watch::channel(false);
+        // Process shutdown stops every follower too.
+        let relay = stop.clone();
+        let mut shutdown = self.shutdown.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = shutdown.wait_for(|&stopping| stopping) => {
+                    let _ = relay.send(true);
+                }
+                _ = relay.closed() => {}
+            }
+        });
+        let tasks = indexer::spawn_wallet(
+            self.client.clone(),
+            self.config.clone(),
+            self.store.clone(),
+            wallet,
+            initial_sync,
+            stopped,
+        );
+        Follower { stop, tasks }
+    }
+
+    /// Starts indexing `wallet` and records it for later restarts. Returns
+    /// `None` if it was already tracked.
+    pub async fn watch(&self, wallet: Pubkey) -> Result<Option<WatchedWallet>, IndexerError> {
+        let mut followers = self.followers.lock().await;
+        if followers.contains_key(&wallet) {
+            return Ok(None);
+        }
+        let watched = WatchedWallet {
+            wallet: wallet.to_string(),
+            added_at: Utc::now().timestamp(),
+        };
+        self.store.add_watched_wallet(&watched).await?;
+        followers.insert(wallet, self.follow(wallet, true));
+        info!(%wallet, "wallet added");
+        Ok(Some(watched))
+    }
+
+    /// Stops indexing a wallet added at runtime, waiting for a sync in
+    /// progress to finish; `purge` then deletes what was stored for it.
+    pub async fn unwatch(&self, wallet: &Pubkey, purge: bool) -> Result<(), IndexerError> {
+        if self.config.wallets.contains(wallet) {
+            return Err(IndexerError::InvalidParameter(format!(
+                "wallet {} is set in the configuration and can't be removed at runtime",
+                wallet
+            )));
+        }
+        let mut followers = self.followers.lock().await;
+        let Some(follower) = followers.remove(wallet) else {
+            return Err(IndexerError::NotFound(format!(
+                "wallet {} is not watched",
+                wallet
+            )));
+        };
+        let _ = follower.stop.send(true);
+        for task in follower.tasks {
+            let _ = task.await;
+        }
+        self.store.remove_watched_wallet(wallet).await?;
+        if purge {
+            self.store.purge_wallet(wallet).await?;
+        }
+        info!(%wallet, purge, "wallet removed");
+        Ok(())
+    }
+
+    /// Waits for every follower to stop, after `shutdown` flipped.
+    pub async fn join(&self) {
+        let followers: Vec<Follower> = self
+            .followers
+            .lock()
+            .await
+            .drain()
+            .map(|(_, f)| f)
+            .collect();
+        for follower in followers {
+            for task in follower.tasks {
+                let _ = task.await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockRpc;
+    use crate::store::MemoryStore;
+
+    #[tokio::test]
+    async fn persists_runtime_wallets_across_restarts() {
+        let dir = std::env::temp_dir().join(format!("watchlist-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state_path = dir.join("state.json");
+        let _ = std::fs::remove_file(&state_path);
+
+        let config = Arc::new(Config::default());
+        let open = || async {
+            let store: Storage =
+                Arc::new(MemoryStore::open(Some(state_path.clone())).await.unwrap());
+            store
+        };
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        let client: Arc<dyn SolanaRpc> = Arc::new(MockRpc::default());
+        let store = open().await;
+        let watchlist = Watchlist::start(client, config.clone(), store, shutdown)
+            .await
+            .unwrap();
+        let added = Pubkey::new_unique();
+        assert!(watchlist.watch(added).await.unwrap().is_some());
+        assert!(watchlist.watch(added).await.unwrap().is_none());
+        assert!(matches!(
+            watchlist.unwatch(&config.wallet, false).await,
+            Err(IndexerError::InvalidParameter(_))
+        ));
+
+        let tracked = tracked_wallets(&config, &open().await).await.unwrap();
+        assert_eq!(tracked.len(), 2);
+        assert_eq!(tracked[1].wallet, added);
+        assert_eq!(tracked[1].source, WalletSource::Api);
+
+        watchlist.unwatch(&added, true).await.unwrap();
+        assert!(matches!(
+            watchlist.unwatch(&added, true).await,
+            Err(IndexerError::NotFound(_))
+        ));
+        let tracked = tracked_wallets(&config, &open().await).await.unwrap();
+        assert_eq!(tracked.len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,285 @@
+//! Pushes newly indexed transfers to the HTTP endpoints configured under
+//! `[[webhooks]]`. Each delivery is retried with exponential backoff so a
+//! receiver that is briefly down doesn't lose events.
+
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use solana_sdk::pubkey::Pubkey;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::config::{redact_url, Config};
+use crate::events::{self, TransferEvent};
+use crate::metrics::METRICS;
+use crate::model::{parse_amount, Direction};
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Total tries per event and target, the first one included.
+const MAX_ATTEMPTS: u32 = 8;
+const RETRY_MIN_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+/// Deliveries in flight or waiting for a retry; events beyond this are
+/// dropped (and counted) rather than queued without bound.
+const MAX_PENDING_DELIVERIES: usize = 1000;
+
+/// One `[[webhooks]]` entry.
+#[derive(Debug, Clone)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// Signs each body into `X-Signature` when set.
+    pub secret: Option<String>,
+    pub filter: WebhookFilter,
+}
+
+/// Which transfers a target receives; unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct WebhookFilter {
+    pub direction: Option<Direction>,
+    /// Inclusive lower bound in UI units, checked against each registered
+    /// mint's decimals when the config is loaded.
+    pub min_amount: Option<String>,
+    pub mint: Option<Pubkey>,
+}
+
+impl WebhookFilter {
+    pub fn matches(&self, event: &TransferEvent) -> bool {
+        let transfer = &event.transfer;
+        self.direction.is_none_or(|d| transfer.direction == d)
+            && self.mint.is_none_or(|mint| event.mint.mint == mint)
+            && self.min_amount.as_deref().is_none_or(|min| {
+                parse_amount(min, event.mint.decimals).is_ok_and(|min| transfer.amount_raw >= min)
+            })
+    }
+}
+
+/// `sha256=<hex>` HMAC of `body` keyed with `secret`, as sent in
+/// `X-Signature`. Receivers recompute it over the raw request body.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Starts the dispatcher if any webhook is configured. It stops taking new
+/// events once `shutdown` flips; deliveries still being retried then are
+/// abandoned.
+pub fn spawn(config: Arc<Config>, shutdown: watch::Receiver<bool>) -> Option<JoinHandle<()>> {
+    if config.webhooks.is_empty() {
+        return None;
+    }
+    Some(tokio::spawn(run(config, shutdown)))
+}
+
+async fn run(config: Arc<Config>, mut shutdown: watch::Receiver<bool>) {
+    let client = match reqwest::Client::builder().timeout(DELIVERY_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!(error = %e, "cannot build webhook client, webhooks disabled");
+            return;
+        }
+    };
+    let pending = Arc::new(Semaphore::new(MAX_PENDING_DELIVERIES));
+    let mut feed = events::subscribe();
+    info!(
+        targets = config.webhooks.len(),
+        "webhook dispatcher started"
+    );
+    loop {
+        let event = tokio::select! {
+            event = feed.recv() => event,
+            _ = shutdown.changed() => return,
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(missed, "webhook dispatcher fell behind, events dropped");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        let mut transfer = event.transfer.clone();
+        let explorer_url = config.explorer_link(&transfer.signature);
+        transfer.annotate(explorer_url);
+        let body = match serde_json::to_vec(&transfer) {
+            Ok(body) => Arc::new(body),
+            Err(e) => {
+                error!(signature = %transfer.signature, error = %e, "cannot serialize transfer");
+                continue;
+            }
+        };
+        for target in config.webhooks.iter().filter(|t| t.filter.matches(&event)) {
+            let host = redact_url(&target.url);
+            let Ok(permit) = pending.clone().try_acquire_owned() else {
+                warn!(
+                    target = %host,
+                    signature = %transfer.signature,
+                    "webhook queue full, dropping event"
+                );
+                METRICS
+                    .webhook_deliveries
+                    .with_label_values(&[&host, "dropped"])
+                    .inc();
+                continue;
+            };
+            tokio::spawn(deliver(
+                client.clone(),
+                target.clone(),
+                host,
+                transfer.signature.clone(),
+                body.clone(),
+                permit,
+            ));
+        }
+    }
+}
+
+/// Posts `body` until the receiver answers 2xx or the attempts run out. The
+/// permit holds the event's place in the pending queue until then.
+async fn deliver(
+    client: reqwest::Client,
+    target: WebhookTarget,
+    host: String,
+    signature: String,
+    body: Arc<Vec<u8>>,
+    _permit: OwnedSemaphorePermit,
+) {
+    let mut delay = RETRY_MIN_DELAY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        match post(&client, &target, &body, attempt).await {
+            Ok(()) => {
+                info!(target = %host, %signature, attempt, "webhook delivered");
+                METRICS
+                    .webhook_deliveries
+                    .with_label_values(&[&host, "delivered"])
+                    .inc();
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    target = %host,
+                    %signature,
+                    attempt,
+                    ?delay,
+                    error = %e,
+                    "webhook delivery failed"
+                );
+                METRICS
+                    .webhook_deliveries
+                    .with_label_values(&[&host, "failed"])
+                    .inc();
+            }
+        }
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(RETRY_MAX_DELAY);
+        }
+    }
+    error!(
+        target = %host,
+        %signature,
+        attempts = MAX_ATTEMPTS,
+        "giving up on webhook delivery"
+    );
+    METRICS
+        .webhook_deliveries
+        .with_label_values(&[&host, "abandoned"])
+        .inc();
+}
+
+/// Posts an alert body once; see [`crate::alerts`].
+pub(crate) async fn post_alert(
+    client: &reqwest::Client,
+    target: &WebhookTarget,
+    body: &serde_json::Value,
+) -> Result<()> {
+    post(client, target, &serde_json::to_vec(body)?, 1).await
+}
+
+/// Posts a scheduled report once; `id` goes out as `Idempotency-Key`, the
+/// same for every run of a report's day. See [`crate::reports`].
+pub(crate) async fn post_report(
+    client: &reqwest::Client,
+    target: &WebhookTarget,
+    id: &str,
+    body: &serde_json::Value,
+) -> Result<()> {
+    let body = serde_json::to_vec(body)?;
+    let mut request = client
+        .post(&target.url)
+        .header("Content-Type", "application/json")
+        .header("Idempotency-Key", id)
+        .body(body.clone());
+    if let Some(secret) = &target.secret {
+        request = request.header("X-Signature", sign(secret, &body));
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+async fn post(
+    client: &reqwest::Client,
+    target: &WebhookTarget,
+    body: &[u8],
+    attempt: u32,
+) -> Result<()> {
+    let mut request = client
+        .post(&target.url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Attempt", attempt.to_string())
+        .body(body.to_vec());
+    if let Some(secret) = &target.secret {
+        request = request.header("X-Signature", sign(secret, body));
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MintRegistry;
+    use crate::config::DEFAULT_MINTS;
+    use crate::model::fixtures::transfer;
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn filters_by_direction_mint_and_amount() {
+        let registry = MintRegistry::parse(DEFAULT_MINTS).unwrap();
+        let usdc = registry.mints[0].clone();
+        let event = |direction, amount_raw| TransferEvent {
+            id: 0,
+            wallet: Pubkey::default(),
+            mint: usdc.clone(),
+            transfer: transfer(direction, amount_raw, "alice"),
+        };
+        let filter = WebhookFilter {
+            direction: Some(Direction::Received),
+            min_amount: Some("100".to_string()),
+            mint: Some(usdc.mint),
+        };
+        assert!(filter.matches(&event(Direction::Received, 100_000_000)));
+        assert!(!filter.matches(&event(Direction::Received, 99_999_999)));
+        assert!(!filter.matches(&event(Direction::Sent, 100_000_000)));
+
+        let other_mint = WebhookFilter {
+            mint: Some(registry.mints[1].mint),
+            ..WebhookFilter::default()
+        };
+        assert!(!other_mint.matches(&event(Direction::Received, 1)));
+    }
+}
@@ -0,0 +1,228 @@
+//! `GET /ws`: the live transfer feed over a websocket, for clients that
+//! can't consume server-sent events. After the upgrade the client sends a
+//! subscription such as `{"wallets": ["..."], "min_amount": "100"}` and gets
+//! a `transfer` frame for each matching event from then on; sending another
+//! subscription replaces the filters. Each connection reads the shared
+//! event feed on its own, and one that falls behind is disconnected rather
+//! than holding anything up.
+
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+use warp::ws::{Message, WebSocket};
+
+use crate::config::{resolve_filter, Config};
+use crate::events::{self, TransferEvent};
+use crate::webhook::WebhookFilter;
+
+pub const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// Silence after which the client is taken to be gone.
+const PONG_TIMEOUT: Duration = Duration::from_secs(75);
+/// How long one frame may take to go out before the client counts as too
+/// slow.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Close code for a client dropped for falling behind the feed.
+const CLOSE_TOO_SLOW: u16 = 1008;
+
+/// A client's subscription message; unset filters match everything.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Subscription {
+    /// Wallet addresses; every tracked wallet when empty.
+    #[serde(default)]
+    pub wallets: Vec<String>,
+    /// `sent`, `received`, `self`, `minted` or `burned`.
+    pub direction: Option<String>,
+    /// Inclusive lower bound in UI units.
+    pub min_amount: Option<String>,
+    /// Mint pubkey or symbol.
+    pub mint: Option<String>,
+}
+
+/// A validated [`Subscription`].
+#[derive(Debug)]
+struct Filters {
+    wallets: Vec<Pubkey>,
+    filter: WebhookFilter,
+}
+
+impl Filters {
+    fn parse(message: &str, config: &Config) -> Result<Self, String> {
+        let subscription: Subscription =
+            serde_json::from_str(message).map_err(|e| format!("invalid subscription: {}", e))?;
+        let wallets = subscription
+            .wallets
+            .iter()
+            .map(|wallet| {
+                Pubkey::from_str(wallet).map_err(|e| format!("invalid wallet '{}': {}", wallet, e))
+            })
+            .collect::<Result<_, _>>()?;
+        let filter = resolve_filter(
+            subscription.direction.as_deref(),
+            subscription.min_amount.as_deref(),
+            subscription.mint.as_deref(),
+            &config.mints,
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Filters { wallets, filter })
+    }
+
+    fn matches(&self, event: &TransferEvent) -> bool {
+        (self.wallets.is_empty() || self.wallets.contains(&event.wallet))
+            && self.filter.matches(event)
+    }
+}
+
+fn transfer_frame(event: &TransferEvent, config: &Config) -> Value {
+    let mut transfer = event.transfer.clone();
+    transfer.wallet = Some(event.wallet.to_string());
+    let explorer_url = config.explorer_link(&transfer.signature);
+    transfer.annotate(explorer_url);
+    json!({ "type": "transfer", "id": event.id, "transfer": transfer })
+}
+
+/// Runs one upgraded connection until the client leaves, stops answering
+/// pings or can't keep up.
+#[utoipa::path(
+    get,
+    path = "/v1/ws",
+    responses((
+        status = 101,
+        description = "Upgraded; send a subscription, receive `transfer` frames",
+        body = Subscription,
+    )),
+    security(("bearer" = []), ("api_key" = []))
+)]
+pub async fn serve(socket: WebSocket, config: Arc<Config>) {
+    let (mut sink, mut incoming) = socket.split();
+    let mut feed = events::subscribe();
+    let mut filters: Option<Filters> = None;
+    let mut ping =
+        tokio::time::interval_at(tokio::time::Instant::now() + PING_INTERVAL, PING_INTERVAL);
+    let mut heard_from = Instant::now();
+
+    loop {
+        let frame = tokio::select! {
+            message = incoming.next() => {
+                let message = match message {
+                    Some(Ok(message)) => message,
+                    Some(Err(e)) => {
+                        debug!(error = %e, "websocket read failed");
+                        break;
+                    }
+                    None => break,
+                };
+                heard_from = Instant::now();
+                if message.is_close() {
+                    break;
+                }
+                // Pings are answered by the websocket layer, binary frames
+                // mean nothing here.
+                let Ok(text) = message.to_str() else {
+                    continue;
+                };
+                match Filters::parse(text, &config) {
+                    Ok(parsed) => {
+                        filters = Some(parsed);
+                        Message::text(json!({ "type": "subscribed" }).to_string())
+                    }
+                    Err(message) => {
+                        Message::text(json!({ "type": "error", "message": message }).to_string())
+                    }
+                }
+            }
+            event = feed.recv() => match event {
+                Ok(event) => match &filters {
+                    Some(filters) if filters.matches(&event) => {
+                        Message::text(transfer_frame(&event, &config).to_string())
+                    }
+                    _ => continue,
+                },
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "websocket client fell behind, disconnecting");
+                    let close = Message::close_with(CLOSE_TOO_SLOW, "too slow");
+                    let _ = tokio::time::timeout(SEND_TIMEOUT, sink.send(close)).await;
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = ping.tick() => {
+                if heard_from.elapsed() > PONG_TIMEOUT {
+                    debug!("websocket client stopped answering pings");
+                    break;
+                }
+                Message::ping(Vec::new())
+            }
+        };
+        match tokio::time::timeout(SEND_TIMEOUT, sink.send(frame)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                debug!(error = %e, "websocket write failed");
+                break;
+            }
+            Err(_) => {
+                warn!("websocket client stopped reading, disconnecting");
+                break;
+            }
+        }
+    }
+    // Dropping the receiver is the unsubscription.
+    let _ = sink.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::fixtures::transfer;
+    use crate::model::Direction;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn sends_only_the_subscribed_transfers() {
+        let config = Arc::new(Config::default());
+        let route = warp::path!("ws").and(warp::ws()).map({
+            let config = config.clone();
+            move |ws: warp::ws::Ws| {
+                let config = config.clone();
+                ws.on_upgrade(move |socket| serve(socket, config))
+            }
+        });
+        let mut client = warp::test::ws().path("/ws").handshake(route).await.unwrap();
+
+        client.send_text(r#"{"wallets": ["nope"]}"#).await;
+        let reply: Value =
+            serde_json::from_str(client.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(reply["type"], "error");
+
+        // Other tests publish too, so this one listens for its own wallet.
+        let wallet = Pubkey::new_unique();
+        let subscription = json!({ "wallets": [wallet.to_string()], "min_amount": "1" });
+        client.send_text(subscription.to_string()).await;
+        let reply: Value =
+            serde_json::from_str(client.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(reply["type"], "subscribed");
+
+        let mint = &config.mints.mints[0];
+        events::publish(
+            &wallet,
+            mint,
+            &[
+                transfer(Direction::Received, 999_999, "dust"),
+                transfer(Direction::Received, 2_000_000, "alice"),
+            ],
+        );
+        let frame: Value =
+            serde_json::from_str(client.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(frame["type"], "transfer");
+        assert_eq!(frame["transfer"]["amount_raw"], 2_000_000);
+        assert_eq!(frame["transfer"]["wallet"], wallet.to_string());
+    }
+}